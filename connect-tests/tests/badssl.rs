@@ -42,7 +42,7 @@ mod online {
     fn wrong_host() {
         connect("wrong.host.badssl.com")
             .fails()
-            .expect(r"TLS error: InvalidCertificate\(NotValidForName\)")
+            .expect(r#"TLS error: InvalidCertificate\(NameMismatch \{ requested: "wrong.host.badssl.com", presented: \[.*\] \}\)"#)
             .go()
             .unwrap();
     }
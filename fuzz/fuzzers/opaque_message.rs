@@ -0,0 +1,17 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rustls;
+
+use rustls::internal::msgs::message::{Message, OpaqueMessage};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+
+    let typ = data[0];
+    let version = u16::from_be_bytes([data[1], data[2]]);
+    let msg = OpaqueMessage::from_raw(typ, version, &data[3..]);
+    Message::try_from(msg.into_plain_message()).ok();
+});
@@ -1,4 +1,6 @@
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
 use chacha20poly1305::{AeadInPlace, KeyInit, KeySizeUser};
+use poly1305::universal_hash::UniversalHash;
 use rustls::crypto::cipher::{self, AeadKey, Iv, UnsupportedOperationError, NONCE_LEN};
 use rustls::{ConnectionTrafficSecrets, ContentType, ProtocolVersion};
 
@@ -13,9 +15,13 @@ impl cipher::Tls13AeadAlgorithm for Chacha20Poly1305 {
     }
 
     fn decrypter(&self, key: cipher::AeadKey, iv: cipher::Iv) -> Box<dyn cipher::MessageDecrypter> {
-        Box::new(Tls13Cipher(
-            chacha20poly1305::ChaCha20Poly1305::new_from_slice(key.as_ref()).unwrap(),
-            iv,
+        let chunked = ChunkedDecrypter::new(&key, cipher::Iv::copy(iv.as_ref()), true);
+        Box::new(Decrypter(
+            Tls13Cipher(
+                chacha20poly1305::ChaCha20Poly1305::new_from_slice(key.as_ref()).unwrap(),
+                iv,
+            ),
+            chunked,
         ))
     }
 
@@ -46,9 +52,12 @@ impl cipher::Tls12AeadAlgorithm for Chacha20Poly1305 {
     }
 
     fn decrypter(&self, key: cipher::AeadKey, iv: &[u8]) -> Box<dyn cipher::MessageDecrypter> {
-        Box::new(Tls12Cipher(
-            chacha20poly1305::ChaCha20Poly1305::new_from_slice(key.as_ref()).unwrap(),
-            cipher::Iv::copy(iv),
+        Box::new(Decrypter(
+            Tls12Cipher(
+                chacha20poly1305::ChaCha20Poly1305::new_from_slice(key.as_ref()).unwrap(),
+                cipher::Iv::copy(iv),
+            ),
+            ChunkedDecrypter::new(&key, cipher::Iv::copy(iv), false),
         ))
     }
 
@@ -179,4 +188,162 @@ impl cipher::MessageDecrypter for Tls12Cipher {
     }
 }
 
+/// A decrypter which can also decrypt records as they arrive.
+struct Decrypter<C>(C, ChunkedDecrypter);
+
+impl<C: cipher::MessageDecrypter> cipher::MessageDecrypter for Decrypter<C> {
+    fn decrypt(
+        &mut self,
+        m: cipher::OpaqueMessage,
+        seq: u64,
+    ) -> Result<cipher::PlainMessage, rustls::Error> {
+        self.0.decrypt(m, seq)
+    }
+
+    fn streaming(&mut self) -> Option<&mut dyn cipher::StreamingDecrypter> {
+        Some(&mut self.1)
+    }
+}
+
+/// Decrypts a record in chunks, using chacha20 and poly1305 directly as
+/// `chacha20poly1305` does, so the tag is checked once the record is complete.
+struct ChunkedDecrypter {
+    key: chacha20::Key,
+    iv: cipher::Iv,
+    tls13: bool,
+    record: Option<ChunkedRecord>,
+}
+
+struct ChunkedRecord {
+    typ: ContentType,
+    version: ProtocolVersion,
+    aad_len: usize,
+    ciphertext_len: usize,
+    chacha: chacha20::ChaCha20,
+    poly: poly1305::Poly1305,
+    /// Ciphertext not yet given to `poly`, which takes whole blocks.
+    unauthenticated: Vec<u8>,
+    /// Withheld until the tag is verified.
+    plaintext: Vec<u8>,
+    tag: Vec<u8>,
+}
+
+impl ChunkedDecrypter {
+    fn new(key: &cipher::AeadKey, iv: cipher::Iv, tls13: bool) -> Self {
+        Self {
+            key: *chacha20::Key::from_slice(key.as_ref()),
+            iv,
+            tls13,
+            record: None,
+        }
+    }
+}
+
+impl cipher::StreamingDecrypter for ChunkedDecrypter {
+    fn start(
+        &mut self,
+        typ: ContentType,
+        version: ProtocolVersion,
+        len: usize,
+        seq: u64,
+    ) -> Result<(), rustls::Error> {
+        self.record = None;
+        let ciphertext_len = len
+            .checked_sub(CHACHAPOLY1305_OVERHEAD)
+            .ok_or(rustls::Error::DecryptError)?;
+
+        let aad = match self.tls13 {
+            true => cipher::make_tls13_aad(len).to_vec(),
+            false => cipher::make_tls12_aad(seq, typ, version, ciphertext_len).to_vec(),
+        };
+
+        let nonce = chacha20::Nonce::from(cipher::Nonce::new(&self.iv, seq).0);
+        let mut chacha = chacha20::ChaCha20::new(&self.key, &nonce);
+        let mut poly_key = poly1305::Key::default();
+        chacha.apply_keystream(&mut poly_key);
+        chacha.seek(64);
+
+        let mut poly = <poly1305::Poly1305 as poly1305::universal_hash::KeyInit>::new(&poly_key);
+        poly.update_padded(&aad);
+
+        self.record = Some(ChunkedRecord {
+            typ,
+            version,
+            aad_len: aad.len(),
+            ciphertext_len,
+            chacha,
+            poly,
+            unauthenticated: Vec::new(),
+            plaintext: Vec::with_capacity(ciphertext_len),
+            tag: Vec::with_capacity(CHACHAPOLY1305_OVERHEAD),
+        });
+        Ok(())
+    }
+
+    fn update(&mut self, chunk: &[u8]) -> Result<(), rustls::Error> {
+        let record = self
+            .record
+            .as_mut()
+            .ok_or(rustls::Error::DecryptError)?;
+
+        let ciphertext_left = record.ciphertext_len - record.plaintext.len();
+        let (ciphertext, tag) = chunk.split_at(chunk.len().min(ciphertext_left));
+        if record.tag.len() + tag.len() > CHACHAPOLY1305_OVERHEAD {
+            self.record = None;
+            return Err(rustls::Error::DecryptError);
+        }
+
+        record
+            .unauthenticated
+            .extend_from_slice(ciphertext);
+        let whole_blocks = record.unauthenticated.len() / 16 * 16;
+        record
+            .poly
+            .update_padded(&record.unauthenticated[..whole_blocks]);
+        record
+            .unauthenticated
+            .drain(..whole_blocks);
+
+        let start = record.plaintext.len();
+        record
+            .plaintext
+            .extend_from_slice(ciphertext);
+        record
+            .chacha
+            .apply_keystream(&mut record.plaintext[start..]);
+
+        record.tag.extend_from_slice(tag);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<cipher::PlainMessage, rustls::Error> {
+        let mut record = self
+            .record
+            .take()
+            .ok_or(rustls::Error::DecryptError)?;
+        if record.tag.len() != CHACHAPOLY1305_OVERHEAD {
+            return Err(rustls::Error::DecryptError);
+        }
+
+        record
+            .poly
+            .update_padded(&record.unauthenticated);
+        let mut lengths = poly1305::Block::default();
+        lengths[..8].copy_from_slice(&(record.aad_len as u64).to_le_bytes());
+        lengths[8..].copy_from_slice(&(record.ciphertext_len as u64).to_le_bytes());
+        record.poly.update(&[lengths]);
+
+        record
+            .poly
+            .verify(poly1305::Tag::from_slice(&record.tag))
+            .map_err(|_| rustls::Error::DecryptError)?;
+
+        let m = cipher::OpaqueMessage::new(record.typ, record.version, record.plaintext);
+        match self.tls13 {
+            true => m.into_tls13_unpadded_message(),
+            false => Ok(m.into_plain_message()),
+        }
+    }
+}
+
 const CHACHAPOLY1305_OVERHEAD: usize = 16;
@@ -0,0 +1,135 @@
+use rustls::crypto::cipher::{
+    AeadKey, BorrowedPlainMessage, Iv, MessageDecrypter, MessageEncrypter, PlainMessage,
+};
+use rustls::{ContentType, Error, ProtocolVersion, SupportedCipherSuite};
+use rustls_provider_example::{
+    TLS13_CHACHA20_POLY1305_SHA256, TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+};
+
+const PLAINTEXT: &[u8] = b"a record of some length, decrypted in two chunks";
+
+fn ciphers(suite: SupportedCipherSuite) -> (Box<dyn MessageEncrypter>, Box<dyn MessageDecrypter>) {
+    let key = || AeadKey::from([0x42; 32]);
+    match suite {
+        SupportedCipherSuite::Tls13(suite) => (
+            suite
+                .aead_alg
+                .encrypter(key(), Iv::from([0x24; 12])),
+            suite
+                .aead_alg
+                .decrypter(key(), Iv::from([0x24; 12])),
+        ),
+        SupportedCipherSuite::Tls12(suite) => (
+            suite
+                .aead_alg
+                .encrypter(key(), &[0x24; 12], &[]),
+            suite
+                .aead_alg
+                .decrypter(key(), &[0x24; 12]),
+        ),
+    }
+}
+
+fn decrypt_in_two_chunks(
+    decrypter: &mut dyn MessageDecrypter,
+    (typ, version): (ContentType, ProtocolVersion),
+    ciphertext: &[u8],
+    split: usize,
+    seq: u64,
+) -> Result<PlainMessage, Error> {
+    let streaming = decrypter.streaming().unwrap();
+    streaming.start(typ, version, ciphertext.len(), seq)?;
+    streaming.update(&ciphertext[..split])?;
+    streaming.update(&ciphertext[split..])?;
+    streaming.finish()
+}
+
+#[test]
+fn streaming_decryption_matches_whole_record_decryption() {
+    for suite in [
+        TLS13_CHACHA20_POLY1305_SHA256,
+        TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+    ] {
+        let (mut encrypter, mut decrypter) = ciphers(suite);
+        let encrypted = encrypter
+            .encrypt(
+                BorrowedPlainMessage {
+                    typ: ContentType::ApplicationData,
+                    version: ProtocolVersion::TLSv1_2,
+                    payload: PLAINTEXT,
+                },
+                7,
+            )
+            .unwrap();
+        let header = (encrypted.typ, encrypted.version);
+        let ciphertext = encrypted.payload().to_vec();
+
+        // Every split, including those inside the tag.
+        for split in 0..=ciphertext.len() {
+            let plain =
+                decrypt_in_two_chunks(decrypter.as_mut(), header, &ciphertext, split, 7).unwrap();
+            assert_eq!(plain.typ, ContentType::ApplicationData);
+            assert_eq!(plain.payload.0, PLAINTEXT);
+        }
+
+        let whole = decrypter.decrypt(encrypted, 7).unwrap();
+        assert_eq!(whole.payload.0, PLAINTEXT);
+    }
+}
+
+#[test]
+fn streaming_decryption_detects_tampering_in_either_chunk() {
+    for suite in [
+        TLS13_CHACHA20_POLY1305_SHA256,
+        TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+    ] {
+        let (mut encrypter, mut decrypter) = ciphers(suite);
+        let encrypted = encrypter
+            .encrypt(
+                BorrowedPlainMessage {
+                    typ: ContentType::ApplicationData,
+                    version: ProtocolVersion::TLSv1_2,
+                    payload: PLAINTEXT,
+                },
+                7,
+            )
+            .unwrap();
+        let header = (encrypted.typ, encrypted.version);
+        let ciphertext = encrypted.payload().to_vec();
+        let split = ciphertext.len() / 2;
+
+        for tampered_at in [0, split - 1, split, ciphertext.len() - 1] {
+            let mut tampered = ciphertext.clone();
+            tampered[tampered_at] ^= 0x01;
+            assert_eq!(
+                decrypt_in_two_chunks(decrypter.as_mut(), header, &tampered, split, 7).unwrap_err(),
+                Error::DecryptError
+            );
+        }
+
+        // The wrong sequence number, too much or too little ciphertext also fail.
+        assert_eq!(
+            decrypt_in_two_chunks(decrypter.as_mut(), header, &ciphertext, split, 8).unwrap_err(),
+            Error::DecryptError
+        );
+
+        let streaming = decrypter.streaming().unwrap();
+        streaming
+            .start(header.0, header.1, ciphertext.len() - 1, 7)
+            .unwrap();
+        assert_eq!(
+            streaming
+                .update(&ciphertext)
+                .unwrap_err(),
+            Error::DecryptError
+        );
+
+        streaming
+            .start(header.0, header.1, ciphertext.len(), 7)
+            .unwrap();
+        streaming
+            .update(&ciphertext[..split])
+            .unwrap();
+        assert_eq!(streaming.finish().unwrap_err(), Error::DecryptError);
+    }
+}
@@ -1,9 +1,12 @@
 use crate::builder::{ConfigBuilder, WantsVerifier};
 use crate::client::handy;
 use crate::client::{ClientConfig, ResolvesClientCert};
+use crate::common_state::RecordPadding;
 use crate::crypto::CryptoProvider;
+use crate::enums::ProtocolVersion;
 use crate::error::Error;
 use crate::key_log::NoKeyLog;
+use crate::msgs::enums::PSKKeyExchangeMode;
 use crate::msgs::handshake::CertificateChain;
 use crate::webpki::{self, WebPkiServerVerifier};
 use crate::{verify, versions};
@@ -13,6 +16,7 @@ use super::client_conn::Resumption;
 use pki_types::{CertificateDer, PrivateKeyDer};
 
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::marker::PhantomData;
 
@@ -121,7 +125,8 @@ impl ConfigBuilder<ClientConfig, WantsClientCert> {
     /// `aws-lc-rs` and `ring` [`CryptoProvider`]s support all three encodings,
     /// but other `CryptoProviders` may not.
     ///
-    /// This function fails if `key_der` is invalid.
+    /// This function fails if `key_der` is invalid, or does not match the end-entity
+    /// certificate ([`Error::InconsistentKeys`]).
     pub fn with_client_auth_cert(
         self,
         cert_chain: Vec<CertificateDer<'static>>,
@@ -151,14 +156,35 @@ impl ConfigBuilder<ClientConfig, WantsClientCert> {
             provider: self.state.provider,
             alpn_protocols: Vec::new(),
             resumption: Resumption::default(),
+            ticket_request: None,
+            external_psk: None,
             max_fragment_size: None,
+            max_handshake_flight_bytes: None,
+            heartbeat_mode: None,
             client_auth_cert_resolver,
             versions: self.state.versions,
             enable_sni: true,
+            send_tls12_supported_groups: true,
             verifier: self.state.verifier,
             key_log: Arc::new(NoKeyLog {}),
             enable_secret_extraction: false,
             enable_early_data: false,
+            enable_false_start: false,
+            require_rfc1123_server_name: false,
+            record_padding: RecordPadding::None,
+            alert_observer: None,
+            aad_observer: None,
+            explicit_nonce_random: None,
+            alps_settings: Vec::new(),
+            client_hello_record_version: ProtocolVersion::TLSv1_0,
+            preserve_record_boundaries: false,
+            client_hello_mutator: None,
+            on_new_ticket: None,
+            psk_key_exchange_modes: vec![PSKKeyExchangeMode::PSK_DHE_KE],
+            pad_client_hello_to: None,
+            require_ems: false,
+            on_handshake_message: None,
+            cipher_suite_order: None,
         }
     }
 }
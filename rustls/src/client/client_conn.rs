@@ -1,33 +1,46 @@
 use crate::builder::ConfigBuilder;
-use crate::common_state::{CommonState, Protocol, Side};
+use crate::common_state::{
+    CommonState, ObservesAlerts, ObservesHandshakeMessages, Protocol, RecordPadding, Side,
+};
 use crate::conn::{ConnectionCommon, ConnectionCore};
-use crate::crypto::{CryptoProvider, SupportedKxGroup};
+use crate::crypto::cipher::ObservesAad;
+use crate::crypto::{CryptoProvider, SecureRandom, SupportedKxGroup};
 use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
-use crate::error::Error;
+use crate::error::{CertificateError, Error};
 #[cfg(feature = "logging")]
 use crate::log::trace;
-use crate::msgs::enums::NamedGroup;
-use crate::msgs::handshake::ClientExtension;
+use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::{HeartbeatMode, NamedGroup, PSKKeyExchangeMode};
+use crate::msgs::handshake::{
+    ClientExtension, ClientHelloPayload, DistinguishedName, TicketRequest,
+};
 use crate::msgs::persist;
 use crate::sign;
 use crate::suites::{ExtractedSecrets, SupportedCipherSuite};
+use crate::tls13::ExternalPsk;
 use crate::versions;
 use crate::KeyLog;
 #[cfg(feature = "ring")]
 use crate::WantsVerifier;
 use crate::{verify, WantsVersions};
 
-use super::handy::{ClientSessionMemoryCache, NoClientSessionStorage};
+use super::handy::{
+    ClientSessionMemoryCache, NoClientSessionStorage, StoreOnlyClientSessionStorage,
+};
 use super::hs;
 
-use pki_types::ServerName;
+use pki_types::{ServerName, UnixTime};
+use zeroize::Zeroizing;
 
+use alloc::boxed::Box;
+use alloc::format;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt;
 use core::marker::PhantomData;
 use core::mem;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
 use std::io;
 
 /// A trait for the ability to store client session data, so that sessions
@@ -141,6 +154,11 @@ pub trait ResolvesClientCert: fmt::Debug + Send + Sync {
 ///    ids or tickets, with a max of eight tickets per server.
 /// * [`ClientConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
 /// * [`ClientConfig::key_log`]: key material is not logged.
+/// * [`ClientConfig::require_rfc1123_server_name`]: `false`.
+/// * [`ClientConfig::record_padding`]: [`RecordPadding::None`].
+/// * [`ClientConfig::client_hello_record_version`]: [`ProtocolVersion::TLSv1_0`].
+/// * [`ClientConfig::heartbeat_mode`]: `None`, so heartbeats are not negotiated.
+/// * [`ClientConfig::psk_key_exchange_modes`]: `[PSK_DHE_KE]`.
 ///
 /// [`RootCertStore`]: crate::RootCertStore
 #[derive(Debug)]
@@ -155,6 +173,22 @@ pub struct ClientConfig {
     /// How and when the client can resume a previous session.
     pub resumption: Resumption,
 
+    /// How many TLS1.3 tickets to ask the server for, using the [RFC9149]
+    /// `ticket_request` extension.
+    ///
+    /// The server may send fewer.  The default is `None`, leaving the number
+    /// of tickets up to the server.
+    ///
+    /// [RFC9149]: https://datatracker.ietf.org/doc/html/rfc9149
+    pub ticket_request: Option<TicketRequest>,
+
+    /// An externally-provisioned TLS1.3 pre-shared key to offer the server.
+    ///
+    /// If the server accepts it, the handshake is authenticated by the key
+    /// and the server sends no certificate.  It is not offered when resuming
+    /// a TLS1.3 session.  The default is `None`.
+    pub external_psk: Option<Arc<ExternalPsk>>,
+
     /// The maximum size of plaintext input to be emitted in a single TLS record.
     /// A value of None is equivalent to the [TLS maximum] of 16 kB.
     ///
@@ -168,6 +202,28 @@ pub struct ClientConfig {
     /// [ClientConnection::new]: crate::client::ClientConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// The maximum total size of the handshake messages the peer may send in
+    /// a single flight during the handshake, that is, between handshake
+    /// messages sent by us.
+    ///
+    /// Each message is already limited to 64kB; this additionally bounds the
+    /// work done for a flight made of many messages.  Exceeding it fails the
+    /// connection with a `decode_error` alert.  The default is `None`,
+    /// meaning no limit beyond the per-message one.
+    pub max_handshake_flight_bytes: Option<usize>,
+
+    /// Whether to negotiate the [RFC6520] `heartbeat` extension, and if so,
+    /// whether the peer may send us heartbeat requests.
+    ///
+    /// Heartbeats are only negotiated when both peers configure this. Once the
+    /// handshake is complete, requests can be sent with
+    /// [`CommonState::send_heartbeat`] if the peer allows it.  The default is
+    /// `None`: the extension is neither offered nor accepted.
+    ///
+    /// [RFC6520]: https://datatracker.ietf.org/doc/html/rfc6520
+    /// [`CommonState::send_heartbeat`]: crate::CommonState::send_heartbeat
+    pub heartbeat_mode: Option<HeartbeatMode>,
+
     /// How to decide what client auth certificate/keys to use.
     pub client_auth_cert_resolver: Arc<dyn ResolvesClientCert>,
 
@@ -181,6 +237,17 @@ pub struct ClientConfig {
     /// The default is true.
     pub enable_sni: bool,
 
+    /// Whether to send the `supported_groups` extension when only TLS1.2
+    /// is offered.
+    ///
+    /// Some legacy servers fail to handle this extension.  Without it, a
+    /// TLS1.2 server is free to choose any group, though rustls servers
+    /// refuse such a handshake.  The extension is always sent when TLS1.3
+    /// is offered, since TLS1.3 requires it.
+    ///
+    /// The default is true.
+    pub send_tls12_supported_groups: bool,
+
     /// How to verify the server certificate chain.
     pub(super) verifier: Arc<dyn verify::ServerCertVerifier>,
 
@@ -197,6 +264,282 @@ pub struct ClientConfig {
     ///
     /// The default is false.
     pub enable_early_data: bool,
+
+    /// Whether to "False Start" full TLS1.2 handshakes, as described in RFC7918.
+    ///
+    /// If enabled, application data is sent straight after our Finished message,
+    /// without waiting for the server's, when the negotiated cipher suite and key
+    /// exchange group are safe for this: an ECDHE key exchange over a well-known
+    /// group, and an AEAD cipher.  Resumed handshakes, and TLS1.3, are unaffected.
+    ///
+    /// The default is false.
+    pub enable_false_start: bool,
+
+    /// Whether a DNS server name must be a valid RFC 1123 hostname, on top of
+    /// the rules applied by [`ServerName`] and webpki.
+    ///
+    /// If enabled, the name must be at most 253 octets, made of labels of 1 to
+    /// 63 letters, digits and hyphens which neither start nor end with a hyphen.
+    /// Other names are rejected with [`CertificateError::NotValidForName`]
+    /// before the server's certificate is verified.  Like certificate
+    /// verification, this is not repeated when a session is resumed.
+    ///
+    /// This can only make name checking stricter.  [`ServerName`] and webpki
+    /// already accept labels containing underscores, as used by some internal
+    /// services, but reject labels longer than 63 octets, and rustls has no
+    /// way to relax their rules.
+    ///
+    /// The default is false.
+    ///
+    /// [`CertificateError::NotValidForName`]: crate::CertificateError::NotValidForName
+    pub require_rfc1123_server_name: bool,
+
+    /// How much padding to add to TLS1.3 records.
+    pub record_padding: RecordPadding,
+
+    /// Called for each alert sent or received.  The default is `None`.
+    pub alert_observer: Option<Arc<dyn ObservesAlerts>>,
+
+    /// Told the exact `additional_data` given to the AEAD for each record
+    /// this connection encrypts or decrypts, for auditing.  The default is
+    /// `None`.
+    ///
+    /// Only records protected by ciphers which report their
+    /// `additional_data` are observed; those of the built-in providers do.
+    pub aad_observer: Option<Arc<dyn ObservesAad>>,
+
+    /// Source of the starting point for the explicit nonces in TLS1.2 records
+    /// this connection sends, for cipher suites that have them (AES-GCM).
+    ///
+    /// If this is `None` (the default), the starting point is derived from
+    /// the key block.  This allows record-layer nonce generation to be
+    /// controlled separately from the provider's `secure_random`.
+    pub explicit_nonce_random: Option<&'static dyn SecureRandom>,
+
+    /// Application-layer protocol settings (ALPS) to exchange in TLS1.3
+    /// handshakes, as pairs of an ALPN protocol and the settings to send
+    /// when that protocol is agreed.  The default is empty.
+    ///
+    /// Settings are exchanged only if both peers have settings for the agreed
+    /// protocol; the peer's are then available from
+    /// [`CommonState::peer_alps_settings`].
+    ///
+    /// Settings are only offered for protocols also listed in
+    /// [`ClientConfig::alpn_protocols`].
+    pub alps_settings: Vec<(Vec<u8>, Vec<u8>)>,
+
+    /// The version in the record header of the initial `ClientHello`.
+    ///
+    /// This is conventionally `0x0301` ([`ProtocolVersion::TLSv1_0`]) for
+    /// compatibility with old servers, and that is the default; some clients
+    /// use `0x0303` ([`ProtocolVersion::TLSv1_2`]) instead.  Every other record,
+    /// including a `ClientHello` sent after a `HelloRetryRequest`, uses `0x0303`.
+    ///
+    /// Peers only accept record versions of the form `0x03XX`, so other
+    /// values are reported as errors from [`ClientConnection::new`].
+    pub client_hello_record_version: ProtocolVersion,
+
+    /// Whether reads from [`ClientConnection::reader`] stop at the end of
+    /// each received TLS record, rather than coalescing data across records.
+    ///
+    /// The default is false.
+    ///
+    /// [`ClientConnection::reader`]: crate::ConnectionCommon::reader
+    pub preserve_record_boundaries: bool,
+
+    /// Called with each `ClientHello` just before it is encoded.  The default
+    /// is `None`.
+    ///
+    /// Set with [`DangerousClientConfig::set_client_hello_mutator`].
+    ///
+    /// [`DangerousClientConfig::set_client_hello_mutator`]: danger::DangerousClientConfig::set_client_hello_mutator
+    pub(super) client_hello_mutator: Option<ClientHelloMutator>,
+
+    /// Called each time a session ticket is received and stored.  The default
+    /// is `None`.
+    pub on_new_ticket: Option<Arc<dyn ObservesNewTickets>>,
+
+    /// The `psk_key_exchange_modes` values sent in TLS1.3 `ClientHello`s, in
+    /// the order given.
+    ///
+    /// Resumption only ever uses [`PSKKeyExchangeMode::PSK_DHE_KE`], so
+    /// this list must contain it; other values only change what is offered.
+    /// A list without it is reported as an error from [`ClientConnection::new`].
+    ///
+    /// The default is `[PSK_DHE_KE]`.
+    pub psk_key_exchange_modes: Vec<PSKKeyExchangeMode>,
+
+    /// Pad `ClientHello`s to at least this many bytes, using the [RFC7685]
+    /// `padding` extension.
+    ///
+    /// The size counted is that of the handshake message, including its
+    /// four byte header, but not the record header.  Some middleboxes
+    /// mishandle `ClientHello`s of between 256 and 511 bytes, which padding
+    /// to 512 avoids.  The default is `None`, sending no padding.
+    ///
+    /// [RFC7685]: https://datatracker.ietf.org/doc/html/rfc7685
+    pub pad_client_hello_to: Option<usize>,
+
+    /// Whether to refuse TLS1.2 handshakes with servers that do not agree
+    /// to the [RFC7627] `extended_master_secret` extension.
+    ///
+    /// Such handshakes fail with a `handshake_failure` alert.  This mitigates
+    /// the triple handshake attack.  TLS1.3 is unaffected.  The default is
+    /// false.
+    ///
+    /// [RFC7627]: https://datatracker.ietf.org/doc/html/rfc7627
+    pub require_ems: bool,
+
+    /// Called with each handshake message as it is added to the handshake
+    /// transcript.  The default is `None`.
+    pub on_handshake_message: Option<Arc<dyn ObservesHandshakeMessages>>,
+
+    /// The exact cipher suite list to send, set by [`ClientConfig::set_cipher_suite_order`].
+    pub(super) cipher_suite_order: Option<Vec<CipherSuite>>,
+}
+
+#[derive(Clone)]
+pub(super) struct ClientHelloMutator(pub(super) Arc<dyn Fn(&mut ClientHelloPayload) + Send + Sync>);
+
+impl fmt::Debug for ClientHelloMutator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ClientHelloMutator")
+    }
+}
+
+/// Observes the session tickets received by client connections.
+///
+/// This is for applications that want to know when tickets arrive, for instance
+/// to maintain their own records alongside a [`ClientSessionStore`].  The ticket
+/// has already been stored when this is called.
+pub trait ObservesNewTickets: fmt::Debug + Send + Sync {
+    /// Called when a ticket for `server_name` has been received.
+    fn new_ticket(&self, server_name: &ServerName<'_>, ticket: &NewSessionTicketMetadata);
+}
+
+/// Details of a session ticket received from a server.
+///
+/// Passed to [`ObservesNewTickets::new_ticket`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NewSessionTicketMetadata {
+    /// The protocol version of the connection the ticket was received on.
+    pub version: ProtocolVersion,
+    /// The cipher suite of the connection the ticket was received on.
+    pub cipher_suite: CipherSuite,
+    /// The lifetime the server gave the ticket.
+    ///
+    /// In TLS1.2, zero means the server gave no lifetime hint.
+    pub lifetime: Duration,
+    /// The amount of early data the server will accept when resuming with the
+    /// ticket.  This is always zero in TLS1.2.
+    pub max_early_data_size: u32,
+    /// The length of the opaque ticket, in bytes.
+    pub ticket_len: usize,
+}
+
+/// The age and lifetime of the ticket a client resumed with.
+///
+/// Returned by [`ClientConnection::resumed_ticket`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ResumedTicket {
+    age_secs: u64,
+    lifetime_secs: u32,
+}
+
+impl ResumedTicket {
+    pub(super) fn new(session: &persist::ClientSessionCommon, now: UnixTime) -> Self {
+        Self {
+            age_secs: session.age_secs(now),
+            lifetime_secs: session.lifetime_secs(),
+        }
+    }
+
+    /// How long before resumption the ticket was received from the server.
+    ///
+    /// This has a resolution of whole seconds.
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(self.age_secs)
+    }
+
+    /// The lifetime the server gave the ticket, or `None` if it gave none.
+    pub fn lifetime(&self) -> Option<Duration> {
+        match self.lifetime_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(u64::from(secs))),
+        }
+    }
+
+    /// Returns true if less than a tenth of the ticket's lifetime remained
+    /// when it was used.
+    ///
+    /// Clients that want fresh keys may like to follow up with a full handshake.
+    pub fn is_near_expiry(&self) -> bool {
+        self.lifetime_secs != 0
+            && self.age_secs.saturating_mul(10) > u64::from(self.lifetime_secs) * 9
+    }
+}
+
+/// Returns true if `suite` is not a real cipher suite, but a signalling value
+/// we may send: `TLS_EMPTY_RENEGOTIATION_INFO_SCSV` or an [RFC8701] GREASE value.
+///
+/// [RFC8701]: https://datatracker.ietf.org/doc/html/rfc8701
+fn is_signalling(suite: CipherSuite) -> bool {
+    let value = suite.get_u16();
+    suite == CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV
+        || (value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff)
+}
+
+/// The contents of a server's `CertificateRequest`.
+///
+/// Returned by [`ClientConnection::client_auth_request`].
+#[derive(Clone, Debug)]
+pub struct ClientAuthRequest {
+    root_hint_subjects: Vec<DistinguishedName>,
+    signature_schemes: Vec<SignatureScheme>,
+}
+
+impl ClientAuthRequest {
+    pub(super) fn new(
+        root_hint_subjects: Option<&[DistinguishedName]>,
+        signature_schemes: &[SignatureScheme],
+    ) -> Self {
+        Self {
+            root_hint_subjects: root_hint_subjects
+                .map(<[_]>::to_vec)
+                .unwrap_or_default(),
+            signature_schemes: signature_schemes.to_vec(),
+        }
+    }
+
+    /// The subjects of the CAs the server will accept client certificates from.
+    ///
+    /// Empty if the server did not say.
+    pub fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &self.root_hint_subjects
+    }
+
+    /// The signature schemes the server will accept, in its order of preference.
+    pub fn signature_schemes(&self) -> &[SignatureScheme] {
+        &self.signature_schemes
+    }
+}
+
+fn is_rfc1123_hostname(name: &str) -> bool {
+    // A single trailing dot denotes an absolute name, and is not part of any label.
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > 253 {
+        return false;
+    }
+
+    name.split('.').all(|label| {
+        (1..=63).contains(&label.len())
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    })
 }
 
 /// What mechanisms to support for resuming a TLS 1.2 session.
@@ -220,15 +563,36 @@ impl Clone for ClientConfig {
         Self {
             provider: Arc::<CryptoProvider>::clone(&self.provider),
             resumption: self.resumption.clone(),
+            ticket_request: self.ticket_request,
+            external_psk: self.external_psk.clone(),
             alpn_protocols: self.alpn_protocols.clone(),
             max_fragment_size: self.max_fragment_size,
+            max_handshake_flight_bytes: self.max_handshake_flight_bytes,
+            heartbeat_mode: self.heartbeat_mode,
             client_auth_cert_resolver: Arc::clone(&self.client_auth_cert_resolver),
             versions: self.versions,
             enable_sni: self.enable_sni,
+            send_tls12_supported_groups: self.send_tls12_supported_groups,
             verifier: Arc::clone(&self.verifier),
             key_log: Arc::clone(&self.key_log),
             enable_secret_extraction: self.enable_secret_extraction,
             enable_early_data: self.enable_early_data,
+            enable_false_start: self.enable_false_start,
+            require_rfc1123_server_name: self.require_rfc1123_server_name,
+            record_padding: self.record_padding,
+            alert_observer: self.alert_observer.clone(),
+            aad_observer: self.aad_observer.clone(),
+            explicit_nonce_random: self.explicit_nonce_random,
+            alps_settings: self.alps_settings.clone(),
+            client_hello_record_version: self.client_hello_record_version,
+            psk_key_exchange_modes: self.psk_key_exchange_modes.clone(),
+            pad_client_hello_to: self.pad_client_hello_to,
+            require_ems: self.require_ems,
+            on_handshake_message: self.on_handshake_message.clone(),
+            cipher_suite_order: self.cipher_suite_order.clone(),
+            preserve_record_boundaries: self.preserve_record_boundaries,
+            client_hello_mutator: self.client_hello_mutator.clone(),
+            on_new_ticket: self.on_new_ticket.clone(),
         }
     }
 }
@@ -287,30 +651,135 @@ impl ClientConfig {
     pub(crate) fn supports_version(&self, v: ProtocolVersion) -> bool {
         self.versions.contains(v)
             && self
-                .provider
-                .cipher_suites
-                .iter()
+                .enabled_cipher_suites()
                 .any(|cs| cs.version().version == v)
     }
 
     pub(crate) fn supports_protocol(&self, proto: Protocol) -> bool {
-        self.provider
-            .cipher_suites
-            .iter()
+        self.enabled_cipher_suites()
             .any(|cs| cs.usable_for_protocol(proto))
     }
 
+    /// The cipher suites offered in this configuration's `ClientHello`s, in
+    /// order of preference.
+    ///
+    /// These are the suites of the configured [`CryptoProvider`] for the
+    /// enabled protocol versions.  QUIC connections further leave out suites
+    /// unusable with QUIC.  The `TLS_EMPTY_RENEGOTIATION_INFO_SCSV` signalling
+    /// value that is always sent is not included, unless placed in the list
+    /// with [`ClientConfig::set_cipher_suite_order`].
+    pub fn advertised_cipher_suites(&self) -> Vec<CipherSuite> {
+        self.advertised_cipher_suites_for(Protocol::Tcp)
+    }
+
+    pub(crate) fn advertised_cipher_suites_for(&self, proto: Protocol) -> Vec<CipherSuite> {
+        let supported = self
+            .enabled_cipher_suites()
+            .filter(|cs| {
+                self.versions
+                    .contains(cs.version().version)
+                    && cs.usable_for_protocol(proto)
+            })
+            .map(|cs| cs.suite());
+
+        match &self.cipher_suite_order {
+            Some(order) => {
+                let supported = supported.collect::<Vec<_>>();
+                order
+                    .iter()
+                    .copied()
+                    .filter(|suite| supported.contains(suite) || is_signalling(*suite))
+                    .collect()
+            }
+            None => supported.collect(),
+        }
+    }
+
+    /// Sets exactly which cipher suites are sent in `ClientHello`s, and in what order.
+    ///
+    /// Suites in `order` that the configured [`CryptoProvider`] does not support,
+    /// or that are not usable with the enabled protocol versions, are left out.
+    /// Provider suites not in `order` are no longer offered or accepted.
+    /// [RFC8701] GREASE values are sent where they appear in `order`, and so is
+    /// `TLS_EMPTY_RENEGOTIATION_INFO_SCSV`: otherwise that is sent last, as usual.
+    ///
+    /// Fails, leaving the configuration unchanged, if none of the provider's
+    /// suites are in `order`.
+    ///
+    /// [RFC8701]: https://datatracker.ietf.org/doc/html/rfc8701
+    pub fn set_cipher_suite_order(&mut self, order: Vec<CipherSuite>) -> Result<(), Error> {
+        let provider = &self.provider;
+        let in_provider = |suite: &CipherSuite| {
+            provider
+                .cipher_suites
+                .iter()
+                .any(|cs| cs.suite() == *suite)
+        };
+
+        if !order.iter().any(in_provider) {
+            return Err(Error::General("no cipher suites configured".into()));
+        }
+
+        self.cipher_suite_order = Some(
+            order
+                .into_iter()
+                .filter(|suite| is_signalling(*suite) || in_provider(suite))
+                .collect(),
+        );
+        Ok(())
+    }
+
     /// Access configuration options whose use is dangerous and requires
     /// extra care.
     pub fn dangerous(&mut self) -> danger::DangerousClientConfig<'_> {
         danger::DangerousClientConfig { cfg: self }
     }
 
-    pub(super) fn find_cipher_suite(&self, suite: CipherSuite) -> Option<SupportedCipherSuite> {
+    /// Restricts the key exchange groups used to those in `groups`.
+    ///
+    /// Groups of the configured [`CryptoProvider`] which are not in `groups`
+    /// are no longer offered to or accepted from peers; the provider's order
+    /// of preference is kept.  A peer supporting none of the remaining groups
+    /// fails the handshake with a `handshake_failure` alert.
+    ///
+    /// Fails, leaving the configuration unchanged, if none of the provider's
+    /// groups are in `groups`.
+    pub fn restrict_kx_groups(&mut self, groups: &[NamedGroup]) -> Result<(), Error> {
+        self.provider = Arc::new(
+            self.provider
+                .restricted_to_kx_groups(groups)?,
+        );
+        Ok(())
+    }
+
+    /// The provider's cipher suites, less any left out by
+    /// [`ClientConfig::set_cipher_suite_order`].
+    pub(super) fn enabled_cipher_suites(&self) -> impl Iterator<Item = SupportedCipherSuite> + '_ {
         self.provider
             .cipher_suites
             .iter()
             .copied()
+            .filter(move |scs| match &self.cipher_suite_order {
+                Some(order) => order.contains(&scs.suite()),
+                None => true,
+            })
+    }
+
+    /// Apply [`ClientConfig::require_rfc1123_server_name`] to `server_name`.
+    pub(super) fn check_server_name(&self, server_name: &ServerName<'_>) -> Result<(), Error> {
+        let dns_name = match server_name {
+            ServerName::DnsName(dns_name) if self.require_rfc1123_server_name => dns_name.as_ref(),
+            _ => return Ok(()),
+        };
+
+        match is_rfc1123_hostname(dns_name) {
+            true => Ok(()),
+            false => Err(CertificateError::NotValidForName.into()),
+        }
+    }
+
+    pub(super) fn find_cipher_suite(&self, suite: CipherSuite) -> Option<SupportedCipherSuite> {
+        self.enabled_cipher_suites()
             .find(|&scs| scs.suite() == suite)
     }
 
@@ -346,6 +815,25 @@ impl Resumption {
         }
     }
 
+    /// Create a new `Resumption` that stores data for the given number of sessions in memory,
+    /// keeping at most `tickets_per_server` TLS1.3 tickets for each server.
+    ///
+    /// Servers may send many tickets after each handshake; beyond this limit the
+    /// oldest tickets for that server are discarded.  [`Resumption::in_memory_sessions`]
+    /// keeps up to 8.
+    pub fn in_memory_sessions_with_tls13_tickets_per_server(
+        num: usize,
+        tickets_per_server: usize,
+    ) -> Self {
+        Self {
+            store: Arc::new(ClientSessionMemoryCache::new_with_tls13_tickets_per_server(
+                num,
+                tickets_per_server,
+            )),
+            tls12_resumption: Tls12Resumption::SessionIdOrTickets,
+        }
+    }
+
     /// Use a custom [`ClientSessionStore`] implementation to store sessions.
     ///
     /// By default, enables resuming a TLS 1.2 session with a session id or RFC 5077 ticket.
@@ -386,7 +874,8 @@ pub(super) mod danger {
     use alloc::sync::Arc;
 
     use super::verify::ServerCertVerifier;
-    use super::ClientConfig;
+    use super::{ClientConfig, ClientHelloMutator};
+    use crate::msgs::handshake::ClientHelloPayload;
 
     /// Accessor for dangerous configuration options.
     #[derive(Debug)]
@@ -400,6 +889,22 @@ pub(super) mod danger {
         pub fn set_certificate_verifier(&mut self, verifier: Arc<dyn ServerCertVerifier>) {
             self.cfg.verifier = verifier;
         }
+
+        /// Sets a function which may modify each `ClientHello` just before it is
+        /// encoded and sent: for example, to reorder, add or remove extensions.
+        ///
+        /// This runs after rustls has built the message, and is intended for
+        /// experiments with `ClientHello` fingerprints.  It is easy to produce a
+        /// `ClientHello` that fails the handshake, or that makes rustls
+        /// misinterpret the server's response.  In particular, a
+        /// `pre_shared_key` extension must remain last: its binders are
+        /// computed after the mutation.
+        pub fn set_client_hello_mutator(
+            &mut self,
+            mutator: impl Fn(&mut ClientHelloPayload) + Send + Sync + 'static,
+        ) {
+            self.cfg.client_hello_mutator = Some(ClientHelloMutator(Arc::new(mutator)));
+        }
     }
 }
 
@@ -540,6 +1045,69 @@ impl ClientConnection {
         })
     }
 
+    /// Make a new ClientConnection carrying an opaque `connection_id`.
+    ///
+    /// The identifier is not sent to the server.  It is returned by
+    /// [`CommonState::connection_id`] and passed to the
+    /// [`ClientConfig::alert_observer`] and [`KeyLog::log_for_connection`],
+    /// so that callbacks for this connection can be told apart from those of
+    /// others sharing `config`.
+    pub fn new_with_connection_id(
+        config: Arc<ClientConfig>,
+        name: ServerName<'static>,
+        connection_id: Vec<u8>,
+    ) -> Result<Self, Error> {
+        let mut conn = Self::new(config, name)?;
+        conn.inner
+            .core
+            .common_state
+            .connection_id = Some(connection_id);
+        Ok(conn)
+    }
+
+    /// Make a new ClientConnection which refuses to negotiate any protocol
+    /// version older than `min_version`, regardless of the versions enabled
+    /// in `config`.
+    ///
+    /// Only the permitted versions are offered to the server, and any stored
+    /// session for an older version is not resumed.
+    ///
+    /// Returns an error if no version enabled in `config` meets `min_version`.
+    pub fn new_with_min_version(
+        config: Arc<ClientConfig>,
+        name: ServerName<'static>,
+        min_version: &'static versions::SupportedProtocolVersion,
+    ) -> Result<Self, Error> {
+        let mut config = (*config).clone();
+        config.versions = config
+            .versions
+            .with_minimum(min_version.version);
+        if config.versions.is_empty() {
+            return Err(Error::General(
+                "no enabled protocol versions meet the minimum".into(),
+            ));
+        }
+        Self::new(Arc::new(config), name)
+    }
+
+    /// Make a new ClientConnection which always performs a full handshake,
+    /// even if a session for `name` is stored in `config`'s
+    /// [`ClientConfig::resumption`] store.
+    ///
+    /// No TLS1.3 ticket or TLS1.2 session ID is offered to the server, so
+    /// early data cannot be sent either.  Sessions received on this
+    /// connection are still saved for use by later connections.
+    pub fn new_with_full_handshake(
+        config: Arc<ClientConfig>,
+        name: ServerName<'static>,
+    ) -> Result<Self, Error> {
+        let mut config = (*config).clone();
+        config.resumption.store = Arc::new(StoreOnlyClientSessionStorage(Arc::clone(
+            &config.resumption.store,
+        )));
+        Self::new(Arc::new(config), name)
+    }
+
     /// Returns an `io::Write` implementer you can write bytes to
     /// to send TLS1.3 early data (a.k.a. "0-RTT data") to the server.
     ///
@@ -587,6 +1155,120 @@ impl ClientConnection {
         self.inner.dangerous_extract_secrets()
     }
 
+    /// Returns the age and lifetime of the ticket used to resume this session.
+    ///
+    /// This is `None` until the server has accepted resumption, and for
+    /// full handshakes.  In TLS1.2, session ID resumption is also covered:
+    /// the values then describe the stored session.
+    pub fn resumed_ticket(&self) -> Option<ResumedTicket> {
+        self.inner.core.data.resumed_ticket
+    }
+
+    /// Returns true if the server asked us to authenticate with a certificate.
+    ///
+    /// This is known once the server's `CertificateRequest` arrives, which is
+    /// before we decide whether to send a certificate, and stays true whether
+    /// or not we did.
+    pub fn client_auth_requested(&self) -> bool {
+        self.client_auth_request().is_some()
+    }
+
+    /// Returns what the server asked for in its `CertificateRequest`, if it sent one.
+    pub fn client_auth_request(&self) -> Option<&ClientAuthRequest> {
+        self.inner
+            .core
+            .data
+            .client_auth_request
+            .as_ref()
+    }
+
+    /// Returns the server's `ServerHello` exactly as it was received.
+    ///
+    /// This is the whole handshake message, including its four-byte
+    /// handshake header.  A `HelloRetryRequest` is not recorded here.
+    /// This is `None` until the `ServerHello` has been processed.
+    pub fn server_hello_bytes(&self) -> Option<&[u8]> {
+        self.inner
+            .core
+            .data
+            .server_hello_bytes
+            .as_deref()
+    }
+
+    /// Exports the resumption state of the most recent session this
+    /// connection received from the server, as an opaque blob.
+    ///
+    /// This is the same value the connection stored in the [`ClientSessionStore`]
+    /// configured by [`ClientConfig::resumption`]: a TLS1.3 ticket or a TLS1.2
+    /// session, along with its negotiated parameters.  Pass it to [`ClientConnection::import_session_state`]
+    /// to resume the session elsewhere, for example in another process.
+    ///
+    /// Returns `None` if no session has been received (yet), for instance
+    /// because the server does not support resumption.
+    ///
+    /// **The blob contains the session's secrets**: anyone holding it can resume
+    /// the session as this client.  Protect it as you would a private key.
+    pub fn export_session_state(&self) -> Option<Vec<u8>> {
+        self.inner
+            .core
+            .data
+            .session_state
+            .as_ref()
+            .map(|state| state.to_vec())
+    }
+
+    /// Imports resumption state exported by [`ClientConnection::export_session_state`]
+    /// into the [`ClientSessionStore`] configured by `config`, for use by later
+    /// connections to `server_name`.
+    ///
+    /// Fails if the state cannot be decoded, or uses a protocol version or
+    /// cipher suite not enabled in `config`.
+    pub fn import_session_state(
+        config: &ClientConfig,
+        server_name: ServerName<'static>,
+        state: &[u8],
+    ) -> Result<(), Error> {
+        let mut r = Reader::init(state);
+        let version = ProtocolVersion::read(&mut r)?;
+        let suite = CipherSuite::read(&mut r)?;
+        let suite = config
+            .find_cipher_suite(suite)
+            .ok_or_else(|| {
+                Error::General("session state uses a cipher suite not enabled in config".into())
+            })?;
+
+        match (version, suite) {
+            (ProtocolVersion::TLSv1_3, SupportedCipherSuite::Tls13(suite))
+                if config.supports_version(version) =>
+            {
+                let value = persist::Tls13ClientSessionValue::read_state(suite, &mut r)?;
+                r.expect_empty("Tls13ClientSessionValue")?;
+                config
+                    .resumption
+                    .store
+                    .insert_tls13_ticket(server_name, value);
+            }
+            #[cfg(feature = "tls12")]
+            (ProtocolVersion::TLSv1_2, SupportedCipherSuite::Tls12(suite))
+                if config.supports_version(version) =>
+            {
+                let value = persist::Tls12ClientSessionValue::read_state(suite, &mut r)?;
+                r.expect_empty("Tls12ClientSessionValue")?;
+                config
+                    .resumption
+                    .store
+                    .set_tls12_session(server_name, value);
+            }
+            _ => {
+                return Err(Error::General(
+                    "session state uses a protocol version not enabled in config".into(),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
     fn write_early_data(&mut self, data: &[u8]) -> io::Result<usize> {
         self.inner
             .core
@@ -640,10 +1322,39 @@ impl ConnectionCore<ClientConnectionData> {
         extra_exts: Vec<ClientExtension>,
         proto: Protocol,
     ) -> Result<Self, Error> {
+        if config
+            .client_hello_record_version
+            .get_u16()
+            & 0xff00
+            != 0x0300
+        {
+            return Err(Error::General(format!(
+                "unsupported client_hello_record_version {:?}",
+                config.client_hello_record_version
+            )));
+        }
+
+        if !config
+            .psk_key_exchange_modes
+            .contains(&PSKKeyExchangeMode::PSK_DHE_KE)
+        {
+            return Err(Error::General(
+                "psk_key_exchange_modes must include PSK_DHE_KE".into(),
+            ));
+        }
+
         let mut common_state = CommonState::new(Side::Client);
         common_state.set_max_fragment_size(config.max_fragment_size)?;
         common_state.protocol = proto;
         common_state.enable_secret_extraction = config.enable_secret_extraction;
+        common_state.set_record_padding(config.record_padding, config.provider.secure_random);
+        common_state.alert_observer = config.alert_observer.clone();
+        common_state.preserve_record_boundaries = config.preserve_record_boundaries;
+        common_state.max_handshake_flight_bytes = config.max_handshake_flight_bytes;
+        common_state.heartbeat_mode = config.heartbeat_mode;
+        common_state
+            .record_layer
+            .set_aad_observer(config.aad_observer.clone());
         let mut data = ClientConnectionData::new();
 
         let mut cx = hs::ClientContext {
@@ -665,6 +1376,13 @@ impl ConnectionCore<ClientConnectionData> {
 pub struct ClientConnectionData {
     pub(super) early_data: EarlyData,
     pub(super) resumption_ciphersuite: Option<SupportedCipherSuite>,
+    /// Our ALPS settings, to be sent once the server's `Finished` is received.
+    pub(super) alps_to_send: Option<Vec<u8>>,
+    /// The encoding of the most recent session value we stored.
+    pub(super) session_state: Option<Zeroizing<Vec<u8>>>,
+    pub(super) resumed_ticket: Option<ResumedTicket>,
+    pub(super) client_auth_request: Option<ClientAuthRequest>,
+    pub(super) server_hello_bytes: Option<Box<[u8]>>,
 }
 
 impl ClientConnectionData {
@@ -672,8 +1390,50 @@ impl ClientConnectionData {
         Self {
             early_data: EarlyData::new(),
             resumption_ciphersuite: None,
+            alps_to_send: None,
+            session_state: None,
+            resumed_ticket: None,
+            client_auth_request: None,
+            server_hello_bytes: None,
         }
     }
 }
 
 impl crate::conn::SideData for ClientConnectionData {}
+
+#[cfg(test)]
+mod tests {
+    use super::is_rfc1123_hostname;
+
+    #[test]
+    fn test_rfc1123_hostnames() {
+        let label63 = "a".repeat(63);
+        let name253 = [label63.as_str(); 4].join(".")[..253].to_string();
+
+        for name in [
+            "example.com",
+            "example.com.",
+            "a-b.example",
+            "1.example",
+            label63.as_str(),
+            name253.as_str(),
+        ] {
+            assert!(is_rfc1123_hostname(name), "{name}");
+        }
+
+        for name in [
+            "",
+            ".",
+            "example..com",
+            "under_score.example",
+            "-leading.example",
+            "trailing-.example",
+            "example.-com",
+            "example.com-",
+            &format!("{label63}a.example"),
+            &format!("{name253}a"),
+        ] {
+            assert!(!is_rfc1123_hostname(name), "{name}");
+        }
+    }
+}
@@ -14,25 +14,33 @@ use alloc::vec::Vec;
 pub(super) struct ServerCertDetails {
     pub(super) cert_chain: CertificateChain,
     pub(super) ocsp_response: Vec<u8>,
+    pub(super) scts: Vec<Vec<u8>>,
 }
 
 impl ServerCertDetails {
-    pub(super) fn new(cert_chain: CertificateChain, ocsp_response: Vec<u8>) -> Self {
+    pub(super) fn new(
+        cert_chain: CertificateChain,
+        ocsp_response: Vec<u8>,
+        scts: Vec<Vec<u8>>,
+    ) -> Self {
         Self {
             cert_chain,
             ocsp_response,
+            scts,
         }
     }
 }
 
 pub(super) struct ClientHelloDetails {
     pub(super) sent_extensions: Vec<ExtensionType>,
+    pub(super) offered_external_psk: bool,
 }
 
 impl ClientHelloDetails {
     pub(super) fn new() -> Self {
         Self {
             sent_extensions: Vec::new(),
+            offered_external_psk: false,
         }
     }
 
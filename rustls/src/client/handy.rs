@@ -2,15 +2,17 @@ use crate::client;
 use crate::enums::SignatureScheme;
 use crate::error::Error;
 use crate::limited_cache;
-use crate::msgs::handshake::CertificateChain;
+use crate::msgs::handshake::{CertificateChain, DistinguishedName};
 use crate::msgs::persist;
 use crate::sign;
+use crate::webpki::trust_anchor_subject;
 use crate::NamedGroup;
 
-use pki_types::ServerName;
+use pki_types::{CertificateDer, ServerName};
 
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt;
 use std::sync::Mutex;
 
@@ -40,6 +42,51 @@ impl client::ClientSessionStore for NoClientSessionStorage {
     }
 }
 
+/// An implementer of `ClientSessionStore` which saves sessions in another
+/// store, but never offers them for resumption.
+#[derive(Debug)]
+pub(super) struct StoreOnlyClientSessionStorage(pub(super) Arc<dyn client::ClientSessionStore>);
+
+impl client::ClientSessionStore for StoreOnlyClientSessionStorage {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.0.set_kx_hint(server_name, group)
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.0.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(
+        &self,
+        server_name: ServerName<'static>,
+        value: persist::Tls12ClientSessionValue,
+    ) {
+        self.0
+            .set_tls12_session(server_name, value)
+    }
+
+    fn tls12_session(&self, _: &ServerName<'_>) -> Option<persist::Tls12ClientSessionValue> {
+        None
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.0.remove_tls12_session(server_name)
+    }
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: persist::Tls13ClientSessionValue,
+    ) {
+        self.0
+            .insert_tls13_ticket(server_name, value)
+    }
+
+    fn take_tls13_ticket(&self, _: &ServerName<'_>) -> Option<persist::Tls13ClientSessionValue> {
+        None
+    }
+}
+
 const MAX_TLS13_TICKETS_PER_SERVER: usize = 8;
 
 struct ServerData {
@@ -49,7 +96,8 @@ struct ServerData {
     #[cfg(feature = "tls12")]
     tls12: Option<persist::Tls12ClientSessionValue>,
 
-    // Up to MAX_TLS13_TICKETS_PER_SERVER TLS1.3 tickets, oldest first.
+    // Up to `ClientSessionMemoryCache::max_tls13_tickets_per_server` TLS1.3
+    // tickets, oldest first.
     tls13: VecDeque<persist::Tls13ClientSessionValue>,
 }
 
@@ -70,16 +118,27 @@ impl Default for ServerData {
 /// It enforces a limit on the number of entries to bound memory usage.
 pub struct ClientSessionMemoryCache {
     servers: Mutex<limited_cache::LimitedCache<ServerName<'static>, ServerData>>,
+    max_tls13_tickets_per_server: usize,
 }
 
 impl ClientSessionMemoryCache {
     /// Make a new ClientSessionMemoryCache.  `size` is the
     /// maximum number of stored sessions.
+    ///
+    /// Up to 8 TLS1.3 tickets are kept for each server.
     pub fn new(size: usize) -> Self {
-        let max_servers =
-            size.saturating_add(MAX_TLS13_TICKETS_PER_SERVER - 1) / MAX_TLS13_TICKETS_PER_SERVER;
+        Self::new_with_tls13_tickets_per_server(size, MAX_TLS13_TICKETS_PER_SERVER)
+    }
+
+    /// Make a new ClientSessionMemoryCache which keeps at most
+    /// `tickets_per_server` TLS1.3 tickets for each server, evicting the
+    /// oldest first.  `size` is the maximum number of stored sessions.
+    pub fn new_with_tls13_tickets_per_server(size: usize, tickets_per_server: usize) -> Self {
+        let per_server = tickets_per_server.max(1);
+        let max_servers = size.saturating_add(per_server - 1) / per_server;
         Self {
             servers: Mutex::new(limited_cache::LimitedCache::new(max_servers)),
+            max_tls13_tickets_per_server: tickets_per_server,
         }
     }
 }
@@ -145,10 +204,10 @@ impl client::ClientSessionStore for ClientSessionMemoryCache {
             .lock()
             .unwrap()
             .get_or_insert_default_and_edit(server_name.clone(), |data| {
-                if data.tls13.len() == data.tls13.capacity() {
+                data.tls13.push_back(value);
+                while data.tls13.len() > self.max_tls13_tickets_per_server {
                     data.tls13.pop_front();
                 }
-                data.tls13.push_back(value);
             });
     }
 
@@ -197,10 +256,9 @@ impl AlwaysResolvesClientCert {
         private_key: Arc<dyn sign::SigningKey>,
         chain: CertificateChain,
     ) -> Result<Self, Error> {
-        Ok(Self(Arc::new(sign::CertifiedKey::new(
-            chain.0,
-            private_key,
-        ))))
+        let certified_key = sign::CertifiedKey::new(chain.0, private_key);
+        certified_key.keys_match()?;
+        Ok(Self(Arc::new(certified_key)))
     }
 }
 
@@ -218,9 +276,71 @@ impl client::ResolvesClientCert for AlwaysResolvesClientCert {
     }
 }
 
+/// Something that resolves to one of several client cert chains/keys, based
+/// on the certificate authorities the server says it accepts.
+///
+/// This allows a client holding certificates issued under several CAs to
+/// present one the server will trust.
+#[derive(Debug)]
+pub struct ResolvesClientCertUsingIssuers {
+    keys: Vec<(DistinguishedName, Arc<sign::CertifiedKey>)>,
+}
+
+impl ResolvesClientCertUsingIssuers {
+    /// Create a new and empty (i.e., knows no certificates) resolver.
+    pub fn new() -> Self {
+        Self { keys: Vec::new() }
+    }
+
+    /// Add a new `sign::CertifiedKey` whose chain is anchored in `trust_anchor`,
+    /// the DER-encoded certificate of the root CA a server would verify it with.
+    ///
+    /// If the server names the CAs it accepts, the first key added under one of
+    /// them and usable with the server's signature schemes is chosen.  If it
+    /// names none, the first usable key is chosen.
+    ///
+    /// This function fails if `trust_anchor` cannot be parsed, or if the key
+    /// does not match the certificate.
+    pub fn add(
+        &mut self,
+        trust_anchor: &CertificateDer<'_>,
+        ck: sign::CertifiedKey,
+    ) -> Result<(), Error> {
+        let subject = trust_anchor_subject(trust_anchor)?;
+        ck.keys_match()?;
+
+        self.keys.push((subject, Arc::new(ck)));
+        Ok(())
+    }
+}
+
+impl client::ResolvesClientCert for ResolvesClientCertUsingIssuers {
+    fn resolve(
+        &self,
+        root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        self.keys
+            .iter()
+            .filter(|(subject, _)| {
+                root_hint_subjects.is_empty() || root_hint_subjects.contains(&subject.as_ref())
+            })
+            .find(|(_, ck)| {
+                ck.key
+                    .choose_scheme(sigschemes)
+                    .is_some()
+            })
+            .map(|(_, ck)| Arc::clone(ck))
+    }
+
+    fn has_certs(&self) -> bool {
+        !self.keys.is_empty()
+    }
+}
+
 #[cfg(all(test, any(feature = "ring", feature = "aws_lc_rs")))]
 mod tests {
-    use super::NoClientSessionStorage;
+    use super::{ClientSessionMemoryCache, NoClientSessionStorage};
     use crate::client::ClientSessionStore;
     use crate::msgs::enums::NamedGroup;
     use crate::msgs::handshake::CertificateChain;
@@ -288,4 +408,43 @@ mod tests {
         );
         assert!(c.take_tls13_ticket(&name).is_none());
     }
+
+    #[test]
+    fn test_clientsessionmemorycache_keeps_newest_tls13_tickets() {
+        let c = ClientSessionMemoryCache::new_with_tls13_tickets_per_server(32, 3);
+        let name = ServerName::try_from("example.com").unwrap();
+        let now = UnixTime::now();
+
+        #[cfg_attr(not(feature = "tls12"), allow(clippy::infallible_destructuring_match))]
+        let tls13_suite = match cipher_suite::TLS13_AES_256_GCM_SHA384 {
+            SupportedCipherSuite::Tls13(inner) => inner,
+            #[cfg(feature = "tls12")]
+            _ => unreachable!(),
+        };
+        for i in 0..10u8 {
+            c.insert_tls13_ticket(
+                name.clone(),
+                Tls13ClientSessionValue::new(
+                    tls13_suite,
+                    vec![i],
+                    &[],
+                    CertificateChain::default(),
+                    now,
+                    0,
+                    0,
+                    0,
+                ),
+            );
+        }
+
+        for i in [9, 8, 7] {
+            assert_eq!(
+                c.take_tls13_ticket(&name)
+                    .unwrap()
+                    .ticket(),
+                &[i]
+            );
+        }
+        assert!(c.take_tls13_ticket(&name).is_none());
+    }
 }
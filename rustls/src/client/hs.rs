@@ -10,14 +10,15 @@ use crate::hash_hs::HandshakeHashBuffer;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
 use crate::msgs::base::Payload;
+use crate::msgs::codec::Codec;
+use crate::msgs::enums::ECPointFormat;
 use crate::msgs::enums::{Compression, ExtensionType};
-use crate::msgs::enums::{ECPointFormat, PSKKeyExchangeMode};
 use crate::msgs::handshake::ConvertProtocolNameList;
 use crate::msgs::handshake::{CertificateStatusRequest, ClientSessionTicket};
 use crate::msgs::handshake::{ClientExtension, HasServerExtensions};
 use crate::msgs::handshake::{ClientHelloPayload, HandshakeMessagePayload, HandshakePayload};
 use crate::msgs::handshake::{HelloRetryRequest, KeyShareEntry};
-use crate::msgs::handshake::{Random, SessionId};
+use crate::msgs::handshake::{Random, SessionId, UnknownExtension};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
 use crate::tls13::key_schedule::KeyScheduleEarly;
@@ -97,6 +98,7 @@ pub(super) fn start_handshake(
     cx: &mut ClientContext<'_>,
 ) -> NextStateOrError {
     let mut transcript_buffer = HandshakeHashBuffer::new();
+    transcript_buffer.set_observer(config.on_handshake_message.clone());
     if config
         .client_auth_cert_resolver
         .has_certs()
@@ -216,14 +218,20 @@ fn emit_client_hello_for_retry(
     let mut exts = vec![
         ClientExtension::SupportedVersions(supported_versions),
         ClientExtension::EcPointFormats(ECPointFormat::SUPPORTED.to_vec()),
-        ClientExtension::NamedGroups(
+    ];
+
+    if support_tls13 || config.send_tls12_supported_groups {
+        exts.push(ClientExtension::NamedGroups(
             config
                 .provider
                 .kx_groups
                 .iter()
                 .map(|skxg| skxg.name())
                 .collect(),
-        ),
+        ));
+    }
+
+    exts.extend([
         ClientExtension::SignatureAlgorithms(
             config
                 .verifier
@@ -231,7 +239,7 @@ fn emit_client_hello_for_retry(
         ),
         ClientExtension::ExtendedMasterSecretRequest,
         ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
-    ];
+    ]);
 
     if let (ServerName::DnsName(dns), true) = (&input.server_name, config.enable_sni) {
         // We only want to send the SNI extension if the server name contains a DNS name.
@@ -248,11 +256,20 @@ fn emit_client_hello_for_retry(
         exts.push(ClientExtension::Cookie(cookie.clone()));
     }
 
+    if config.verifier.requests_scts() {
+        exts.push(ClientExtension::SignedCertificateTimestampRequest);
+    }
+
     if support_tls13 {
-        // We could support PSK_KE here too. Such connections don't
-        // have forward secrecy, and are similar to TLS1.2 resumption.
-        let psk_modes = vec![PSKKeyExchangeMode::PSK_DHE_KE];
-        exts.push(ClientExtension::PresharedKeyModes(psk_modes));
+        // Only PSK_DHE_KE is supported for resumption: PSK_KE connections
+        // don't have forward secrecy, and are similar to TLS1.2 resumption.
+        exts.push(ClientExtension::PresharedKeyModes(
+            config.psk_key_exchange_modes.clone(),
+        ));
+
+        if let Some(request) = config.ticket_request {
+            exts.push(ClientExtension::TicketRequest(request));
+        }
     }
 
     if !config.alpn_protocols.is_empty() {
@@ -265,45 +282,80 @@ fn emit_client_hello_for_retry(
         )));
     }
 
+    let alps_protocols = config
+        .alps_settings
+        .iter()
+        .filter(|(protocol, _)| config.alpn_protocols.contains(protocol))
+        .map(|(protocol, _)| &protocol[..])
+        .collect::<Vec<_>>();
+    if support_tls13 && !alps_protocols.is_empty() {
+        exts.push(ClientExtension::ApplicationSettings(Vec::from_slices(
+            &alps_protocols,
+        )));
+    }
+
+    if let Some(mode) = config.heartbeat_mode {
+        if !cx.common.is_quic() {
+            exts.push(ClientExtension::Heartbeat(mode));
+        }
+    }
+
     // Extra extensions must be placed before the PSK extension
     exts.extend(extra_exts.iter().cloned());
 
     // Do we have a SessionID or ticket cached for this host?
     let tls13_session = prepare_resumption(&input.resuming, &mut exts, suite, cx, config);
 
+    // If not, offer our external PSK, if we have one.
+    let external_psk_suite = match tls13_session {
+        None if support_tls13 => {
+            tls13::prepare_external_psk(config, &mut exts, suite.and_then(|cs| cs.tls13()))
+        }
+        _ => None,
+    };
+
+    let mut cipher_suites = config.advertised_cipher_suites_for(cx.common.protocol);
+    // We don't do renegotiation at all, in fact.
+    if !cipher_suites.contains(&CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV) {
+        cipher_suites.push(CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
+    }
+
+    let mut hello = ClientHelloPayload {
+        client_version: ProtocolVersion::TLSv1_2,
+        random: input.random,
+        session_id: input.session_id,
+        cipher_suites,
+        compression_methods: vec![Compression::Null],
+        extensions: exts,
+    };
+
+    if let Some(mutator) = &config.client_hello_mutator {
+        (mutator.0)(&mut hello);
+    }
+
+    if let Some(target) = config.pad_client_hello_to {
+        pad_client_hello(&mut hello, target);
+    }
+
     // Note what extensions we sent.
-    input.hello.sent_extensions = exts
+    input.hello.sent_extensions = hello
+        .extensions
         .iter()
         .map(ClientExtension::get_type)
         .collect();
-
-    let mut cipher_suites: Vec<_> = config
-        .provider
-        .cipher_suites
-        .iter()
-        .filter_map(|cs| match cs.usable_for_protocol(cx.common.protocol) {
-            true => Some(cs.suite()),
-            false => None,
-        })
-        .collect();
-    // We don't do renegotiation at all, in fact.
-    cipher_suites.push(CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
+    input.hello.offered_external_psk = external_psk_suite.is_some();
 
     let mut chp = HandshakeMessagePayload {
         typ: HandshakeType::ClientHello,
-        payload: HandshakePayload::ClientHello(ClientHelloPayload {
-            client_version: ProtocolVersion::TLSv1_2,
-            random: input.random,
-            session_id: input.session_id,
-            cipher_suites,
-            compression_methods: vec![Compression::Null],
-            extensions: exts,
-        }),
+        payload: HandshakePayload::ClientHello(hello),
     };
 
     let early_key_schedule = if let Some(resuming) = tls13_session {
         let schedule = tls13::fill_in_psk_binder(&resuming, &transcript_buffer, &mut chp);
         Some((resuming.suite(), schedule))
+    } else if let (Some(suite), Some(psk)) = (external_psk_suite, &config.external_psk) {
+        let schedule = tls13::fill_in_external_psk_binder(psk, suite, &transcript_buffer, &mut chp);
+        Some((suite, schedule))
     } else {
         None
     };
@@ -315,7 +367,7 @@ fn emit_client_hello_for_retry(
         version: if retryreq.is_some() {
             ProtocolVersion::TLSv1_2
         } else {
-            ProtocolVersion::TLSv1_0
+            config.client_hello_record_version
         },
         payload: MessagePayload::handshake(chp),
     };
@@ -364,6 +416,35 @@ fn emit_client_hello_for_retry(
     }
 }
 
+/// Add a `padding` extension ([RFC7685]) so the encoded `ClientHello` handshake
+/// message is at least `target` bytes long.
+///
+/// [RFC7685]: https://datatracker.ietf.org/doc/html/rfc7685
+fn pad_client_hello(hello: &mut ClientHelloPayload, target: usize) {
+    // Both the handshake message header and an extension header are four bytes.
+    let len = 4 + hello.get_encoding().len();
+    if len >= target {
+        return;
+    }
+
+    let padding_len = (target - len)
+        .saturating_sub(4)
+        .min(u16::MAX as usize);
+    let padding = ClientExtension::Unknown(UnknownExtension {
+        typ: ExtensionType::Padding,
+        payload: Payload::new(vec![0; padding_len]),
+    });
+
+    // "The "pre_shared_key" extension MUST be the last extension in the ClientHello"
+    let position = match hello.extensions.last() {
+        Some(ClientExtension::PresharedKey(_)) => hello.extensions.len() - 1,
+        _ => hello.extensions.len(),
+    };
+    hello
+        .extensions
+        .insert(position, padding);
+}
+
 /// Prepare resumption with the session state retrieved from storage.
 ///
 /// This function will push onto `exts` to
@@ -481,6 +562,9 @@ impl State<ClientConnectionData> for ExpectServerHello {
         let server_hello =
             require_handshake_msg!(m, HandshakeType::ServerHello, HandshakePayload::ServerHello)?;
         trace!("We got ServerHello {:#?}", server_hello);
+        if let MessagePayload::Handshake { encoded, .. } = &m.payload {
+            cx.data.server_hello_bytes = Some(encoded.0.clone().into_boxed_slice());
+        }
 
         use crate::ProtocolVersion::{TLSv1_2, TLSv1_3};
         let config = &self.input.config;
@@ -561,6 +645,7 @@ impl State<ClientConnectionData> for ExpectServerHello {
         // Extract ALPN protocol
         if !cx.common.is_tls13() {
             process_alpn_protocol(cx.common, config, server_hello.get_alpn_protocol())?;
+            cx.common.peer_heartbeat_mode = server_hello.get_heartbeat_mode();
         }
 
         // If ECPointFormats extension is supplied by the server, it must contain
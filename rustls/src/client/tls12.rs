@@ -1,18 +1,19 @@
 use crate::check::{inappropriate_handshake_message, inappropriate_message};
-use crate::common_state::{CommonState, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, ResumptionKind, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::enums::ProtocolVersion;
 use crate::enums::{AlertDescription, ContentType, HandshakeType};
-use crate::error::{Error, InvalidMessage, PeerMisbehaved};
+use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHash;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
 use crate::msgs::base::{Payload, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::Codec;
+use crate::msgs::enums::NamedGroup;
 use crate::msgs::handshake::{
-    CertificateChain, HandshakeMessagePayload, HandshakePayload, NewSessionTicketPayload,
-    ServerEcdhParams, SessionId,
+    CertificateChain, HandshakeMessagePayload, HandshakePayload, KeyExchangeAlgorithm,
+    NewSessionTicketPayload, ServerEcdhParams, SessionId,
 };
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
@@ -21,7 +22,9 @@ use crate::suites::{PartiallyExtractedSecrets, SupportedCipherSuite};
 use crate::tls12::{self, ConnectionSecrets, Tls12CipherSuite};
 use crate::verify::{self, DigitallySignedStruct};
 
-use super::client_conn::ClientConnectionData;
+use super::client_conn::{
+    ClientAuthRequest, ClientConnectionData, NewSessionTicketMetadata, ResumedTicket,
+};
 use super::hs::ClientContext;
 use crate::client::common::ClientAuthDetails;
 use crate::client::common::ServerCertDetails;
@@ -35,6 +38,7 @@ use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::time::Duration;
 
 pub(super) use server_hello::CompleteServerHelloHandling;
 
@@ -81,6 +85,14 @@ mod server_hello {
 
             // Doing EMS?
             self.using_ems = server_hello.ems_support_acked();
+            if self.config.require_ems && !self.using_ems {
+                return Err({
+                    cx.common.send_fatal_alert(
+                        AlertDescription::HandshakeFailure,
+                        PeerIncompatible::ExtendedMasterSecretExtensionRequired,
+                    )
+                });
+            }
 
             // Might the server send a ticket?
             let must_issue_new_ticket = if server_hello
@@ -102,6 +114,16 @@ mod server_hello {
                 debug!("Server may staple OCSP response");
             }
 
+            // Save any sent SCTs for verification against the certificate.
+            let server_cert_sct_list = server_hello
+                .get_sct_list()
+                .map(|scts| {
+                    scts.iter()
+                        .map(|sct| sct.as_ref().to_vec())
+                        .collect()
+                })
+                .unwrap_or_default();
+
             // See if we're successfully resuming.
             if let Some(resuming) = self.resuming_session {
                 if resuming.session_id == server_hello.session_id {
@@ -117,16 +139,25 @@ mod server_hello {
                         return Err(PeerMisbehaved::ResumptionOfferedWithVariedEms.into());
                     }
 
-                    let secrets =
+                    let mut secrets =
                         ConnectionSecrets::new_resume(self.randoms, suite, resuming.secret());
-                    self.config.key_log.log(
+                    secrets.set_explicit_nonce_random(self.config.explicit_nonce_random)?;
+                    self.config.key_log.log_for_connection(
                         "CLIENT_RANDOM",
                         &secrets.randoms.client,
+                        cx.common.connection_id(),
                         &secrets.master_secret,
                     );
                     cx.common
                         .start_encryption_tls12(&secrets, Side::Client);
 
+                    cx.common.handshake_kind =
+                        Some(HandshakeKind::Resumed(match resuming.ticket().is_empty() {
+                            true => ResumptionKind::Tls12SessionId,
+                            false => ResumptionKind::Tls12Ticket,
+                        }));
+                    cx.data.resumed_ticket = Some(ResumedTicket::new(&resuming, UnixTime::now()));
+
                     // Since we're resuming, we verified the certificate and
                     // proof of possession in the prior session.
                     cx.common.peer_certificates = Some(resuming.server_cert_chain().clone());
@@ -164,6 +195,7 @@ mod server_hello {
                 }
             }
 
+            cx.common.handshake_kind = Some(HandshakeKind::Full);
             Ok(Box::new(ExpectCertificate {
                 config: self.config,
                 resuming_session: None,
@@ -174,6 +206,7 @@ mod server_hello {
                 transcript: self.transcript,
                 suite,
                 may_send_cert_status,
+                server_cert_sct_list,
                 must_issue_new_ticket,
             }))
         }
@@ -190,6 +223,7 @@ struct ExpectCertificate {
     transcript: HandshakeHash,
     pub(super) suite: &'static Tls12CipherSuite,
     may_send_cert_status: bool,
+    server_cert_sct_list: Vec<Vec<u8>>,
     must_issue_new_ticket: bool,
 }
 
@@ -217,10 +251,12 @@ impl State<ClientConnectionData> for ExpectCertificate {
                 transcript: self.transcript,
                 suite: self.suite,
                 server_cert_chain,
+                server_cert_sct_list: self.server_cert_sct_list,
                 must_issue_new_ticket: self.must_issue_new_ticket,
             }))
         } else {
-            let server_cert = ServerCertDetails::new(server_cert_chain, vec![]);
+            let server_cert =
+                ServerCertDetails::new(server_cert_chain, vec![], self.server_cert_sct_list);
 
             Ok(Box::new(ExpectServerKx {
                 config: self.config,
@@ -248,6 +284,7 @@ struct ExpectCertificateStatusOrServerKx {
     transcript: HandshakeHash,
     suite: &'static Tls12CipherSuite,
     server_cert_chain: CertificateChain,
+    server_cert_sct_list: Vec<Vec<u8>>,
     must_issue_new_ticket: bool,
 }
 
@@ -270,7 +307,11 @@ impl State<ClientConnectionData> for ExpectCertificateStatusOrServerKx {
                 using_ems: self.using_ems,
                 transcript: self.transcript,
                 suite: self.suite,
-                server_cert: ServerCertDetails::new(self.server_cert_chain, vec![]),
+                server_cert: ServerCertDetails::new(
+                    self.server_cert_chain,
+                    vec![],
+                    self.server_cert_sct_list,
+                ),
                 must_issue_new_ticket: self.must_issue_new_ticket,
             })
             .handle(cx, m),
@@ -291,6 +332,7 @@ impl State<ClientConnectionData> for ExpectCertificateStatusOrServerKx {
                 transcript: self.transcript,
                 suite: self.suite,
                 server_cert_chain: self.server_cert_chain,
+                server_cert_sct_list: self.server_cert_sct_list,
                 must_issue_new_ticket: self.must_issue_new_ticket,
             })
             .handle(cx, m),
@@ -316,6 +358,7 @@ struct ExpectCertificateStatus {
     transcript: HandshakeHash,
     suite: &'static Tls12CipherSuite,
     server_cert_chain: CertificateChain,
+    server_cert_sct_list: Vec<Vec<u8>>,
     must_issue_new_ticket: bool,
 }
 
@@ -338,7 +381,11 @@ impl State<ClientConnectionData> for ExpectCertificateStatus {
             &server_cert_ocsp_response
         );
 
-        let server_cert = ServerCertDetails::new(self.server_cert_chain, server_cert_ocsp_response);
+        let server_cert = ServerCertDetails::new(
+            self.server_cert_chain,
+            server_cert_ocsp_response,
+            self.server_cert_sct_list,
+        );
 
         Ok(Box::new(ExpectServerKx {
             config: self.config,
@@ -597,11 +644,7 @@ struct ExpectCertificateRequest {
 }
 
 impl State<ClientConnectionData> for ExpectCertificateRequest {
-    fn handle(
-        mut self: Box<Self>,
-        _cx: &mut ClientContext<'_>,
-        m: Message,
-    ) -> hs::NextStateOrError {
+    fn handle(mut self: Box<Self>, cx: &mut ClientContext<'_>, m: Message) -> hs::NextStateOrError {
         let certreq = require_handshake_msg!(
             m,
             HandshakeType::CertificateRequest,
@@ -609,6 +652,10 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
         )?;
         self.transcript.add_message(&m);
         debug!("Got CertificateRequest {:?}", certreq);
+        cx.data.client_auth_request = Some(ClientAuthRequest::new(
+            Some(&certreq.canames),
+            &certreq.sigschemes,
+        ));
 
         // The RFC jovially describes the design here as 'somewhat complicated'
         // and 'somewhat underspecified'.  So thanks for that.
@@ -706,6 +753,13 @@ impl State<ClientConnectionData> for ExpectServerDone {
             .cert_chain
             .split_first()
             .ok_or(Error::NoCertificatesPresented)?;
+        st.config
+            .check_server_name(&st.server_name)
+            .map_err(|err| {
+                cx.common
+                    .send_cert_verify_error_alert(err)
+            })?;
+        let now = UnixTime::now();
         let cert_verified = st
             .config
             .verifier
@@ -714,13 +768,29 @@ impl State<ClientConnectionData> for ExpectServerDone {
                 intermediates,
                 &st.server_name,
                 &st.server_cert.ocsp_response,
-                UnixTime::now(),
+                now,
             )
             .map_err(|err| {
                 cx.common
                     .send_cert_verify_error_alert(err)
             })?;
 
+        if st.config.verifier.requests_scts() {
+            let scts = st
+                .server_cert
+                .scts
+                .iter()
+                .map(|sct| sct.as_slice())
+                .collect::<Vec<_>>();
+            st.config
+                .verifier
+                .verify_server_scts(end_entity, &scts, now)
+                .map_err(|err| {
+                    cx.common
+                        .send_cert_verify_error_alert(err)
+                })?;
+        }
+
         // 3.
         // Build up the contents of the signed message.
         // It's ClientHello.random || ServerHello.random || ServerKeyExchange.params
@@ -792,17 +862,23 @@ impl State<ClientConnectionData> for ExpectServerDone {
         emit_ccs(cx.common);
 
         // 5e. Now commit secrets.
-        let secrets = ConnectionSecrets::from_key_exchange(
-            kx,
-            &ecdh_params.public.0,
-            ems_seed,
-            st.randoms,
-            suite,
-        )?;
+        let mut secrets = cx
+            .common
+            .complete_key_exchange(kx, |kx| {
+                ConnectionSecrets::from_key_exchange(
+                    kx,
+                    &ecdh_params.public.0,
+                    ems_seed,
+                    st.randoms,
+                    suite,
+                )
+            })?;
+        secrets.set_explicit_nonce_random(st.config.explicit_nonce_random)?;
 
-        st.config.key_log.log(
+        st.config.key_log.log_for_connection(
             "CLIENT_RANDOM",
             &secrets.randoms.client,
+            cx.common.connection_id(),
             &secrets.master_secret,
         );
         cx.common
@@ -814,6 +890,14 @@ impl State<ClientConnectionData> for ExpectServerDone {
         // 6.
         emit_finished(&secrets, &mut transcript, cx.common);
 
+        if st.config.enable_false_start && false_start_permitted(suite, named_group) {
+            debug!(
+                "False Starting with {:?} and {:?}",
+                suite.common.suite, named_group
+            );
+            cx.common.start_outgoing_traffic();
+        }
+
         if st.must_issue_new_ticket {
             Ok(Box::new(ExpectNewTicket {
                 config: st.config,
@@ -845,6 +929,23 @@ impl State<ClientConnectionData> for ExpectServerDone {
     }
 }
 
+/// Whether RFC7918 allows sending application data before the server's Finished,
+/// after a full handshake using `suite` and `named_group`.
+///
+/// All our TLS1.2 cipher suites use AEADs, so this comes down to requiring a
+/// forward-secret key exchange over a group of adequate strength.
+fn false_start_permitted(suite: &Tls12CipherSuite, named_group: NamedGroup) -> bool {
+    suite.kx == KeyExchangeAlgorithm::ECDHE
+        && matches!(
+            named_group,
+            NamedGroup::X25519
+                | NamedGroup::X448
+                | NamedGroup::secp256r1
+                | NamedGroup::secp384r1
+                | NamedGroup::secp521r1
+        )
+}
+
 struct ExpectNewTicket {
     config: Arc<ClientConfig>,
     secrets: ConnectionSecrets,
@@ -955,13 +1056,20 @@ struct ExpectFinished {
 
 impl ExpectFinished {
     // -- Waiting for their finished --
-    fn save_session(&mut self, cx: &ClientContext<'_>) {
+    fn save_session(&mut self, cx: &mut ClientContext<'_>) {
         // Save a ticket.  If we got a new ticket, save that.  Otherwise, save the
         // original ticket again.
         let (mut ticket, lifetime) = match self.ticket.take() {
             Some(nst) => (nst.ticket.0, nst.lifetime_hint),
             None => (Vec::new(), 0),
         };
+        let new_ticket = (!ticket.is_empty()).then(|| NewSessionTicketMetadata {
+            version: ProtocolVersion::TLSv1_2,
+            cipher_suite: self.secrets.suite().common.suite,
+            lifetime: Duration::from_secs(u64::from(lifetime)),
+            max_early_data_size: 0,
+            ticket_len: ticket.len(),
+        });
 
         if ticket.is_empty() {
             if let Some(resuming_session) = &mut self.resuming_session {
@@ -988,10 +1096,15 @@ impl ExpectFinished {
             self.using_ems,
         );
 
+        cx.data.session_state = Some(session_value.get_state_encoding());
         self.config
             .resumption
             .store
             .set_tls12_session(self.server_name.clone(), session_value);
+
+        if let (Some(observer), Some(new_ticket)) = (&self.config.on_new_ticket, new_ticket) {
+            observer.new_ticket(&self.server_name, &new_ticket);
+        }
     }
 }
 
@@ -1,6 +1,6 @@
 use crate::check::inappropriate_handshake_message;
 use crate::common_state::Protocol;
-use crate::common_state::{CommonState, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, ResumptionKind, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::crypto;
 use crate::crypto::ActiveKeyExchange;
@@ -9,6 +9,7 @@ use crate::enums::{
 };
 use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
+use crate::key_log::ConnectionKeyLog;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
 use crate::msgs::base::{Payload, PayloadU8};
@@ -30,11 +31,14 @@ use crate::tls13::construct_server_verify_message;
 use crate::tls13::key_schedule::{
     KeyScheduleEarly, KeyScheduleHandshake, KeySchedulePreHandshake, KeyScheduleTraffic,
 };
-use crate::tls13::Tls13CipherSuite;
+use crate::tls13::{ExternalPsk, Tls13CipherSuite};
 use crate::verify::{self, DigitallySignedStruct};
 use crate::KeyLog;
 
-use super::client_conn::ClientConnectionData;
+use super::client_conn::{
+    ClientAuthRequest, ClientConnectionData, NewSessionTicketMetadata, ObservesNewTickets,
+    ResumedTicket,
+};
 use super::hs::ClientContext;
 use crate::client::common::ServerCertDetails;
 use crate::client::common::{ClientAuthDetails, ClientHelloDetails};
@@ -47,6 +51,7 @@ use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::time::Duration;
 
 // Extensions we expect in plaintext in the ServerHello.
 static ALLOWED_PLAINTEXT_EXTS: &[ExtensionType] = &[
@@ -98,6 +103,7 @@ pub(super) fn handle_server_hello(
         });
     }
 
+    let mut using_external_psk = false;
     let key_schedule_pre_handshake = if let (Some(selected_psk), Some(early_key_schedule)) =
         (server_hello.get_psk_index(), early_key_schedule)
     {
@@ -135,13 +141,43 @@ pub(super) fn handle_server_hello(
             }
 
             debug!("Resuming using PSK");
+            cx.common.handshake_kind = Some(HandshakeKind::Resumed(ResumptionKind::Tls13Psk));
+            cx.data.resumed_ticket = Some(ResumedTicket::new(resuming, UnixTime::now()));
             // The key schedule has been initialized and set in fill_in_psk_binder()
+        } else if hello.offered_external_psk {
+            if !config
+                .external_psk
+                .as_ref()
+                .map_or(false, |psk| psk.usable_with(suite))
+            {
+                return Err({
+                    cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::ExternalPskOfferedWithIncompatibleCipherSuite,
+                    )
+                });
+            }
+
+            if selected_psk != 0 {
+                return Err({
+                    cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::SelectedInvalidPsk,
+                    )
+                });
+            }
+
+            debug!("Authenticating using external PSK");
+            cx.common.handshake_kind = Some(HandshakeKind::ExternalPsk);
+            using_external_psk = true;
+            // As for resumption, the key schedule was set up in fill_in_external_psk_binder()
         } else {
             return Err(PeerMisbehaved::SelectedUnofferedPsk.into());
         }
         KeySchedulePreHandshake::from(early_key_schedule)
     } else {
         debug!("Not resuming");
+        cx.common.handshake_kind = Some(HandshakeKind::Full);
         // Discard the early data key schedule.
         cx.data.early_data.rejected();
         cx.common.early_traffic = false;
@@ -149,8 +185,11 @@ pub(super) fn handle_server_hello(
         KeySchedulePreHandshake::new(suite)
     };
 
-    let key_schedule =
-        key_schedule_pre_handshake.into_handshake(our_key_share, &their_key_share.payload.0)?;
+    let key_schedule = cx
+        .common
+        .complete_key_exchange(our_key_share, |kx| {
+            key_schedule_pre_handshake.into_handshake(kx, &their_key_share.payload.0)
+        })?;
 
     // Remember what KX group the server liked for next time.
     config
@@ -167,7 +206,7 @@ pub(super) fn handle_server_hello(
         cx.data.early_data.is_enabled(),
         hash_at_client_recvd_server_hello,
         suite,
-        &*config.key_log,
+        &ConnectionKeyLog::new(&*config.key_log, cx.common),
         &randoms.client,
         cx.common,
     );
@@ -183,6 +222,7 @@ pub(super) fn handle_server_hello(
         transcript,
         key_schedule,
         hello,
+        using_external_psk,
     }))
 }
 
@@ -254,6 +294,57 @@ pub(super) fn fill_in_psk_binder(
     key_schedule
 }
 
+/// As [`fill_in_psk_binder`], but for an external PSK, which uses a
+/// different binder key label.
+pub(super) fn fill_in_external_psk_binder(
+    psk: &ExternalPsk,
+    suite: &'static Tls13CipherSuite,
+    transcript: &HandshakeHashBuffer,
+    hmp: &mut HandshakeMessagePayload,
+) -> KeyScheduleEarly {
+    let binder_plaintext = hmp.get_encoding_for_binder_signing();
+    let handshake_hash = transcript.get_hash_given(suite.common.hash_provider, &binder_plaintext);
+
+    let key_schedule = KeyScheduleEarly::new(suite, psk.secret());
+    let real_binder = key_schedule.external_psk_binder_key_and_sign_verify_data(&handshake_hash);
+
+    if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
+        ch.set_psk_binder(real_binder.as_ref());
+    };
+
+    key_schedule
+}
+
+/// Offer the configured external PSK, if there is one and we have a cipher
+/// suite it can be used with.  `retry_suite` is the suite chosen by a
+/// HelloRetryRequest, if any.
+///
+/// This must be called last, as the PSK extension must be the final one.
+/// Returns the suite the binder must be computed with.
+pub(super) fn prepare_external_psk(
+    config: &ClientConfig,
+    exts: &mut Vec<ClientExtension>,
+    retry_suite: Option<&'static Tls13CipherSuite>,
+) -> Option<&'static Tls13CipherSuite> {
+    let psk = config.external_psk.as_ref()?;
+    let suite = match retry_suite {
+        Some(suite) => Some(suite).filter(|suite| psk.usable_with(suite)),
+        None => config
+            .enabled_cipher_suites()
+            .filter_map(|cs| cs.tls13())
+            .find(|suite| psk.usable_with(suite)),
+    }?;
+
+    // As with resumption, the binder is filled in once the message is complete.
+    let binder = vec![0u8; suite.common.hash_provider.output_len()];
+    let psk_identity = PresharedKeyIdentity::new(psk.identity().to_vec(), 0);
+    exts.push(ClientExtension::PresharedKey(PresharedKeyOffer::new(
+        psk_identity,
+        binder,
+    )));
+    Some(suite)
+}
+
 pub(super) fn prepare_resumption(
     config: &ClientConfig,
     cx: &mut ClientContext<'_>,
@@ -309,7 +400,7 @@ pub(super) fn derive_early_traffic_secret(
         transcript_buffer.get_hash_given(resuming_suite.common.hash_provider, &[]);
     early_key_schedule.client_early_traffic_secret(
         &client_hello_hash,
-        key_log,
+        &ConnectionKeyLog::new(key_log, cx.common),
         client_random,
         cx.common,
     );
@@ -377,6 +468,7 @@ struct ExpectEncryptedExtensions {
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     hello: ClientHelloDetails,
+    using_external_psk: bool,
 }
 
 impl State<ClientConnectionData> for ExpectEncryptedExtensions {
@@ -391,6 +483,32 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
 
         validate_encrypted_extensions(cx.common, &self.hello, exts)?;
         hs::process_alpn_protocol(cx.common, &self.config, exts.get_alpn_protocol())?;
+        cx.common.peer_heartbeat_mode = exts.get_heartbeat_mode();
+
+        if let Some(peer_settings) = exts.get_alps_settings() {
+            let our_settings = cx
+                .common
+                .alpn_protocol
+                .as_ref()
+                .and_then(|agreed| {
+                    self.config
+                        .alps_settings
+                        .iter()
+                        .find(|(protocol, _)| protocol == agreed)
+                });
+            match our_settings {
+                Some((_, settings)) => {
+                    cx.common.peer_alps_settings = Some(peer_settings.to_vec());
+                    cx.data.alps_to_send = Some(settings.clone());
+                }
+                None => {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::UnsupportedExtension,
+                        PeerMisbehaved::UnsolicitedApplicationSettings,
+                    ));
+                }
+            }
+        }
 
         // QUIC transport parameters
         if cx.common.is_quic() {
@@ -442,6 +560,26 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
                 cert_verified,
                 sig_verified,
             }))
+        } else if self.using_external_psk {
+            if exts.early_data_extension_offered() {
+                return Err(PeerMisbehaved::EarlyDataExtensionWithoutResumption.into());
+            }
+
+            // The server proved knowledge of the external PSK in place of
+            // presenting a certificate.
+            let cert_verified = verify::ServerCertVerified::assertion();
+            let sig_verified = verify::HandshakeSignatureValid::assertion();
+            Ok(Box::new(ExpectFinished {
+                config: self.config,
+                server_name: self.server_name,
+                randoms: self.randoms,
+                suite: self.suite,
+                transcript: self.transcript,
+                key_schedule: self.key_schedule,
+                client_auth: None,
+                cert_verified,
+                sig_verified,
+            }))
         } else {
             if exts.early_data_extension_offered() {
                 return Err(PeerMisbehaved::EarlyDataExtensionWithoutResumption.into());
@@ -550,9 +688,15 @@ impl State<ClientConnectionData> for ExpectCertificateRequest {
         }
 
         let no_sigschemes = Vec::new();
-        let compat_sigschemes = certreq
+        let sigschemes = certreq
             .get_sigalgs_extension()
-            .unwrap_or(&no_sigschemes)
+            .unwrap_or(&no_sigschemes);
+        cx.data.client_auth_request = Some(ClientAuthRequest::new(
+            certreq.get_authorities_extension(),
+            sigschemes,
+        ));
+
+        let compat_sigschemes = sigschemes
             .iter()
             .cloned()
             .filter(SignatureScheme::supported_in_tls13)
@@ -621,8 +765,18 @@ impl State<ClientConnectionData> for ExpectCertificate {
                 PeerMisbehaved::BadCertChainExtensions,
             ));
         }
+
+        if !self.config.verifier.requests_scts() && cert_chain.any_entry_has_sct_list() {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::UnsupportedExtension,
+                PeerMisbehaved::UnsolicitedSctList,
+            ));
+        }
+
         let end_entity_ocsp = cert_chain.get_end_entity_ocsp();
-        let server_cert = ServerCertDetails::new(cert_chain.convert(), end_entity_ocsp);
+        let end_entity_scts = cert_chain.get_end_entity_scts();
+        let server_cert =
+            ServerCertDetails::new(cert_chain.convert(), end_entity_ocsp, end_entity_scts);
 
         Ok(Box::new(ExpectCertificateVerify {
             config: self.config,
@@ -665,6 +819,13 @@ impl State<ClientConnectionData> for ExpectCertificateVerify {
             .cert_chain
             .split_first()
             .ok_or(Error::NoCertificatesPresented)?;
+        self.config
+            .check_server_name(&self.server_name)
+            .map_err(|err| {
+                cx.common
+                    .send_cert_verify_error_alert(err)
+            })?;
+        let now = UnixTime::now();
         let cert_verified = self
             .config
             .verifier
@@ -673,13 +834,29 @@ impl State<ClientConnectionData> for ExpectCertificateVerify {
                 intermediates,
                 &self.server_name,
                 &self.server_cert.ocsp_response,
-                UnixTime::now(),
+                now,
             )
             .map_err(|err| {
                 cx.common
                     .send_cert_verify_error_alert(err)
             })?;
 
+        if self.config.verifier.requests_scts() {
+            let scts = self
+                .server_cert
+                .scts
+                .iter()
+                .map(|sct| sct.as_slice())
+                .collect::<Vec<_>>();
+            self.config
+                .verifier
+                .verify_server_scts(end_entity, &scts, now)
+                .map_err(|err| {
+                    cx.common
+                        .send_cert_verify_error_alert(err)
+                })?;
+        }
+
         // 2. Verify their signature on the handshake.
         let handshake_hash = self.transcript.get_current_hash();
         let sig_verified = self
@@ -787,6 +964,26 @@ fn emit_finished_tls13(
     common.send_msg(m, true);
 }
 
+fn emit_client_encrypted_extensions(
+    transcript: &mut HandshakeHash,
+    alps_settings: Vec<u8>,
+    common: &mut CommonState,
+) {
+    let m = Message {
+        version: ProtocolVersion::TLSv1_3,
+        payload: MessagePayload::handshake(HandshakeMessagePayload {
+            typ: HandshakeType::ClientEncryptedExtensions,
+            payload: HandshakePayload::ClientEncryptedExtensions(vec![
+                ServerExtension::ApplicationSettings(Payload::new(alps_settings)),
+            ]),
+        }),
+    };
+
+    trace!("sending client encrypted extensions {:?}", m);
+    transcript.add_message(&m);
+    common.send_msg(m, true);
+}
+
 fn emit_end_of_early_data_tls13(transcript: &mut HandshakeHash, common: &mut CommonState) {
     if common.is_quic() {
         return;
@@ -851,6 +1048,10 @@ impl State<ClientConnectionData> for ExpectFinished {
 
         /* Send our authentication/finished messages.  These are still encrypted
          * with our handshake keys. */
+        if let Some(settings) = cx.data.alps_to_send.take() {
+            emit_client_encrypted_extensions(&mut st.transcript, settings, cx.common);
+        }
+
         if let Some(client_auth) = st.client_auth {
             match client_auth {
                 ClientAuthDetails::Empty {
@@ -879,7 +1080,7 @@ impl State<ClientConnectionData> for ExpectFinished {
             .into_pre_finished_client_traffic(
                 hash_after_handshake,
                 st.transcript.get_current_hash(),
-                &*st.config.key_log,
+                &ConnectionKeyLog::new(&*st.config.key_log, cx.common),
                 &st.randoms.client,
             );
 
@@ -899,6 +1100,7 @@ impl State<ClientConnectionData> for ExpectFinished {
 
         let st = ExpectTraffic {
             session_storage: Arc::clone(&st.config.resumption.store),
+            on_new_ticket: st.config.on_new_ticket.clone(),
             server_name: st.server_name,
             suite: st.suite,
             transcript: st.transcript,
@@ -920,6 +1122,7 @@ impl State<ClientConnectionData> for ExpectFinished {
 // and application data.
 struct ExpectTraffic {
     session_storage: Arc<dyn ClientSessionStore>,
+    on_new_ticket: Option<Arc<dyn ObservesNewTickets>>,
     server_name: ServerName<'static>,
     suite: &'static Tls13CipherSuite,
     transcript: HandshakeHash,
@@ -975,8 +1178,24 @@ impl ExpectTraffic {
             }
         }
 
+        cx.data.session_state = Some(value.get_state_encoding());
         self.session_storage
             .insert_tls13_ticket(self.server_name.clone(), value);
+
+        if let Some(observer) = &self.on_new_ticket {
+            observer.new_ticket(
+                &self.server_name,
+                &NewSessionTicketMetadata {
+                    version: ProtocolVersion::TLSv1_3,
+                    cipher_suite: self.suite.common.suite,
+                    lifetime: Duration::from_secs(u64::from(nst.lifetime)),
+                    max_early_data_size: nst
+                        .get_max_early_data_size()
+                        .unwrap_or_default(),
+                    ticket_len: nst.ticket.0.len(),
+                },
+            );
+        }
         Ok(())
     }
 
@@ -1055,6 +1274,22 @@ impl State<ClientConnectionData> for ExpectTraffic {
         self.key_schedule
             .extract_secrets(Side::Client)
     }
+
+    fn resumption_master_secret(&self) -> Option<Vec<u8>> {
+        let handshake_hash = self.transcript.get_current_hash();
+        Some(
+            self.key_schedule
+                .resumption_master_secret(&handshake_hash)
+                .as_ref()
+                .to_vec(),
+        )
+    }
+
+    fn send_key_update(&mut self, common: &mut CommonState) -> Result<(), Error> {
+        self.key_schedule
+            .send_key_update(common);
+        Ok(())
+    }
 }
 
 struct ExpectQuicTraffic(ExpectTraffic);
@@ -1080,4 +1315,8 @@ impl State<ClientConnectionData> for ExpectQuicTraffic {
         self.0
             .export_keying_material(output, label, context)
     }
+
+    fn resumption_master_secret(&self) -> Option<Vec<u8>> {
+        self.0.resumption_master_secret()
+    }
 }
@@ -1,26 +1,151 @@
+use crate::crypto::{ActiveKeyExchange, MeasuredKeyExchange, SecureRandom};
 use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
 use crate::error::{Error, InvalidMessage, PeerMisbehaved};
 #[cfg(feature = "logging")]
 use crate::log::{debug, warn};
 use crate::msgs::alert::AlertMessagePayload;
 use crate::msgs::base::Payload;
-use crate::msgs::enums::{AlertLevel, KeyUpdateRequest};
-use crate::msgs::fragmenter::MessageFragmenter;
-use crate::msgs::handshake::CertificateChain;
+use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::{
+    AlertLevel, HeartbeatMessageType, HeartbeatMode, KeyUpdateRequest, NamedGroup,
+};
+use crate::msgs::fragmenter::{MessageFragmenter, MAX_FRAGMENT_LEN, PACKET_OVERHEAD};
+use crate::msgs::handshake::{CertificateChain, HandshakeMessagePayload, HandshakePayload};
 use crate::msgs::message::MessagePayload;
 use crate::msgs::message::{BorrowedPlainMessage, Message, OpaqueMessage, PlainMessage};
 use crate::quic;
 use crate::record_layer;
+use crate::record_layer::PreEncryptAction;
 use crate::suites::PartiallyExtractedSecrets;
 use crate::suites::SupportedCipherSuite;
 #[cfg(feature = "tls12")]
 use crate::tls12::ConnectionSecrets;
 use crate::vecbuf::ChunkVecBuffer;
+use crate::x509;
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::sync::atomic::Ordering;
 
-use pki_types::CertificateDer;
+use pki_types::{CertificateDer, UnixTime};
+
+/// Observes the alerts sent and received by a connection.
+///
+/// Each call carries the connection's [`CommonState::connection_id`], if one
+/// was supplied, so alerts can be correlated with the application's own logs.
+pub trait ObservesAlerts: Debug + Send + Sync {
+    /// Called when an alert is queued for sending to the peer.
+    fn alert_sent(&self, _connection_id: Option<&[u8]>, _description: AlertDescription) {}
+
+    /// Called when an alert is received from the peer.
+    fn alert_received(&self, _connection_id: Option<&[u8]>, _description: AlertDescription) {}
+}
+
+/// Observes the handshake messages of a connection, as they are added to the
+/// handshake transcript.
+pub trait ObservesHandshakeMessages: Debug + Send + Sync {
+    /// Called with each handshake message added to the transcript, whether
+    /// sent or received, in transcript order.
+    ///
+    /// `encoding` is the message as sent on the wire, including its four
+    /// byte header.  Messages outside the transcript, such as TLS1.3
+    /// `NewSessionTicket`s, and the synthetic `message_hash` which replaces
+    /// the first `ClientHello` after a `HelloRetryRequest`, are not reported.
+    fn handshake_message(&self, typ: HandshakeType, encoding: &[u8]);
+}
+
+/// Parameters agreed with the peer, from [`CommonState::negotiated_parameters`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NegotiatedParameters {
+    /// The protocol version.
+    pub protocol_version: Option<ProtocolVersion>,
+    /// The cipher suite.
+    pub cipher_suite: Option<SupportedCipherSuite>,
+    /// The group used for the (EC)DHE key exchange.
+    pub key_exchange_group: Option<NamedGroup>,
+    /// The length in bytes of the shared secret the key exchange produced.
+    ///
+    /// This is `None` until the key exchange has completed, and when a
+    /// TLS1.2 session was resumed without one.
+    pub shared_secret_len: Option<usize>,
+}
+
+/// Observes the amount of received plaintext waiting to be read, against the
+/// watermarks set with [`CommonState::set_read_watermarks`].
+///
+/// This allows an application to stop reading from the socket while the
+/// plaintext is not being consumed, and to resume once it has been.
+pub trait ObservesReadWatermarks: Debug + Send + Sync {
+    /// Called when the buffered plaintext grows to `buffered` bytes, reaching
+    /// the high watermark.
+    fn high_watermark_reached(&self, _buffered: usize) {}
+
+    /// Called when, after reaching the high watermark, the buffered plaintext
+    /// is read down to `buffered` bytes, at or below the low watermark.
+    fn low_watermark_reached(&self, _buffered: usize) {}
+}
+
+/// The read watermarks of a connection, and which side of them we are on.
+#[derive(Debug, Default)]
+pub(crate) struct ReadWatermarks {
+    levels: Option<(usize, usize)>,
+    above_high: bool,
+    observer: Option<Arc<dyn ObservesReadWatermarks>>,
+}
+
+impl ReadWatermarks {
+    /// Tell the observer if `buffered` bytes of plaintext crosses a watermark.
+    pub(crate) fn update(&mut self, buffered: usize) {
+        let (low, high) = match self.levels {
+            Some(levels) => levels,
+            None => return,
+        };
+
+        if !self.above_high && buffered >= high {
+            self.above_high = true;
+            if let Some(observer) = &self.observer {
+                observer.high_watermark_reached(buffered);
+            }
+        } else if self.above_high && buffered <= low {
+            self.above_high = false;
+            if let Some(observer) = &self.observer {
+                observer.low_watermark_reached(buffered);
+            }
+        }
+    }
+}
+
+/// When queued records are worth writing, set with
+/// [`CommonState::set_flush_after_records`] and [`CommonState::set_flush_after_bytes`].
+#[derive(Debug, Default)]
+struct FlushThresholds {
+    records: Option<usize>,
+    bytes: Option<usize>,
+    /// Application data records queued since the outgoing buffer was last empty.
+    queued_records: usize,
+    /// Whether something other than application data has been queued, or a
+    /// flush was requested, since the outgoing buffer was last empty.
+    urgent: bool,
+}
+
+impl FlushThresholds {
+    fn reached(&self, queued: &ChunkVecBuffer) -> bool {
+        if self.records.is_none() && self.bytes.is_none() {
+            return true;
+        }
+
+        // Once `queued` is at its limit nothing more can be written to the
+        // connection, so holding its contents back would never end.
+        self.urgent
+            || queued.is_at_limit()
+            || matches!(self.records, Some(records) if self.queued_records >= records)
+            || matches!(self.bytes, Some(bytes) if queued.len() >= bytes)
+    }
+}
 
 /// Connection state common to both client and server connections.
 pub struct CommonState {
@@ -38,6 +163,8 @@ pub struct CommonState {
     pub(crate) has_received_close_notify: bool,
     pub(crate) has_seen_eof: bool,
     pub(crate) received_middlebox_ccs: u8,
+    post_handshake_messages: u64,
+    pub(crate) handshake_kind: Option<HandshakeKind>,
     pub(crate) peer_certificates: Option<CertificateChain>,
     message_fragmenter: MessageFragmenter,
     pub(crate) received_plaintext: ChunkVecBuffer,
@@ -49,6 +176,29 @@ pub struct CommonState {
     pub(crate) protocol: Protocol,
     pub(crate) quic: quic::Quic,
     pub(crate) enable_secret_extraction: bool,
+    record_padding: RecordPadding,
+    padding_random: Option<&'static dyn SecureRandom>,
+    pub(crate) connection_id: Option<Vec<u8>>,
+    pub(crate) alert_observer: Option<Arc<dyn ObservesAlerts>>,
+    pub(crate) peer_alps_settings: Option<Vec<u8>>,
+    pub(crate) preserve_record_boundaries: bool,
+    pub(crate) max_handshake_flight_bytes: Option<usize>,
+    /// Total size of the handshake messages received since we last sent one.
+    handshake_flight_bytes: usize,
+    pub(crate) heartbeat_mode: Option<HeartbeatMode>,
+    /// The mode the peer sent in its `heartbeat` extension, if any.
+    pub(crate) peer_heartbeat_mode: Option<HeartbeatMode>,
+    /// The payload of our unanswered `HeartbeatRequest`, if any.
+    heartbeat_in_flight: Option<Vec<u8>>,
+    /// The `verify_data` of the Finished messages we sent and received.
+    local_verify_data: Option<Vec<u8>>,
+    peer_verify_data: Option<Vec<u8>>,
+    pub(crate) read_watermarks: ReadWatermarks,
+    kx_group: Option<NamedGroup>,
+    kx_shared_secret_len: Option<usize>,
+    flush_thresholds: FlushThresholds,
+    /// The largest record we'll write, including header and encryption overhead.
+    max_record_size: Option<usize>,
 }
 
 impl CommonState {
@@ -67,6 +217,8 @@ impl CommonState {
             has_received_close_notify: false,
             has_seen_eof: false,
             received_middlebox_ccs: 0,
+            post_handshake_messages: 0,
+            handshake_kind: None,
             peer_certificates: None,
             message_fragmenter: MessageFragmenter::default(),
             received_plaintext: ChunkVecBuffer::new(Some(DEFAULT_RECEIVED_PLAINTEXT_LIMIT)),
@@ -76,14 +228,62 @@ impl CommonState {
             protocol: Protocol::Tcp,
             quic: quic::Quic::default(),
             enable_secret_extraction: false,
+            record_padding: RecordPadding::None,
+            padding_random: None,
+            connection_id: None,
+            alert_observer: None,
+            peer_alps_settings: None,
+            preserve_record_boundaries: false,
+            max_handshake_flight_bytes: None,
+            handshake_flight_bytes: 0,
+            heartbeat_mode: None,
+            peer_heartbeat_mode: None,
+            heartbeat_in_flight: None,
+            local_verify_data: None,
+            peer_verify_data: None,
+            read_watermarks: ReadWatermarks::default(),
+            kx_group: None,
+            kx_shared_secret_len: None,
+            flush_thresholds: FlushThresholds::default(),
+            max_record_size: None,
         }
     }
 
+    /// Returns the opaque identifier supplied when this connection was made,
+    /// if any.
+    ///
+    /// See [`ClientConnection::new_with_connection_id`] and
+    /// [`ServerConnection::new_with_connection_id`].
+    ///
+    /// [`ClientConnection::new_with_connection_id`]: crate::ClientConnection::new_with_connection_id
+    /// [`ServerConnection::new_with_connection_id`]: crate::ServerConnection::new_with_connection_id
+    pub fn connection_id(&self) -> Option<&[u8]> {
+        self.connection_id.as_deref()
+    }
+
     /// Returns true if the caller should call [`Connection::write_tls`] as soon as possible.
     ///
+    /// If thresholds were set with [`CommonState::set_flush_after_records`] or
+    /// [`CommonState::set_flush_after_bytes`], queued application data only makes
+    /// this true once a threshold is reached.
+    ///
     /// [`Connection::write_tls`]: crate::Connection::write_tls
     pub fn wants_write(&self) -> bool {
         !self.sendable_tls.is_empty()
+            && self
+                .flush_thresholds
+                .reached(&self.sendable_tls)
+    }
+
+    /// Returns how many bytes of TLS records are queued, waiting to be written
+    /// by [`Connection::write_tls`].
+    ///
+    /// Plaintext written before the handshake completes is not counted until
+    /// it has been encrypted into records.
+    ///
+    /// [`Connection::write_tls`]: crate::Connection::write_tls
+    pub fn write_buffer_len(&self) -> usize {
+        self.sendable_tls.len()
     }
 
     /// Returns true if the connection is currently performing the TLS handshake.
@@ -97,6 +297,25 @@ impl CommonState {
         !(self.may_send_application_data && self.may_receive_application_data)
     }
 
+    /// Returns the number of handshake messages received and processed
+    /// after the handshake completed.
+    ///
+    /// These are messages like TLS1.3 `NewSessionTicket` and `KeyUpdate`,
+    /// which may arrive interleaved with application data.
+    pub fn post_handshake_messages_processed(&self) -> u64 {
+        self.post_handshake_messages
+    }
+
+    /// Describes whether the handshake was a full handshake or a resumption.
+    ///
+    /// This returns `None` until the handshake is complete.
+    pub fn handshake_kind(&self) -> Option<HandshakeKind> {
+        match self.is_handshaking() {
+            true => None,
+            false => self.handshake_kind,
+        }
+    }
+
     /// Retrieves the certificate chain used by the peer to authenticate.
     ///
     /// The order of the certificate chain is as it appears in the TLS
@@ -116,6 +335,20 @@ impl CommonState {
         self.peer_certificates.as_deref()
     }
 
+    /// Retrieves the validity period of the peer's end-entity certificate,
+    /// as its `notBefore` and `notAfter` times.
+    ///
+    /// This is useful for re-handshaking before the peer's certificate
+    /// expires.  It is `None` when [`Self::peer_certificates`] is, and if
+    /// the certificate's validity cannot be parsed.
+    pub fn peer_certificate_validity(&self) -> Option<(UnixTime, UnixTime)> {
+        let end_entity = self
+            .peer_certificates
+            .as_ref()?
+            .first()?;
+        x509::validity(end_entity)
+    }
+
     /// Retrieves the protocol agreed with the peer via ALPN.
     ///
     /// A return value of `None` after handshake completion
@@ -125,6 +358,97 @@ impl CommonState {
         self.get_alpn_protocol()
     }
 
+    /// Retrieves the protocol agreed with the peer via ALPN, recognising
+    /// common protocols.
+    ///
+    /// This is the same as [`CommonState::alpn_protocol()`], except that the
+    /// result is an [`AlpnProtocol`].
+    pub fn alpn_protocol_typed(&self) -> Option<AlpnProtocol> {
+        self.get_alpn_protocol()
+            .map(AlpnProtocol::from_bytes)
+    }
+
+    /// Retrieves the application-layer protocol settings (ALPS) the peer sent
+    /// for `protocol`.
+    ///
+    /// This returns `None` unless `protocol` was agreed via ALPN and settings
+    /// were exchanged for it during a TLS1.3 handshake.  See
+    /// [`ClientConfig::alps_settings`] and [`ServerConfig::alps_settings`].
+    ///
+    /// [`ClientConfig::alps_settings`]: crate::ClientConfig::alps_settings
+    /// [`ServerConfig::alps_settings`]: crate::ServerConfig::alps_settings
+    pub fn peer_alps_settings(&self, protocol: &[u8]) -> Option<&[u8]> {
+        match self.get_alpn_protocol() {
+            Some(agreed) if agreed == protocol => self.peer_alps_settings.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Sends a heartbeat request carrying `payload` to the peer.
+    ///
+    /// This fails unless the handshake is complete and the peer agreed to
+    /// receive heartbeats (see [`ClientConfig::heartbeat_mode`] and
+    /// [`ServerConfig::heartbeat_mode`]).  Only one request may be outstanding
+    /// at a time: see [`CommonState::heartbeat_pending`].  The request must fit
+    /// in a single record, so `payload` is limited to 16365 bytes, or less if
+    /// a `max_fragment_size` is configured.
+    ///
+    /// A response whose payload differs from `payload` fails the connection
+    /// with [`PeerMisbehaved::HeartbeatResponseMismatch`].
+    ///
+    /// [`ClientConfig::heartbeat_mode`]: crate::ClientConfig::heartbeat_mode
+    /// [`ServerConfig::heartbeat_mode`]: crate::ServerConfig::heartbeat_mode
+    pub fn send_heartbeat(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if !self.may_send_application_data {
+            return Err(Error::HandshakeNotComplete);
+        }
+
+        if self.peer_heartbeat_mode != Some(HeartbeatMode::PeerAllowedToSend) {
+            return Err(Error::General(
+                "peer does not accept heartbeat requests".into(),
+            ));
+        }
+
+        if self.heartbeat_in_flight.is_some() {
+            return Err(Error::General(
+                "a heartbeat request is already in flight".into(),
+            ));
+        }
+
+        if HEARTBEAT_HEADER_LEN + payload.len() + HEARTBEAT_PADDING_LEN
+            > self
+                .outgoing_fragmenter()
+                .max_fragment_len()
+        {
+            return Err(Error::General("heartbeat payload too long".into()));
+        }
+
+        self.send_heartbeat_message(HeartbeatMessageType::Request, payload)?;
+        self.heartbeat_in_flight = Some(payload.to_vec());
+        Ok(())
+    }
+
+    /// Returns true if a heartbeat request sent with [`CommonState::send_heartbeat`]
+    /// has not yet been answered.
+    pub fn heartbeat_pending(&self) -> bool {
+        self.heartbeat_in_flight.is_some()
+    }
+
+    /// Retrieves the `verify_data` of the handshake's Finished messages, as
+    /// `(local, peer)`: the first was sent by us, the second by the peer.
+    ///
+    /// This returns `None` until the handshake is complete.
+    pub fn finished_verify_data(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        if self.is_handshaking() {
+            return None;
+        }
+
+        match (&self.local_verify_data, &self.peer_verify_data) {
+            (Some(local), Some(peer)) => Some((local.clone(), peer.clone())),
+            _ => None,
+        }
+    }
+
     /// Retrieves the ciphersuite agreed with the peer.
     ///
     /// This returns None until the ciphersuite is agreed.
@@ -139,6 +463,33 @@ impl CommonState {
         self.negotiated_version
     }
 
+    /// Retrieves the parameters agreed with the peer so far.
+    ///
+    /// Each member is `None` until it has been agreed.
+    pub fn negotiated_parameters(&self) -> NegotiatedParameters {
+        NegotiatedParameters {
+            protocol_version: self.negotiated_version,
+            cipher_suite: self.suite,
+            key_exchange_group: self.kx_group,
+            shared_secret_len: self.kx_shared_secret_len,
+        }
+    }
+
+    /// Completes `kx` with `complete`, noting its group and shared secret length.
+    pub(crate) fn complete_key_exchange<T>(
+        &mut self,
+        kx: Box<dyn ActiveKeyExchange>,
+        complete: impl FnOnce(Box<dyn ActiveKeyExchange>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let group = kx.group();
+        let (kx, secret_len) = MeasuredKeyExchange::new(kx);
+        let result = complete(Box::new(kx))?;
+
+        self.kx_group = Some(group);
+        self.kx_shared_secret_len = Some(secret_len.load(Ordering::Relaxed));
+        Ok(result)
+    }
+
     pub(crate) fn is_tls13(&self) -> bool {
         matches!(self.negotiated_version, Some(ProtocolVersion::TLSv1_3))
     }
@@ -162,12 +513,43 @@ impl CommonState {
             }
         }
 
+        let handshake_len = match &msg.payload {
+            MessagePayload::Handshake { encoded, .. } if self.is_handshaking() => {
+                Some(encoded.0.len())
+            }
+            _ => None,
+        };
+        if let Some(len) = handshake_len {
+            self.handshake_flight_bytes += len;
+            if let Some(max) = self.max_handshake_flight_bytes {
+                if self.handshake_flight_bytes > max {
+                    return Err(self.send_fatal_alert(
+                        AlertDescription::DecodeError,
+                        InvalidMessage::HandshakeFlightTooLarge,
+                    ));
+                }
+            }
+        }
+
+        let post_handshake =
+            !self.is_handshaking() && matches!(msg.payload, MessagePayload::Handshake { .. });
+        let peer_verify_data = finished_verify_data(&msg).map(<[u8]>::to_vec);
+
         let mut cx = Context { common: self, data };
         match state.handle(&mut cx, msg) {
             Ok(next) => {
+                if post_handshake {
+                    self.post_handshake_messages += 1;
+                }
+                if peer_verify_data.is_some() {
+                    self.peer_verify_data = peer_verify_data;
+                }
                 state = next;
                 Ok(state)
             }
+            // This includes application data received mid-handshake: only the
+            // traffic states, and the server's states for accepted or skipped
+            // early data, accept it.
             Err(e @ Error::InappropriateMessage { .. })
             | Err(e @ Error::InappropriateHandshakeMessage { .. }) => {
                 Err(self.send_fatal_alert(AlertDescription::UnexpectedMessage, e))
@@ -186,6 +568,13 @@ impl CommonState {
         self.send_plain(data, Limit::Yes)
     }
 
+    /// Like `send_some_plaintext`, but accepts all of `data` regardless of
+    /// buffer limits.
+    pub(crate) fn send_all_plaintext(&mut self, data: &[u8]) {
+        self.perhaps_write_key_update();
+        self.send_plain(data, Limit::No);
+    }
+
     pub(crate) fn send_early_plaintext(&mut self, data: &[u8]) -> usize {
         debug_assert!(self.early_traffic);
         debug_assert!(self.record_layer.is_encrypting());
@@ -216,9 +605,8 @@ impl CommonState {
     /// Fragment `m`, encrypt the fragments, and then queue
     /// the encrypted fragments for sending.
     pub(crate) fn send_msg_encrypt(&mut self, m: PlainMessage) {
-        let iter = self
-            .message_fragmenter
-            .fragment_message(&m);
+        let fragmenter = self.outgoing_fragmenter();
+        let iter = fragmenter.fragment_message(&m);
         for m in iter {
             self.send_single_fragment(m);
         }
@@ -237,36 +625,224 @@ impl CommonState {
             Limit::No => payload.len(),
         };
 
-        let iter = self.message_fragmenter.fragment_slice(
-            ContentType::ApplicationData,
-            ProtocolVersion::TLSv1_2,
-            &payload[..len],
-        );
-        for m in iter {
-            self.send_single_fragment(m);
+        let fragments = self
+            .outgoing_fragmenter()
+            .fragment_slice(
+                ContentType::ApplicationData,
+                ProtocolVersion::TLSv1_2,
+                &payload[..len],
+            )
+            .collect::<Vec<_>>();
+        let records = fragments.len();
+
+        // Encrypt as one batch, unless we're near the end of the sequence
+        // space and need the per-record checks in `send_single_fragment`.
+        if fragments.len() > 1
+            && matches!(self.record_padding, RecordPadding::None)
+            && self
+                .record_layer
+                .can_encrypt_batch(fragments.len())
+        {
+            for em in self
+                .record_layer
+                .encrypt_outgoing_batch(&fragments)
+            {
+                self.queue_tls_message(em);
+            }
+        } else {
+            let mut sent = 0;
+            for m in fragments {
+                if self.wants_traffic_key_refresh() {
+                    // Keep the rest until `ConnectionCore::maybe_refresh_traffic_keys`
+                    // has replaced the exhausted key.
+                    self.sendable_plaintext
+                        .prepend(payload[sent..len].to_vec());
+                    break;
+                }
+                sent += m.payload.len();
+                self.send_single_fragment(m);
+                self.flush_thresholds.queued_records += 1;
+            }
+            return len;
         }
 
+        self.flush_thresholds.queued_records += records;
         len
     }
 
+    /// Whether the TLS1.3 traffic key has reached its usage limit, and must be
+    /// replaced before any more application data is encrypted.
+    pub(crate) fn wants_traffic_key_refresh(&self) -> bool {
+        self.record_layer
+            .next_pre_encrypt_action()
+            == PreEncryptAction::RefreshOrClose
+            && self.negotiated_version == Some(ProtocolVersion::TLSv1_3)
+            && !self.is_quic()
+    }
+
     fn send_single_fragment(&mut self, m: BorrowedPlainMessage) {
-        // Close connection once we start to run out of
-        // sequence space.
-        if self
+        match self
             .record_layer
-            .wants_close_before_encrypt()
+            .next_pre_encrypt_action()
         {
-            self.send_close_notify();
+            PreEncryptAction::Nothing => {}
+            PreEncryptAction::RefreshOrClose => match self.negotiated_version {
+                Some(ProtocolVersion::TLSv1_3) if !self.is_quic() => {
+                    // Application data waits for new keys in `send_appdata_encrypt`,
+                    // but we don't have the `State` here to replace them.  This is a
+                    // handshake or alert message (at least the `KeyUpdate` itself),
+                    // which goes out now; `ConnectionCore::maybe_refresh_traffic_keys`
+                    // sends the key update straight afterwards.
+                }
+                _ => {
+                    // TLS1.2 cannot replace its keys, so close the connection
+                    // before the key is used any further.
+                    warn!("Traffic keys exhausted, closing connection");
+                    self.observe_sent_alert(AlertDescription::CloseNotify);
+                    let alert =
+                        Message::build_alert(AlertLevel::Warning, AlertDescription::CloseNotify);
+                    let em = self
+                        .record_layer
+                        .encrypt_outgoing(PlainMessage::from(alert).borrow());
+                    self.queue_tls_message(em);
+                    self.flush_thresholds.urgent = true;
+                    self.record_layer.exhaust_encrypter();
+                    return;
+                }
+            },
+            // Refuse to wrap counter at all costs.
+            PreEncryptAction::Refuse => return,
         }
 
-        // Refuse to wrap counter at all costs.  This
-        // is basically untestable unfortunately.
-        if self.record_layer.encrypt_exhausted() {
-            return;
+        let padding = self.padding_len(m.payload.len());
+        let em = self
+            .record_layer
+            .encrypt_outgoing_padded(m, padding);
+        self.queue_tls_message(em);
+    }
+
+    /// Process a received heartbeat message, replying to requests and checking
+    /// responses against our outstanding request.
+    pub(crate) fn process_heartbeat(&mut self, message: &[u8]) -> Result<(), Error> {
+        if !self.may_receive_application_data {
+            return Err(self.send_fatal_alert(
+                AlertDescription::UnexpectedMessage,
+                Error::InappropriateMessage {
+                    expect_types: vec![ContentType::Handshake],
+                    got_type: ContentType::Heartbeat,
+                },
+            ));
         }
 
-        let em = self.record_layer.encrypt_outgoing(m);
-        self.queue_tls_message(em);
+        let mut r = Reader::init(message);
+        let (typ, len) = match (HeartbeatMessageType::read(&mut r), u16::read(&mut r)) {
+            (Ok(typ), Ok(len)) => (typ, usize::from(len)),
+            _ => {
+                debug!("Dropping truncated heartbeat message");
+                return Ok(());
+            }
+        };
+
+        // "If the payload_length of a received HeartbeatMessage is too large,
+        //  the received HeartbeatMessage MUST be discarded silently."
+        if len + HEARTBEAT_PADDING_LEN > r.left() {
+            debug!("Dropping heartbeat message with bad payload length");
+            return Ok(());
+        }
+        let payload = r.take(len).unwrap_or_default();
+
+        match typ {
+            HeartbeatMessageType::Request
+                if self.heartbeat_mode == Some(HeartbeatMode::PeerAllowedToSend) =>
+            {
+                self.send_heartbeat_message(HeartbeatMessageType::Response, payload)
+            }
+            HeartbeatMessageType::Request => Err(self.send_fatal_alert(
+                AlertDescription::UnexpectedMessage,
+                PeerMisbehaved::UnexpectedHeartbeatRequest,
+            )),
+            HeartbeatMessageType::Response => match self.heartbeat_in_flight.take() {
+                Some(expected) if expected == payload => Ok(()),
+                Some(_) => Err(self.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::HeartbeatResponseMismatch,
+                )),
+                None => {
+                    debug!("Dropping unsolicited heartbeat response");
+                    Ok(())
+                }
+            },
+            _ => {
+                debug!("Dropping heartbeat message of unknown type {:?}", typ);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn send_heartbeat_message(
+        &mut self,
+        typ: HeartbeatMessageType,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        // "The padding_length MUST be at least 16.  The sender of a
+        //  HeartbeatMessage MUST use a random padding of at least 16 bytes."
+        let mut padding = [0u8; HEARTBEAT_PADDING_LEN];
+        self.padding_random
+            .ok_or(Error::FailedToGetRandomBytes)?
+            .fill(&mut padding)?;
+
+        let mut message = Vec::with_capacity(HEARTBEAT_HEADER_LEN + payload.len() + padding.len());
+        typ.encode(&mut message);
+        (payload.len() as u16).encode(&mut message);
+        message.extend_from_slice(payload);
+        message.extend_from_slice(&padding);
+
+        self.send_single_fragment(BorrowedPlainMessage {
+            typ: ContentType::Heartbeat,
+            version: ProtocolVersion::TLSv1_2,
+            payload: &message,
+        });
+        self.flush_thresholds.urgent = true;
+        Ok(())
+    }
+
+    pub(crate) fn set_record_padding(
+        &mut self,
+        padding: RecordPadding,
+        secure_random: &'static dyn SecureRandom,
+    ) {
+        self.record_padding = padding;
+        self.padding_random = Some(secure_random);
+    }
+
+    /// How many bytes of padding to add to an outgoing record with `payload_len`
+    /// bytes of content.
+    fn padding_len(&self, payload_len: usize) -> usize {
+        if !self.is_tls13() {
+            return 0;
+        }
+
+        let padding = match self.record_padding {
+            RecordPadding::None => 0,
+            RecordPadding::Fixed(n) => n,
+            RecordPadding::UpTo(n) => {
+                let mut random = [0u8; 2];
+                match self
+                    .padding_random
+                    .map(|rng| rng.fill(&mut random))
+                {
+                    Some(Ok(())) => usize::from(u16::from_be_bytes(random)) % (n + 1),
+                    _ => 0,
+                }
+            }
+            RecordPadding::AlignTo(0) => 0,
+            RecordPadding::AlignTo(n) => (n - (payload_len + 1) % n) % n,
+            RecordPadding::Callback(f) => f(payload_len),
+        };
+
+        // The TLSInnerPlaintext, less its content type, must fit in a fragment.
+        let max_len = MAX_FRAGMENT_LEN.min(self.max_record_payload_len());
+        padding.min(max_len.saturating_sub(payload_len))
     }
 
     /// Encrypt and send some plaintext `data`.  `limit` controls
@@ -357,25 +933,110 @@ impl CommonState {
         self.sendable_tls.set_limit(limit);
     }
 
+    /// Hold back [`CommonState::wants_write`] for application data until at
+    /// least `records` TLS records of it are queued.
+    ///
+    /// This allows small writes to be coalesced into fewer calls to
+    /// [`Connection::write_tls`], while bounding how long data sits in the
+    /// outgoing buffer.  Handshake messages and alerts are always signalled
+    /// straight away, as is everything queued when [`Writer::flush`] is called.
+    /// If a byte threshold is also set with [`CommonState::set_flush_after_bytes`],
+    /// reaching either one is enough.  `None`, the default, removes the threshold.
+    ///
+    /// This only affects `wants_write`: [`Connection::write_tls`] still writes
+    /// whatever is queued.
+    ///
+    /// [`Connection::write_tls`]: crate::Connection::write_tls
+    /// [`Writer::flush`]: crate::Writer
+    pub fn set_flush_after_records(&mut self, records: Option<usize>) {
+        self.flush_thresholds.records = records;
+    }
+
+    /// Hold back [`CommonState::wants_write`] for application data until at
+    /// least `bytes` bytes of TLS records are queued.
+    ///
+    /// See [`CommonState::set_flush_after_records`].
+    pub fn set_flush_after_bytes(&mut self, bytes: Option<usize>) {
+        self.flush_thresholds.bytes = bytes;
+    }
+
+    /// Sets watermarks on the amount of received plaintext waiting to be read
+    /// with [`Connection::reader`].
+    ///
+    /// Once `high` or more bytes are buffered, the observer set with
+    /// [`CommonState::set_read_watermark_observer`] is told, and is told again
+    /// when reading brings the buffer down to `low` bytes or fewer.  Each
+    /// watermark is reported once per crossing.
+    ///
+    /// This fails if `low` is greater than `high`.
+    ///
+    /// [`Connection::reader`]: crate::Connection::reader
+    pub fn set_read_watermarks(&mut self, low: usize, high: usize) -> Result<(), Error> {
+        if low > high {
+            return Err(Error::General(
+                "low read watermark is above the high watermark".into(),
+            ));
+        }
+
+        self.read_watermarks.levels = Some((low, high));
+        self.read_watermarks
+            .update(self.received_plaintext.len());
+        Ok(())
+    }
+
+    /// Sets the observer told when the received plaintext crosses the
+    /// watermarks set with [`CommonState::set_read_watermarks`].
+    pub fn set_read_watermark_observer(
+        &mut self,
+        observer: Option<Arc<dyn ObservesReadWatermarks>>,
+    ) {
+        self.read_watermarks.observer = observer;
+    }
+
     /// Send any buffered plaintext.  Plaintext is buffered if
-    /// written during handshake.
-    fn flush_plaintext(&mut self) {
+    /// written during handshake, or once the traffic key is exhausted.
+    pub(crate) fn flush_plaintext(&mut self) {
         if !self.may_send_application_data {
             return;
         }
 
-        while let Some(buf) = self.sendable_plaintext.pop() {
-            self.send_plain(&buf, Limit::No);
+        while !self.wants_traffic_key_refresh() {
+            match self.sendable_plaintext.pop() {
+                Some(buf) => self.send_plain(&buf, Limit::No),
+                None => break,
+            };
         }
     }
 
     // Put m into sendable_tls for writing.
     fn queue_tls_message(&mut self, m: OpaqueMessage) {
+        self.start_queueing();
         self.sendable_tls.append(m.encode());
     }
 
+    /// Forget what was queued before, if it has all been written since.
+    fn start_queueing(&mut self) {
+        if self.sendable_tls.is_empty() {
+            self.flush_thresholds.queued_records = 0;
+            self.flush_thresholds.urgent = false;
+        }
+    }
+
+    /// Make [`CommonState::wants_write`] true for anything already queued.
+    pub(crate) fn request_flush(&mut self) {
+        self.flush_thresholds.urgent = true;
+    }
+
     /// Send a raw TLS message, fragmenting it if needed.
     pub(crate) fn send_msg(&mut self, m: Message, must_encrypt: bool) {
+        if let MessagePayload::Handshake { .. } = m.payload {
+            // This ends the peer's flight.
+            self.handshake_flight_bytes = 0;
+        }
+        if let Some(verify_data) = finished_verify_data(&m) {
+            self.local_verify_data = Some(verify_data.to_vec());
+        }
+
         {
             if let Protocol::Quic = self.protocol {
                 if let MessagePayload::Alert(alert) = m.payload {
@@ -396,26 +1057,34 @@ impl CommonState {
         }
         if !must_encrypt {
             let msg = &m.into();
-            let iter = self
-                .message_fragmenter
-                .fragment_message(msg);
+            let fragmenter = self.outgoing_fragmenter();
+            let iter = fragmenter.fragment_message(msg);
             for m in iter {
                 self.queue_tls_message(m.to_unencrypted_opaque());
             }
         } else {
             self.send_msg_encrypt(m.into());
         }
+        self.flush_thresholds.urgent = true;
     }
 
     pub(crate) fn take_received_plaintext(&mut self, bytes: Payload) {
         self.received_plaintext.append(bytes.0);
+        self.read_watermarks
+            .update(self.received_plaintext.len());
     }
 
     #[cfg(feature = "tls12")]
     pub(crate) fn start_encryption_tls12(&mut self, secrets: &ConnectionSecrets, side: Side) {
         let (dec, enc) = secrets.make_cipher_pair(side);
         self.record_layer
-            .prepare_message_encrypter(enc);
+            .prepare_message_encrypter(
+                enc,
+                secrets
+                    .suite()
+                    .common
+                    .confidentiality_limit,
+            );
         self.record_layer
             .prepare_message_decrypter(dec);
     }
@@ -430,6 +1099,10 @@ impl CommonState {
     }
 
     pub(crate) fn process_alert(&mut self, alert: &AlertMessagePayload) -> Result<(), Error> {
+        if let Some(observer) = &self.alert_observer {
+            observer.alert_received(self.connection_id(), alert.description);
+        }
+
         // Reject unknown AlertLevels.
         if let AlertLevel::Unknown(_) = alert.level {
             return Err(self.send_fatal_alert(
@@ -464,7 +1137,10 @@ impl CommonState {
         self.send_fatal_alert(
             match &err {
                 Error::InvalidCertificate(e) => e.clone().into(),
-                Error::PeerMisbehaved(_) => AlertDescription::IllegalParameter,
+                Error::PeerMisbehaved(_) | Error::UnsupportedSignatureScheme { .. } => {
+                    AlertDescription::IllegalParameter
+                }
+                Error::UnsupportedKeyAlgorithm => AlertDescription::UnsupportedCertificate,
                 _ => AlertDescription::HandshakeFailure,
             },
             err,
@@ -477,6 +1153,7 @@ impl CommonState {
         err: impl Into<Error>,
     ) -> Error {
         debug_assert!(!self.sent_fatal_alert);
+        self.observe_sent_alert(desc);
         let m = Message::build_alert(AlertLevel::Fatal, desc);
         self.send_msg(m, self.record_layer.is_encrypting());
         self.sent_fatal_alert = true;
@@ -493,16 +1170,70 @@ impl CommonState {
         self.send_warning_alert_no_log(AlertDescription::CloseNotify);
     }
 
+    pub(crate) fn send_alert(&mut self, level: AlertLevel, desc: AlertDescription) {
+        warn!("Sending {:?} alert {:?}", level, desc);
+        self.observe_sent_alert(desc);
+        let m = Message::build_alert(level, desc);
+        self.send_msg(m, self.record_layer.is_encrypting());
+        if level == AlertLevel::Fatal {
+            self.sent_fatal_alert = true;
+        }
+    }
+
     fn send_warning_alert_no_log(&mut self, desc: AlertDescription) {
+        self.observe_sent_alert(desc);
         let m = Message::build_alert(AlertLevel::Warning, desc);
         self.send_msg(m, self.record_layer.is_encrypting());
     }
 
+    fn observe_sent_alert(&self, desc: AlertDescription) {
+        if let Some(observer) = &self.alert_observer {
+            observer.alert_sent(self.connection_id(), desc);
+        }
+    }
+
     pub(crate) fn set_max_fragment_size(&mut self, new: Option<usize>) -> Result<(), Error> {
         self.message_fragmenter
             .set_max_fragment_size(new)
     }
 
+    /// Limit the size of every TLS record written from now on to `max_record_size`
+    /// bytes, including the record header and any encryption overhead.
+    ///
+    /// This is intended for transports that must fit each record into a single
+    /// datagram of a known path MTU.  It applies to this connection only, and is
+    /// independent of the `max_fragment_size` configured for it: records are
+    /// split to satisfy both.
+    ///
+    /// `None`, the default, removes the limit.  Returns `BadMaxFragmentSize`
+    /// if `max_record_size` is smaller than 64 bytes.
+    pub fn set_max_record_size(&mut self, max_record_size: Option<usize>) -> Result<(), Error> {
+        match max_record_size {
+            Some(sz) if sz < MIN_RECORD_SIZE => Err(Error::BadMaxFragmentSize),
+            _ => {
+                self.max_record_size = max_record_size;
+                Ok(())
+            }
+        }
+    }
+
+    /// The largest record payload, with padding, that keeps encrypted records
+    /// within `max_record_size`.
+    fn max_record_payload_len(&self) -> usize {
+        match self.max_record_size {
+            Some(sz) => sz
+                .saturating_sub(PACKET_OVERHEAD + self.record_layer.encryption_overhead())
+                .max(1),
+            None => usize::MAX,
+        }
+    }
+
+    /// Our fragmenter, further bounded by `max_record_size` for the current keys.
+    fn outgoing_fragmenter(&self) -> MessageFragmenter {
+        self.message_fragmenter
+            .bounded_to(self.max_record_payload_len())
+    }
+
     pub(crate) fn get_alpn_protocol(&self) -> Option<&[u8]> {
         self.alpn_protocol
             .as_ref()
@@ -567,7 +1298,9 @@ impl CommonState {
 
     pub(crate) fn perhaps_write_key_update(&mut self) {
         if let Some(message) = self.queued_key_update_message.take() {
+            self.start_queueing();
             self.sendable_tls.append(message);
+            self.flush_thresholds.urgent = true;
         }
     }
 }
@@ -630,6 +1363,16 @@ pub(crate) trait State<Data>: Send + Sync {
     fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
         Err(Error::HandshakeNotComplete)
     }
+
+    /// The TLS1.3 resumption master secret, once the handshake is complete.
+    fn resumption_master_secret(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Replace our traffic keys, telling the peer with a `KeyUpdate` message.
+    fn send_key_update(&mut self, _common: &mut CommonState) -> Result<(), Error> {
+        Err(Error::HandshakeNotComplete)
+    }
 }
 
 pub(crate) struct Context<'a, Data> {
@@ -637,6 +1380,94 @@ pub(crate) struct Context<'a, Data> {
     pub(crate) data: &'a mut Data,
 }
 
+/// How much padding to add to outgoing TLS1.3 records.
+///
+/// Padding hides the true length of the record contents from observers.  It is
+/// only applied once TLS1.3 has been negotiated, and never makes a record's
+/// contents and padding longer than the 16KB maximum fragment size.
+#[derive(Clone, Copy, Debug)]
+pub enum RecordPadding {
+    /// Don't add padding.
+    None,
+    /// Add exactly this many zero bytes.
+    Fixed(usize),
+    /// Add a random number of zero bytes, from zero up to and including this many.
+    UpTo(usize),
+    /// Add as many zero bytes as needed to make the record contents, including
+    /// the content type byte, a multiple of this many bytes.
+    ///
+    /// Records near the maximum fragment size are padded less, if at all.
+    /// Zero adds no padding.
+    AlignTo(usize),
+    /// Call this function with the length of the record contents, and add as many
+    /// zero bytes as it returns.
+    Callback(fn(usize) -> usize),
+}
+
+/// Describes which sort of handshake happened.
+///
+/// Returned by [`CommonState::handshake_kind()`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HandshakeKind {
+    /// A full handshake, with the peer authenticated from scratch.
+    Full,
+    /// A resumption of a previous session, using the given mechanism.
+    Resumed(ResumptionKind),
+    /// A TLS1.3 handshake authenticated by an externally-provisioned
+    /// pre-shared key, rather than by certificates.
+    ExternalPsk,
+}
+
+/// The mechanism by which a session was resumed.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ResumptionKind {
+    /// TLS1.3 resumption using a pre-shared key from a previous ticket.
+    Tls13Psk,
+    /// TLS1.2 resumption using a session ID stored by the server.
+    Tls12SessionId,
+    /// TLS1.2 resumption using a session ticket (RFC 5077).
+    Tls12Ticket,
+}
+
+/// An ALPN protocol identifier, with well-known protocols given their own variants.
+///
+/// Returned by [`CommonState::alpn_protocol_typed()`].
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AlpnProtocol {
+    /// HTTP/1.1, identified by `http/1.1`.
+    Http11,
+    /// HTTP/2 over TLS, identified by `h2`.
+    H2,
+    /// HTTP/3, identified by `h3`.
+    H3,
+    /// Any other protocol.
+    Other(Vec<u8>),
+}
+
+impl AlpnProtocol {
+    /// Classify the protocol identifier `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match bytes {
+            b"http/1.1" => Self::Http11,
+            b"h2" => Self::H2,
+            b"h3" => Self::H3,
+            other => Self::Other(other.to_vec()),
+        }
+    }
+
+    /// Returns the protocol identifier, as sent in the ALPN extension.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Http11 => b"http/1.1",
+            Self::H2 => b"h2",
+            Self::H3 => b"h3",
+            Self::Other(bytes) => bytes,
+        }
+    }
+}
+
 /// Side of the connection.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Side {
@@ -668,3 +1499,28 @@ enum Limit {
 
 const DEFAULT_RECEIVED_PLAINTEXT_LIMIT: usize = 16 * 1024;
 const DEFAULT_BUFFER_LIMIT: usize = 64 * 1024;
+
+/// The smallest limit accepted by [`CommonState::set_max_record_size`], leaving
+/// room for a useful payload after the record header and encryption overhead.
+const MIN_RECORD_SIZE: usize = 64;
+
+/// The `verify_data` carried by `msg`, if it is a Finished message.
+fn finished_verify_data(msg: &Message) -> Option<&[u8]> {
+    match &msg.payload {
+        MessagePayload::Handshake {
+            parsed:
+                HandshakeMessagePayload {
+                    payload: HandshakePayload::Finished(verify_data),
+                    ..
+                },
+            ..
+        } => Some(&verify_data.0),
+        _ => None,
+    }
+}
+
+/// A heartbeat message starts with its type and the payload length.
+const HEARTBEAT_HEADER_LEN: usize = 1 + 2;
+
+/// The amount of random padding we send, which is also the minimum we accept.
+const HEARTBEAT_PADDING_LEN: usize = 16;
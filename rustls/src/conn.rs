@@ -1,15 +1,23 @@
-use crate::common_state::{CommonState, Context, IoState, State};
+use crate::common_state::{CommonState, Context, IoState, ReadWatermarks, State};
 use crate::enums::{AlertDescription, ContentType};
 use crate::error::{Error, PeerMisbehaved};
 #[cfg(feature = "logging")]
 use crate::log::trace;
+#[cfg(feature = "plain_messages")]
+use crate::msgs::alert::AlertMessagePayload;
+#[cfg(feature = "plain_messages")]
+use crate::msgs::codec::{Codec, Reader as CodecReader};
 use crate::msgs::deframer::{Deframed, MessageDeframer};
+use crate::msgs::enums::AlertLevel;
 use crate::msgs::handshake::Random;
+#[cfg(feature = "plain_messages")]
+use crate::msgs::message::OpaqueMessage;
 use crate::msgs::message::{Message, MessagePayload, PlainMessage};
 use crate::suites::{ExtractedSecrets, PartiallyExtractedSecrets};
 use crate::vecbuf::ChunkVecBuffer;
 
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::fmt::Debug;
 use core::mem;
 use core::ops::{Deref, DerefMut};
@@ -58,6 +66,16 @@ impl Connection {
         }
     }
 
+    /// Encrypts all of `data` into TLS records.
+    ///
+    /// See [`ConnectionCommon::write_all_records()`] for more information.
+    pub fn write_all_records(&mut self, data: &[u8]) {
+        match self {
+            Self::Client(conn) => conn.write_all_records(data),
+            Self::Server(conn) => conn.write_all_records(data),
+        }
+    }
+
     /// Processes any new packets read by a previous call to [`Connection::read_tls`].
     ///
     /// See [`ConnectionCommon::process_new_packets()`] for more information.
@@ -83,6 +101,16 @@ impl Connection {
         }
     }
 
+    /// Returns the TLS1.3 resumption master secret.
+    ///
+    /// See [`ConnectionCommon::resumption_master_secret()`] for more information.
+    pub fn resumption_master_secret(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::Client(conn) => conn.resumption_master_secret(),
+            Self::Server(conn) => conn.resumption_master_secret(),
+        }
+    }
+
     /// This function uses `io` to complete any outstanding IO for this connection.
     ///
     /// See [`ConnectionCommon::complete_io()`] for more information.
@@ -97,6 +125,38 @@ impl Connection {
         }
     }
 
+    /// Exports the resumption state of the most recent session, for client connections.
+    ///
+    /// Server connections always return `None`: servers keep their resumption
+    /// state in [`ServerConfig::session_storage`] or [`ServerConfig::ticketer`].
+    ///
+    /// See [`ClientConnection::export_session_state()`] for more information,
+    /// including how to import the state and the fact that it contains secrets.
+    ///
+    /// [`ServerConfig::session_storage`]: crate::ServerConfig::session_storage
+    /// [`ServerConfig::ticketer`]: crate::ServerConfig::ticketer
+    /// [`ClientConnection::export_session_state()`]: crate::ClientConnection::export_session_state
+    pub fn export_session_state(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::Client(conn) => conn.export_session_state(),
+            Self::Server(_) => None,
+        }
+    }
+
+    /// Sends an arbitrary alert to the peer.
+    ///
+    /// See [`ConnectionCommon::dangerous_send_alert()`] for more information.
+    pub fn dangerous_send_alert(
+        &mut self,
+        level: AlertLevel,
+        description: AlertDescription,
+    ) -> Result<(), Error> {
+        match self {
+            Self::Client(conn) => conn.dangerous_send_alert(level, description),
+            Self::Server(conn) => conn.dangerous_send_alert(level, description),
+        }
+    }
+
     /// Extract secrets, so they can be used when configuring kTLS, for example.
     /// Should be used with care as it exposes secret key material.
     pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
@@ -132,6 +192,8 @@ pub struct Reader<'a> {
     received_plaintext: &'a mut ChunkVecBuffer,
     peer_cleanly_closed: bool,
     has_seen_eof: bool,
+    preserve_record_boundaries: bool,
+    read_watermarks: &'a mut ReadWatermarks,
 }
 
 impl<'a> io::Read for Reader<'a> {
@@ -155,8 +217,18 @@ impl<'a> io::Read for Reader<'a> {
     ///
     /// You may learn the number of bytes available at any time by inspecting
     /// the return of [`Connection::process_new_packets`].
+    ///
+    /// If `preserve_record_boundaries` is set in the connection's config, a
+    /// single call never returns data from more than one TLS record.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let len = self.received_plaintext.read(buf)?;
+        let len = match self.preserve_record_boundaries {
+            true => self
+                .received_plaintext
+                .read_chunk(buf)?,
+            false => self.received_plaintext.read(buf)?,
+        };
+        self.read_watermarks
+            .update(self.received_plaintext.len());
 
         if len == 0 && !buf.is_empty() {
             // No bytes available:
@@ -203,9 +275,17 @@ impl<'a> io::Read for Reader<'a> {
     #[cfg(read_buf)]
     fn read_buf(&mut self, mut cursor: core::io::BorrowedCursor<'_>) -> io::Result<()> {
         let before = cursor.written();
-        self.received_plaintext
-            .read_buf(cursor.reborrow())?;
+        match self.preserve_record_boundaries {
+            true => self
+                .received_plaintext
+                .read_buf_chunk(cursor.reborrow())?,
+            false => self
+                .received_plaintext
+                .read_buf(cursor.reborrow())?,
+        }
         let len = cursor.written() - before;
+        self.read_watermarks
+            .update(self.received_plaintext.len());
 
         if len == 0 && cursor.capacity() > 0 {
             // No bytes available:
@@ -242,18 +322,22 @@ pub(crate) trait PlaintextSink {
 
 impl<T> PlaintextSink for ConnectionCommon<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        Ok(self.send_some_plaintext(buf))
+        let len = self.send_some_plaintext(buf);
+        self.core.maybe_refresh_traffic_keys();
+        Ok(len)
     }
 
     fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
         let mut sz = 0;
         for buf in bufs {
             sz += self.send_some_plaintext(buf);
+            self.core.maybe_refresh_traffic_keys();
         }
         Ok(sz)
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        self.request_flush();
         Ok(())
     }
 }
@@ -341,6 +425,8 @@ impl<Data> ConnectionCommon<Data> {
             peer_cleanly_closed: common.has_received_close_notify
                 && !self.core.message_deframer.has_pending(),
             has_seen_eof: common.has_seen_eof,
+            preserve_record_boundaries: common.preserve_record_boundaries,
+            read_watermarks: &mut common.read_watermarks,
         }
     }
 
@@ -349,6 +435,21 @@ impl<Data> ConnectionCommon<Data> {
         Writer::new(self)
     }
 
+    /// Encrypts all of `data` into TLS records, queueing them to be sent
+    /// with [`Connection::write_tls`].
+    ///
+    /// Unlike writing through [`ConnectionCommon::writer`], this ignores the
+    /// limit set with [`CommonState::set_buffer_limit`], so a large buffer is
+    /// fragmented and encrypted in one pass rather than over many calls.  The
+    /// caller is then responsible for bounding how much data it queues.
+    ///
+    /// Before the handshake completes, `data` is buffered and sent once it is
+    /// done, as with [`ConnectionCommon::writer`].
+    pub fn write_all_records(&mut self, data: &[u8]) {
+        self.send_all_plaintext(data);
+        self.core.maybe_refresh_traffic_keys();
+    }
+
     /// This function uses `io` to complete any outstanding IO for
     /// this connection.
     ///
@@ -489,6 +590,48 @@ impl<Data> ConnectionCommon<Data> {
         self.core.process_new_packets()
     }
 
+    /// Restricts the content types of records accepted from the peer.
+    ///
+    /// Once set, records with a content type not in `types` are rejected with
+    /// an `unexpected_message` alert as soon as their header is read, before any
+    /// decryption.  This applies to the outer content type on the wire: in TLS1.3
+    /// every encrypted record appears as `ApplicationData`.  Pass `None` to
+    /// accept all content types again, which is the default.
+    pub fn accept_content_types(&mut self, types: Option<Vec<ContentType>>) {
+        self.core
+            .message_deframer
+            .set_accepted_content_types(types);
+    }
+
+    /// Sets what to do with records carrying an unexpected outer version, once
+    /// TLS1.3 has been negotiated.
+    ///
+    /// The default is [`RecordVersionPolicy::Warn`].
+    pub fn set_record_version_policy(&mut self, policy: RecordVersionPolicy) {
+        self.core
+            .message_deframer
+            .set_record_version_policy(policy);
+    }
+
+    /// Processes a single message which was deframed and decrypted outside of
+    /// this connection, for example by a kernel TLS offload.
+    ///
+    /// This runs the same handshake and application data logic as
+    /// [`Connection::process_new_packets`], bypassing the internal deframer and
+    /// decrypter.  Rather than leaving output in the connection's buffers, this
+    /// returns everything the message caused as a list of [`CoreEffect`]s.
+    ///
+    /// Any TLS records queued for sending are drained into a
+    /// [`CoreEffect::EmitRecords`], and any received plaintext into a
+    /// [`CoreEffect::DeliverAppData`].  If the message was fatal to the
+    /// connection, the last effect is a [`CoreEffect::Error`] and future calls do
+    /// no new work, in the same way as errors from
+    /// [`Connection::process_new_packets`].
+    #[cfg(feature = "plain_messages")]
+    pub fn process_message(&mut self, msg: PlainMessage) -> Vec<CoreEffect> {
+        self.core.process_message(msg)
+    }
+
     /// Read TLS content from `rd` into the internal buffer.
     ///
     /// Due to the internal buffering, `rd` can supply TLS messages in arbitrary-sized chunks (like
@@ -562,6 +705,62 @@ impl<Data> ConnectionCommon<Data> {
             .export_keying_material(output, label, context)
     }
 
+    /// Returns the TLS1.3 resumption master secret.
+    ///
+    /// This is for layered protocols that derive their own pre-shared keys
+    /// from it, as described in RFC8446 section 4.6.1.  Both peers obtain the
+    /// same value.
+    ///
+    /// The value is secret: anyone holding it can resume sessions established
+    /// from this connection, so it must be handled with the same care as
+    /// the connection's traffic keys.  For this reason it is only available
+    /// when `enable_secret_extraction` is set in the connection's config.
+    ///
+    /// Returns `None` if secret extraction is disabled, the handshake is not
+    /// yet complete, the connection has failed, or TLS1.2 was negotiated.
+    pub fn resumption_master_secret(&self) -> Option<Vec<u8>> {
+        if !self.enable_secret_extraction {
+            return None;
+        }
+
+        self.core
+            .state
+            .as_ref()
+            .ok()?
+            .resumption_master_secret()
+    }
+
+    /// Queues an arbitrary alert to be sent in the next
+    /// [`Connection::write_tls`] call, regardless of the protocol state.
+    ///
+    /// This exists for testing peers' handling of unexpected alerts and
+    /// should never be needed in normal use; see [`CommonState::send_close_notify`]
+    /// for ending a connection cleanly.
+    ///
+    /// Sending a fatal alert ends the connection: later calls to
+    /// [`Connection::process_new_packets`] return [`Error::AlertSent`].
+    /// This function fails with the connection's error if it has already failed.
+    ///
+    /// [`Connection::write_tls`]: crate::Connection::write_tls
+    /// [`Connection::process_new_packets`]: crate::Connection::process_new_packets
+    pub fn dangerous_send_alert(
+        &mut self,
+        level: AlertLevel,
+        description: AlertDescription,
+    ) -> Result<(), Error> {
+        if let Err(e) = &self.core.state {
+            return Err(e.clone());
+        }
+
+        self.core
+            .common_state
+            .send_alert(level, description);
+        if level == AlertLevel::Fatal {
+            self.core.state = Err(Error::AlertSent(description));
+        }
+        Ok(())
+    }
+
     /// Extract secrets, so they can be used when configuring kTLS, for example.
     /// Should be used with care as it exposes secret key material.
     pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
@@ -617,6 +816,28 @@ pub(crate) struct ConnectionCore<Data> {
 }
 
 impl<Data> ConnectionCore<Data> {
+    /// Replace our TLS1.3 traffic keys whenever they reach their usage limit,
+    /// and send the application data which was waiting for the new keys.
+    pub(crate) fn maybe_refresh_traffic_keys(&mut self) {
+        while self
+            .common_state
+            .wants_traffic_key_refresh()
+        {
+            let refreshed = match &mut self.state {
+                Ok(state) => state
+                    .send_key_update(&mut self.common_state)
+                    .is_ok(),
+                Err(_) => false,
+            };
+            // Only fails before the handshake completes, when the
+            // application data keys are not yet in use.
+            if !refreshed {
+                return;
+            }
+            self.common_state.flush_plaintext();
+        }
+    }
+
     pub(crate) fn new(state: Box<dyn State<Data>>, data: Data, common_state: CommonState) -> Self {
         Self {
             state: Ok(state),
@@ -646,9 +867,84 @@ impl<Data> ConnectionCore<Data> {
         }
 
         self.state = Ok(state);
+        // Handling messages can send records, including buffered application
+        // data once the handshake completes.
+        self.maybe_refresh_traffic_keys();
         Ok(self.common_state.current_io_state())
     }
 
+    #[cfg(feature = "plain_messages")]
+    pub(crate) fn process_message(&mut self, msg: PlainMessage) -> Vec<CoreEffect> {
+        let mut effects = Vec::new();
+        let was_handshaking = self.common_state.is_handshaking();
+
+        let received_alert = match msg.typ {
+            ContentType::Alert => AlertMessagePayload::read_bytes(&msg.payload.0)
+                .ok()
+                .map(|alert| alert.description),
+            _ => None,
+        };
+
+        let result = match mem::replace(&mut self.state, Err(Error::HandshakeNotComplete)) {
+            Ok(state) => self.process_msg(msg, state),
+            Err(e) => Err(e),
+        };
+
+        let mut result = result;
+        let mut records = Vec::new();
+        while let Some(chunk) = self.common_state.sendable_tls.pop() {
+            let mut rd = CodecReader::init(&chunk);
+            while rd.any_left() {
+                match OpaqueMessage::read(&mut rd) {
+                    Ok(m) => records.push(m),
+                    Err(err) => {
+                        // Everything we queued should parse; failing that, the
+                        // rest of the output cannot be trusted either.
+                        result = Err(Error::InvalidMessage(err.into()));
+                        break;
+                    }
+                }
+            }
+        }
+        if !records.is_empty() {
+            effects.push(CoreEffect::EmitRecords(records));
+        }
+
+        let mut app_data = Vec::new();
+        while let Some(chunk) = self
+            .common_state
+            .received_plaintext
+            .pop()
+        {
+            app_data.extend_from_slice(&chunk);
+        }
+        self.common_state
+            .read_watermarks
+            .update(0);
+        if !app_data.is_empty() {
+            effects.push(CoreEffect::DeliverAppData(app_data));
+        }
+
+        if let Some(description) = received_alert {
+            effects.push(CoreEffect::Alert(description));
+        }
+
+        match result {
+            Ok(state) => {
+                self.state = Ok(state);
+                if was_handshaking && !self.common_state.is_handshaking() {
+                    effects.push(CoreEffect::HandshakeComplete);
+                }
+            }
+            Err(e) => {
+                self.state = Err(e.clone());
+                effects.push(CoreEffect::Error(e));
+            }
+        }
+
+        effects
+    }
+
     /// Pull a message out of the deframer and send any messages that need to be sent as a result.
     fn deframe(&mut self) -> Result<Option<PlainMessage>, Error> {
         match self.message_deframer.pop(
@@ -693,6 +989,12 @@ impl<Data> ConnectionCore<Data> {
             Err(err @ Error::DecryptError) => Err(self
                 .common_state
                 .send_fatal_alert(AlertDescription::BadRecordMac, err)),
+            Err(err @ Error::InappropriateMessage { .. }) => Err(self
+                .common_state
+                .send_fatal_alert(AlertDescription::UnexpectedMessage, err)),
+            Err(err @ Error::PeerMisbehaved(PeerMisbehaved::UnexpectedVersion)) => Err(self
+                .common_state
+                .send_fatal_alert(AlertDescription::IllegalParameter, err)),
             Err(e) => Err(e),
         }
     }
@@ -726,6 +1028,18 @@ impl<Data> ConnectionCore<Data> {
             }
         }
 
+        // Heartbeats are handled outside the main protocol, once negotiated.
+        if msg.typ == ContentType::Heartbeat
+            && self
+                .common_state
+                .peer_heartbeat_mode
+                .is_some()
+        {
+            self.common_state
+                .process_heartbeat(&msg.payload.0)?;
+            return Ok(state);
+        }
+
         // Now we can fully parse the message payload.
         let msg = match Message::try_from(msg) {
             Ok(msg) => msg,
@@ -767,8 +1081,67 @@ impl<Data> ConnectionCore<Data> {
     }
 }
 
+/// What to do with a record whose outer version is not `0x0303` after TLS1.3
+/// has been negotiated.
+///
+/// RFC8446 requires this version for every record other than an initial
+/// `ClientHello`, so anything else suggests tampering or a broken middlebox.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordVersionPolicy {
+    /// Log a warning and process the record as normal.
+    Warn,
+    /// Fail the connection with [`PeerMisbehaved::UnexpectedVersion`], sending
+    /// an `illegal_parameter` alert.
+    Reject,
+}
+
+impl Default for RecordVersionPolicy {
+    fn default() -> Self {
+        Self::Warn
+    }
+}
+
+/// An output of [`ConnectionCommon::process_message`].
+#[cfg(feature = "plain_messages")]
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum CoreEffect {
+    /// These TLS records should be sent to the peer, in order.
+    EmitRecords(Vec<OpaqueMessage>),
+    /// The peer sent this application data.
+    DeliverAppData(Vec<u8>),
+    /// The handshake completed.
+    HandshakeComplete,
+    /// The peer sent an alert with this description.
+    Alert(AlertDescription),
+    /// The connection failed with this error.
+    Error(Error),
+}
+
 /// Data specific to the peer's side (client or server).
 pub trait SideData: Debug {}
 
+/// Move `client`'s write sequence number and `server`'s read sequence number to
+/// `seq`, as if that many records had already been sent from one to the other.
+///
+/// This lets tests reach the record layer's sequence number limits without
+/// exchanging that many records.
+pub fn advance_client_seq(
+    client: &mut ConnectionCommon<crate::client::ClientConnectionData>,
+    server: &mut ConnectionCommon<crate::server::ServerConnectionData>,
+    seq: u64,
+) {
+    client
+        .core
+        .common_state
+        .record_layer
+        .set_write_seq(seq);
+    server
+        .core
+        .common_state
+        .record_layer
+        .set_read_seq(seq);
+}
+
 const UNEXPECTED_EOF_MESSAGE: &str = "peer closed connection without sending TLS close_notify: \
 https://docs.rs/rustls/latest/rustls/manual/_03_howto/index.html#unexpected-eof";
@@ -1,6 +1,6 @@
 use crate::crypto::cipher::{
-    make_tls12_aad, AeadKey, Iv, KeyBlockShape, MessageDecrypter, MessageEncrypter, Nonce,
-    Tls12AeadAlgorithm, UnsupportedOperationError, NONCE_LEN,
+    make_tls12_aad, AadDirection, AeadKey, Iv, KeyBlockShape, MessageDecrypter, MessageEncrypter,
+    Nonce, ObservesAad, Tls12AeadAlgorithm, UnsupportedOperationError, NONCE_LEN,
 };
 use crate::crypto::tls12::Prf;
 use crate::crypto::{ActiveKeyExchange, KeyExchangeAlgorithm};
@@ -12,6 +12,7 @@ use crate::suites::{CipherSuiteCommon, ConnectionTrafficSecrets, SupportedCipher
 use crate::tls12::Tls12CipherSuite;
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use aws_lc_rs::{aead, tls_prf};
@@ -137,6 +138,7 @@ impl Tls12AeadAlgorithm for GcmAlgorithm {
         let mut ret = GcmMessageDecrypter {
             dec_key,
             dec_salt: [0u8; 4],
+            aad_observer: None,
         };
 
         debug_assert_eq!(dec_iv.len(), 4);
@@ -166,7 +168,11 @@ impl Tls12AeadAlgorithm for GcmAlgorithm {
             aead::TlsRecordSealingKey::new(self.0, aead::TlsProtocolId::TLS13, enc_key.as_ref())
                 .unwrap();
         let iv = gcm_iv(write_iv, explicit);
-        Box::new(GcmMessageEncrypter { enc_key, iv })
+        Box::new(GcmMessageEncrypter {
+            enc_key,
+            iv,
+            aad_observer: None,
+        })
     }
 
     fn key_block_shape(&self) -> KeyBlockShape {
@@ -200,6 +206,7 @@ impl Tls12AeadAlgorithm for ChaCha20Poly1305 {
         Box::new(ChaCha20Poly1305MessageDecrypter {
             dec_key,
             dec_offset: Iv::copy(iv),
+            aad_observer: None,
         })
     }
 
@@ -210,6 +217,7 @@ impl Tls12AeadAlgorithm for ChaCha20Poly1305 {
         Box::new(ChaCha20Poly1305MessageEncrypter {
             enc_key,
             enc_offset: Iv::copy(enc_iv),
+            aad_observer: None,
         })
     }
 
@@ -240,12 +248,14 @@ impl Tls12AeadAlgorithm for ChaCha20Poly1305 {
 struct GcmMessageEncrypter {
     enc_key: aead::TlsRecordSealingKey,
     iv: Iv,
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 /// A `MessageDecrypter` for AES-GCM AEAD ciphersuites.  TLS1.2 only.
 struct GcmMessageDecrypter {
     dec_key: aead::TlsRecordOpeningKey,
     dec_salt: [u8; 4],
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 const GCM_EXPLICIT_NONCE_LEN: usize = 8;
@@ -265,19 +275,22 @@ impl MessageDecrypter for GcmMessageDecrypter {
             aead::Nonce::assume_unique_for_key(nonce)
         };
 
-        let aad = aead::Aad::from(make_tls12_aad(
-            seq,
-            msg.typ,
-            msg.version,
-            payload.len() - GCM_OVERHEAD,
-        ));
+        let aad = make_tls12_aad(seq, msg.typ, msg.version, payload.len() - GCM_OVERHEAD);
 
         let payload = msg.payload_mut();
         let plain_len = self
             .dec_key
-            .open_within(nonce, aad, payload, GCM_EXPLICIT_NONCE_LEN..)
+            .open_within(
+                nonce,
+                aead::Aad::from(aad),
+                payload,
+                GCM_EXPLICIT_NONCE_LEN..,
+            )
             .map_err(|_| Error::DecryptError)?
             .len();
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Decrypt);
+        }
 
         if plain_len > MAX_FRAGMENT_LEN {
             return Err(Error::PeerSentOversizedRecord);
@@ -286,12 +299,16 @@ impl MessageDecrypter for GcmMessageDecrypter {
         payload.truncate(plain_len);
         Ok(msg.into_plain_message())
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 impl MessageEncrypter for GcmMessageEncrypter {
     fn encrypt(&mut self, msg: BorrowedPlainMessage, seq: u64) -> Result<OpaqueMessage, Error> {
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.iv, seq).0);
-        let aad = aead::Aad::from(make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len()));
+        let aad = make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len());
 
         let total_len = self.encrypted_payload_len(msg.payload.len());
         let mut payload = Vec::with_capacity(total_len);
@@ -299,9 +316,16 @@ impl MessageEncrypter for GcmMessageEncrypter {
         payload.extend_from_slice(msg.payload);
 
         self.enc_key
-            .seal_in_place_separate_tag(nonce, aad, &mut payload[GCM_EXPLICIT_NONCE_LEN..])
+            .seal_in_place_separate_tag(
+                nonce,
+                aead::Aad::from(aad),
+                &mut payload[GCM_EXPLICIT_NONCE_LEN..],
+            )
             .map(|tag| payload.extend(tag.as_ref()))
             .map_err(|_| Error::EncryptError)?;
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Encrypt);
+        }
 
         Ok(OpaqueMessage::new(msg.typ, msg.version, payload))
     }
@@ -309,6 +333,10 @@ impl MessageEncrypter for GcmMessageEncrypter {
     fn encrypted_payload_len(&self, payload_len: usize) -> usize {
         payload_len + GCM_EXPLICIT_NONCE_LEN + self.enc_key.algorithm().tag_len()
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 /// The RFC7905/RFC7539 ChaCha20Poly1305 construction.
@@ -317,6 +345,7 @@ impl MessageEncrypter for GcmMessageEncrypter {
 struct ChaCha20Poly1305MessageEncrypter {
     enc_key: aead::LessSafeKey,
     enc_offset: Iv,
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 /// The RFC7905/RFC7539 ChaCha20Poly1305 construction.
@@ -325,6 +354,7 @@ struct ChaCha20Poly1305MessageEncrypter {
 struct ChaCha20Poly1305MessageDecrypter {
     dec_key: aead::LessSafeKey,
     dec_offset: Iv,
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 const CHACHAPOLY1305_OVERHEAD: usize = 16;
@@ -338,19 +368,22 @@ impl MessageDecrypter for ChaCha20Poly1305MessageDecrypter {
         }
 
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.dec_offset, seq).0);
-        let aad = aead::Aad::from(make_tls12_aad(
+        let aad = make_tls12_aad(
             seq,
             msg.typ,
             msg.version,
             payload.len() - CHACHAPOLY1305_OVERHEAD,
-        ));
+        );
 
         let payload = msg.payload_mut();
         let plain_len = self
             .dec_key
-            .open_in_place(nonce, aad, payload)
+            .open_in_place(nonce, aead::Aad::from(aad), payload)
             .map_err(|_| Error::DecryptError)?
             .len();
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Decrypt);
+        }
 
         if plain_len > MAX_FRAGMENT_LEN {
             return Err(Error::PeerSentOversizedRecord);
@@ -359,20 +392,27 @@ impl MessageDecrypter for ChaCha20Poly1305MessageDecrypter {
         payload.truncate(plain_len);
         Ok(msg.into_plain_message())
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 impl MessageEncrypter for ChaCha20Poly1305MessageEncrypter {
     fn encrypt(&mut self, msg: BorrowedPlainMessage, seq: u64) -> Result<OpaqueMessage, Error> {
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.enc_offset, seq).0);
-        let aad = aead::Aad::from(make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len()));
+        let aad = make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len());
 
         let total_len = self.encrypted_payload_len(msg.payload.len());
         let mut buf = Vec::with_capacity(total_len);
         buf.extend_from_slice(msg.payload);
 
         self.enc_key
-            .seal_in_place_append_tag(nonce, aad, &mut buf)
+            .seal_in_place_append_tag(nonce, aead::Aad::from(aad), &mut buf)
             .map_err(|_| Error::EncryptError)?;
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Encrypt);
+        }
 
         Ok(OpaqueMessage::new(msg.typ, msg.version, buf))
     }
@@ -380,6 +420,10 @@ impl MessageEncrypter for ChaCha20Poly1305MessageEncrypter {
     fn encrypted_payload_len(&self, payload_len: usize) -> usize {
         payload_len + self.enc_key.algorithm().tag_len()
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 fn gcm_iv(write_iv: &[u8], explicit: &[u8]) -> Iv {
@@ -1,10 +1,11 @@
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use crate::crypto;
 use crate::crypto::cipher::{
-    make_tls13_aad, AeadKey, Iv, MessageDecrypter, MessageEncrypter, Nonce, Tls13AeadAlgorithm,
-    UnsupportedOperationError,
+    make_tls13_aad, AadDirection, AeadKey, Iv, MessageDecrypter, MessageEncrypter, Nonce,
+    ObservesAad, Tls13AeadAlgorithm, UnsupportedOperationError,
 };
 use crate::crypto::tls13::{Hkdf, HkdfExpander, OkmBlock, OutputLengthError};
 use crate::enums::{CipherSuite, ContentType, ProtocolVersion};
@@ -82,6 +83,7 @@ impl Tls13AeadAlgorithm for Chacha20Poly1305Aead {
                 aead::UnboundKey::new(self.0 .0, key.as_ref()).unwrap(),
             ),
             iv,
+            aad_observer: None,
         })
     }
 
@@ -92,6 +94,7 @@ impl Tls13AeadAlgorithm for Chacha20Poly1305Aead {
                 aead::UnboundKey::new(self.0 .0, key.as_ref()).unwrap(),
             ),
             iv,
+            aad_observer: None,
         })
     }
 
@@ -173,6 +176,7 @@ impl AeadAlgorithm {
             )
             .unwrap(),
             iv,
+            aad_observer: None,
         })
     }
 
@@ -189,6 +193,7 @@ impl AeadAlgorithm {
             )
             .unwrap(),
             iv,
+            aad_observer: None,
         })
     }
 
@@ -200,11 +205,13 @@ impl AeadAlgorithm {
 struct AeadMessageEncrypter {
     enc_key: aead::LessSafeKey,
     iv: Iv,
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 struct AeadMessageDecrypter {
     dec_key: aead::LessSafeKey,
     iv: Iv,
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 impl MessageEncrypter for AeadMessageEncrypter {
@@ -215,10 +222,13 @@ impl MessageEncrypter for AeadMessageEncrypter {
         msg.typ.encode(&mut payload);
 
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.iv, seq).0);
-        let aad = aead::Aad::from(make_tls13_aad(total_len));
+        let aad = make_tls13_aad(total_len);
         self.enc_key
-            .seal_in_place_append_tag(nonce, aad, &mut payload)
+            .seal_in_place_append_tag(nonce, aead::Aad::from(aad), &mut payload)
             .map_err(|_| Error::EncryptError)?;
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Encrypt);
+        }
 
         Ok(OpaqueMessage::new(
             ContentType::ApplicationData,
@@ -230,6 +240,10 @@ impl MessageEncrypter for AeadMessageEncrypter {
     fn encrypted_payload_len(&self, payload_len: usize) -> usize {
         payload_len + 1 + self.enc_key.algorithm().tag_len()
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 impl MessageDecrypter for AeadMessageDecrypter {
@@ -240,21 +254,29 @@ impl MessageDecrypter for AeadMessageDecrypter {
         }
 
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.iv, seq).0);
-        let aad = aead::Aad::from(make_tls13_aad(payload.len()));
+        let aad = make_tls13_aad(payload.len());
         let plain_len = self
             .dec_key
-            .open_in_place(nonce, aad, payload)
+            .open_in_place(nonce, aead::Aad::from(aad), payload)
             .map_err(|_| Error::DecryptError)?
             .len();
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Decrypt);
+        }
 
         payload.truncate(plain_len);
         msg.into_tls13_unpadded_message()
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 struct GcmMessageEncrypter {
     enc_key: aead::TlsRecordSealingKey,
     iv: Iv,
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 impl MessageEncrypter for GcmMessageEncrypter {
@@ -265,10 +287,13 @@ impl MessageEncrypter for GcmMessageEncrypter {
         msg.typ.encode(&mut payload);
 
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.iv, seq).0);
-        let aad = aead::Aad::from(make_tls13_aad(total_len));
+        let aad = make_tls13_aad(total_len);
         self.enc_key
-            .seal_in_place_append_tag(nonce, aad, &mut payload)
+            .seal_in_place_append_tag(nonce, aead::Aad::from(aad), &mut payload)
             .map_err(|_| Error::EncryptError)?;
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Encrypt);
+        }
 
         Ok(OpaqueMessage::new(
             ContentType::ApplicationData,
@@ -280,11 +305,16 @@ impl MessageEncrypter for GcmMessageEncrypter {
     fn encrypted_payload_len(&self, payload_len: usize) -> usize {
         payload_len + 1 + self.enc_key.algorithm().tag_len()
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 struct GcmMessageDecrypter {
     dec_key: aead::TlsRecordOpeningKey,
     iv: Iv,
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 impl MessageDecrypter for GcmMessageDecrypter {
@@ -295,16 +325,23 @@ impl MessageDecrypter for GcmMessageDecrypter {
         }
 
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.iv, seq).0);
-        let aad = aead::Aad::from(make_tls13_aad(payload.len()));
+        let aad = make_tls13_aad(payload.len());
         let plain_len = self
             .dec_key
-            .open_in_place(nonce, aad, payload)
+            .open_in_place(nonce, aead::Aad::from(aad), payload)
             .map_err(|_| Error::DecryptError)?
             .len();
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Decrypt);
+        }
 
         payload.truncate(plain_len);
         msg.into_tls13_unpadded_message()
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 struct RingHkdf(hkdf::Algorithm, hmac::Algorithm);
@@ -1,5 +1,7 @@
 use alloc::boxed::Box;
 use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt;
 use std::error::Error as StdError;
 
@@ -125,6 +127,59 @@ pub trait MessageDecrypter: Send + Sync {
     /// Decrypt the given TLS message `msg`, using the sequence number
     /// `seq` which can be used to derive a unique [`Nonce`].
     fn decrypt(&mut self, msg: OpaqueMessage, seq: u64) -> Result<PlainMessage, Error>;
+
+    /// Report the `additional_data` given to the AEAD for each record this
+    /// successfully decrypts to `observer`.
+    ///
+    /// The default implementation ignores `observer`: records decrypted by
+    /// implementations which do not override this are not reported.
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        let _ = observer;
+    }
+
+    /// Return a [`StreamingDecrypter`] for these keys, if this decrypter can
+    /// decrypt a record as its ciphertext arrives.
+    ///
+    /// The default implementation returns `None`, and records are only
+    /// decrypted once they have been received whole.
+    fn streaming(&mut self) -> Option<&mut dyn StreamingDecrypter> {
+        None
+    }
+}
+
+/// Objects with this trait can decrypt a TLS record whose ciphertext is
+/// supplied in chunks, so that the whole record need not be buffered.
+///
+/// This requires an AEAD which can decrypt incrementally and verify the tag
+/// at the end.  No plaintext may be released before the tag is verified:
+/// [`StreamingDecrypter::update`] only absorbs ciphertext, and the plaintext
+/// is returned by [`StreamingDecrypter::finish`].
+///
+/// The result of a record must be the same as that of
+/// [`MessageDecrypter::decrypt`] for the whole record.
+pub trait StreamingDecrypter {
+    /// Start decrypting a record of `len` bytes of ciphertext, using the
+    /// sequence number `seq`.
+    ///
+    /// `typ` and `version` are taken from the record header.  This abandons
+    /// any record already in progress.
+    fn start(
+        &mut self,
+        typ: ContentType,
+        version: ProtocolVersion,
+        len: usize,
+        seq: u64,
+    ) -> Result<(), Error>;
+
+    /// Decrypt the next chunk of the record's ciphertext.
+    ///
+    /// This fails if more ciphertext is supplied than the record holds.
+    fn update(&mut self, chunk: &[u8]) -> Result<(), Error>;
+
+    /// Verify the record, and return its plaintext.
+    ///
+    /// This fails if the record is incomplete or does not verify.
+    fn finish(&mut self) -> Result<PlainMessage, Error>;
 }
 
 /// Objects with this trait can encrypt TLS messages.
@@ -133,9 +188,35 @@ pub trait MessageEncrypter: Send + Sync {
     /// `seq which can be used to derive a unique [`Nonce`].
     fn encrypt(&mut self, msg: BorrowedPlainMessage, seq: u64) -> Result<OpaqueMessage, Error>;
 
+    /// Encrypt each of the given TLS messages `msgs`, in order, using consecutive
+    /// sequence numbers starting at `first_seq`.
+    ///
+    /// The output must be identical to calling [`MessageEncrypter::encrypt`] for
+    /// each message in turn, which is what the default implementation does.
+    /// Implementations may override this to amortise per-call costs across records.
+    fn encrypt_batch(
+        &mut self,
+        msgs: &[BorrowedPlainMessage],
+        first_seq: u64,
+    ) -> Result<Vec<OpaqueMessage>, Error> {
+        msgs.iter()
+            .zip(first_seq..)
+            .map(|(msg, seq)| self.encrypt(*msg, seq))
+            .collect()
+    }
+
     /// Return the length of the ciphertext that results from encrypting plaintext of
     /// length `payload_len`
     fn encrypted_payload_len(&self, payload_len: usize) -> usize;
+
+    /// Report the `additional_data` given to the AEAD for each record this
+    /// encrypts to `observer`.
+    ///
+    /// The default implementation ignores `observer`: records encrypted by
+    /// implementations which do not override this are not reported.
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        let _ = observer;
+    }
 }
 
 impl dyn MessageEncrypter {
@@ -246,6 +327,32 @@ pub fn make_tls12_aad(
 
 const TLS12_AAD_SIZE: usize = 8 + 1 + 2 + 2;
 
+/// Observes the `additional_data` used to protect each record.
+///
+/// This is intended for auditing the record layer against an independent
+/// implementation.  It is installed with [`ClientConfig::aad_observer`] or
+/// [`ServerConfig::aad_observer`], and called by each [`MessageEncrypter`] and
+/// [`MessageDecrypter`] implementing `set_aad_observer`, as the built-in
+/// providers do.
+///
+/// [`ClientConfig::aad_observer`]: crate::ClientConfig::aad_observer
+/// [`ServerConfig::aad_observer`]: crate::ServerConfig::aad_observer
+pub trait ObservesAad: Send + Sync + fmt::Debug {
+    /// Called after the record with sequence number `seq` is successfully
+    /// encrypted or decrypted, with the exact `additional_data` given to the
+    /// AEAD.
+    fn on_aad(&self, seq: u64, aad: &[u8], direction: AadDirection);
+}
+
+/// Whether an [`ObservesAad`] is seeing a record we sent or received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AadDirection {
+    /// We encrypted the record.
+    Encrypt,
+    /// We decrypted the record.
+    Decrypt,
+}
+
 /// A key for an AEAD algorithm.
 ///
 /// This is a value type for a byte string up to `AeadKey::MAX_LEN` bytes in length.
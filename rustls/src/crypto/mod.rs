@@ -6,6 +6,7 @@ use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::Debug;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use pki_types::PrivateKeyDer;
 use zeroize::Zeroize;
@@ -178,6 +179,30 @@ pub struct CryptoProvider {
     pub key_provider: &'static dyn KeyProvider,
 }
 
+impl CryptoProvider {
+    /// Returns a copy of this provider keeping only the key exchange groups in
+    /// `groups`, in this provider's order of preference.
+    ///
+    /// Fails if none of this provider's groups are in `groups`.
+    pub(crate) fn restricted_to_kx_groups(&self, groups: &[NamedGroup]) -> Result<Self, Error> {
+        let kx_groups: Vec<_> = self
+            .kx_groups
+            .iter()
+            .copied()
+            .filter(|skxg| groups.contains(&skxg.name()))
+            .collect();
+
+        if kx_groups.is_empty() {
+            return Err(Error::General("no kx groups configured".into()));
+        }
+
+        Ok(Self {
+            kx_groups,
+            ..self.clone()
+        })
+    }
+}
+
 /// A source of cryptographically secure randomness.
 pub trait SecureRandom: Send + Sync + Debug {
     /// Fill the given buffer with random bytes.
@@ -258,6 +283,48 @@ pub trait ActiveKeyExchange: Send + Sync {
     fn group(&self) -> NamedGroup;
 }
 
+/// An [`ActiveKeyExchange`] that notes the length of the shared secret it produces.
+///
+/// The key exchange is completed inside the key schedule, so this is how the
+/// length is recovered for [`CommonState::negotiated_parameters`].
+///
+/// [`CommonState::negotiated_parameters`]: crate::CommonState::negotiated_parameters
+pub(crate) struct MeasuredKeyExchange {
+    inner: Box<dyn ActiveKeyExchange>,
+    secret_len: Arc<AtomicUsize>,
+}
+
+impl MeasuredKeyExchange {
+    /// Wrap `inner`; the returned counter holds the secret length once completed.
+    pub(crate) fn new(inner: Box<dyn ActiveKeyExchange>) -> (Self, Arc<AtomicUsize>) {
+        let secret_len = Arc::new(AtomicUsize::new(0));
+        (
+            Self {
+                inner,
+                secret_len: Arc::clone(&secret_len),
+            },
+            secret_len,
+        )
+    }
+}
+
+impl ActiveKeyExchange for MeasuredKeyExchange {
+    fn complete(self: Box<Self>, peer_pub_key: &[u8]) -> Result<SharedSecret, Error> {
+        let secret = self.inner.complete(peer_pub_key)?;
+        self.secret_len
+            .store(secret.secret_bytes().len(), Ordering::Relaxed);
+        Ok(secret)
+    }
+
+    fn pub_key(&self) -> &[u8] {
+        self.inner.pub_key()
+    }
+
+    fn group(&self) -> NamedGroup {
+        self.inner.group()
+    }
+}
+
 /// The result from [`ActiveKeyExchange::complete`].
 pub struct SharedSecret(Vec<u8>);
 
@@ -7,7 +7,7 @@ use crate::x509::{asn1_wrap, wrap_in_sequence};
 
 use super::ring_like::io::der;
 use super::ring_like::rand::{SecureRandom, SystemRandom};
-use super::ring_like::signature::{self, EcdsaKeyPair, Ed25519KeyPair, RsaKeyPair};
+use super::ring_like::signature::{self, EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair};
 use pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
 
 use alloc::boxed::Box;
@@ -127,6 +127,10 @@ impl SigningKey for RsaSigningKey {
     fn algorithm(&self) -> SignatureAlgorithm {
         SignatureAlgorithm::RSA
     }
+
+    fn public_key(&self) -> Option<&[u8]> {
+        Some(self.key.public_key().as_ref())
+    }
 }
 
 impl Debug for RsaSigningKey {
@@ -289,6 +293,10 @@ impl SigningKey for EcdsaSigningKey {
     fn algorithm(&self) -> SignatureAlgorithm {
         self.scheme.sign()
     }
+
+    fn public_key(&self) -> Option<&[u8]> {
+        Some(self.key.public_key().as_ref())
+    }
 }
 
 impl Debug for EcdsaSigningKey {
@@ -373,6 +381,10 @@ impl SigningKey for Ed25519SigningKey {
     fn algorithm(&self) -> SignatureAlgorithm {
         self.scheme.sign()
     }
+
+    fn public_key(&self) -> Option<&[u8]> {
+        Some(self.key.public_key().as_ref())
+    }
 }
 
 impl Debug for Ed25519SigningKey {
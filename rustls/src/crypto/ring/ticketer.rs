@@ -3,6 +3,7 @@
 use crate::error::Error;
 use crate::rand::GetRandomFailed;
 use crate::server::ProducesTickets;
+use crate::ticketer::TicketAead;
 
 use super::ring_like::aead;
 use super::ring_like::rand::{SecureRandom, SystemRandom};
@@ -22,21 +23,38 @@ impl Ticketer {
     ///
     /// The encryption mechanism used is Chacha20Poly1305.
     pub fn new() -> Result<Arc<dyn ProducesTickets>, Error> {
+        Self::new_with_aead(TicketAead::default())
+    }
+
+    /// Like [`Ticketer::new`], but encrypting tickets with `aead`.
+    pub fn new_with_aead(aead: TicketAead) -> Result<Arc<dyn ProducesTickets>, Error> {
+        let generator: fn() -> Result<Box<dyn ProducesTickets>, GetRandomFailed> = match aead {
+            TicketAead::Aes128Gcm => || make_ticket_generator_for(&aead::AES_128_GCM),
+            TicketAead::Aes256Gcm => || make_ticket_generator_for(&aead::AES_256_GCM),
+            TicketAead::Chacha20Poly1305 => make_ticket_generator,
+        };
+
         Ok(Arc::new(crate::ticketer::TicketSwitcher::new(
             6 * 60 * 60,
-            make_ticket_generator,
+            generator,
         )?))
     }
 }
 
 fn make_ticket_generator() -> Result<Box<dyn ProducesTickets>, GetRandomFailed> {
+    make_ticket_generator_for(&aead::CHACHA20_POLY1305)
+}
+
+fn make_ticket_generator_for(
+    alg: &'static aead::Algorithm,
+) -> Result<Box<dyn ProducesTickets>, GetRandomFailed> {
     let mut key = [0u8; 32];
+    let key = &mut key[..alg.key_len()];
     SystemRandom::new()
-        .fill(&mut key)
+        .fill(key)
         .map_err(|_| GetRandomFailed)?;
 
-    let alg = &aead::CHACHA20_POLY1305;
-    let key = aead::UnboundKey::new(alg, &key).unwrap();
+    let key = aead::UnboundKey::new(alg, key).unwrap();
 
     Ok(Box::new(AeadTicketer {
         alg,
@@ -134,6 +152,19 @@ mod tests {
         assert_eq!(plain, b"hello world");
     }
 
+    #[test]
+    fn all_aeads_pairwise_test() {
+        for aead in [
+            TicketAead::Aes128Gcm,
+            TicketAead::Aes256Gcm,
+            TicketAead::Chacha20Poly1305,
+        ] {
+            let t = Ticketer::new_with_aead(aead).unwrap();
+            let cipher = t.encrypt(b"hello world").unwrap();
+            assert_eq!(t.decrypt(&cipher).unwrap(), b"hello world");
+        }
+    }
+
     #[test]
     fn ticketswitcher_switching_test() {
         let t = Arc::new(crate::ticketer::TicketSwitcher::new(1, make_ticket_generator).unwrap());
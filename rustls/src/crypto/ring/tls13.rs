@@ -1,10 +1,11 @@
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use crate::crypto;
 use crate::crypto::cipher::{
-    make_tls13_aad, AeadKey, Iv, MessageDecrypter, MessageEncrypter, Nonce, Tls13AeadAlgorithm,
-    UnsupportedOperationError,
+    make_tls13_aad, AadDirection, AeadKey, Iv, MessageDecrypter, MessageEncrypter, Nonce,
+    ObservesAad, Tls13AeadAlgorithm, UnsupportedOperationError,
 };
 use crate::crypto::tls13::{Hkdf, HkdfExpander, OkmBlock, OutputLengthError};
 use crate::enums::{CipherSuite, ContentType, ProtocolVersion};
@@ -153,6 +154,7 @@ impl AeadAlgorithm {
         Box::new(Tls13MessageEncrypter {
             enc_key: aead::LessSafeKey::new(aead::UnboundKey::new(self.0, key.as_ref()).unwrap()),
             iv,
+            aad_observer: None,
         })
     }
 
@@ -161,6 +163,7 @@ impl AeadAlgorithm {
         Box::new(Tls13MessageDecrypter {
             dec_key: aead::LessSafeKey::new(aead::UnboundKey::new(self.0, key.as_ref()).unwrap()),
             iv,
+            aad_observer: None,
         })
     }
 
@@ -172,11 +175,13 @@ impl AeadAlgorithm {
 struct Tls13MessageEncrypter {
     enc_key: aead::LessSafeKey,
     iv: Iv,
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 struct Tls13MessageDecrypter {
     dec_key: aead::LessSafeKey,
     iv: Iv,
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 impl MessageEncrypter for Tls13MessageEncrypter {
@@ -187,10 +192,13 @@ impl MessageEncrypter for Tls13MessageEncrypter {
         msg.typ.encode(&mut payload);
 
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.iv, seq).0);
-        let aad = aead::Aad::from(make_tls13_aad(total_len));
+        let aad = make_tls13_aad(total_len);
         self.enc_key
-            .seal_in_place_append_tag(nonce, aad, &mut payload)
+            .seal_in_place_append_tag(nonce, aead::Aad::from(aad), &mut payload)
             .map_err(|_| Error::EncryptError)?;
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Encrypt);
+        }
 
         Ok(OpaqueMessage::new(
             ContentType::ApplicationData,
@@ -202,6 +210,10 @@ impl MessageEncrypter for Tls13MessageEncrypter {
     fn encrypted_payload_len(&self, payload_len: usize) -> usize {
         payload_len + 1 + self.enc_key.algorithm().tag_len()
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 impl MessageDecrypter for Tls13MessageDecrypter {
@@ -212,16 +224,23 @@ impl MessageDecrypter for Tls13MessageDecrypter {
         }
 
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.iv, seq).0);
-        let aad = aead::Aad::from(make_tls13_aad(payload.len()));
+        let aad = make_tls13_aad(payload.len());
         let plain_len = self
             .dec_key
-            .open_in_place(nonce, aad, payload)
+            .open_in_place(nonce, aead::Aad::from(aad), payload)
             .map_err(|_| Error::DecryptError)?
             .len();
+        if let Some(observer) = &self.aad_observer {
+            observer.on_aad(seq, &aad, AadDirection::Decrypt);
+        }
 
         payload.truncate(plain_len);
         msg.into_tls13_unpadded_message()
     }
+
+    fn set_aad_observer(&mut self, observer: Arc<dyn ObservesAad>) {
+        self.aad_observer = Some(observer);
+    }
 }
 
 struct RingHkdf(hkdf::Algorithm, hmac::Algorithm);
@@ -1,5 +1,6 @@
 use crate::enums::{SignatureAlgorithm, SignatureScheme};
 use crate::error::Error;
+use crate::x509;
 
 use pki_types::CertificateDer;
 
@@ -61,6 +62,16 @@ pub trait SigningKey: Debug + Send + Sync {
 
     /// What kind of key we have.
     fn algorithm(&self) -> SignatureAlgorithm;
+
+    /// The public half of this key, encoded as in the `subjectPublicKey` field of a
+    /// certificate's `SubjectPublicKeyInfo`.
+    ///
+    /// This is used by [`CertifiedKey::keys_match()`] to detect a certificate paired
+    /// with the wrong key.  The default implementation returns `None`, which skips that
+    /// check.
+    fn public_key(&self) -> Option<&[u8]> {
+        None
+    }
 }
 
 /// A thing that can sign a message.
@@ -90,6 +101,10 @@ pub struct CertifiedKey {
     /// An optional OCSP response from the certificate issuer,
     /// attesting to its continued validity.
     pub ocsp: Option<Vec<u8>>,
+
+    /// A list of serialized `SignedCertificateTimestamp`s (RFC6962) for
+    /// the end-entity certificate, delivered to clients that ask for them.
+    pub scts: Vec<Vec<u8>>,
 }
 
 impl CertifiedKey {
@@ -102,6 +117,7 @@ impl CertifiedKey {
             cert,
             key,
             ocsp: None,
+            scts: Vec::new(),
         }
     }
 
@@ -111,4 +127,21 @@ impl CertifiedKey {
             .first()
             .ok_or(Error::NoCertificatesPresented)
     }
+
+    /// Check that the key's public key matches the end-entity certificate.
+    ///
+    /// Returns [`Error::InconsistentKeys`] if they differ.  If the key does not
+    /// expose its [`SigningKey::public_key()`], or the certificate's public key
+    /// cannot be found, no check is made.
+    pub fn keys_match(&self) -> Result<(), Error> {
+        let key = match self.key.public_key() {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        match x509::public_key(self.end_entity_cert()?) {
+            Some(cert_key) if cert_key != key => Err(Error::InconsistentKeys),
+            _ => Ok(()),
+        }
+    }
 }
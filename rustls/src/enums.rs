@@ -69,6 +69,7 @@ enum_builder! {
         CertificateURL => 0x15,
         CertificateStatus => 0x16,
         KeyUpdate => 0x18,
+        ClientEncryptedExtensions => 0xcb,
         MessageHash => 0xfe
     }
 }
@@ -105,6 +106,33 @@ enum_builder! {
     }
 }
 
+impl ProtocolVersion {
+    /// Returns true if this is TLS1.3.
+    pub fn is_tls13(&self) -> bool {
+        *self == Self::TLSv1_3
+    }
+
+    /// Returns true if this is TLS1.2.
+    pub fn is_tls12(&self) -> bool {
+        *self == Self::TLSv1_2
+    }
+
+    /// Returns true if this version is the same as, or newer than, `other`.
+    ///
+    /// TLS versions are compared by their wire encoding, so `Unknown` values
+    /// from the future (such as `0x0305`) are newer than everything known here.
+    /// DTLS versions count downwards, and are only comparable with each other:
+    /// comparing a TLS version with a DTLS version always gives false.
+    pub fn at_least(&self, other: Self) -> bool {
+        let (ours, theirs) = (self.get_u16(), other.get_u16());
+        match (ours >> 8, theirs >> 8) {
+            (0xfe, 0xfe) => ours <= theirs,
+            (0xfe, _) | (_, 0xfe) => false,
+            _ => ours >= theirs,
+        }
+    }
+}
+
 enum_builder! {
     /// The `CipherSuite` TLS protocol enum.  Values in this enum are taken
     /// from the various RFCs covering TLS, and are listed by IANA.
@@ -303,6 +331,7 @@ enum_builder! {
         TLS13_CHACHA20_POLY1305_SHA256 => 0x1303,
         TLS13_AES_128_CCM_SHA256 => 0x1304,
         TLS13_AES_128_CCM_8_SHA256 => 0x1305,
+        TLS_FALLBACK_SCSV => 0x5600,
         TLS_ECDH_ECDSA_WITH_NULL_SHA => 0xc001,
         TLS_ECDH_ECDSA_WITH_RC4_128_SHA => 0xc002,
         TLS_ECDH_ECDSA_WITH_3DES_EDE_CBC_SHA => 0xc003,
@@ -579,4 +608,41 @@ mod tests {
             AlertDescription::NoApplicationProtocol,
         );
     }
+
+    #[test]
+    fn test_protocol_version_helpers() {
+        assert!(ProtocolVersion::TLSv1_3.is_tls13());
+        assert!(!ProtocolVersion::TLSv1_3.is_tls12());
+        assert!(ProtocolVersion::TLSv1_2.is_tls12());
+        assert!(!ProtocolVersion::TLSv1_2.is_tls13());
+        assert!(!ProtocolVersion::DTLSv1_3.is_tls13());
+
+        let tls = [
+            ProtocolVersion::SSLv2,
+            ProtocolVersion::SSLv3,
+            ProtocolVersion::TLSv1_0,
+            ProtocolVersion::TLSv1_1,
+            ProtocolVersion::TLSv1_2,
+            ProtocolVersion::TLSv1_3,
+            ProtocolVersion::Unknown(0x0305),
+        ];
+        for (i, a) in tls.iter().enumerate() {
+            for (j, b) in tls.iter().enumerate() {
+                assert_eq!(a.at_least(*b), i >= j, "{:?} vs {:?}", a, b);
+            }
+        }
+
+        let dtls = [
+            ProtocolVersion::DTLSv1_0,
+            ProtocolVersion::DTLSv1_2,
+            ProtocolVersion::DTLSv1_3,
+        ];
+        for (i, a) in dtls.iter().enumerate() {
+            for (j, b) in dtls.iter().enumerate() {
+                assert_eq!(a.at_least(*b), i >= j, "{:?} vs {:?}", a, b);
+            }
+            assert!(!a.at_least(ProtocolVersion::TLSv1_0));
+            assert!(!ProtocolVersion::TLSv1_3.at_least(*a));
+        }
+    }
 }
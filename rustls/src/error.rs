@@ -1,4 +1,4 @@
-use crate::enums::{AlertDescription, ContentType, HandshakeType};
+use crate::enums::{AlertDescription, ContentType, HandshakeType, SignatureScheme};
 use crate::msgs::handshake::KeyExchangeAlgorithm;
 use crate::rand;
 
@@ -63,6 +63,12 @@ pub enum Error {
     /// We received a fatal alert.  This means the peer is unhappy.
     AlertReceived(AlertDescription),
 
+    /// We sent a fatal alert on request of the application, using
+    /// [`ConnectionCommon::dangerous_send_alert`].
+    ///
+    /// [`ConnectionCommon::dangerous_send_alert`]: crate::ConnectionCommon::dangerous_send_alert
+    AlertSent(AlertDescription),
+
     /// We saw an invalid certificate.
     ///
     /// The contained error is from the certificate validation trait
@@ -91,6 +97,29 @@ pub enum Error {
     /// An incoming connection did not support any known application protocol.
     NoApplicationProtocol,
 
+    /// The peer offered no signature schemes we can use with our credentials.
+    ///
+    /// `offered` holds the schemes the peer advertised, for diagnostic purposes.
+    NoSignatureSchemes {
+        /// The signature schemes offered by the peer.
+        offered: Vec<SignatureScheme>,
+    },
+
+    /// A private key does not match the public key of the end-entity certificate
+    /// it was configured with.
+    InconsistentKeys,
+
+    /// The peer signed a handshake message using a signature scheme we did not
+    /// offer, or one which cannot be used with the negotiated protocol version.
+    UnsupportedSignatureScheme {
+        /// The signature scheme the peer used.
+        scheme: SignatureScheme,
+    },
+
+    /// The peer's certificate has a public key of a type we cannot verify
+    /// signatures from.
+    UnsupportedKeyAlgorithm,
+
     /// The `max_fragment_size` value supplied in configuration was too small,
     /// or too large.
     BadMaxFragmentSize,
@@ -110,6 +139,8 @@ pub enum Error {
 #[derive(Debug, Clone, Copy, PartialEq)]
 
 pub enum InvalidMessage {
+    /// The handshake messages of a single flight were larger in total than allowed.
+    HandshakeFlightTooLarge,
     /// An advertised message was larger then expected.
     HandshakePayloadTooLarge,
     /// The peer sent us a syntactically incorrect ChangeCipherSpec payload.
@@ -185,7 +216,10 @@ pub enum PeerMisbehaved {
     EarlyDataAttemptedInSecondClientHello,
     EarlyDataExtensionWithoutResumption,
     EarlyDataOfferedWithVariedCipherSuite,
+    ExternalPskOfferedWithIncompatibleCipherSuite,
     HandshakeHashVariedAfterRetry,
+    HeartbeatResponseMismatch,
+    IllegalHelloRetryCookie,
     IllegalHelloRetryRequestWithEmptyCookie,
     IllegalHelloRetryRequestWithNoChanges,
     IllegalHelloRetryRequestWithOfferedGroup,
@@ -195,12 +229,14 @@ pub enum PeerMisbehaved {
     IllegalHelloRetryRequestWithWrongSessionId,
     IllegalMiddleboxChangeCipherSpec,
     IllegalTlsInnerPlaintext,
+    InappropriateFallback,
     IncorrectBinder,
     InvalidMaxEarlyDataSize,
     InvalidKeyShare,
     KeyEpochWithPendingFragment,
     KeyUpdateReceivedInQuicConnection,
     MessageInterleavedWithHandshakeMessage,
+    MissingApplicationSettings,
     MissingBinderInPskExtension,
     MissingKeyShare,
     MissingPskModesExtension,
@@ -209,6 +245,7 @@ pub enum PeerMisbehaved {
     OfferedEarlyDataWithOldProtocolVersion,
     OfferedEmptyApplicationProtocol,
     OfferedIncorrectCompressions,
+    OfferedKeyShareForUnlistedGroup,
     PskExtensionMustBeLast,
     PskExtensionWithMismatchedIdsAndBinders,
     RefusedToFollowHelloRetryRequest,
@@ -233,6 +270,9 @@ pub enum PeerMisbehaved {
     SignedHandshakeWithUnadvertisedSigScheme,
     TooMuchEarlyDataReceived,
     UnexpectedCleartextExtension,
+    UnexpectedHeartbeatRequest,
+    UnexpectedVersion,
+    UnsolicitedApplicationSettings,
     UnsolicitedCertExtension,
     UnsolicitedEncryptedExtension,
     UnsolicitedSctList,
@@ -257,6 +297,7 @@ impl From<PeerMisbehaved> for Error {
 /// versions.
 pub enum PeerIncompatible {
     EcPointsExtensionRequired,
+    ExtendedMasterSecretExtensionRequired,
     KeyShareExtensionRequired,
     NamedGroupsExtensionRequired,
     NoCertificateRequestSignatureSchemesInCommon,
@@ -323,6 +364,18 @@ pub enum CertificateError {
     /// the expected name.
     NotValidForName,
 
+    /// The subject names in an end-entity certificate do not include
+    /// the expected name.
+    ///
+    /// This is a more detailed version of [`CertificateError::NotValidForName`],
+    /// and is what the built-in verifier returns.
+    NameMismatch {
+        /// The server name that was expected.
+        requested: String,
+        /// The DNS names the certificate is valid for.
+        presented: Vec<String>,
+    },
+
     /// The certificate is being used for a different purpose than allowed.
     InvalidPurpose,
 
@@ -330,6 +383,30 @@ pub enum CertificateError {
     /// reasons.
     ApplicationVerificationFailure,
 
+    /// The peer presented more certificates than the verifier is configured
+    /// to accept.
+    ChainTooLong,
+
+    /// A certificate carries an RSA key smaller than the verifier is
+    /// configured to accept.
+    KeyTooSmall,
+
+    /// A certificate is signed using a hash algorithm the verifier is
+    /// configured to reject.
+    DisallowedSignatureHash,
+
+    /// A certificate's names fall outside the namespace permitted by the
+    /// name constraints of a CA in its chain.
+    NameConstraintViolation,
+
+    /// The verifier is configured to require name constraints, but no CA
+    /// in the verified chain carries any.
+    NameConstraintsMissing,
+
+    /// A signed certificate timestamp for the certificate was malformed,
+    /// carried a bad signature, or was issued in the future.
+    InvalidSct,
+
     /// Any other error.
     ///
     /// This can be used by custom verifiers to expose the underlying error
@@ -356,8 +433,24 @@ impl PartialEq<Self> for CertificateError {
             (UnknownIssuer, UnknownIssuer) => true,
             (BadSignature, BadSignature) => true,
             (NotValidForName, NotValidForName) => true,
+            (
+                NameMismatch {
+                    requested: r1,
+                    presented: p1,
+                },
+                NameMismatch {
+                    requested: r2,
+                    presented: p2,
+                },
+            ) => r1 == r2 && p1 == p2,
             (InvalidPurpose, InvalidPurpose) => true,
             (ApplicationVerificationFailure, ApplicationVerificationFailure) => true,
+            (ChainTooLong, ChainTooLong) => true,
+            (KeyTooSmall, KeyTooSmall) => true,
+            (DisallowedSignatureHash, DisallowedSignatureHash) => true,
+            (NameConstraintViolation, NameConstraintViolation) => true,
+            (NameConstraintsMissing, NameConstraintsMissing) => true,
+            (InvalidSct, InvalidSct) => true,
             _ => false,
         }
     }
@@ -370,7 +463,15 @@ impl From<CertificateError> for AlertDescription {
     fn from(e: CertificateError) -> Self {
         use CertificateError::*;
         match e {
-            BadEncoding | UnhandledCriticalExtension | NotValidForName => Self::BadCertificate,
+            BadEncoding
+            | UnhandledCriticalExtension
+            | NotValidForName
+            | NameMismatch { .. }
+            | KeyTooSmall
+            | DisallowedSignatureHash
+            | NameConstraintViolation
+            | NameConstraintsMissing
+            | InvalidSct => Self::BadCertificate,
             // RFC 5246/RFC 8446
             // certificate_expired
             //  A certificate has expired or **is not currently valid**.
@@ -380,7 +481,7 @@ impl From<CertificateError> for AlertDescription {
             // the case where revocation status can not be determined, so we do the same here.
             UnknownIssuer | UnknownRevocationStatus => Self::UnknownCA,
             BadSignature => Self::DecryptError,
-            InvalidPurpose => Self::UnsupportedCertificate,
+            InvalidPurpose | ChainTooLong => Self::UnsupportedCertificate,
             ApplicationVerificationFailure => Self::AccessDenied,
             // RFC 5246/RFC 8446
             // certificate_unknown
@@ -504,6 +605,7 @@ impl fmt::Display for Error {
             Self::PeerIncompatible(ref why) => write!(f, "peer is incompatible: {:?}", why),
             Self::PeerMisbehaved(ref why) => write!(f, "peer misbehaved: {:?}", why),
             Self::AlertReceived(ref alert) => write!(f, "received fatal alert: {:?}", alert),
+            Self::AlertSent(ref alert) => write!(f, "sent fatal alert: {:?}", alert),
             Self::InvalidCertificate(ref err) => {
                 write!(f, "invalid peer certificate: {:?}", err)
             }
@@ -517,8 +619,29 @@ impl fmt::Display for Error {
             Self::PeerSentOversizedRecord => write!(f, "peer sent excess record size"),
             Self::HandshakeNotComplete => write!(f, "handshake not complete"),
             Self::NoApplicationProtocol => write!(f, "peer doesn't support any known protocol"),
+            Self::NoSignatureSchemes { ref offered } => write!(
+                f,
+                "peer offered no signature schemes we can use (offered {:?})",
+                offered
+            ),
             Self::FailedToGetCurrentTime => write!(f, "failed to get current time"),
             Self::FailedToGetRandomBytes => write!(f, "failed to get random bytes"),
+            Self::InconsistentKeys => {
+                write!(f, "private key does not match the certificate's public key")
+            }
+            Self::UnsupportedSignatureScheme { scheme } => {
+                write!(
+                    f,
+                    "peer signed with unsupported signature scheme {:?}",
+                    scheme
+                )
+            }
+            Self::UnsupportedKeyAlgorithm => {
+                write!(
+                    f,
+                    "peer's certificate has an unsupported public key algorithm"
+                )
+            }
             Self::BadMaxFragmentSize => {
                 write!(f, "the supplied max_fragment_size was too small or large")
             }
@@ -592,11 +715,30 @@ mod tests {
         assert_eq!(UnknownIssuer, UnknownIssuer);
         assert_eq!(BadSignature, BadSignature);
         assert_eq!(NotValidForName, NotValidForName);
+        let mismatch = NameMismatch {
+            requested: "wrong.example.com".into(),
+            presented: vec!["right.example.com".into()],
+        };
+        assert_eq!(mismatch, mismatch.clone());
+        assert_ne!(
+            mismatch,
+            NameMismatch {
+                requested: "wrong.example.com".into(),
+                presented: vec![],
+            }
+        );
+        assert_ne!(mismatch, NotValidForName);
         assert_eq!(InvalidPurpose, InvalidPurpose);
         assert_eq!(
             ApplicationVerificationFailure,
             ApplicationVerificationFailure
         );
+        assert_eq!(ChainTooLong, ChainTooLong);
+        assert_eq!(KeyTooSmall, KeyTooSmall);
+        assert_eq!(DisallowedSignatureHash, DisallowedSignatureHash);
+        assert_eq!(NameConstraintViolation, NameConstraintViolation);
+        assert_eq!(NameConstraintsMissing, NameConstraintsMissing);
+        assert_eq!(InvalidSct, InvalidSct);
         let other = Other(OtherError(alloc::sync::Arc::from(Box::from(""))));
         assert_ne!(other, other);
         assert_ne!(BadEncoding, Expired);
@@ -633,7 +775,7 @@ mod tests {
 
     #[test]
     fn smoke() {
-        use crate::enums::{AlertDescription, ContentType, HandshakeType};
+        use crate::enums::{AlertDescription, ContentType, HandshakeType, SignatureScheme};
 
         let all = vec![
             Error::InappropriateMessage {
@@ -650,6 +792,7 @@ mod tests {
             super::PeerIncompatible::Tls12NotOffered.into(),
             super::PeerMisbehaved::UnsolicitedCertExtension.into(),
             Error::AlertReceived(AlertDescription::ExportRestriction),
+            Error::AlertSent(AlertDescription::DecodeError),
             super::CertificateError::Expired.into(),
             Error::General("undocumented error".to_string()),
             Error::FailedToGetCurrentTime,
@@ -657,7 +800,15 @@ mod tests {
             Error::HandshakeNotComplete,
             Error::PeerSentOversizedRecord,
             Error::NoApplicationProtocol,
+            Error::NoSignatureSchemes {
+                offered: vec![SignatureScheme::ED25519],
+            },
             Error::BadMaxFragmentSize,
+            Error::InconsistentKeys,
+            Error::UnsupportedSignatureScheme {
+                scheme: SignatureScheme::ED448,
+            },
+            Error::UnsupportedKeyAlgorithm,
             Error::InvalidCertRevocationList(CertRevocationListError::BadSignature),
             Error::Other(OtherError(alloc::sync::Arc::from(Box::from("")))),
         ];
@@ -1,3 +1,4 @@
+use crate::common_state::ObservesHandshakeMessages;
 use crate::crypto::hash;
 use crate::msgs::codec::Codec;
 use crate::msgs::enums::HashAlgorithm;
@@ -5,6 +6,7 @@ use crate::msgs::handshake::HandshakeMessagePayload;
 use crate::msgs::message::{Message, MessagePayload};
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::mem;
 
@@ -16,6 +18,7 @@ use core::mem;
 pub(crate) struct HandshakeHashBuffer {
     buffer: Vec<u8>,
     client_auth_enabled: bool,
+    observer: Option<Arc<dyn ObservesHandshakeMessages>>,
 }
 
 impl HandshakeHashBuffer {
@@ -23,6 +26,7 @@ impl HandshakeHashBuffer {
         Self {
             buffer: Vec::new(),
             client_auth_enabled: false,
+            observer: None,
         }
     }
 
@@ -32,11 +36,19 @@ impl HandshakeHashBuffer {
         self.client_auth_enabled = true;
     }
 
+    /// Report each message added to the transcript from now on to `observer`.
+    pub(crate) fn set_observer(&mut self, observer: Option<Arc<dyn ObservesHandshakeMessages>>) {
+        self.observer = observer;
+    }
+
     /// Hash/buffer a handshake message.
     pub(crate) fn add_message(&mut self, m: &Message) {
-        if let MessagePayload::Handshake { encoded, .. } = &m.payload {
+        if let MessagePayload::Handshake { parsed, encoded } = &m.payload {
             self.buffer
                 .extend_from_slice(&encoded.0);
+            if let Some(observer) = &self.observer {
+                observer.handshake_message(parsed.typ, &encoded.0);
+            }
         }
     }
 
@@ -69,6 +81,7 @@ impl HandshakeHashBuffer {
                 true => Some(self.buffer),
                 false => None,
             },
+            observer: self.observer,
         }
     }
 }
@@ -86,6 +99,8 @@ pub(crate) struct HandshakeHash {
 
     /// buffer for client-auth.
     client_auth: Option<Vec<u8>>,
+
+    observer: Option<Arc<dyn ObservesHandshakeMessages>>,
 }
 
 impl HandshakeHash {
@@ -97,8 +112,11 @@ impl HandshakeHash {
 
     /// Hash/buffer a handshake message.
     pub(crate) fn add_message(&mut self, m: &Message) -> &mut Self {
-        if let MessagePayload::Handshake { encoded, .. } = &m.payload {
+        if let MessagePayload::Handshake { parsed, encoded } = &m.payload {
             self.update_raw(&encoded.0);
+            if let Some(observer) = &self.observer {
+                observer.handshake_message(parsed.typ, &encoded.0);
+            }
         }
         self
     }
@@ -130,6 +148,7 @@ impl HandshakeHash {
         HandshakeHashBuffer {
             client_auth_enabled: self.client_auth.is_some(),
             buffer: old_handshake_hash_msg.get_encoding(),
+            observer: self.observer,
         }
     }
 
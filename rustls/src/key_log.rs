@@ -1,3 +1,6 @@
+use crate::common_state::CommonState;
+
+use alloc::vec::Vec;
 use core::fmt::Debug;
 
 /// This trait represents the ability to do something useful
@@ -35,6 +38,28 @@ pub trait KeyLog: Debug + Send + Sync {
     /// <https://developer.mozilla.org/en-US/docs/Mozilla/Projects/NSS/Key_Log_Format>
     fn log(&self, label: &str, client_random: &[u8], secret: &[u8]);
 
+    /// Log the given `secret` for a connection with an opaque `connection_id`.
+    ///
+    /// `connection_id` is the one given to
+    /// [`ClientConnection::new_with_connection_id`],
+    /// [`ServerConnection::new_with_connection_id`] or
+    /// [`Acceptor::new_with_connection_id`], if any.  The other arguments are
+    /// as for [`KeyLog::log`], which the default implementation calls, ignoring
+    /// `connection_id`.
+    ///
+    /// [`ClientConnection::new_with_connection_id`]: crate::ClientConnection::new_with_connection_id
+    /// [`ServerConnection::new_with_connection_id`]: crate::ServerConnection::new_with_connection_id
+    /// [`Acceptor::new_with_connection_id`]: crate::server::Acceptor::new_with_connection_id
+    fn log_for_connection(
+        &self,
+        label: &str,
+        client_random: &[u8],
+        _connection_id: Option<&[u8]>,
+        secret: &[u8],
+    ) {
+        self.log(label, client_random, secret)
+    }
+
     /// Indicates whether the secret with label `label` will be logged.
     ///
     /// If `will_log` returns true then `log` will be called with the secret.
@@ -56,3 +81,35 @@ impl KeyLog for NoKeyLog {
         false
     }
 }
+
+/// Gives the log calls made by the key schedule a connection's id, passing
+/// them on to [`KeyLog::log_for_connection`].
+#[derive(Debug)]
+pub(crate) struct ConnectionKeyLog<'a> {
+    key_log: &'a dyn KeyLog,
+    connection_id: Option<Vec<u8>>,
+}
+
+impl<'a> ConnectionKeyLog<'a> {
+    pub(crate) fn new(key_log: &'a dyn KeyLog, common: &CommonState) -> Self {
+        Self {
+            key_log,
+            connection_id: common.connection_id.clone(),
+        }
+    }
+}
+
+impl KeyLog for ConnectionKeyLog<'_> {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        self.key_log.log_for_connection(
+            label,
+            client_random,
+            self.connection_id.as_deref(),
+            secret,
+        );
+    }
+
+    fn will_log(&self, label: &str) -> bool {
+        self.key_log.will_log(label)
+    }
+}
@@ -269,6 +269,11 @@
 //!   `std::io::ReadBuf` and related APIs. This reduces costs from initializing
 //!   buffers. Will do nothing on non-Nightly releases.
 //!
+//! - `plain_messages`: adds `ConnectionCommon::process_message`, for feeding
+//!   messages which were decrypted outside of rustls into a connection, and
+//!   receiving what they caused as a list of `CoreEffect`s. This is intended for
+//!   callers which offload record processing, such as to kernel TLS.
+//!
 
 // Require docs for public APIs, deny unsafe code, etc.
 #![forbid(unsafe_code, unused_must_use)]
@@ -383,7 +388,7 @@ pub mod internal {
     /// Low-level TLS message parsing and encoding functions.
     pub mod msgs {
         pub mod base {
-            pub use crate::msgs::base::Payload;
+            pub use crate::msgs::base::{Payload, PayloadU8};
         }
         pub mod codec {
             pub use crate::msgs::codec::{Codec, Reader};
@@ -401,13 +406,20 @@ pub mod internal {
         }
         pub mod handshake {
             pub use crate::msgs::handshake::{
-                CertificateChain, ClientExtension, ClientHelloPayload, DistinguishedName,
-                EchConfig, EchConfigContents, HandshakeMessagePayload, HandshakePayload,
-                HpkeKeyConfig, HpkeSymmetricCipherSuite, KeyShareEntry, Random, SessionId,
+                CertificateChain, CertificateEntry, CertificateExtension, CertificatePayloadTls13,
+                ClientExtension, ClientHelloPayload, DistinguishedName, EchConfig,
+                EchConfigContents, HandshakeMessagePayload, HandshakePayload, HpkeKeyConfig,
+                HpkeSymmetricCipherSuite, KeyShareEntry, Random, ServerExtension,
+                ServerHelloPayload, SessionId,
             };
         }
+        pub mod hsjoiner {
+            pub use crate::msgs::hsjoiner::HandshakeJoiner;
+        }
         pub mod message {
-            pub use crate::msgs::message::{Message, MessagePayload, OpaqueMessage, PlainMessage};
+            pub use crate::msgs::message::{
+                Message, MessageError, MessagePayload, OpaqueMessage, PlainMessage, RecordHeader,
+            };
         }
         pub mod persist {
             pub use crate::msgs::persist::ServerSessionValue;
@@ -415,6 +427,7 @@ pub mod internal {
     }
 
     pub mod record_layer {
+        pub use crate::conn::advance_client_seq;
         pub use crate::record_layer::RecordLayer;
     }
 }
@@ -428,8 +441,15 @@ use crate::crypto::ring as test_provider;
 
 // The public interface is:
 pub use crate::builder::{ConfigBuilder, ConfigSide, WantsVerifier, WantsVersions};
-pub use crate::common_state::{CommonState, IoState, Side};
-pub use crate::conn::{Connection, ConnectionCommon, Reader, SideData, Writer};
+pub use crate::common_state::{
+    AlpnProtocol, CommonState, HandshakeKind, IoState, NegotiatedParameters, ObservesAlerts,
+    ObservesHandshakeMessages, ObservesReadWatermarks, RecordPadding, ResumptionKind, Side,
+};
+#[cfg(feature = "plain_messages")]
+pub use crate::conn::CoreEffect;
+pub use crate::conn::{
+    Connection, ConnectionCommon, Reader, RecordVersionPolicy, SideData, Writer,
+};
 pub use crate::enums::{
     AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion, SignatureAlgorithm,
     SignatureScheme,
@@ -440,13 +460,15 @@ pub use crate::error::{
 };
 pub use crate::key_log::{KeyLog, NoKeyLog};
 pub use crate::key_log_file::KeyLogFile;
-pub use crate::msgs::enums::NamedGroup;
+pub use crate::msgs::enums::{HeartbeatMode, NamedGroup};
 pub use crate::msgs::handshake::DistinguishedName;
 pub use crate::stream::{Stream, StreamOwned};
-pub use crate::suites::{ConnectionTrafficSecrets, ExtractedSecrets, SupportedCipherSuite};
+pub use crate::suites::{
+    BulkAlgorithm, ConnectionTrafficSecrets, ExtractedSecrets, SupportedCipherSuite,
+};
 #[cfg(feature = "tls12")]
 pub use crate::tls12::Tls12CipherSuite;
-pub use crate::tls13::Tls13CipherSuite;
+pub use crate::tls13::{ExternalPsk, Tls13CipherSuite};
 pub use crate::verify::DigitallySignedStruct;
 pub use crate::versions::{SupportedProtocolVersion, ALL_VERSIONS, DEFAULT_VERSIONS};
 pub use crate::webpki::RootCertStore;
@@ -464,10 +486,14 @@ pub mod client {
 
     pub use builder::WantsClientCert;
     pub use client_conn::{
-        ClientConfig, ClientConnection, ClientConnectionData, ClientSessionStore,
-        ResolvesClientCert, Resumption, Tls12Resumption, WriteEarlyData,
+        ClientAuthRequest, ClientConfig, ClientConnection, ClientConnectionData,
+        ClientSessionStore, NewSessionTicketMetadata, ObservesNewTickets, ResolvesClientCert,
+        ResumedTicket, Resumption, Tls12Resumption, WriteEarlyData,
     };
-    pub use handy::ClientSessionMemoryCache;
+    pub use handy::{ClientSessionMemoryCache, ResolvesClientCertUsingIssuers};
+
+    pub use crate::msgs::enums::PSKKeyExchangeMode;
+    pub use crate::msgs::handshake::TicketRequest;
 
     /// Dangerous configuration that should be audited and used with extreme care.
     pub mod danger {
@@ -477,8 +503,9 @@ pub mod client {
     }
 
     pub use crate::webpki::{
-        verify_server_cert_signed_by_trust_anchor, verify_server_name, ServerCertVerifierBuilder,
-        VerifierBuilderError, WebPkiServerVerifier,
+        verify_scts, verify_server_cert_signed_by_trust_anchor, verify_server_name, CtLog,
+        ServerCertVerifierBuilder, UnknownCriticalExtensionPolicy, VerifierBuilderError,
+        WebPkiServerVerifier,
     };
 
     pub use crate::msgs::persist::Tls12ClientSessionValue;
@@ -500,16 +527,21 @@ pub mod server {
 
     pub use crate::verify::NoClientAuth;
     pub use crate::webpki::{
-        ClientCertVerifierBuilder, ParsedCertificate, VerifierBuilderError, WebPkiClientVerifier,
+        ClientCertVerifierBuilder, ParsedCertificate, UnknownCriticalExtensionPolicy,
+        VerifierBuilderError, WebPkiClientVerifier,
     };
     pub use builder::WantsServerCert;
-    pub use handy::ResolvesServerCertUsingSni;
     pub use handy::{NoServerSessionStorage, ServerSessionMemoryCache};
+    pub use handy::{ResolvesServerCertUsingSigSchemes, ResolvesServerCertUsingSni};
     pub use server_conn::StoresServerSessions;
     pub use server_conn::{
-        Accepted, Acceptor, ReadEarlyData, ServerConfig, ServerConnection, ServerConnectionData,
+        Accepted, Acceptor, EarlyDataRejectReason, ReadEarlyData, ServerConfig, ServerConnection,
+        ServerConnectionData, MAX_TLS13_TICKETS,
+    };
+    pub use server_conn::{
+        ClientHello, CookieProvider, FiltersCipherSuites, ObservesUnknownExtensions,
+        ProducesTickets, ResolvesServerCert,
     };
-    pub use server_conn::{ClientHello, ProducesTickets, ResolvesServerCert};
 
     /// Dangerous configuration that should be audited and used with extreme care.
     pub mod danger {
@@ -119,9 +119,11 @@ rustls naturally does not support SSLv2, but most importantly does not support R
 is an attack against CBC mode ciphersuites in SSLv3.  This was possible in most cases because some clients willingly
 downgraded to SSLv3 after failed handshakes for later versions.
 
-rustls does not support CBC mode ciphersuites, or SSLv3.  Note that rustls does not need to implement `TLS_FALLBACK_SCSV`
-introduced as a countermeasure because it contains no ability to downgrade from TLS 1.2 to earlier protocol versions,
-and TLS 1.3 has protocol-level downgrade protection based on the [ServerHello server random value](https://www.rfc-editor.org/rfc/rfc8446#section-4.1.3).
+rustls does not support CBC mode ciphersuites, or SSLv3.  rustls clients never downgrade, so never send
+`TLS_FALLBACK_SCSV`, and TLS 1.3 has protocol-level downgrade protection based on the
+[ServerHello server random value](https://www.rfc-editor.org/rfc/rfc8446#section-4.1.3).  rustls servers do honour
+`TLS_FALLBACK_SCSV` from other clients, and refuse with an `inappropriate_fallback` alert a client that fell back
+to TLS 1.2 when TLS 1.3 was available.
 
 ## GCM nonces
 
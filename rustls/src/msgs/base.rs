@@ -126,7 +126,7 @@ impl fmt::Debug for PayloadU16 {
 
 /// An arbitrary, unknown-content, u8-length-prefixed payload
 #[derive(Clone, Eq, PartialEq)]
-pub struct PayloadU8(pub(crate) Vec<u8>);
+pub struct PayloadU8(pub Vec<u8>);
 
 impl PayloadU8 {
     pub(crate) fn encode_slice(slice: &[u8], bytes: &mut Vec<u8>) {
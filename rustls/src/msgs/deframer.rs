@@ -5,10 +5,13 @@ use std::io;
 use super::base::Payload;
 use super::codec::Codec;
 use super::message::PlainMessage;
+use crate::conn::RecordVersionPolicy;
 use crate::enums::{ContentType, ProtocolVersion};
 use crate::error::{Error, InvalidMessage, PeerMisbehaved};
+#[cfg(feature = "logging")]
+use crate::log::warn;
 use crate::msgs::codec;
-use crate::msgs::message::{MessageError, OpaqueMessage};
+use crate::msgs::message::{MessageError, OpaqueMessage, RecordHeader};
 use crate::record_layer::{Decrypted, RecordLayer};
 
 /// This deframer works to reconstruct TLS messages from a stream of arbitrary-sized reads.
@@ -32,6 +35,16 @@ pub struct MessageDeframer {
 
     /// What size prefix of `buf` is used.
     used: usize,
+
+    /// If set, records with any other content type are rejected before decryption.
+    accepted_content_types: Option<Vec<ContentType>>,
+
+    /// What to do with TLS1.3 records that don't carry the expected outer version.
+    record_version_policy: RecordVersionPolicy,
+
+    /// If we're decrypting a record as it arrives, how much of its ciphertext is
+    /// still to come.  The ciphertext received so far is no longer in `buf`.
+    streaming: Option<u16>,
 }
 
 impl MessageDeframer {
@@ -55,6 +68,30 @@ impl MessageDeframer {
         // For records that decrypt as `Handshake`, we keep the current state of the joined
         // handshake message payload in `self.joining_hs`, appending to it as we see records.
         let expected_len = loop {
+            if let Some(remaining) = self.streaming {
+                let msg = match self.stream(record_layer, remaining)? {
+                    Some(msg) => msg,
+                    None => return Ok(None),
+                };
+
+                if msg.typ != ContentType::Handshake {
+                    return Ok(Some(Deframed {
+                        want_close_before_decrypt: false,
+                        aligned: true,
+                        trial_decryption_finished: false,
+                        message: msg,
+                    }));
+                }
+
+                // The record is gone from `buf`, so make room for its payload to be joined.
+                let end = self.make_room(msg.payload.0.len());
+                match self.append_hs(msg.version, &msg.payload.0, end, false)? {
+                    HandshakePayloadState::Blocked => return Ok(None),
+                    HandshakePayloadState::Complete(len) => break len,
+                    HandshakePayloadState::Continue => continue,
+                }
+            }
+
             let start = match &self.joining_hs {
                 Some(meta) => {
                     match meta.expected_len {
@@ -75,25 +112,22 @@ impl MessageDeframer {
             let mut rd = codec::Reader::init(&self.buf[start..self.used]);
             let m = match OpaqueMessage::read(&mut rd) {
                 Ok(m) => m,
-                Err(msg_err) => {
-                    let err_kind = match msg_err {
-                        MessageError::TooShortForHeader | MessageError::TooShortForLength => {
-                            return Ok(None)
-                        }
-                        MessageError::InvalidEmptyPayload => InvalidMessage::InvalidEmptyPayload,
-                        MessageError::MessageTooLarge => InvalidMessage::MessageTooLarge,
-                        MessageError::InvalidContentType => InvalidMessage::InvalidContentType,
-                        MessageError::UnknownProtocolVersion => {
-                            InvalidMessage::UnknownProtocolVersion
-                        }
-                    };
-
-                    return Err(self.set_err(err_kind));
+                Err(MessageError::TooShortForLength) if self.joining_hs.is_none() => {
+                    match self.start_streaming(record_layer, negotiated_version)? {
+                        true => continue,
+                        false => return Ok(None),
+                    }
+                }
+                Err(MessageError::TooShortForHeader | MessageError::TooShortForLength) => {
+                    return Ok(None)
                 }
+                Err(msg_err) => return Err(self.set_err(InvalidMessage::from(msg_err))),
             };
 
-            // Return CCS messages and early plaintext alerts immediately without decrypting.
             let end = start + rd.used();
+            self.check_header(m.typ, m.version, negotiated_version)?;
+
+            // Return CCS messages and early plaintext alerts immediately without decrypting.
             let version_is_tls13 = matches!(negotiated_version, Some(ProtocolVersion::TLSv1_3));
             let allowed_plaintext = match m.typ {
                 // CCS messages are always plaintext.
@@ -159,7 +193,6 @@ impl MessageDeframer {
 
             // If it's not a handshake message, just return it -- no joining necessary.
             if msg.typ != ContentType::Handshake {
-                let end = start + rd.used();
                 self.discard(end);
                 return Ok(Some(Deframed {
                     want_close_before_decrypt: false,
@@ -210,6 +243,128 @@ impl MessageDeframer {
         }))
     }
 
+    /// Check a record header against our content type and version policies.
+    fn check_header(
+        &mut self,
+        typ: ContentType,
+        version: ProtocolVersion,
+        negotiated_version: Option<ProtocolVersion>,
+    ) -> Result<(), Error> {
+        if let Some(accepted) = &self.accepted_content_types {
+            if !accepted.contains(&typ) {
+                let err = Error::InappropriateMessage {
+                    expect_types: accepted.clone(),
+                    got_type: typ,
+                };
+                return Err(self.set_err(err));
+            }
+        }
+
+        // "legacy_record_version:  MUST be set to 0x0303 for all records
+        //  generated by a TLS 1.3 implementation other than an initial
+        //  ClientHello"
+        // https://www.rfc-editor.org/rfc/rfc8446#section-5.1
+        if matches!(negotiated_version, Some(ProtocolVersion::TLSv1_3))
+            && version != ProtocolVersion::TLSv1_2
+        {
+            match self.record_version_policy {
+                RecordVersionPolicy::Warn => {
+                    warn!("Received TLS1.3 record with version {:?}", version);
+                }
+                RecordVersionPolicy::Reject => {
+                    return Err(self.set_err(PeerMisbehaved::UnexpectedVersion));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start decrypting the partially received record at the start of `buf` as
+    /// it arrives, if the record layer can.
+    ///
+    /// Only encrypted `ApplicationData` records are streamed: in TLS1.3 these
+    /// are all the encrypted records, and in TLS1.2 the others are small.
+    fn start_streaming(
+        &mut self,
+        record_layer: &mut RecordLayer,
+        negotiated_version: Option<ProtocolVersion>,
+    ) -> Result<bool, Error> {
+        let mut rd = codec::Reader::init(&self.buf[..self.used]);
+        let header = match RecordHeader::read(&mut rd) {
+            Ok(header) if header.typ == ContentType::ApplicationData => header,
+            _ => return Ok(false),
+        };
+        let header_len = rd.used();
+        self.check_header(header.typ, header.version, negotiated_version)?;
+
+        match record_layer.start_streaming(header.typ, header.version, usize::from(header.len)) {
+            Ok(true) => {}
+            Ok(false) => return Ok(false),
+            Err(err) => return Err(self.set_err(err)),
+        }
+
+        self.discard(header_len);
+        self.streaming = Some(header.len);
+        Ok(true)
+    }
+
+    /// Give the ciphertext in `buf` to the record being decrypted as it arrives.
+    ///
+    /// Returns the record's plaintext once it is complete and verified.
+    /// Errors are fused, because the ciphertext given so far can't be replayed.
+    fn stream(
+        &mut self,
+        record_layer: &mut RecordLayer,
+        remaining: u16,
+    ) -> Result<Option<PlainMessage>, Error> {
+        let chunk_len = Ord::min(usize::from(remaining), self.used);
+        if let Err(err) = record_layer.stream_incoming(&self.buf[..chunk_len]) {
+            return Err(self.set_err(err));
+        }
+        self.discard(chunk_len);
+
+        if chunk_len < usize::from(remaining) {
+            self.streaming = Some(remaining - chunk_len as u16);
+            return Ok(None);
+        }
+
+        self.streaming = None;
+        match record_layer.finish_streaming() {
+            Ok(Decrypted {
+                want_close_before_decrypt,
+                plaintext,
+            }) => {
+                debug_assert!(!want_close_before_decrypt);
+                Ok(Some(plaintext))
+            }
+            Err(err) => Err(self.set_err(err)),
+        }
+    }
+
+    /// Move the contents of `buf` along by `len` bytes, and return `len`.
+    fn make_room(&mut self, len: usize) -> usize {
+        let end = self.used + len;
+        if self.buf.len() < end {
+            self.buf.resize(end, 0);
+        }
+        self.buf.copy_within(..self.used, len);
+        self.used = end;
+        len
+    }
+
+    /// Only accept records with one of the given content types, or any known
+    /// content type if `types` is `None`.
+    pub(crate) fn set_accepted_content_types(&mut self, types: Option<Vec<ContentType>>) {
+        self.accepted_content_types = types;
+    }
+
+    /// Choose what happens to records whose outer version is not TLS1.2 once
+    /// TLS1.3 has been negotiated.
+    pub(crate) fn set_record_version_policy(&mut self, policy: RecordVersionPolicy) {
+        self.record_version_policy = policy;
+    }
+
     /// Fuses this deframer's error and returns the set value.
     ///
     /// Any future calls to `pop` will return `err` again.
@@ -349,7 +504,7 @@ impl MessageDeframer {
     /// to process, either whole messages in our output
     /// queue or partial messages in our buffer.
     pub fn has_pending(&self) -> bool {
-        self.used > 0
+        self.used > 0 || self.streaming.is_some()
     }
 
     /// Discard `taken` bytes from the start of our buffer.
@@ -414,7 +569,7 @@ struct HandshakePayloadMeta {
 /// Returns `Err` if the advertised length is larger than what we want to accept
 /// (`MAX_HANDSHAKE_SIZE`), `Ok(None)` if the buffer is too small to contain a complete header,
 /// and `Ok(Some(len))` otherwise.
-fn payload_size(buf: &[u8]) -> Result<Option<usize>, Error> {
+pub(crate) fn payload_size(buf: &[u8]) -> Result<Option<usize>, Error> {
     if buf.len() < HEADER_SIZE {
         return Ok(None);
     }
@@ -451,6 +606,12 @@ mod tests {
     use super::MessageDeframer;
     use crate::msgs::message::{Message, OpaqueMessage};
     use crate::record_layer::RecordLayer;
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    use crate::{
+        crypto::cipher::{MessageDecrypter, StreamingDecrypter},
+        msgs::message::{BorrowedPlainMessage, PlainMessage},
+        ProtocolVersion,
+    };
     use crate::{ContentType, Error, InvalidMessage};
 
     use std::io;
@@ -742,6 +903,209 @@ mod tests {
         );
     }
 
+    /// Decrypts records as they arrive, by gathering their ciphertext for `inner`.
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    struct GatheringDecrypter {
+        inner: Box<dyn MessageDecrypter>,
+        record: Option<(ContentType, ProtocolVersion, usize, u64)>,
+        ciphertext: Vec<u8>,
+    }
+
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    impl MessageDecrypter for GatheringDecrypter {
+        fn decrypt(&mut self, msg: OpaqueMessage, seq: u64) -> Result<PlainMessage, Error> {
+            self.inner.decrypt(msg, seq)
+        }
+
+        fn streaming(&mut self) -> Option<&mut dyn StreamingDecrypter> {
+            Some(self)
+        }
+    }
+
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    impl StreamingDecrypter for GatheringDecrypter {
+        fn start(
+            &mut self,
+            typ: ContentType,
+            version: ProtocolVersion,
+            len: usize,
+            seq: u64,
+        ) -> Result<(), Error> {
+            self.record = Some((typ, version, len, seq));
+            self.ciphertext.clear();
+            Ok(())
+        }
+
+        fn update(&mut self, chunk: &[u8]) -> Result<(), Error> {
+            match self.record {
+                Some((_, _, len, _)) if self.ciphertext.len() + chunk.len() <= len => {
+                    self.ciphertext.extend_from_slice(chunk);
+                    Ok(())
+                }
+                _ => Err(Error::DecryptError),
+            }
+        }
+
+        fn finish(&mut self) -> Result<PlainMessage, Error> {
+            match self.record.take() {
+                Some((typ, version, len, seq)) if self.ciphertext.len() == len => {
+                    let ciphertext = core::mem::take(&mut self.ciphertext);
+                    self.inner
+                        .decrypt(OpaqueMessage::new(typ, version, ciphertext), seq)
+                }
+                _ => Err(Error::DecryptError),
+            }
+        }
+    }
+
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    fn streaming_record_layers(streaming: bool) -> (RecordLayer, RecordLayer) {
+        use crate::crypto::cipher::{AeadKey, Iv, MessageEncrypter};
+        use crate::test_provider::cipher_suite::TLS13_AES_128_GCM_SHA256;
+
+        let aead_alg = TLS13_AES_128_GCM_SHA256
+            .tls13()
+            .unwrap()
+            .aead_alg;
+        let key = || AeadKey::from([0x42; 32]).with_length(aead_alg.key_len());
+        let iv = || Iv::from([0x24; 12]);
+
+        let mut decrypter = aead_alg.decrypter(key(), iv());
+        if streaming {
+            decrypter = Box::new(GatheringDecrypter {
+                inner: decrypter,
+                record: None,
+                ciphertext: Vec::new(),
+            });
+        }
+
+        (
+            RecordLayer::with_ciphers(
+                aead_alg.encrypter(key(), iv()),
+                <dyn MessageDecrypter>::invalid(),
+            ),
+            RecordLayer::with_ciphers(<dyn MessageEncrypter>::invalid(), decrypter),
+        )
+    }
+
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    fn encrypt_record(
+        sender: &mut RecordLayer,
+        typ: ContentType,
+        payload: &[u8],
+        seq: u64,
+    ) -> Vec<u8> {
+        sender
+            .dangerous_encrypt(
+                BorrowedPlainMessage {
+                    typ,
+                    version: ProtocolVersion::TLSv1_2,
+                    payload,
+                },
+                seq,
+            )
+            .unwrap()
+            .encode()
+    }
+
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    #[test]
+    fn test_streaming_record_in_two_chunks() {
+        let (mut sender, _) = streaming_record_layers(true);
+        let record = encrypt_record(
+            &mut sender,
+            ContentType::ApplicationData,
+            b"a record decrypted in two chunks",
+            0,
+        );
+        let split = record.len() / 2;
+
+        let deframe_in_two_chunks = |record: &[u8]| -> Result<PlainMessage, Error> {
+            let (_, mut receiver) = streaming_record_layers(true);
+            let mut d = MessageDeframer::default();
+
+            assert_len(split, input_bytes(&mut d, &record[..split]));
+            assert!(d.pop(&mut receiver, None)?.is_none());
+            // The first chunk has been taken from the buffer, but nothing released.
+            assert_eq!(d.used, 0);
+            assert!(d.has_pending());
+
+            assert_len(record.len() - split, input_bytes(&mut d, &record[split..]));
+            let result = d
+                .pop(&mut receiver, None)
+                .map(|deframed| deframed.unwrap().message);
+            assert!(!d.has_pending());
+            result
+        };
+
+        let plain = deframe_in_two_chunks(&record).unwrap();
+        assert_eq!(plain.typ, ContentType::ApplicationData);
+        assert_eq!(plain.payload.0, b"a record decrypted in two chunks");
+
+        // Tampering with either chunk is caught.
+        for tampered_at in [5, record.len() - 1] {
+            let mut tampered = record.clone();
+            tampered[tampered_at] ^= 0x01;
+            assert_eq!(
+                deframe_in_two_chunks(&tampered).unwrap_err(),
+                Error::DecryptError
+            );
+        }
+
+        // Without a streaming decrypter, the record waits in the buffer.
+        let (_, mut receiver) = streaming_record_layers(false);
+        let mut d = MessageDeframer::default();
+        assert_len(split, input_bytes(&mut d, &record[..split]));
+        assert!(d
+            .pop(&mut receiver, None)
+            .unwrap()
+            .is_none());
+        assert_eq!(d.used, split);
+    }
+
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    #[test]
+    fn test_streaming_handshake_record_is_joined() {
+        let (mut sender, mut receiver) = streaming_record_layers(true);
+        let handshake = [0x14, 0x00, 0x00, 0x04, 0x01, 0x02, 0x03, 0x04];
+        let mut records = encrypt_record(&mut sender, ContentType::Handshake, &handshake, 0);
+        let split = records.len() / 2;
+        records.extend(encrypt_record(
+            &mut sender,
+            ContentType::ApplicationData,
+            b"following",
+            1,
+        ));
+
+        let mut d = MessageDeframer::default();
+        assert_len(split, input_bytes(&mut d, &records[..split]));
+        assert!(d
+            .pop(&mut receiver, None)
+            .unwrap()
+            .is_none());
+        assert_len(
+            records.len() - split,
+            input_bytes(&mut d, &records[split..]),
+        );
+
+        let m = d
+            .pop(&mut receiver, None)
+            .unwrap()
+            .unwrap()
+            .message;
+        assert_eq!(m.typ, ContentType::Handshake);
+        assert_eq!(m.payload.0, handshake);
+
+        let m = d
+            .pop(&mut receiver, None)
+            .unwrap()
+            .unwrap()
+            .message;
+        assert_eq!(m.typ, ContentType::ApplicationData);
+        assert_eq!(m.payload.0, b"following");
+        assert!(!d.has_pending());
+    }
+
     #[test]
     fn test_limited_buffer() {
         const PAYLOAD_LEN: usize = 16_384;
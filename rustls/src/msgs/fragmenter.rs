@@ -47,6 +47,19 @@ impl MessageFragmenter {
             })
     }
 
+    /// The largest fragment payload that will be produced.
+    pub(crate) fn max_fragment_len(&self) -> usize {
+        self.max_frag
+    }
+
+    /// A fragmenter producing fragment payloads no longer than `max_len`,
+    /// as well as respecting our own maximum.
+    pub(crate) fn bounded_to(&self, max_len: usize) -> Self {
+        Self {
+            max_frag: self.max_frag.min(max_len),
+        }
+    }
+
     /// Set the maximum fragment size that will be produced.
     ///
     /// This includes overhead. A `max_fragment_size` of 10 will produce TLS fragments
@@ -11,8 +11,8 @@ use crate::msgs::base::{Payload, PayloadU16, PayloadU24, PayloadU8};
 use crate::msgs::codec::{self, Codec, LengthPrefixedBuffer, ListLength, Reader, TlsListElement};
 use crate::msgs::enums::{
     CertificateStatusType, ClientCertificateType, Compression, ECCurveType, ECPointFormat,
-    EchVersion, ExtensionType, HpkeAead, HpkeKdf, HpkeKem, KeyUpdateRequest, NamedGroup,
-    PSKKeyExchangeMode, ServerNameType,
+    EchVersion, ExtensionType, HeartbeatMode, HpkeAead, HpkeKdf, HpkeKem, KeyUpdateRequest,
+    NamedGroup, PSKKeyExchangeMode, ServerNameType,
 };
 use crate::rand;
 use crate::verify::DigitallySignedStruct;
@@ -316,6 +316,16 @@ impl TlsListElement for ProtocolName {
     const SIZE_LEN: ListLength = ListLength::U16;
 }
 
+wrapped_payload!(
+    /// A serialized `SignedCertificateTimestamp`, as defined by RFC6962.
+    pub struct Sct,
+    PayloadU16,
+);
+
+impl TlsListElement for Sct {
+    const SIZE_LEN: ListLength = ListLength::U16;
+}
+
 pub(crate) trait ConvertProtocolNameList {
     fn from_slices(names: &[&[u8]]) -> Self;
     fn to_slices(&self) -> Vec<&[u8]>;
@@ -480,6 +490,32 @@ impl Codec for OcspCertificateStatusRequest {
     }
 }
 
+/// How many TLS1.3 tickets a client would like, sent in the
+/// [RFC9149] `ticket_request` extension.
+///
+/// [RFC9149]: https://datatracker.ietf.org/doc/html/rfc9149
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TicketRequest {
+    /// How many tickets to send after a full handshake.
+    pub new_session_count: u8,
+    /// How many tickets to send after a resumed handshake.
+    pub resumption_count: u8,
+}
+
+impl Codec for TicketRequest {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.new_session_count.encode(bytes);
+        self.resumption_count.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            new_session_count: u8::read(r)?,
+            resumption_count: u8::read(r)?,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum CertificateStatusRequest {
     Ocsp(OcspCertificateStatusRequest),
@@ -555,6 +591,10 @@ pub enum ClientExtension {
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    ApplicationSettings(Vec<ProtocolName>),
+    SignedCertificateTimestampRequest,
+    Heartbeat(HeartbeatMode),
+    TicketRequest(TicketRequest),
     Unknown(UnknownExtension),
 }
 
@@ -577,6 +617,10 @@ impl ClientExtension {
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
+            Self::ApplicationSettings(_) => ExtensionType::ApplicationSettings,
+            Self::SignedCertificateTimestampRequest => ExtensionType::SCT,
+            Self::Heartbeat(_) => ExtensionType::Heartbeat,
+            Self::TicketRequest(_) => ExtensionType::TicketRequest,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -594,9 +638,10 @@ impl Codec for ClientExtension {
             Self::ServerName(ref r) => r.encode(nested.buf),
             Self::SessionTicket(ClientSessionTicket::Request)
             | Self::ExtendedMasterSecretRequest
+            | Self::SignedCertificateTimestampRequest
             | Self::EarlyData => {}
             Self::SessionTicket(ClientSessionTicket::Offer(ref r)) => r.encode(nested.buf),
-            Self::Protocols(ref r) => r.encode(nested.buf),
+            Self::Protocols(ref r) | Self::ApplicationSettings(ref r) => r.encode(nested.buf),
             Self::SupportedVersions(ref r) => r.encode(nested.buf),
             Self::KeyShare(ref r) => r.encode(nested.buf),
             Self::PresharedKeyModes(ref r) => r.encode(nested.buf),
@@ -606,6 +651,8 @@ impl Codec for ClientExtension {
             Self::TransportParameters(ref r) | Self::TransportParametersDraft(ref r) => {
                 nested.buf.extend_from_slice(r);
             }
+            Self::Heartbeat(ref r) => r.encode(nested.buf),
+            Self::TicketRequest(ref r) => r.encode(nested.buf),
             Self::Unknown(ref r) => r.encode(nested.buf),
         }
     }
@@ -646,6 +693,10 @@ impl Codec for ClientExtension {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData if !sub.any_left() => Self::EarlyData,
+            ExtensionType::ApplicationSettings => Self::ApplicationSettings(Vec::read(&mut sub)?),
+            ExtensionType::SCT if !sub.any_left() => Self::SignedCertificateTimestampRequest,
+            ExtensionType::Heartbeat => Self::Heartbeat(HeartbeatMode::read(&mut sub)?),
+            ExtensionType::TicketRequest => Self::TicketRequest(TicketRequest::read(&mut sub)?),
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -702,6 +753,10 @@ pub enum ServerExtension {
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    ApplicationSettings(Payload),
+    SignedCertificateTimestamp(Vec<Sct>),
+    Heartbeat(HeartbeatMode),
+    TicketRequest(u8),
     Unknown(UnknownExtension),
 }
 
@@ -721,6 +776,10 @@ impl ServerExtension {
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
+            Self::ApplicationSettings(_) => ExtensionType::ApplicationSettings,
+            Self::SignedCertificateTimestamp(_) => ExtensionType::SCT,
+            Self::Heartbeat(_) => ExtensionType::Heartbeat,
+            Self::TicketRequest(_) => ExtensionType::TicketRequest,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -746,6 +805,10 @@ impl Codec for ServerExtension {
             Self::TransportParameters(ref r) | Self::TransportParametersDraft(ref r) => {
                 nested.buf.extend_from_slice(r);
             }
+            Self::ApplicationSettings(ref r) => r.encode(nested.buf),
+            Self::SignedCertificateTimestamp(ref r) => r.encode(nested.buf),
+            Self::Heartbeat(ref r) => r.encode(nested.buf),
+            Self::TicketRequest(r) => r.encode(nested.buf),
             Self::Unknown(ref r) => r.encode(nested.buf),
         }
     }
@@ -773,6 +836,12 @@ impl Codec for ServerExtension {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData => Self::EarlyData,
+            ExtensionType::ApplicationSettings => {
+                Self::ApplicationSettings(Payload::read(&mut sub))
+            }
+            ExtensionType::SCT => Self::SignedCertificateTimestamp(Vec::read(&mut sub)?),
+            ExtensionType::Heartbeat => Self::Heartbeat(HeartbeatMode::read(&mut sub)?),
+            ExtensionType::TicketRequest => Self::TicketRequest(u8::read(&mut sub)?),
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -939,6 +1008,38 @@ impl ClientHelloPayload {
         }
     }
 
+    pub(crate) fn get_alps_extension(&self) -> Option<&Vec<ProtocolName>> {
+        let ext = self.find_extension(ExtensionType::ApplicationSettings)?;
+        match *ext {
+            ClientExtension::ApplicationSettings(ref protocols) => Some(protocols),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_ticket_request_extension(&self) -> Option<TicketRequest> {
+        let ext = self.find_extension(ExtensionType::TicketRequest)?;
+        match *ext {
+            ClientExtension::TicketRequest(request) => Some(request),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_heartbeat_extension(&self) -> Option<HeartbeatMode> {
+        let ext = self.find_extension(ExtensionType::Heartbeat)?;
+        match *ext {
+            ClientExtension::Heartbeat(mode) => Some(mode),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_cookie_extension(&self) -> Option<&PayloadU16> {
+        let ext = self.find_extension(ExtensionType::Cookie)?;
+        match *ext {
+            ClientExtension::Cookie(ref ck) => Some(ck),
+            _ => None,
+        }
+    }
+
     pub fn get_keyshare_extension(&self) -> Option<&[KeyShareEntry]> {
         let ext = self.find_extension(ExtensionType::KeyShare)?;
         match *ext {
@@ -1160,12 +1261,12 @@ impl HelloRetryRequest {
 
 #[derive(Debug)]
 pub struct ServerHelloPayload {
-    pub(crate) legacy_version: ProtocolVersion,
-    pub(crate) random: Random,
-    pub(crate) session_id: SessionId,
-    pub(crate) cipher_suite: CipherSuite,
-    pub(crate) compression_method: Compression,
-    pub(crate) extensions: Vec<ServerExtension>,
+    pub legacy_version: ProtocolVersion,
+    pub random: Random,
+    pub session_id: SessionId,
+    pub cipher_suite: CipherSuite,
+    pub compression_method: Compression,
+    pub extensions: Vec<ServerExtension>,
 }
 
 impl Codec for ServerHelloPayload {
@@ -1283,9 +1384,11 @@ impl TlsListElement for CertificateDer<'_> {
 // That's annoying. It means the parsing is not
 // context-free any more.
 
+/// An extension carried by a TLS1.3 [`CertificateEntry`].
 #[derive(Debug)]
-pub(crate) enum CertificateExtension {
+pub enum CertificateExtension {
     CertificateStatus(CertificateStatus),
+    SignedCertificateTimestamp(Vec<Sct>),
     Unknown(UnknownExtension),
 }
 
@@ -1293,6 +1396,7 @@ impl CertificateExtension {
     pub(crate) fn get_type(&self) -> ExtensionType {
         match *self {
             Self::CertificateStatus(_) => ExtensionType::StatusRequest,
+            Self::SignedCertificateTimestamp(_) => ExtensionType::SCT,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -1303,6 +1407,13 @@ impl CertificateExtension {
             _ => None,
         }
     }
+
+    pub(crate) fn get_sct_list(&self) -> Option<&[Sct]> {
+        match *self {
+            Self::SignedCertificateTimestamp(ref sctl) => Some(sctl),
+            _ => None,
+        }
+    }
 }
 
 impl Codec for CertificateExtension {
@@ -1312,6 +1423,7 @@ impl Codec for CertificateExtension {
         let nested = LengthPrefixedBuffer::new(ListLength::U16, bytes);
         match *self {
             Self::CertificateStatus(ref r) => r.encode(nested.buf),
+            Self::SignedCertificateTimestamp(ref r) => r.encode(nested.buf),
             Self::Unknown(ref r) => r.encode(nested.buf),
         }
     }
@@ -1326,6 +1438,7 @@ impl Codec for CertificateExtension {
                 let st = CertificateStatus::read(&mut sub)?;
                 Self::CertificateStatus(st)
             }
+            ExtensionType::SCT => Self::SignedCertificateTimestamp(Vec::read(&mut sub)?),
             _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -1338,10 +1451,11 @@ impl TlsListElement for CertificateExtension {
     const SIZE_LEN: ListLength = ListLength::U16;
 }
 
+/// One certificate in a TLS1.3 Certificate message, with its extensions.
 #[derive(Debug)]
-pub(crate) struct CertificateEntry {
-    pub(crate) cert: CertificateDer<'static>,
-    pub(crate) exts: Vec<CertificateExtension>,
+pub struct CertificateEntry {
+    pub cert: CertificateDer<'static>,
+    pub exts: Vec<CertificateExtension>,
 }
 
 impl Codec for CertificateEntry {
@@ -1382,9 +1496,9 @@ impl CertificateEntry {
     }
 
     pub(crate) fn has_unknown_extension(&self) -> bool {
-        self.exts
-            .iter()
-            .any(|ext| ext.get_type() != ExtensionType::StatusRequest)
+        self.exts.iter().any(|ext| {
+            ext.get_type() != ExtensionType::StatusRequest && ext.get_type() != ExtensionType::SCT
+        })
     }
 
     pub(crate) fn get_ocsp_response(&self) -> Option<&Vec<u8>> {
@@ -1393,16 +1507,24 @@ impl CertificateEntry {
             .find(|ext| ext.get_type() == ExtensionType::StatusRequest)
             .and_then(CertificateExtension::get_cert_status)
     }
+
+    pub(crate) fn get_scts(&self) -> Option<&[Sct]> {
+        self.exts
+            .iter()
+            .find(|ext| ext.get_type() == ExtensionType::SCT)
+            .and_then(CertificateExtension::get_sct_list)
+    }
 }
 
 impl TlsListElement for CertificateEntry {
     const SIZE_LEN: ListLength = ListLength::U24 { max: 0x1_0000 };
 }
 
+/// The body of a TLS1.3 Certificate message.
 #[derive(Debug)]
 pub struct CertificatePayloadTls13 {
-    pub(crate) context: PayloadU8,
-    pub(crate) entries: Vec<CertificateEntry>,
+    pub context: PayloadU8,
+    pub entries: Vec<CertificateEntry>,
 }
 
 impl Codec for CertificatePayloadTls13 {
@@ -1457,6 +1579,24 @@ impl CertificatePayloadTls13 {
         false
     }
 
+    pub(crate) fn any_entry_has_sct_list(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.get_scts().is_some())
+    }
+
+    pub(crate) fn get_end_entity_scts(&self) -> Vec<Vec<u8>> {
+        self.entries
+            .first()
+            .and_then(CertificateEntry::get_scts)
+            .map(|scts| {
+                scts.iter()
+                    .map(|sct| sct.as_ref().to_vec())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     pub(crate) fn get_end_entity_ocsp(&self) -> Vec<u8> {
         self.entries
             .first()
@@ -1476,6 +1616,9 @@ impl CertificatePayloadTls13 {
 }
 
 /// Describes supported key exchange mechanisms.
+///
+/// Static RSA key exchange is deliberately not supported: suites without
+/// forward secrecy (`TLS_RSA_WITH_*`) are never negotiated.
 #[derive(Clone, Copy, Debug, PartialEq)]
 #[non_exhaustive]
 pub enum KeyExchangeAlgorithm {
@@ -1668,6 +1811,30 @@ pub(crate) trait HasServerExtensions {
         }
     }
 
+    fn get_heartbeat_mode(&self) -> Option<HeartbeatMode> {
+        let ext = self.find_extension(ExtensionType::Heartbeat)?;
+        match *ext {
+            ServerExtension::Heartbeat(mode) => Some(mode),
+            _ => None,
+        }
+    }
+
+    fn get_alps_settings(&self) -> Option<&[u8]> {
+        let ext = self.find_extension(ExtensionType::ApplicationSettings)?;
+        match *ext {
+            ServerExtension::ApplicationSettings(ref settings) => Some(&settings.0),
+            _ => None,
+        }
+    }
+
+    fn get_sct_list(&self) -> Option<&[Sct]> {
+        let ext = self.find_extension(ExtensionType::SCT)?;
+        match *ext {
+            ServerExtension::SignedCertificateTimestamp(ref sctl) => Some(sctl),
+            _ => None,
+        }
+    }
+
     fn get_quic_params_extension(&self) -> Option<Vec<u8>> {
         let ext = self
             .find_extension(ExtensionType::TransportParameters)
@@ -2080,6 +2247,7 @@ pub enum HandshakePayload {
     NewSessionTicket(NewSessionTicketPayload),
     NewSessionTicketTls13(NewSessionTicketPayloadTls13),
     EncryptedExtensions(Vec<ServerExtension>),
+    ClientEncryptedExtensions(Vec<ServerExtension>),
     KeyUpdate(KeyUpdateRequest),
     Finished(Payload),
     CertificateStatus(CertificateStatus),
@@ -2104,7 +2272,7 @@ impl HandshakePayload {
             CertificateVerify(ref x) => x.encode(bytes),
             NewSessionTicket(ref x) => x.encode(bytes),
             NewSessionTicketTls13(ref x) => x.encode(bytes),
-            EncryptedExtensions(ref x) => x.encode(bytes),
+            EncryptedExtensions(ref x) | ClientEncryptedExtensions(ref x) => x.encode(bytes),
             KeyUpdate(ref x) => x.encode(bytes),
             Finished(ref x) => x.encode(bytes),
             CertificateStatus(ref x) => x.encode(bytes),
@@ -2139,10 +2307,10 @@ impl Codec for HandshakeMessagePayload {
 }
 
 impl HandshakeMessagePayload {
-    pub(crate) fn read_version(
-        r: &mut Reader,
-        vers: ProtocolVersion,
-    ) -> Result<Self, InvalidMessage> {
+    /// Decode a handshake message, as sent in protocol version `vers`.
+    ///
+    /// Some messages (such as Certificate) are encoded differently in TLS1.3.
+    pub fn read_version(r: &mut Reader, vers: ProtocolVersion) -> Result<Self, InvalidMessage> {
         let mut typ = HandshakeType::read(r)?;
         let len = codec::u24::read(r)?.0 as usize;
         let mut sub = r.sub(len)?;
@@ -2208,6 +2376,9 @@ impl HandshakeMessagePayload {
             HandshakeType::EncryptedExtensions => {
                 HandshakePayload::EncryptedExtensions(Vec::read(&mut sub)?)
             }
+            HandshakeType::ClientEncryptedExtensions => {
+                HandshakePayload::ClientEncryptedExtensions(Vec::read(&mut sub)?)
+            }
             HandshakeType::KeyUpdate => {
                 HandshakePayload::KeyUpdate(KeyUpdateRequest::read(&mut sub)?)
             }
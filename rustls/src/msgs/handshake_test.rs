@@ -2,7 +2,7 @@ use crate::enums::{CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme}
 use crate::msgs::base::{Payload, PayloadU16, PayloadU24, PayloadU8};
 use crate::msgs::codec::{put_u16, Codec, Reader};
 use crate::msgs::enums::{
-    ClientCertificateType, Compression, ECCurveType, ECPointFormat, ExtensionType,
+    ClientCertificateType, Compression, ECCurveType, ECPointFormat, ExtensionType, HeartbeatMode,
     KeyUpdateRequest, NamedGroup, PSKKeyExchangeMode, ServerNameType,
 };
 use crate::msgs::handshake::{
@@ -13,9 +13,9 @@ use crate::msgs::handshake::{
     EcParameters, EcdheServerKeyExchange, HandshakeMessagePayload, HandshakePayload,
     HasServerExtensions, HelloRetryExtension, HelloRetryRequest, KeyShareEntry,
     NewSessionTicketExtension, NewSessionTicketPayload, NewSessionTicketPayloadTls13,
-    PresharedKeyBinder, PresharedKeyIdentity, PresharedKeyOffer, ProtocolName, Random,
+    PresharedKeyBinder, PresharedKeyIdentity, PresharedKeyOffer, ProtocolName, Random, Sct,
     ServerEcdhParams, ServerExtension, ServerHelloPayload, ServerKeyExchangePayload, SessionId,
-    UnknownExtension,
+    TicketRequest, UnknownExtension,
 };
 use crate::verify::DigitallySignedStruct;
 
@@ -389,6 +389,13 @@ fn get_sample_clienthellopayload() -> ClientHelloPayload {
             ClientExtension::ExtendedMasterSecretRequest,
             ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
             ClientExtension::TransportParameters(vec![1, 2, 3]),
+            ClientExtension::ApplicationSettings(vec![ProtocolName::from(b"h2".to_vec())]),
+            ClientExtension::SignedCertificateTimestampRequest,
+            ClientExtension::Heartbeat(HeartbeatMode::PeerAllowedToSend),
+            ClientExtension::TicketRequest(TicketRequest {
+                new_session_count: 2,
+                resumption_count: 1,
+            }),
             ClientExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -759,6 +766,9 @@ fn get_sample_serverhellopayload() -> ServerHelloPayload {
             ServerExtension::CertificateStatusAck,
             ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
             ServerExtension::TransportParameters(vec![1, 2, 3]),
+            ServerExtension::SignedCertificateTimestamp(vec![Sct::from(vec![1, 2, 3])]),
+            ServerExtension::Heartbeat(HeartbeatMode::PeerNotAllowedToSend),
+            ServerExtension::TicketRequest(2),
             ServerExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -803,6 +813,7 @@ fn get_sample_certificatepayloadtls13() -> CertificatePayloadTls13 {
                 CertificateExtension::CertificateStatus(CertificateStatus {
                     ocsp_response: PayloadU24(vec![1, 2, 3]),
                 }),
+                CertificateExtension::SignedCertificateTimestamp(vec![Sct::from(vec![1, 2, 3])]),
                 CertificateExtension::Unknown(UnknownExtension {
                     typ: ExtensionType::Unknown(12345),
                     payload: Payload(vec![1, 2, 3]),
@@ -1076,6 +1087,12 @@ fn get_all_tls13_handshake_payloads() -> Vec<HandshakeMessagePayload> {
             typ: HandshakeType::EncryptedExtensions,
             payload: HandshakePayload::EncryptedExtensions(get_sample_encryptedextensions()),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::ClientEncryptedExtensions,
+            payload: HandshakePayload::ClientEncryptedExtensions(vec![
+                ServerExtension::ApplicationSettings(Payload(vec![1, 2, 3])),
+            ]),
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::KeyUpdate,
             payload: HandshakePayload::KeyUpdate(KeyUpdateRequest::UpdateRequested),
@@ -0,0 +1,312 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+
+use super::base::Payload;
+use super::deframer::payload_size;
+use super::message::PlainMessage;
+use crate::enums::{ContentType, ProtocolVersion};
+use crate::error::{Error, InvalidMessage};
+
+/// Reassembles handshake messages from the payloads of handshake records.
+///
+/// A record may contain several handshake messages, and a handshake message
+/// may be split over several records.  Records go in with [`HandshakeJoiner::push`],
+/// and complete handshake messages come out of [`HandshakeJoiner::pop`], each as
+/// a `PlainMessage` holding exactly one message.
+///
+/// Handshake messages larger than the limit applied by `MessageDeframer`
+/// (64KB) are rejected as soon as their header is seen.
+///
+/// Unframed handshake bytes, such as those seen by a proxy, can be fed in
+/// arbitrarily small chunks with [`HandshakeJoiner::push_bytes`].  Use
+/// [`HandshakeJoiner::with_max_buffered`] to bound the memory this uses.
+#[derive(Debug)]
+pub struct HandshakeJoiner {
+    /// Buffered handshake payload bytes, beginning at a message boundary.
+    buf: Vec<u8>,
+
+    /// The record version of the first record contributing to `buf`.
+    version: ProtocolVersion,
+
+    /// The most bytes `buf` may hold, if limited.
+    max_buffered: Option<usize>,
+}
+
+impl HandshakeJoiner {
+    /// Make a new, empty, joiner.
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            version: ProtocolVersion::TLSv1_2,
+            max_buffered: None,
+        }
+    }
+
+    /// Make a new, empty, joiner which refuses to buffer more than
+    /// `max_buffered` bytes.
+    ///
+    /// A handshake message that cannot fit in `max_buffered` bytes is rejected
+    /// as soon as its header is seen, as is input that would exceed the limit
+    /// because complete messages have not been taken with [`HandshakeJoiner::pop`].
+    pub fn with_max_buffered(max_buffered: usize) -> Self {
+        Self {
+            max_buffered: Some(max_buffered),
+            ..Self::new()
+        }
+    }
+
+    /// Returns true if no partial or complete messages are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Add the payload of the handshake record `msg`.
+    ///
+    /// This fails if `msg` is not a handshake record, or if it starts a
+    /// handshake message that is too large.
+    pub fn push(&mut self, msg: PlainMessage) -> Result<(), Error> {
+        if msg.typ != ContentType::Handshake {
+            return Err(Error::InappropriateMessage {
+                expect_types: vec![ContentType::Handshake],
+                got_type: msg.typ,
+            });
+        }
+
+        if self.buf.is_empty() {
+            self.version = msg.version;
+        }
+        self.push_bytes(&msg.payload.0)
+    }
+
+    /// Add a chunk of handshake messages that are not framed in records.
+    ///
+    /// `chunk` may contain any part of one or more messages, down to a single
+    /// byte.  This fails if the buffered data would exceed the limit given to
+    /// [`HandshakeJoiner::with_max_buffered`], or if `chunk` starts a handshake
+    /// message that is too large.
+    pub fn push_bytes(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        if let Some(max) = self.max_buffered {
+            if self.buf.len() + chunk.len() > max {
+                return Err(Error::InvalidMessage(
+                    InvalidMessage::HandshakePayloadTooLarge,
+                ));
+            }
+        }
+
+        self.buf.extend_from_slice(chunk);
+
+        // Check the message we're currently joining.  Any messages after that
+        // are checked as they reach the front of the buffer.
+        self.check_front()
+    }
+
+    /// Take the next complete handshake message, if there is one.
+    pub fn pop(&mut self) -> Result<Option<PlainMessage>, Error> {
+        let len = match payload_size(&self.buf)? {
+            Some(len) if len <= self.buf.len() => len,
+            _ => return Ok(None),
+        };
+
+        let rest = self.buf.split_off(len);
+        let message = mem::replace(&mut self.buf, rest);
+        let version = self.version;
+        self.check_front()?;
+
+        Ok(Some(PlainMessage {
+            typ: ContentType::Handshake,
+            version,
+            payload: Payload::new(message),
+        }))
+    }
+
+    /// Reject the message at the front of `buf` if it is too large.
+    fn check_front(&self) -> Result<(), Error> {
+        match (payload_size(&self.buf)?, self.max_buffered) {
+            (Some(len), Some(max)) if len > max => Err(Error::InvalidMessage(
+                InvalidMessage::HandshakePayloadTooLarge,
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for HandshakeJoiner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HandshakeJoiner;
+    use crate::enums::{CipherSuite, ContentType, ProtocolVersion};
+    use crate::error::{Error, InvalidMessage};
+    use crate::msgs::base::Payload;
+    use crate::msgs::handshake::HandshakePayload;
+    use crate::msgs::message::{Message, MessagePayload, PlainMessage};
+
+    fn handshake_record(payload: &[u8]) -> PlainMessage {
+        PlainMessage {
+            typ: ContentType::Handshake,
+            version: ProtocolVersion::TLSv1_2,
+            payload: Payload::new(payload),
+        }
+    }
+
+    fn assert_finished(msg: PlainMessage, verify_data: &[u8]) {
+        match Message::try_from(msg).unwrap().payload {
+            MessagePayload::Handshake { encoded, .. } => {
+                assert_eq!(&encoded.0[4..], verify_data);
+            }
+            other => panic!("unexpected payload {:?}", other),
+        }
+    }
+
+    #[test]
+    fn single_message_split_across_records() {
+        let mut joiner = HandshakeJoiner::new();
+        joiner
+            .push(handshake_record(b"\x14\x00\x00\x04ab"))
+            .unwrap();
+        assert!(joiner.pop().unwrap().is_none());
+        assert!(!joiner.is_empty());
+
+        joiner
+            .push(handshake_record(b"cd"))
+            .unwrap();
+        assert_finished(joiner.pop().unwrap().unwrap(), b"abcd");
+        assert!(joiner.pop().unwrap().is_none());
+        assert!(joiner.is_empty());
+    }
+
+    #[test]
+    fn two_messages_packed_into_one_record() {
+        let mut joiner = HandshakeJoiner::new();
+        joiner
+            .push(handshake_record(
+                b"\x14\x00\x00\x04abcd\x14\x00\x00\x04efgh",
+            ))
+            .unwrap();
+        assert_finished(joiner.pop().unwrap().unwrap(), b"abcd");
+        assert_finished(joiner.pop().unwrap().unwrap(), b"efgh");
+        assert!(joiner.pop().unwrap().is_none());
+        assert!(joiner.is_empty());
+    }
+
+    #[test]
+    fn message_split_over_header_boundary() {
+        let mut joiner = HandshakeJoiner::new();
+        joiner
+            .push(handshake_record(b"\x14\x00\x00\x04abcd\x14\x00"))
+            .unwrap();
+        assert_finished(joiner.pop().unwrap().unwrap(), b"abcd");
+        assert!(joiner.pop().unwrap().is_none());
+
+        joiner
+            .push(handshake_record(b"\x00\x04efgh"))
+            .unwrap();
+        assert_finished(joiner.pop().unwrap().unwrap(), b"efgh");
+        assert!(joiner.is_empty());
+    }
+
+    fn client_hello_bytes() -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0x5a; 32]); // random
+        body.push(0x00); // session id
+        body.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]); // cipher suites
+        body.extend_from_slice(&[0x01, 0x00]); // compression methods
+        body.extend_from_slice(&[0x00, 0x07, 0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04]); // supported_versions
+
+        let mut msg = vec![0x01, 0x00, 0x00, body.len() as u8];
+        msg.extend_from_slice(&body);
+        msg
+    }
+
+    #[test]
+    fn client_hello_fed_one_byte_at_a_time() {
+        let encoded = client_hello_bytes();
+        let mut joiner = HandshakeJoiner::with_max_buffered(encoded.len());
+
+        let (last, init) = encoded.split_last().unwrap();
+        for byte in init {
+            joiner.push_bytes(&[*byte]).unwrap();
+            assert!(joiner.pop().unwrap().is_none());
+        }
+        joiner.push_bytes(&[*last]).unwrap();
+
+        let msg = Message::try_from(joiner.pop().unwrap().unwrap()).unwrap();
+        match msg.payload {
+            MessagePayload::Handshake {
+                parsed,
+                encoded: enc,
+            } => {
+                assert_eq!(enc.0, encoded);
+                match parsed.payload {
+                    HandshakePayload::ClientHello(ch) => {
+                        assert_eq!(
+                            ch.cipher_suites,
+                            vec![CipherSuite::TLS13_AES_128_GCM_SHA256]
+                        );
+                    }
+                    other => panic!("unexpected handshake payload {:?}", other),
+                }
+            }
+            other => panic!("unexpected payload {:?}", other),
+        }
+        assert!(joiner.is_empty());
+    }
+
+    #[test]
+    fn rejects_input_beyond_max_buffered() {
+        // a message too large for the limit is rejected once its header is seen
+        let mut joiner = HandshakeJoiner::with_max_buffered(16);
+        assert_eq!(
+            joiner.push_bytes(b"\x14\x00\x00\x20"),
+            Err(Error::InvalidMessage(
+                InvalidMessage::HandshakePayloadTooLarge
+            ))
+        );
+
+        // as is input that overflows the buffer before complete messages are taken
+        let mut joiner = HandshakeJoiner::with_max_buffered(12);
+        joiner
+            .push_bytes(b"\x14\x00\x00\x04abcd\x14\x00\x00\x04")
+            .unwrap();
+        assert_eq!(
+            joiner.push_bytes(b"efgh"),
+            Err(Error::InvalidMessage(
+                InvalidMessage::HandshakePayloadTooLarge
+            ))
+        );
+        assert_finished(joiner.pop().unwrap().unwrap(), b"abcd");
+        joiner.push_bytes(b"efgh").unwrap();
+        assert_finished(joiner.pop().unwrap().unwrap(), b"efgh");
+    }
+
+    #[test]
+    fn rejects_non_handshake_records() {
+        let mut joiner = HandshakeJoiner::new();
+        let mut msg = handshake_record(b"\x01");
+        msg.typ = ContentType::ChangeCipherSpec;
+        assert_eq!(
+            joiner.push(msg),
+            Err(Error::InappropriateMessage {
+                expect_types: vec![ContentType::Handshake],
+                got_type: ContentType::ChangeCipherSpec,
+            })
+        );
+        assert!(joiner.is_empty());
+    }
+
+    #[test]
+    fn rejects_oversized_messages() {
+        let mut joiner = HandshakeJoiner::new();
+        assert_eq!(
+            joiner.push(handshake_record(b"\x01\x01\x00\x00")),
+            Err(Error::InvalidMessage(
+                InvalidMessage::HandshakePayloadTooLarge
+            ))
+        );
+    }
+}
@@ -9,6 +9,7 @@ use crate::msgs::enums::AlertLevel;
 use crate::msgs::fragmenter::MAX_FRAGMENT_LEN;
 use crate::msgs::handshake::HandshakeMessagePayload;
 
+use alloc::vec;
 use alloc::vec::Vec;
 
 #[derive(Debug)]
@@ -102,6 +103,20 @@ impl OpaqueMessage {
         }
     }
 
+    /// Construct an `OpaqueMessage` from raw wire values, for fuzzing.
+    ///
+    /// Unlike [`OpaqueMessage::read`], no checks are made on the content type,
+    /// version or payload length, so arbitrary fuzzer input can reach the
+    /// decryption and state machine code directly.
+    #[cfg(fuzzing)]
+    pub fn from_raw(typ: u8, version: u16, payload: &[u8]) -> Self {
+        Self::new(
+            ContentType::from(typ),
+            ProtocolVersion::from(version),
+            payload.to_vec(),
+        )
+    }
+
     /// Access the message payload as a slice.
     pub fn payload(&self) -> &[u8] {
         &self.payload.0
@@ -115,47 +130,50 @@ impl OpaqueMessage {
     /// `MessageError` allows callers to distinguish between valid prefixes (might
     /// become valid if we read more data) and invalid data.
     pub fn read(r: &mut Reader) -> Result<Self, MessageError> {
-        let typ = ContentType::read(r).map_err(|_| MessageError::TooShortForHeader)?;
-        // Don't accept any new content-types.
-        if let ContentType::Unknown(_) = typ {
-            return Err(MessageError::InvalidContentType);
-        }
-
-        let version = ProtocolVersion::read(r).map_err(|_| MessageError::TooShortForHeader)?;
-        // Accept only versions 0x03XX for any XX.
-        match version {
-            ProtocolVersion::Unknown(ref v) if (v & 0xff00) != 0x0300 => {
-                return Err(MessageError::UnknownProtocolVersion);
-            }
-            _ => {}
-        };
-
-        let len = u16::read(r).map_err(|_| MessageError::TooShortForHeader)?;
-
-        // Reject undersize messages
-        //  implemented per section 5.1 of RFC8446 (TLSv1.3)
-        //              per section 6.2.1 of RFC5246 (TLSv1.2)
-        if typ != ContentType::ApplicationData && len == 0 {
-            return Err(MessageError::InvalidEmptyPayload);
-        }
+        Self::read_with_needed(r).map_err(|(err, _)| err)
+    }
 
-        // Reject oversize messages
-        if len >= Self::MAX_PAYLOAD {
-            return Err(MessageError::MessageTooLarge);
-        }
+    /// Like [`OpaqueMessage::read`], but errors also carry the number of bytes
+    /// needed to complete the record.
+    ///
+    /// For `TooShortForHeader` this is the count of missing header bytes, and for
+    /// `TooShortForLength` the count of missing payload bytes.  It is zero for
+    /// other errors, which more data cannot fix.
+    pub fn read_with_needed(r: &mut Reader) -> Result<Self, (MessageError, usize)> {
+        let header = RecordHeader::read(r)?;
 
+        let missing = usize::from(header.len).saturating_sub(r.left());
         let mut sub = r
-            .sub(len as usize)
-            .map_err(|_| MessageError::TooShortForLength)?;
+            .sub(header.len as usize)
+            .map_err(|_| (MessageError::TooShortForLength, missing))?;
         let payload = Payload::read(&mut sub);
 
         Ok(Self {
-            typ,
-            version,
+            typ: header.typ,
+            version: header.version,
             payload,
         })
     }
 
+    /// Build the `ChangeCipherSpec` record sent for middlebox compatibility.
+    ///
+    /// See RFC8446 appendix D.4.
+    pub fn build_ccs() -> Self {
+        Self::new(
+            ContentType::ChangeCipherSpec,
+            ProtocolVersion::TLSv1_2,
+            vec![0x01],
+        )
+    }
+
+    /// How many more bytes `r` needs before a full record header can be read.
+    ///
+    /// This is zero once the header is complete, and otherwise between one and
+    /// five.  It says nothing about whether the header is valid.
+    pub fn header_bytes_needed(r: &Reader) -> usize {
+        usize::from(Self::HEADER_SIZE).saturating_sub(r.left())
+    }
+
     pub fn encode(self) -> Vec<u8> {
         let mut buf = Vec::new();
         self.typ.encode(&mut buf);
@@ -335,6 +353,7 @@ impl TryFrom<PlainMessage> for Message {
 ///
 /// This type also cannot decode its internals and
 /// cannot be read/encoded; only `OpaqueMessage` can do that.
+#[derive(Clone, Copy, Debug)]
 pub struct BorrowedPlainMessage<'a> {
     pub typ: ContentType,
     pub version: ProtocolVersion,
@@ -351,7 +370,59 @@ impl<'a> BorrowedPlainMessage<'a> {
     }
 }
 
-#[derive(Debug)]
+/// The header of a TLS record: its content type, version and payload length.
+///
+/// Decoding this only inspects the header bytes, and never allocates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordHeader {
+    pub typ: ContentType,
+    pub version: ProtocolVersion,
+    pub len: u16,
+}
+
+impl RecordHeader {
+    /// Decode and validate a record header, without reading the payload.
+    ///
+    /// Errors carry the number of bytes needed to complete the header, as
+    /// with [`OpaqueMessage::read_with_needed`].
+    pub fn read(r: &mut Reader) -> Result<Self, (MessageError, usize)> {
+        let header_needed = OpaqueMessage::header_bytes_needed(r);
+        let too_short_for_header = |_| (MessageError::TooShortForHeader, header_needed);
+
+        let typ = ContentType::read(r).map_err(too_short_for_header)?;
+        // Don't accept any new content-types.
+        if let ContentType::Unknown(_) = typ {
+            return Err((MessageError::InvalidContentType, 0));
+        }
+
+        let version = ProtocolVersion::read(r).map_err(too_short_for_header)?;
+        // Accept only versions 0x03XX for any XX.
+        match version {
+            ProtocolVersion::Unknown(ref v) if (v & 0xff00) != 0x0300 => {
+                return Err((MessageError::UnknownProtocolVersion, 0));
+            }
+            _ => {}
+        };
+
+        let len = u16::read(r).map_err(too_short_for_header)?;
+
+        // Reject undersize messages
+        //  implemented per section 5.1 of RFC8446 (TLSv1.3)
+        //              per section 6.2.1 of RFC5246 (TLSv1.2)
+        if typ != ContentType::ApplicationData && len == 0 {
+            return Err((MessageError::InvalidEmptyPayload, 0));
+        }
+
+        // Reject oversize messages
+        if len >= OpaqueMessage::MAX_PAYLOAD {
+            return Err((MessageError::MessageTooLarge, 0));
+        }
+
+        Ok(Self { typ, version, len })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MessageError {
     TooShortForHeader,
     TooShortForLength,
@@ -360,3 +431,17 @@ pub enum MessageError {
     InvalidContentType,
     UnknownProtocolVersion,
 }
+
+impl From<MessageError> for InvalidMessage {
+    fn from(err: MessageError) -> Self {
+        match err {
+            MessageError::TooShortForHeader | MessageError::TooShortForLength => {
+                Self::MessageTooShort
+            }
+            MessageError::InvalidEmptyPayload => Self::InvalidEmptyPayload,
+            MessageError::MessageTooLarge => Self::MessageTooLarge,
+            MessageError::InvalidContentType => Self::InvalidContentType,
+            MessageError::UnknownProtocolVersion => Self::UnknownProtocolVersion,
+        }
+    }
+}
@@ -1,10 +1,12 @@
-use crate::enums::{AlertDescription, HandshakeType};
+use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
 use crate::msgs::base::{PayloadU16, PayloadU24, PayloadU8};
 
 use super::base::Payload;
 use super::codec::Reader;
 use super::enums::AlertLevel;
-use super::message::{Message, OpaqueMessage, PlainMessage};
+use super::message::{
+    Message, MessageError, MessagePayload, OpaqueMessage, PlainMessage, RecordHeader,
+};
 
 use std::fs;
 use std::io::Read;
@@ -105,3 +107,116 @@ fn debug_payload() {
     assert_eq!("01020304", format!("{:?}", PayloadU16(vec![1, 2, 3, 4])));
     assert_eq!("01020304", format!("{:?}", PayloadU24(vec![1, 2, 3, 4])));
 }
+
+#[test]
+fn test_header_bytes_needed() {
+    assert_eq!(OpaqueMessage::header_bytes_needed(&Reader::init(&[])), 5);
+    assert_eq!(
+        OpaqueMessage::header_bytes_needed(&Reader::init(&[0x17, 0x03])),
+        3
+    );
+    assert_eq!(
+        OpaqueMessage::header_bytes_needed(&Reader::init(&[0x17, 0x03, 0x03, 0x00, 0x01])),
+        0
+    );
+    assert_eq!(
+        OpaqueMessage::header_bytes_needed(&Reader::init(&[0x17, 0x03, 0x03, 0x00, 0x01, 0xff])),
+        0
+    );
+
+    // only the unread part of the reader counts
+    let mut r = Reader::init(&[0x17, 0x03, 0x03]);
+    r.take(1);
+    assert_eq!(OpaqueMessage::header_bytes_needed(&r), 3);
+}
+
+#[test]
+fn test_record_header_decodes_without_allocating() {
+    // only `core` types are touched here: a fixed buffer, `Reader` and integers
+    let bytes: [u8; 7] = [0x17, 0x03, 0x03, 0x00, 0x02, 0xaa, 0xbb];
+    let mut r = Reader::init(&bytes);
+    assert_eq!(
+        RecordHeader::read(&mut r),
+        Ok(RecordHeader {
+            typ: ContentType::ApplicationData,
+            version: ProtocolVersion::TLSv1_2,
+            len: 2,
+        })
+    );
+    assert_eq!(r.left(), 2);
+
+    assert_eq!(
+        RecordHeader::read(&mut Reader::init(&bytes[..4])),
+        Err((MessageError::TooShortForHeader, 1))
+    );
+    assert_eq!(
+        RecordHeader::read(&mut Reader::init(&[0x16, 0x03, 0x03, 0x00, 0x00])),
+        Err((MessageError::InvalidEmptyPayload, 0))
+    );
+    assert_eq!(
+        RecordHeader::read(&mut Reader::init(&[0x16, 0x03, 0x03, 0xff, 0xff])),
+        Err((MessageError::MessageTooLarge, 0))
+    );
+}
+
+#[test]
+fn test_read_with_needed_reports_shortfall() {
+    // handshake record claiming a 16 byte payload, with only 6 present
+    let mut bytes = vec![0x16, 0x03, 0x03, 0x00, 0x10];
+    bytes.extend_from_slice(&[0u8; 6]);
+
+    match OpaqueMessage::read_with_needed(&mut Reader::init(&bytes)) {
+        Err((MessageError::TooShortForLength, missing)) => assert_eq!(missing, 10),
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    match OpaqueMessage::read_with_needed(&mut Reader::init(&bytes[..2])) {
+        Err((MessageError::TooShortForHeader, missing)) => assert_eq!(missing, 3),
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    bytes[0] = 0xff;
+    match OpaqueMessage::read_with_needed(&mut Reader::init(&bytes)) {
+        Err((MessageError::InvalidContentType, missing)) => assert_eq!(missing, 0),
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    // the plain `read` is unaffected
+    bytes[0] = 0x16;
+    assert!(matches!(
+        OpaqueMessage::read(&mut Reader::init(&bytes)),
+        Err(MessageError::TooShortForLength)
+    ));
+}
+
+#[test]
+fn test_build_ccs() {
+    assert_eq!(
+        OpaqueMessage::build_ccs().encode(),
+        vec![0x14, 0x03, 0x03, 0x00, 0x01, 0x01]
+    );
+
+    let plain = OpaqueMessage::build_ccs().into_plain_message();
+    assert!(matches!(
+        Message::try_from(plain)
+            .unwrap()
+            .payload,
+        MessagePayload::ChangeCipherSpec(_)
+    ));
+}
+
+#[cfg(fuzzing)]
+#[test]
+fn test_from_raw_skips_validation() {
+    use crate::enums::{ContentType, ProtocolVersion};
+
+    let payload = vec![0u8; 0x10000];
+    let msg = OpaqueMessage::from_raw(0xff, 0x1234, &payload);
+    assert_eq!(msg.typ, ContentType::Unknown(0xff));
+    assert_eq!(msg.version, ProtocolVersion::Unknown(0x1234));
+    assert_eq!(msg.payload(), &payload[..]);
+
+    // the same bytes would be rejected by `read`
+    let encoded = msg.encode();
+    assert!(OpaqueMessage::read(&mut Reader::init(&encoded)).is_err());
+}
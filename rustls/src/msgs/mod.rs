@@ -12,6 +12,7 @@ pub(crate) mod deframer;
 pub(crate) mod enums;
 pub(crate) mod fragmenter;
 pub(crate) mod handshake;
+pub(crate) mod hsjoiner;
 pub(crate) mod message;
 pub(crate) mod persist;
 
@@ -124,6 +124,39 @@ impl Tls13ClientSessionValue {
     pub fn quic_params(&self) -> Vec<u8> {
         self.quic_params.0.clone()
     }
+
+    /// Encodes this value, preceded by its protocol version and cipher suite.
+    ///
+    /// The remainder is decoded by [`Tls13ClientSessionValue::read_state`].
+    pub(crate) fn get_state_encoding(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::new());
+        ProtocolVersion::TLSv1_3.encode(&mut bytes);
+        self.suite
+            .common
+            .suite
+            .encode(&mut bytes);
+        self.age_add.encode(&mut bytes);
+        self.max_early_data_size
+            .encode(&mut bytes);
+        self.common.encode(&mut bytes);
+        self.quic_params.encode(&mut bytes);
+        bytes
+    }
+
+    /// Decodes a value encoded by [`Tls13ClientSessionValue::get_state_encoding`],
+    /// whose protocol version and cipher suite the caller has already read.
+    pub(crate) fn read_state(
+        suite: &'static Tls13CipherSuite,
+        r: &mut Reader,
+    ) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            suite,
+            age_add: u32::read(r)?,
+            max_early_data_size: u32::read(r)?,
+            common: ClientSessionCommon::read(r)?,
+            quic_params: PayloadU16::read(r)?,
+        })
+    }
 }
 
 impl core::ops::Deref for Tls13ClientSessionValue {
@@ -190,6 +223,36 @@ impl Tls12ClientSessionValue {
     pub fn rewind_epoch(&mut self, delta: u32) {
         self.common.epoch -= delta as u64;
     }
+
+    /// Encodes this value, preceded by its protocol version and cipher suite.
+    ///
+    /// The remainder is decoded by [`Tls12ClientSessionValue::read_state`].
+    pub(crate) fn get_state_encoding(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::new());
+        ProtocolVersion::TLSv1_2.encode(&mut bytes);
+        self.suite
+            .common
+            .suite
+            .encode(&mut bytes);
+        self.session_id.encode(&mut bytes);
+        u8::from(self.extended_ms).encode(&mut bytes);
+        self.common.encode(&mut bytes);
+        bytes
+    }
+
+    /// Decodes a value encoded by [`Tls12ClientSessionValue::get_state_encoding`],
+    /// whose protocol version and cipher suite the caller has already read.
+    pub(crate) fn read_state(
+        suite: &'static Tls12CipherSuite,
+        r: &mut Reader,
+    ) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            suite,
+            session_id: SessionId::read(r)?,
+            extended_ms: u8::read(r)? == 1,
+            common: ClientSessionCommon::read(r)?,
+        })
+    }
 }
 
 #[cfg(feature = "tls12")]
@@ -238,6 +301,35 @@ impl ClientSessionCommon {
     pub(crate) fn ticket(&self) -> &[u8] {
         self.ticket.0.as_ref()
     }
+
+    /// How many seconds before `now` this session was received.
+    pub(crate) fn age_secs(&self, now: UnixTime) -> u64 {
+        now.as_secs().saturating_sub(self.epoch)
+    }
+
+    pub(crate) fn lifetime_secs(&self) -> u32 {
+        self.lifetime_secs
+    }
+}
+
+impl Codec for ClientSessionCommon {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.ticket.encode(bytes);
+        self.secret.encode(bytes);
+        self.epoch.encode(bytes);
+        self.lifetime_secs.encode(bytes);
+        self.server_cert_chain.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            ticket: PayloadU16::read(r)?,
+            secret: Zeroizing::new(PayloadU8::read(r)?),
+            epoch: u64::read(r)?,
+            lifetime_secs: cmp::min(u32::read(r)?, MAX_TICKET_LIFETIME),
+            server_cert_chain: CertificateChain::read(r)?,
+        })
+    }
 }
 
 static MAX_TICKET_LIFETIME: u32 = 7 * 24 * 60 * 60;
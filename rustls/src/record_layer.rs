@@ -1,4 +1,5 @@
-use crate::crypto::cipher::{MessageDecrypter, MessageEncrypter};
+use crate::crypto::cipher::{MessageDecrypter, MessageEncrypter, ObservesAad};
+use crate::enums::{ContentType, ProtocolVersion};
 use crate::error::Error;
 use crate::msgs::message::{BorrowedPlainMessage, OpaqueMessage, PlainMessage};
 
@@ -6,8 +7,10 @@ use crate::msgs::message::{BorrowedPlainMessage, OpaqueMessage, PlainMessage};
 use crate::log::trace;
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
-static SEQ_SOFT_LIMIT: u64 = 0xffff_ffff_ffff_0000u64;
+pub(crate) static SEQ_SOFT_LIMIT: u64 = 0xffff_ffff_ffff_0000u64;
 static SEQ_HARD_LIMIT: u64 = 0xffff_ffff_ffff_fffeu64;
 
 #[derive(PartialEq)]
@@ -27,6 +30,10 @@ pub struct RecordLayer {
     message_encrypter: Box<dyn MessageEncrypter>,
     message_decrypter: Box<dyn MessageDecrypter>,
     write_seq: u64,
+    // The write sequence number at which the encryption key should be replaced:
+    // the lesser of `SEQ_SOFT_LIMIT` and the last record allowed by the suite's
+    // confidentiality limit, which is kept for the `KeyUpdate` or `close_notify`.
+    write_seq_max: u64,
     read_seq: u64,
     has_decrypted: bool,
     encrypt_state: DirectionState,
@@ -36,6 +43,10 @@ pub struct RecordLayer {
     // should be swallowed by the caller.  This struct tracks the amount
     // of message size this is allowed for.
     trial_decryption_len: Option<usize>,
+
+    // Given to each cipher we are handed, so it can report the records'
+    // `additional_data`.
+    aad_observer: Option<Arc<dyn ObservesAad>>,
 }
 
 impl RecordLayer {
@@ -45,14 +56,91 @@ impl RecordLayer {
             message_encrypter: <dyn MessageEncrypter>::invalid(),
             message_decrypter: <dyn MessageDecrypter>::invalid(),
             write_seq: 0,
+            write_seq_max: SEQ_SOFT_LIMIT,
             read_seq: 0,
             has_decrypted: false,
             encrypt_state: DirectionState::Invalid,
             decrypt_state: DirectionState::Invalid,
             trial_decryption_len: None,
+            aad_observer: None,
         }
     }
 
+    /// Create a record layer that is already encrypting and decrypting with the
+    /// given ciphers.
+    ///
+    /// This is useful for handing bulk record protection to another thread:
+    /// the result is `Send`, and is used through [`Self::dangerous_encrypt`] and
+    /// [`Self::dangerous_decrypt`], which take the sequence number explicitly so
+    /// the caller can coordinate it with the connection.  Read their
+    /// documentation before using this.
+    pub fn with_ciphers(
+        encrypter: Box<dyn MessageEncrypter>,
+        decrypter: Box<dyn MessageDecrypter>,
+    ) -> Self {
+        let mut record_layer = Self::new();
+        record_layer.set_message_encrypter(encrypter, u64::MAX);
+        record_layer.set_message_decrypter(decrypter);
+        record_layer
+    }
+
+    /// Encrypt `plain` using the sequence number `seq`.
+    ///
+    /// Unlike the connection's own use of the record layer, this does not
+    /// consult or advance the internal write sequence number.
+    ///
+    /// The sequence number determines the AEAD nonce.  Encrypting two records
+    /// with the same `seq` under the same keys reuses a nonce, which destroys
+    /// the confidentiality and integrity of everything protected with those
+    /// keys.  The caller must use each `seq` at most once per encrypter, in
+    /// the order the peer will decrypt them.
+    pub fn dangerous_encrypt(
+        &mut self,
+        plain: BorrowedPlainMessage,
+        seq: u64,
+    ) -> Result<OpaqueMessage, Error> {
+        self.encrypt(plain, seq)
+    }
+
+    /// Decrypt `encr` using the sequence number `seq`.
+    ///
+    /// Unlike the connection's own use of the record layer, this does not
+    /// consult or advance the internal read sequence number.
+    ///
+    /// The caller must give each record the sequence number it was sent with,
+    /// and accept each `seq` at most once: otherwise records can be replayed,
+    /// dropped or reordered without detection.
+    pub fn dangerous_decrypt(
+        &mut self,
+        encr: OpaqueMessage,
+        seq: u64,
+    ) -> Result<PlainMessage, Error> {
+        self.decrypt(encr, seq)
+    }
+
+    fn encrypt(&mut self, plain: BorrowedPlainMessage, seq: u64) -> Result<OpaqueMessage, Error> {
+        if self.encrypt_state != DirectionState::Active || seq >= SEQ_HARD_LIMIT {
+            return Err(Error::EncryptError);
+        }
+        self.message_encrypter
+            .encrypt(plain, seq)
+    }
+
+    fn decrypt(&mut self, encr: OpaqueMessage, seq: u64) -> Result<PlainMessage, Error> {
+        if self.decrypt_state != DirectionState::Active {
+            return Err(Error::DecryptError);
+        }
+        self.message_decrypter
+            .decrypt(encr, seq)
+    }
+
+    /// Report the `additional_data` of each record we protect to `observer`.
+    ///
+    /// This applies to ciphers prepared after this call.
+    pub(crate) fn set_aad_observer(&mut self, observer: Option<Arc<dyn ObservesAad>>) {
+        self.aad_observer = observer;
+    }
+
     /// Decrypt a TLS message.
     ///
     /// `encr` is a decoded message allegedly received from the peer.
@@ -80,10 +168,7 @@ impl RecordLayer {
         let want_close_before_decrypt = self.read_seq == SEQ_SOFT_LIMIT;
 
         let encrypted_len = encr.payload().len();
-        match self
-            .message_decrypter
-            .decrypt(encr, self.read_seq)
-        {
+        match self.decrypt(encr, self.read_seq) {
             Ok(plaintext) => {
                 self.read_seq += 1;
                 if !self.has_decrypted {
@@ -102,6 +187,57 @@ impl RecordLayer {
         }
     }
 
+    /// Start decrypting a record of `len` bytes of ciphertext as it arrives.
+    ///
+    /// Returns `false` if the record must instead be given whole to
+    /// [`Self::decrypt_incoming`]: our decrypter cannot stream, or we are
+    /// trial decrypting.
+    pub(crate) fn start_streaming(
+        &mut self,
+        typ: ContentType,
+        version: ProtocolVersion,
+        len: usize,
+    ) -> Result<bool, Error> {
+        if self.decrypt_state != DirectionState::Active || self.trial_decryption_len.is_some() {
+            return Ok(false);
+        }
+
+        match self.message_decrypter.streaming() {
+            Some(streaming) => streaming
+                .start(typ, version, len, self.read_seq)
+                .map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Decrypt the next chunk of a record started with [`Self::start_streaming`].
+    pub(crate) fn stream_incoming(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        match self.message_decrypter.streaming() {
+            Some(streaming) => streaming.update(chunk),
+            None => Err(Error::DecryptError),
+        }
+    }
+
+    /// Finish a record started with [`Self::start_streaming`], returning its
+    /// plaintext if it verifies.
+    pub(crate) fn finish_streaming(&mut self) -> Result<Decrypted, Error> {
+        let want_close_before_decrypt = self.read_seq == SEQ_SOFT_LIMIT;
+
+        let plaintext = match self.message_decrypter.streaming() {
+            Some(streaming) => streaming.finish()?,
+            None => return Err(Error::DecryptError),
+        };
+
+        self.read_seq += 1;
+        if !self.has_decrypted {
+            self.has_decrypted = true;
+        }
+        Ok(Decrypted {
+            want_close_before_decrypt,
+            plaintext,
+        })
+    }
+
     /// Encrypt a TLS message.
     ///
     /// `plain` is a TLS message we'd like to send.  This function
@@ -111,22 +247,87 @@ impl RecordLayer {
         assert!(!self.encrypt_exhausted());
         let seq = self.write_seq;
         self.write_seq += 1;
+        self.encrypt(plain, seq).unwrap()
+    }
+
+    /// Encrypt a TLS1.3 message, adding `padding` zero bytes to its `TLSInnerPlaintext`.
+    ///
+    /// TLS1.3 message encrypters append the content type to the payload.  So we
+    /// pass on the real content type and all but one of the padding bytes as part
+    /// of the payload, with a content type of zero supplying the last padding byte.
+    pub(crate) fn encrypt_outgoing_padded(
+        &mut self,
+        plain: BorrowedPlainMessage,
+        padding: usize,
+    ) -> OpaqueMessage {
+        if padding == 0 {
+            return self.encrypt_outgoing(plain);
+        }
+
+        let mut inner = Vec::with_capacity(plain.payload.len() + padding);
+        inner.extend_from_slice(plain.payload);
+        inner.push(plain.typ.get_u8());
+        inner.resize(plain.payload.len() + padding, 0);
+
+        self.encrypt_outgoing(BorrowedPlainMessage {
+            typ: ContentType::Unknown(0),
+            version: plain.version,
+            payload: &inner,
+        })
+    }
+
+    /// Encrypt several TLS messages, using consecutive sequence numbers.
+    ///
+    /// The caller must check [`Self::can_encrypt_batch`] first.
+    pub(crate) fn encrypt_outgoing_batch(
+        &mut self,
+        plain: &[BorrowedPlainMessage],
+    ) -> Vec<OpaqueMessage> {
+        debug_assert!(self.encrypt_state == DirectionState::Active);
+        assert!(self.can_encrypt_batch(plain.len()));
+        let seq = self.write_seq;
+        self.write_seq += plain.len() as u64;
         self.message_encrypter
-            .encrypt(plain, seq)
+            .encrypt_batch(plain, seq)
             .unwrap()
     }
 
+    /// Returns true if `count` messages can be encrypted without reaching
+    /// the point where we should close the connection.
+    pub(crate) fn can_encrypt_batch(&self, count: usize) -> bool {
+        self.write_seq
+            .checked_add(count as u64)
+            .map_or(false, |end| end <= self.write_seq_max)
+    }
+
     /// Prepare to use the given `MessageEncrypter` for future message encryption.
     /// It is not used until you call `start_encrypting`.
-    pub(crate) fn prepare_message_encrypter(&mut self, cipher: Box<dyn MessageEncrypter>) {
+    ///
+    /// `max_messages` is the suite's confidentiality limit: once all but one of
+    /// that many messages have been encrypted, [`Self::next_pre_encrypt_action`]
+    /// asks for the key to be replaced.
+    pub(crate) fn prepare_message_encrypter(
+        &mut self,
+        mut cipher: Box<dyn MessageEncrypter>,
+        max_messages: u64,
+    ) {
+        if let Some(observer) = &self.aad_observer {
+            cipher.set_aad_observer(Arc::clone(observer));
+        }
         self.message_encrypter = cipher;
         self.write_seq = 0;
+        self.write_seq_max = max_messages
+            .saturating_sub(1)
+            .min(SEQ_SOFT_LIMIT);
         self.encrypt_state = DirectionState::Prepared;
     }
 
     /// Prepare to use the given `MessageDecrypter` for future message decryption.
     /// It is not used until you call `start_decrypting`.
-    pub(crate) fn prepare_message_decrypter(&mut self, cipher: Box<dyn MessageDecrypter>) {
+    pub(crate) fn prepare_message_decrypter(&mut self, mut cipher: Box<dyn MessageDecrypter>) {
+        if let Some(observer) = &self.aad_observer {
+            cipher.set_aad_observer(Arc::clone(observer));
+        }
         self.message_decrypter = cipher;
         self.read_seq = 0;
         self.decrypt_state = DirectionState::Prepared;
@@ -148,8 +349,12 @@ impl RecordLayer {
 
     /// Set and start using the given `MessageEncrypter` for future outgoing
     /// message encryption.
-    pub(crate) fn set_message_encrypter(&mut self, cipher: Box<dyn MessageEncrypter>) {
-        self.prepare_message_encrypter(cipher);
+    pub(crate) fn set_message_encrypter(
+        &mut self,
+        cipher: Box<dyn MessageEncrypter>,
+        max_messages: u64,
+    ) {
+        self.prepare_message_encrypter(cipher, max_messages);
         self.start_encrypting();
     }
 
@@ -178,10 +383,19 @@ impl RecordLayer {
         self.trial_decryption_len = None;
     }
 
-    /// Return true if we are getting close to encrypting too many
-    /// messages with our encryption key.
-    pub(crate) fn wants_close_before_encrypt(&self) -> bool {
-        self.write_seq == SEQ_SOFT_LIMIT
+    /// What to do before encrypting the next message, given how many messages
+    /// have been encrypted with the current key.
+    pub(crate) fn next_pre_encrypt_action(&self) -> PreEncryptAction {
+        match self.write_seq {
+            seq if seq >= SEQ_HARD_LIMIT => PreEncryptAction::Refuse,
+            seq if seq >= self.write_seq_max => PreEncryptAction::RefreshOrClose,
+            _ => PreEncryptAction::Nothing,
+        }
+    }
+
+    /// Refuse to encrypt anything further with the current key.
+    pub(crate) fn exhaust_encrypter(&mut self) {
+        self.write_seq = SEQ_HARD_LIMIT;
     }
 
     /// Return true if we outright refuse to do anything with the
@@ -194,6 +408,17 @@ impl RecordLayer {
         self.encrypt_state == DirectionState::Active
     }
 
+    /// How many bytes encryption adds to a record's payload, or zero
+    /// if we're not yet encrypting.
+    pub(crate) fn encryption_overhead(&self) -> usize {
+        match self.is_encrypting() {
+            true => self
+                .message_encrypter
+                .encrypted_payload_len(0),
+            false => 0,
+        }
+    }
+
     /// Return true if we have ever decrypted a message. This is used in place
     /// of checking the read_seq since that will be reset on key updates.
     pub(crate) fn has_decrypted(&self) -> bool {
@@ -208,6 +433,14 @@ impl RecordLayer {
         self.read_seq
     }
 
+    pub(crate) fn set_write_seq(&mut self, seq: u64) {
+        self.write_seq = seq;
+    }
+
+    pub(crate) fn set_read_seq(&mut self, seq: u64) {
+        self.read_seq = seq;
+    }
+
     fn doing_trial_decryption(&mut self, requested: usize) -> bool {
         match self
             .trial_decryption_len
@@ -222,6 +455,20 @@ impl RecordLayer {
     }
 }
 
+/// What to do before encrypting a message.
+#[derive(Debug, PartialEq)]
+pub(crate) enum PreEncryptAction {
+    /// Encrypt as normal.
+    Nothing,
+
+    /// The key has reached its usage limit: replace it now (TLS1.3), or close
+    /// the connection (TLS1.2).
+    RefreshOrClose,
+
+    /// The sequence number is about to wrap: encrypting would be unsafe.
+    Refuse,
+}
+
 /// Result of decryption.
 #[derive(Debug)]
 pub(crate) struct Decrypted {
@@ -292,4 +539,185 @@ mod tests {
         assert_eq!(record_layer.read_seq, 0);
         assert!(record_layer.has_decrypted());
     }
+
+    #[test]
+    fn test_encrypt_batch_matches_single_encryption() {
+        use crate::{ContentType, ProtocolVersion};
+
+        struct XorSeqEncrypter;
+        impl MessageEncrypter for XorSeqEncrypter {
+            fn encrypt(
+                &mut self,
+                m: BorrowedPlainMessage,
+                seq: u64,
+            ) -> Result<OpaqueMessage, Error> {
+                let payload = m
+                    .payload
+                    .iter()
+                    .map(|b| b ^ (seq as u8))
+                    .collect();
+                Ok(OpaqueMessage::new(m.typ, m.version, payload))
+            }
+
+            fn encrypted_payload_len(&self, payload_len: usize) -> usize {
+                payload_len
+            }
+        }
+
+        let payloads: [&[u8]; 3] = [b"hello", b"world", b"!"];
+        let msgs = payloads
+            .iter()
+            .map(|payload| BorrowedPlainMessage {
+                typ: ContentType::ApplicationData,
+                version: ProtocolVersion::TLSv1_2,
+                payload,
+            })
+            .collect::<Vec<_>>();
+
+        let mut single = RecordLayer::new();
+        single.prepare_message_encrypter(Box::new(XorSeqEncrypter), u64::MAX);
+        single.start_encrypting();
+        let expected = msgs
+            .iter()
+            .map(|m| single.encrypt_outgoing(*m).encode())
+            .collect::<Vec<_>>();
+
+        let mut batch = RecordLayer::new();
+        batch.prepare_message_encrypter(Box::new(XorSeqEncrypter), u64::MAX);
+        batch.start_encrypting();
+        assert!(batch.can_encrypt_batch(msgs.len()));
+        let actual = batch
+            .encrypt_outgoing_batch(&msgs)
+            .into_iter()
+            .map(|m| m.encode())
+            .collect::<Vec<_>>();
+
+        assert_eq!(expected, actual);
+        assert_eq!(single.write_seq, batch.write_seq);
+        assert_eq!(batch.write_seq, 3);
+    }
+
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    #[test]
+    fn test_encrypt_and_decrypt_on_different_threads() {
+        use crate::crypto::cipher::{AeadKey, Iv};
+        use crate::test_provider::cipher_suite::TLS13_AES_128_GCM_SHA256;
+        use crate::{ContentType, ProtocolVersion};
+        use std::sync::mpsc;
+
+        let aead_alg = TLS13_AES_128_GCM_SHA256
+            .tls13()
+            .unwrap()
+            .aead_alg;
+        let key = || AeadKey::from([0x42; 32]).with_length(aead_alg.key_len());
+        let iv = || Iv::from([0x24; 12]);
+
+        let mut sender = RecordLayer::with_ciphers(
+            aead_alg.encrypter(key(), iv()),
+            <dyn MessageDecrypter>::invalid(),
+        );
+        let mut receiver = RecordLayer::with_ciphers(
+            <dyn MessageEncrypter>::invalid(),
+            aead_alg.decrypter(key(), iv()),
+        );
+
+        let (tx, rx) = mpsc::channel();
+        let encrypting = std::thread::spawn(move || {
+            for seq in 0..16u64 {
+                let payload = seq.to_be_bytes();
+                let msg = BorrowedPlainMessage {
+                    typ: ContentType::ApplicationData,
+                    version: ProtocolVersion::TLSv1_2,
+                    payload: &payload,
+                };
+                tx.send((
+                    seq,
+                    sender
+                        .dangerous_encrypt(msg, seq)
+                        .unwrap(),
+                ))
+                .unwrap();
+            }
+        });
+        let decrypting = std::thread::spawn(move || {
+            let mut count = 0;
+            for (seq, msg) in rx {
+                let plain = receiver
+                    .dangerous_decrypt(msg, seq)
+                    .unwrap();
+                assert_eq!(plain.typ, ContentType::ApplicationData);
+                assert_eq!(plain.payload.0, seq.to_be_bytes());
+                count += 1;
+            }
+            count
+        });
+
+        encrypting.join().unwrap();
+        assert_eq!(decrypting.join().unwrap(), 16);
+    }
+
+    #[test]
+    fn test_encrypt_and_decrypt_require_keys() {
+        use crate::{ContentType, ProtocolVersion};
+
+        let mut record_layer = RecordLayer::new();
+        let msg = BorrowedPlainMessage {
+            typ: ContentType::ApplicationData,
+            version: ProtocolVersion::TLSv1_2,
+            payload: b"hello",
+        };
+        assert_eq!(
+            record_layer
+                .dangerous_encrypt(msg, 0)
+                .err(),
+            Some(Error::EncryptError)
+        );
+        let msg = OpaqueMessage::new(
+            ContentType::ApplicationData,
+            ProtocolVersion::TLSv1_2,
+            b"hello".to_vec(),
+        );
+        assert_eq!(
+            record_layer
+                .dangerous_decrypt(msg, 0)
+                .err(),
+            Some(Error::DecryptError)
+        );
+    }
+
+    #[test]
+    fn test_pre_encrypt_action() {
+        let mut record_layer = RecordLayer::new();
+        record_layer.set_message_encrypter(<dyn MessageEncrypter>::invalid(), 16);
+        assert_eq!(
+            record_layer.next_pre_encrypt_action(),
+            PreEncryptAction::Nothing
+        );
+        assert!(record_layer.can_encrypt_batch(15));
+        assert!(!record_layer.can_encrypt_batch(16));
+
+        // the last record is kept for a KeyUpdate or close_notify
+        record_layer.write_seq = 15;
+        assert_eq!(
+            record_layer.next_pre_encrypt_action(),
+            PreEncryptAction::RefreshOrClose
+        );
+
+        record_layer.write_seq = 16;
+        assert_eq!(
+            record_layer.next_pre_encrypt_action(),
+            PreEncryptAction::RefreshOrClose
+        );
+
+        record_layer.exhaust_encrypter();
+        assert_eq!(
+            record_layer.next_pre_encrypt_action(),
+            PreEncryptAction::Refuse
+        );
+
+        // a replacement key starts afresh
+        record_layer.set_message_encrypter(<dyn MessageEncrypter>::invalid(), u64::MAX);
+        assert_eq!(record_layer.write_seq, 0);
+        assert_eq!(record_layer.write_seq_max, SEQ_SOFT_LIMIT);
+    }
 }
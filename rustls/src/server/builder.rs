@@ -1,9 +1,11 @@
 use crate::builder::{ConfigBuilder, WantsVerifier};
+use crate::common_state::RecordPadding;
 use crate::crypto::CryptoProvider;
 use crate::error::Error;
 use crate::msgs::handshake::CertificateChain;
 use crate::server::handy;
 use crate::server::{ResolvesServerCert, ServerConfig};
+use crate::tls13::key_schedule::BinderKeyCache;
 use crate::verify::{ClientCertVerifier, NoClientAuth};
 use crate::versions;
 use crate::NoKeyLog;
@@ -62,7 +64,8 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
     /// `aws-lc-rs` and `ring` [`CryptoProvider`]s support all three encodings,
     /// but other `CryptoProviders` may not.
     ///
-    /// This function fails if `key_der` is invalid.
+    /// This function fails if `key_der` is invalid, or does not match the end-entity
+    /// certificate ([`Error::InconsistentKeys`]).
     pub fn with_single_cert(
         self,
         cert_chain: Vec<CertificateDer<'static>>,
@@ -73,7 +76,7 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
             .provider
             .key_provider
             .load_private_key(key_der)?;
-        let resolver = handy::AlwaysResolvesChain::new(private_key, CertificateChain(cert_chain));
+        let resolver = handy::AlwaysResolvesChain::new(private_key, CertificateChain(cert_chain))?;
         Ok(self.with_cert_resolver(Arc::new(resolver)))
     }
 
@@ -87,7 +90,8 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
     /// but other `CryptoProviders` may not.
     /// `ocsp` is a DER-encoded OCSP response.  Ignored if zero length.
     ///
-    /// This function fails if `key_der` is invalid.
+    /// This function fails if `key_der` is invalid, or does not match the end-entity
+    /// certificate ([`Error::InconsistentKeys`]).
     pub fn with_single_cert_with_ocsp(
         self,
         cert_chain: Vec<CertificateDer<'static>>,
@@ -103,7 +107,7 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
             private_key,
             CertificateChain(cert_chain),
             ocsp,
-        );
+        )?;
         Ok(self.with_cert_resolver(Arc::new(resolver)))
     }
 
@@ -115,15 +119,34 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
             cert_resolver,
             ignore_client_order: false,
             max_fragment_size: None,
+            max_handshake_flight_bytes: None,
+            heartbeat_mode: None,
             session_storage: handy::ServerSessionMemoryCache::new(256),
             ticketer: Arc::new(handy::NeverProducesTickets {}),
+            external_psks: Vec::new(),
             alpn_protocols: Vec::new(),
+            require_resumption_alpn_match: false,
             versions: self.state.versions,
             key_log: Arc::new(NoKeyLog {}),
             enable_secret_extraction: false,
             max_early_data_size: 0,
             send_half_rtt_data: false,
+            on_unknown_extension: None,
+            cookie_provider: None,
             send_tls13_tickets: 4,
+            send_tls13_tickets_jitter: 0,
+            allow_tls13_compression_offers: false,
+            record_padding: RecordPadding::None,
+            alert_observer: None,
+            aad_observer: None,
+            explicit_nonce_random: None,
+            alps_settings: Vec::new(),
+            preserve_record_boundaries: false,
+            require_ems: false,
+            tls12_session_id_resumption: true,
+            on_handshake_message: None,
+            filter_cipher_suites: None,
+            binder_key_cache: Arc::new(BinderKeyCache::new(256)),
         }
     }
 }
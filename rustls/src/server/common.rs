@@ -2,10 +2,13 @@ use crate::sign;
 
 use pki_types::CertificateDer;
 
-/// ActiveCertifiedKey wraps [`sign::CertifiedKey`] and tracks OSCP state in a single handshake.
+use alloc::vec::Vec;
+
+/// ActiveCertifiedKey wraps [`sign::CertifiedKey`] and tracks OSCP and SCT state in a single handshake.
 pub(super) struct ActiveCertifiedKey<'a> {
     key: &'a sign::CertifiedKey,
     ocsp: Option<&'a [u8]>,
+    scts: Option<&'a [Vec<u8>]>,
 }
 
 impl<'a> ActiveCertifiedKey<'a> {
@@ -13,6 +16,7 @@ impl<'a> ActiveCertifiedKey<'a> {
         ActiveCertifiedKey {
             key,
             ocsp: key.ocsp.as_deref(),
+            scts: Some(&key.scts[..]).filter(|scts| !scts.is_empty()),
         }
     }
 
@@ -32,4 +36,9 @@ impl<'a> ActiveCertifiedKey<'a> {
     pub(super) fn get_ocsp(&self) -> Option<&[u8]> {
         self.ocsp
     }
+
+    #[inline]
+    pub(super) fn get_scts(&self) -> Option<&[Vec<u8>]> {
+        self.scts
+    }
 }
@@ -110,8 +110,15 @@ pub(super) struct AlwaysResolvesChain(Arc<sign::CertifiedKey>);
 
 impl AlwaysResolvesChain {
     /// Creates an `AlwaysResolvesChain`, using the supplied key and certificate chain.
-    pub(super) fn new(private_key: Arc<dyn sign::SigningKey>, chain: CertificateChain) -> Self {
-        Self(Arc::new(sign::CertifiedKey::new(chain.0, private_key)))
+    ///
+    /// Fails if the key does not match the end-entity certificate.
+    pub(super) fn new(
+        private_key: Arc<dyn sign::SigningKey>,
+        chain: CertificateChain,
+    ) -> Result<Self, Error> {
+        let certified_key = sign::CertifiedKey::new(chain.0, private_key);
+        certified_key.keys_match()?;
+        Ok(Self(Arc::new(certified_key)))
     }
 
     /// Creates an `AlwaysResolvesChain`, using the supplied key, certificate chain and OCSP response.
@@ -121,8 +128,8 @@ impl AlwaysResolvesChain {
         private_key: Arc<dyn sign::SigningKey>,
         chain: CertificateChain,
         ocsp: Vec<u8>,
-    ) -> Self {
-        let mut r = Self::new(private_key, chain);
+    ) -> Result<Self, Error> {
+        let mut r = Self::new(private_key, chain)?;
 
         {
             let cert = Arc::make_mut(&mut r.0);
@@ -131,7 +138,7 @@ impl AlwaysResolvesChain {
             }
         }
 
-        r
+        Ok(r)
     }
 }
 
@@ -159,8 +166,9 @@ impl ResolvesServerCertUsingSni {
     /// Add a new `sign::CertifiedKey` to be used for the given SNI `name`.
     ///
     /// This function fails if `name` is not a valid DNS name, or if
-    /// it's not valid for the supplied certificate, or if the certificate
-    /// chain is syntactically faulty.
+    /// it's not valid for the supplied certificate, if the certificate
+    /// chain is syntactically faulty, or if the key does not match the
+    /// certificate.
     pub fn add(&mut self, name: &str, ck: sign::CertifiedKey) -> Result<(), Error> {
         let server_name = {
             let checked_name = DnsName::try_from(name)
@@ -181,6 +189,7 @@ impl ResolvesServerCertUsingSni {
         ck.end_entity_cert()
             .and_then(ParsedCertificate::try_from)
             .and_then(|cert| verify_server_name(&cert, &server_name))?;
+        ck.keys_match()?;
 
         if let ServerName::DnsName(name) = server_name {
             self.by_name
@@ -201,6 +210,59 @@ impl server::ResolvesServerCert for ResolvesServerCertUsingSni {
     }
 }
 
+/// Something that resolves to one of several cert chains/keys, based
+/// on the signature schemes offered by the client.
+///
+/// This allows a server to hold, for example, both an ECDSA and an RSA
+/// certificate, and present whichever one the client can use.
+#[derive(Debug)]
+pub struct ResolvesServerCertUsingSigSchemes {
+    keys: Vec<Arc<sign::CertifiedKey>>,
+}
+
+impl ResolvesServerCertUsingSigSchemes {
+    /// Create a new and empty (i.e., knows no certificates) resolver.
+    pub fn new() -> Self {
+        Self { keys: Vec::new() }
+    }
+
+    /// Add a new `sign::CertifiedKey` to choose from.
+    ///
+    /// The first key added is used when none of the keys are usable with
+    /// the client's offered signature schemes.
+    ///
+    /// This function fails if the certificate chain is syntactically faulty,
+    /// or if the key does not match the certificate.
+    pub fn add(&mut self, ck: sign::CertifiedKey) -> Result<(), Error> {
+        ck.end_entity_cert()
+            .and_then(ParsedCertificate::try_from)?;
+        ck.keys_match()?;
+
+        self.keys.push(Arc::new(ck));
+        Ok(())
+    }
+}
+
+impl server::ResolvesServerCert for ResolvesServerCertUsingSigSchemes {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        // The offered signature schemes are in the client's order of
+        // preference, and have already been reduced to those usable with
+        // the cipher suites we share with the client.
+        client_hello
+            .signature_schemes()
+            .iter()
+            .find_map(|scheme| {
+                self.keys.iter().find(|ck| {
+                    ck.key
+                        .choose_scheme(core::slice::from_ref(scheme))
+                        .is_some()
+                })
+            })
+            .or_else(|| self.keys.first())
+            .map(Arc::clone)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -284,7 +346,7 @@ mod tests {
     fn test_resolvesservercertusingsni_requires_sni() {
         let rscsni = ResolvesServerCertUsingSni::new();
         assert!(rscsni
-            .resolve(ClientHello::new(&None, &[], None, &[]))
+            .resolve(ClientHello::new(&None, &[], None, &[], None, None, None))
             .is_none());
     }
 
@@ -295,7 +357,15 @@ mod tests {
             .unwrap()
             .to_owned();
         assert!(rscsni
-            .resolve(ClientHello::new(&Some(name), &[], None, &[]))
+            .resolve(ClientHello::new(
+                &Some(name),
+                &[],
+                None,
+                &[],
+                None,
+                None,
+                None
+            ))
             .is_none());
     }
 }
@@ -1,8 +1,8 @@
-use crate::common_state::State;
+use crate::common_state::{CommonState, State};
 use crate::conn::ConnectionRandoms;
-#[cfg(feature = "tls12")]
-use crate::enums::CipherSuite;
-use crate::enums::{AlertDescription, HandshakeType, ProtocolVersion, SignatureScheme};
+use crate::enums::{
+    AlertDescription, CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme,
+};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 #[cfg(feature = "logging")]
@@ -10,7 +10,7 @@ use crate::log::{debug, trace};
 use crate::msgs::enums::{Compression, ExtensionType};
 #[cfg(feature = "tls12")]
 use crate::msgs::handshake::SessionId;
-use crate::msgs::handshake::{ClientHelloPayload, Random, ServerExtension};
+use crate::msgs::handshake::{ClientExtension, ClientHelloPayload, Random, Sct, ServerExtension};
 use crate::msgs::handshake::{ConvertProtocolNameList, ConvertServerNameList, HandshakePayload};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
@@ -53,6 +53,41 @@ pub(super) fn can_resume(
         && &resumedata.sni == sni
 }
 
+/// The first of our ALPN protocols that the client offered in `hello`, if any.
+pub(super) fn choose_alpn<'a>(
+    config: &'a ServerConfig,
+    hello: &ClientHelloPayload,
+) -> Option<&'a [u8]> {
+    let their_protocols = hello.get_alpn_extension()?.to_slices();
+    config
+        .alpn_protocols
+        .iter()
+        .map(Vec::as_slice)
+        .find(|protocol| their_protocols.contains(protocol))
+}
+
+/// Whether `resumedata` may be resumed given the ALPN protocols offered in
+/// `hello`, according to [`ServerConfig::require_resumption_alpn_match`].
+pub(super) fn resumption_alpn_acceptable(
+    config: &ServerConfig,
+    hello: &ClientHelloPayload,
+    resumedata: &persist::ServerSessionValue,
+) -> bool {
+    if !config.require_resumption_alpn_match {
+        return true;
+    }
+
+    let matches = choose_alpn(config, hello)
+        == resumedata
+            .alpn
+            .as_ref()
+            .map(|alpn| alpn.0.as_slice());
+    if !matches {
+        debug!("Not resuming session with a different ALPN protocol");
+    }
+    matches
+}
+
 #[derive(Default)]
 pub(super) struct ExtensionProcessing {
     // extensions to reply with
@@ -71,6 +106,7 @@ impl ExtensionProcessing {
         config: &ServerConfig,
         cx: &mut ServerContext<'_>,
         ocsp_response: &mut Option<&[u8]>,
+        sct_list: &mut Option<&[Vec<u8>]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
         extra_exts: Vec<ServerExtension>,
@@ -88,10 +124,7 @@ impl ExtensionProcessing {
                 return Err(PeerMisbehaved::OfferedEmptyApplicationProtocol.into());
             }
 
-            cx.common.alpn_protocol = our_protocols
-                .iter()
-                .find(|protocol| their_protocols.contains(&protocol.as_slice()))
-                .cloned();
+            cx.common.alpn_protocol = choose_alpn(config, hello).map(<[u8]>::to_vec);
             if let Some(ref selected_protocol) = cx.common.alpn_protocol {
                 debug!("Chosen ALPN protocol {:?}", selected_protocol);
                 self.exts
@@ -156,6 +189,40 @@ impl ExtensionProcessing {
             ocsp_response.take();
         }
 
+        // Likewise for SCTs: these are only sent in a full handshake to
+        // a client that asked for them.
+        if !for_resume
+            && hello
+                .find_extension(ExtensionType::SCT)
+                .is_some()
+        {
+            match sct_list {
+                // Only TLS1.2 sends the list in ServerHello; TLS1.3 puts it
+                // in the Certificate message instead.
+                Some(scts) if !cx.common.is_tls13() => {
+                    self.exts
+                        .push(ServerExtension::SignedCertificateTimestamp(
+                            scts.iter()
+                                .map(|sct| Sct::from(sct.clone()))
+                                .collect(),
+                        ));
+                }
+                _ => {}
+            }
+        } else {
+            sct_list.take();
+        }
+
+        // Heartbeats are only used if both sides asked for them.
+        if let (Some(ours), Some(theirs)) = (config.heartbeat_mode, hello.get_heartbeat_extension())
+        {
+            if !cx.common.is_quic() {
+                cx.common.peer_heartbeat_mode = Some(theirs);
+                self.exts
+                    .push(ServerExtension::Heartbeat(ours));
+            }
+        }
+
         self.exts.extend(extra_exts);
 
         Ok(())
@@ -218,6 +285,7 @@ pub(super) struct ExpectClientHello {
 impl ExpectClientHello {
     pub(super) fn new(config: Arc<ServerConfig>, extra_exts: Vec<ServerExtension>) -> Self {
         let mut transcript_buffer = HandshakeHashBuffer::new();
+        transcript_buffer.set_observer(config.on_handshake_message.clone());
 
         if config.verifier.offer_client_auth() {
             transcript_buffer.set_client_auth_enabled();
@@ -244,6 +312,14 @@ impl ExpectClientHello {
         m: &Message,
         cx: &mut ServerContext<'_>,
     ) -> NextStateOrError {
+        if let Some(observer) = &self.config.on_unknown_extension {
+            for ext in &client_hello.extensions {
+                if let ClientExtension::Unknown(unknown) = ext {
+                    observer.observe(unknown.typ.get_u16(), &unknown.payload.0);
+                }
+            }
+        }
+
         let tls13_enabled = self
             .config
             .supports_version(ProtocolVersion::TLSv1_3);
@@ -253,6 +329,37 @@ impl ExpectClientHello {
 
         // Are we doing TLS1.3?
         let maybe_versions_ext = client_hello.get_versions_extension();
+
+        // "If TLS_FALLBACK_SCSV appears in ClientHello.cipher_suites and the
+        //  highest protocol version supported by the server is higher than
+        //  the version indicated in ClientHello.client_version, the server
+        //  MUST respond with a fatal inappropriate_fallback alert" - RFC7507 3
+        //
+        // The supported_versions extension supersedes client_version, if present.
+        if client_hello
+            .cipher_suites
+            .contains(&CipherSuite::TLS_FALLBACK_SCSV)
+        {
+            let offered = match maybe_versions_ext {
+                Some(versions) if versions.contains(&ProtocolVersion::TLSv1_3) => {
+                    ProtocolVersion::TLSv1_3
+                }
+                Some(versions) if versions.contains(&ProtocolVersion::TLSv1_2) => {
+                    ProtocolVersion::TLSv1_2
+                }
+                _ => client_hello.client_version,
+            };
+            let ours = match tls13_enabled {
+                true => ProtocolVersion::TLSv1_3,
+                false => ProtocolVersion::TLSv1_2,
+            };
+            if offered.get_u16() < ours.get_u16() {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::InappropriateFallback,
+                    PeerMisbehaved::InappropriateFallback,
+                ));
+            }
+        }
         let version = if let Some(versions) = maybe_versions_ext {
             if versions.contains(&ProtocolVersion::TLSv1_3) && tls13_enabled {
                 ProtocolVersion::TLSv1_3
@@ -311,13 +418,35 @@ impl ExpectClientHello {
         sig_schemes
             .retain(|scheme| suites::compatible_sigscheme_for_suites(*scheme, &client_suites));
 
+        // A client offering one of our external PSKs doesn't need a certificate
+        // from us, as the PSK authenticates us instead.
+        let offered_external_psks = match (version, client_hello.get_psk()) {
+            (ProtocolVersion::TLSv1_3, Some(offer)) => self
+                .config
+                .usable_external_psks()
+                .iter()
+                .filter(|psk| {
+                    offer
+                        .identities
+                        .iter()
+                        .any(|id| psk.identity() == id.identity.0)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
         // Choose a certificate.
         let certkey = {
+            let selected_alpn = choose_alpn(&self.config, client_hello);
+
             let client_hello = ClientHello::new(
                 &cx.data.sni,
                 &sig_schemes,
                 client_hello.get_alpn_extension(),
                 &client_hello.cipher_suites,
+                client_hello.get_namedgroups_extension(),
+                client_hello.get_psk(),
+                selected_alpn,
             );
 
             let certkey = self
@@ -325,29 +454,56 @@ impl ExpectClientHello {
                 .cert_resolver
                 .resolve(client_hello);
 
-            certkey.ok_or_else(|| {
-                cx.common.send_fatal_alert(
-                    AlertDescription::AccessDenied,
-                    Error::General("no server certificate chain resolved".to_owned()),
-                )
-            })?
+            match certkey {
+                Some(certkey) => Some(certkey),
+                None if !offered_external_psks.is_empty() => None,
+                None => return Err(no_certificate_resolved(cx.common)),
+            }
         };
-        let certkey = ActiveCertifiedKey::from_certified_key(&certkey);
+        let certkey = certkey
+            .as_deref()
+            .map(ActiveCertifiedKey::from_certified_key);
 
         // Reduce our supported ciphersuites by the certificate.
-        // (no-op for TLS1.3)
-        let suitable_suites = suites::reduce_given_sigalg(
-            &self.config.provider.cipher_suites,
-            certkey.get_key().algorithm(),
-        );
+        // (no-op for TLS1.3)  Without one, we must choose a suite usable
+        // with an offered external PSK.
+        let suitable_suites = match &certkey {
+            Some(certkey) => suites::reduce_given_sigalg(
+                &self.config.provider.cipher_suites,
+                certkey.get_key().algorithm(),
+            ),
+            None => self
+                .config
+                .provider
+                .cipher_suites
+                .iter()
+                .copied()
+                .filter(|scs| match scs.tls13() {
+                    Some(suite) => offered_external_psks
+                        .iter()
+                        .any(|psk| psk.usable_with(suite)),
+                    None => false,
+                })
+                .collect(),
+        };
 
         // And version
-        let suitable_suites = suites::reduce_given_version_and_protocol(
+        let mut suitable_suites = suites::reduce_given_version_and_protocol(
             &suitable_suites,
             version,
             cx.common.protocol,
         );
 
+        // And any runtime policy
+        if let Some(filter) = &self.config.filter_cipher_suites {
+            let acceptable = suitable_suites
+                .iter()
+                .map(|scs| scs.suite())
+                .collect::<Vec<_>>();
+            let allowed = filter.filter(&acceptable);
+            suitable_suites.retain(|scs| allowed.contains(&scs.suite()));
+        }
+
         let suite = if self.config.ignore_client_order {
             suites::choose_ciphersuite_preferring_server(
                 &client_hello.cipher_suites,
@@ -403,28 +559,38 @@ impl ExpectClientHello {
             }
             .handle_client_hello(cx, certkey, m, client_hello, sig_schemes),
             #[cfg(feature = "tls12")]
-            SupportedCipherSuite::Tls12(suite) => tls12::CompleteClientHelloHandling {
-                config: self.config,
-                transcript,
-                session_id: self.session_id,
-                suite,
-                using_ems: self.using_ems,
-                randoms,
-                send_ticket: self.send_tickets > 0,
-                extra_exts: self.extra_exts,
+            SupportedCipherSuite::Tls12(suite) => {
+                let certkey = certkey.ok_or_else(|| no_certificate_resolved(cx.common))?;
+                tls12::CompleteClientHelloHandling {
+                    config: self.config,
+                    transcript,
+                    session_id: self.session_id,
+                    suite,
+                    using_ems: self.using_ems,
+                    randoms,
+                    send_ticket: self.send_tickets > 0,
+                    extra_exts: self.extra_exts,
+                }
+                .handle_client_hello(
+                    cx,
+                    certkey,
+                    m,
+                    client_hello,
+                    sig_schemes,
+                    tls13_enabled,
+                )
             }
-            .handle_client_hello(
-                cx,
-                certkey,
-                m,
-                client_hello,
-                sig_schemes,
-                tls13_enabled,
-            ),
         }
     }
 }
 
+pub(super) fn no_certificate_resolved(common: &mut CommonState) -> Error {
+    common.send_fatal_alert(
+        AlertDescription::AccessDenied,
+        Error::General("no server certificate chain resolved".to_owned()),
+    )
+}
+
 impl State<ServerConnectionData> for ExpectClientHello {
     fn handle(self: Box<Self>, cx: &mut ServerContext<'_>, m: Message) -> NextStateOrError {
         let (client_hello, sig_schemes) = process_client_hello(&m, self.done_retry, cx)?;
@@ -1,18 +1,27 @@
 use crate::builder::ConfigBuilder;
-use crate::common_state::{CommonState, Context, Protocol, Side, State};
+use crate::common_state::{
+    CommonState, Context, ObservesAlerts, ObservesHandshakeMessages, Protocol, RecordPadding, Side,
+    State,
+};
 use crate::conn::{ConnectionCommon, ConnectionCore};
-use crate::crypto::CryptoProvider;
+use crate::crypto::cipher::ObservesAad;
+use crate::crypto::{CryptoProvider, SecureRandom};
 use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
 use crate::error::Error;
 #[cfg(feature = "logging")]
 use crate::log::trace;
 use crate::msgs::base::Payload;
-use crate::msgs::handshake::{ClientHelloPayload, ProtocolName, ServerExtension};
+use crate::msgs::enums::{HeartbeatMode, NamedGroup};
+use crate::msgs::handshake::{
+    ClientHelloPayload, PresharedKeyOffer, ProtocolName, ServerExtension,
+};
 use crate::msgs::message::Message;
+use crate::rand;
 use crate::suites::ExtractedSecrets;
+use crate::tls13::key_schedule::BinderKeyCache;
+use crate::tls13::ExternalPsk;
 use crate::vecbuf::ChunkVecBuffer;
 use crate::verify;
-#[cfg(feature = "ring")]
 use crate::versions;
 use crate::KeyLog;
 #[cfg(feature = "ring")]
@@ -32,6 +41,48 @@ use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
 use std::io;
 
+/// Observes `ClientHello` extensions that rustls does not recognise.
+///
+/// Unknown extensions (including GREASE values) are always ignored during
+/// negotiation; this only allows them to be logged or counted.
+pub trait ObservesUnknownExtensions: Debug + Send + Sync {
+    /// Called once for each unrecognised extension in a `ClientHello`, with its
+    /// type code point and its undecoded body.
+    fn observe(&self, typ: u16, payload: &[u8]);
+}
+
+/// Narrows the cipher suites a server will accept, per `ClientHello`.
+///
+/// This allows suites to be withdrawn at runtime without building a new
+/// [`ServerConfig`].
+pub trait FiltersCipherSuites: Debug + Send + Sync {
+    /// Called during negotiation with the suites this server could select
+    /// for the current `ClientHello`, in its order of preference.
+    ///
+    /// Returns the suites that remain acceptable.  Suites that were not
+    /// passed in are ignored, and the order of the result does not matter.
+    fn filter(&self, acceptable: &[CipherSuite]) -> Vec<CipherSuite>;
+}
+
+/// Produces and checks the cookies a server sends in TLS1.3 `HelloRetryRequest`s.
+///
+/// The client echoes the cookie verbatim in its second `ClientHello`, so
+/// this lets a server carry state across a retry without keeping it
+/// itself.  Cookies are untrusted input when they come back, so
+/// implementations should authenticate them (for example, with a MAC
+/// under a server-only key) and reject anything they did not produce.
+pub trait CookieProvider: Debug + Send + Sync {
+    /// Produce the cookie to send in a `HelloRetryRequest`.
+    ///
+    /// `client_hello_hash` is the transcript hash of the first `ClientHello`.
+    /// The returned cookie must not be empty.
+    fn generate(&self, client_hello_hash: &[u8]) -> Vec<u8>;
+
+    /// Returns `true` if `cookie`, echoed by the client in its retried
+    /// `ClientHello`, is one produced by [`CookieProvider::generate`].
+    fn verify(&self, cookie: &[u8]) -> bool;
+}
+
 /// A trait for the ability to store server session data.
 ///
 /// The keys and values are opaque.
@@ -126,6 +177,9 @@ pub struct ClientHello<'a> {
     signature_schemes: &'a [SignatureScheme],
     alpn: Option<&'a Vec<ProtocolName>>,
     cipher_suites: &'a [CipherSuite],
+    named_groups: Option<&'a [NamedGroup]>,
+    psk_offer: Option<&'a PresharedKeyOffer>,
+    selected_alpn: Option<&'a [u8]>,
 }
 
 impl<'a> ClientHello<'a> {
@@ -135,17 +189,26 @@ impl<'a> ClientHello<'a> {
         signature_schemes: &'a [SignatureScheme],
         alpn: Option<&'a Vec<ProtocolName>>,
         cipher_suites: &'a [CipherSuite],
+        named_groups: Option<&'a [NamedGroup]>,
+        psk_offer: Option<&'a PresharedKeyOffer>,
+        selected_alpn: Option<&'a [u8]>,
     ) -> Self {
         trace!("sni {:?}", server_name);
         trace!("sig schemes {:?}", signature_schemes);
         trace!("alpn protocols {:?}", alpn);
         trace!("cipher suites {:?}", cipher_suites);
+        trace!("named groups {:?}", named_groups);
+        trace!("psk offer {:?}", psk_offer);
+        trace!("selected alpn {:?}", selected_alpn);
 
         ClientHello {
             server_name,
             signature_schemes,
             alpn,
             cipher_suites,
+            named_groups,
+            psk_offer,
+            selected_alpn,
         }
     }
 
@@ -190,10 +253,59 @@ impl<'a> ClientHello<'a> {
         })
     }
 
+    /// Get the ALPN protocol the server will agree, if the handshake goes ahead.
+    ///
+    /// This is the first of [`ServerConfig::alpn_protocols`] that the client
+    /// offered in [`ClientHello::alpn`], and is `None` if there is no such
+    /// protocol.  Certificate resolution happens before ALPN is agreed, so
+    /// this lets a [`ResolvesServerCert`] choose a certificate to suit it.
+    ///
+    /// This is always `None` from [`Accepted::client_hello`], as no
+    /// [`ServerConfig`] has been chosen at that point.
+    pub fn selected_alpn(&self) -> Option<&'a [u8]> {
+        self.selected_alpn
+    }
+
     /// Get cipher suites.
     pub fn cipher_suites(&self) -> &[CipherSuite] {
         self.cipher_suites
     }
+
+    /// Get the key exchange groups offered by the client, in the client's order.
+    ///
+    /// Returns `None` if the client did not include a `supported_groups` extension.
+    pub fn named_groups(&self) -> Option<impl Iterator<Item = NamedGroup> + 'a> {
+        self.named_groups
+            .map(|groups| groups.iter().copied())
+    }
+
+    /// Get the PSK identities offered by the client in its `pre_shared_key` extension,
+    /// in the client's order.
+    ///
+    /// Returns `None` if the client did not include a `pre_shared_key` extension. These
+    /// are typically resumption tickets, or the identities of external PSKs; they have not
+    /// been checked in any way at this point.
+    pub fn psk_identities(&self) -> Option<impl Iterator<Item = &'a [u8]>> {
+        self.psk_offer.map(|offer| {
+            offer
+                .identities
+                .iter()
+                .map(|id| id.identity.0.as_slice())
+        })
+    }
+
+    /// Get the obfuscated ticket ages offered by the client alongside each of its
+    /// [`psk_identities()`][Self::psk_identities].
+    ///
+    /// Returns `None` if the client did not include a `pre_shared_key` extension.
+    pub fn psk_obfuscated_ticket_ages(&self) -> Option<impl Iterator<Item = u32> + 'a> {
+        self.psk_offer.map(|offer| {
+            offer
+                .identities
+                .iter()
+                .map(|id| id.obfuscated_ticket_age)
+        })
+    }
 }
 
 /// Common configuration for a set of server sessions.
@@ -212,6 +324,10 @@ impl<'a> ClientHello<'a> {
 /// * [`ServerConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
 /// * [`ServerConfig::key_log`]: key material is not logged.
 /// * [`ServerConfig::send_tls13_tickets`]: 4 tickets are sent.
+/// * [`ServerConfig::send_tls13_tickets_jitter`]: 0, so exactly that many are sent.
+/// * [`ServerConfig::allow_tls13_compression_offers`]: false.
+/// * [`ServerConfig::record_padding`]: [`RecordPadding::None`].
+/// * [`ServerConfig::heartbeat_mode`]: `None`, so heartbeats are not negotiated.
 ///
 /// [`RootCertStore`]: crate::RootCertStore
 #[derive(Debug)]
@@ -237,12 +353,47 @@ pub struct ServerConfig {
     /// [ServerConnection::new]: crate::server::ServerConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// The maximum total size of the handshake messages the peer may send in
+    /// a single flight during the handshake, that is, between handshake
+    /// messages sent by us.
+    ///
+    /// Each message is already limited to 64kB; this additionally bounds the
+    /// work done for a flight made of many messages.  Exceeding it fails the
+    /// connection with a `decode_error` alert.  The default is `None`,
+    /// meaning no limit beyond the per-message one.
+    pub max_handshake_flight_bytes: Option<usize>,
+
+    /// Whether to negotiate the [RFC6520] `heartbeat` extension, and if so,
+    /// whether the peer may send us heartbeat requests.
+    ///
+    /// Heartbeats are only negotiated when both peers configure this. Once the
+    /// handshake is complete, requests can be sent with
+    /// [`CommonState::send_heartbeat`] if the peer allows it.  The default is
+    /// `None`: the extension is neither offered nor accepted.
+    ///
+    /// [RFC6520]: https://datatracker.ietf.org/doc/html/rfc6520
+    /// [`CommonState::send_heartbeat`]: crate::CommonState::send_heartbeat
+    pub heartbeat_mode: Option<HeartbeatMode>,
+
     /// How to store client sessions.
     pub session_storage: Arc<dyn StoresServerSessions + Send + Sync>,
 
     /// How to produce tickets.
     pub ticketer: Arc<dyn ProducesTickets>,
 
+    /// Externally-provisioned TLS1.3 pre-shared keys that clients may
+    /// authenticate with, in place of certificates.
+    ///
+    /// A client offering the identity of one of these keys, with a valid
+    /// binder, gets a handshake without any certificates.  The default is
+    /// empty.
+    ///
+    /// These are ignored if the [`ClientCertVerifier`](verify::ClientCertVerifier) mandates client
+    /// authentication: clients must then present a certificate in a full
+    /// handshake.  A verifier which only offers client authentication is
+    /// skipped by handshakes using one of these keys.
+    pub external_psks: Vec<Arc<ExternalPsk>>,
+
     /// How to choose a server cert and key. This is usually set by
     /// [ConfigBuilder::with_single_cert] or [ConfigBuilder::with_cert_resolver].
     /// For async applications, see also [Acceptor].
@@ -252,6 +403,14 @@ pub struct ServerConfig {
     /// If empty we don't do ALPN at all.
     pub alpn_protocols: Vec<Vec<u8>>,
 
+    /// Only resume a session if the ALPN protocol we would negotiate now
+    /// is the same as the one the session was established with.
+    ///
+    /// Otherwise the client can, for example, resume an `h2` session into an
+    /// `http/1.1` context.  A mismatched session falls back to a full
+    /// handshake.  The default is `false`.
+    pub require_resumption_alpn_match: bool,
+
     /// Supported protocol versions, in no particular order.
     /// The default is all supported versions.
     pub(super) versions: crate::versions::EnabledVersions,
@@ -311,7 +470,125 @@ pub struct ServerConfig {
     ///
     /// If this is 0, no tickets are sent and clients will not be able to
     /// do any resumption.
+    ///
+    /// At most [`MAX_TLS13_TICKETS`] tickets are sent, whatever this is set to.
+    /// See also [`ServerConfig::num_tickets`].
+    ///
+    /// A client may ask for fewer tickets using the `ticket_request` extension,
+    /// which is honoured.  It cannot ask for more.
     pub send_tls13_tickets: usize,
+
+    /// Up to how many extra TLS1.3 tickets to send, chosen uniformly at random
+    /// for each handshake.
+    ///
+    /// This stops the number of tickets from fingerprinting the server.  For
+    /// example, with [`ServerConfig::send_tls13_tickets`] of 2 and a jitter of 3,
+    /// each handshake is followed by between 2 and 5 tickets.  The total is
+    /// still capped at [`MAX_TLS13_TICKETS`].
+    ///
+    /// The default is 0.
+    pub send_tls13_tickets_jitter: usize,
+
+    /// Whether to accept TLS1.3 `ClientHello`s that offer compression methods
+    /// besides null.
+    ///
+    /// RFC 8446 requires a TLS1.3 `ClientHello` to offer only the null
+    /// compression method, and by default one that does not is rejected with
+    /// an `illegal_parameter` alert.  Setting this to true logs a warning
+    /// instead and continues without compression, for interoperability with
+    /// nonconforming clients.  A `ClientHello` that does not offer null
+    /// compression at all is always rejected.
+    ///
+    /// The default is false.
+    pub allow_tls13_compression_offers: bool,
+
+    /// Called for each extension in the client's `ClientHello` that is not
+    /// recognised.  The default is `None`.
+    ///
+    /// This cannot affect the handshake.
+    pub on_unknown_extension: Option<Arc<dyn ObservesUnknownExtensions>>,
+
+    /// Generates and validates the `cookie` sent with TLS1.3 `HelloRetryRequest`s.
+    /// The default is `None`, meaning no cookie is sent.
+    ///
+    /// If set, a retried `ClientHello` that does not echo a cookie accepted by
+    /// [`CookieProvider::verify`] fails the handshake.
+    pub cookie_provider: Option<Arc<dyn CookieProvider>>,
+
+    /// How much padding to add to TLS1.3 records.
+    pub record_padding: RecordPadding,
+
+    /// Called for each alert sent or received.  The default is `None`.
+    pub alert_observer: Option<Arc<dyn ObservesAlerts>>,
+
+    /// Told the exact `additional_data` given to the AEAD for each record
+    /// this connection encrypts or decrypts, for auditing.  The default is
+    /// `None`.
+    ///
+    /// Only records protected by ciphers which report their
+    /// `additional_data` are observed; those of the built-in providers do.
+    pub aad_observer: Option<Arc<dyn ObservesAad>>,
+
+    /// Source of the starting point for the explicit nonces in TLS1.2 records
+    /// this connection sends, for cipher suites that have them (AES-GCM).
+    ///
+    /// If this is `None` (the default), the starting point is derived from
+    /// the key block.  This allows record-layer nonce generation to be
+    /// controlled separately from the provider's `secure_random`.
+    pub explicit_nonce_random: Option<&'static dyn SecureRandom>,
+
+    /// Application-layer protocol settings (ALPS) to exchange in TLS1.3
+    /// handshakes, as pairs of an ALPN protocol and the settings to send
+    /// when that protocol is agreed.  The default is empty.
+    ///
+    /// Settings are exchanged only if both peers have settings for the agreed
+    /// protocol; the peer's are then available from
+    /// [`CommonState::peer_alps_settings`].
+    ///
+    /// Settings are not exchanged in handshakes that accept early data.
+    pub alps_settings: Vec<(Vec<u8>, Vec<u8>)>,
+
+    /// Whether reads from [`ServerConnection::reader`] stop at the end of
+    /// each received TLS record, rather than coalescing data across records.
+    ///
+    /// The default is false.
+    ///
+    /// [`ServerConnection::reader`]: crate::ConnectionCommon::reader
+    pub preserve_record_boundaries: bool,
+
+    /// Whether to refuse TLS1.2 handshakes with clients that do not offer
+    /// the [RFC7627] `extended_master_secret` extension.
+    ///
+    /// Such handshakes fail with a `handshake_failure` alert.  This mitigates
+    /// the triple handshake attack.  TLS1.3 is unaffected.  The default is
+    /// false.
+    ///
+    /// [RFC7627]: https://datatracker.ietf.org/doc/html/rfc7627
+    pub require_ems: bool,
+
+    /// Whether TLS1.2 clients may resume by offering a session ID.
+    ///
+    /// When false, no session IDs are issued or stored in
+    /// [`ServerConfig::session_storage`], and offered session IDs are not
+    /// looked up.  Ticket resumption (see [`ServerConfig::ticketer`]) is
+    /// unaffected.  The default is true.
+    pub tls12_session_id_resumption: bool,
+
+    /// Called with each handshake message as it is added to the handshake
+    /// transcript.  The default is `None`.
+    pub on_handshake_message: Option<Arc<dyn ObservesHandshakeMessages>>,
+
+    /// Called before a cipher suite is selected, to remove suites from
+    /// those acceptable for this handshake.  The default is `None`.
+    ///
+    /// If nothing acceptable remains in common with the client, the
+    /// handshake fails as though the client offered no usable suite.
+    pub filter_cipher_suites: Option<Arc<dyn FiltersCipherSuites>>,
+
+    /// Cache of TLS1.3 PSK binder keys, shared between clones of this config.
+    ///
+    /// Sized with [`ServerConfig::binder_key_cache_size`].
+    pub(super) binder_key_cache: Arc<BinderKeyCache>,
 }
 
 // Avoid a `Clone` bound on `C`.
@@ -321,10 +598,14 @@ impl Clone for ServerConfig {
             provider: Arc::<CryptoProvider>::clone(&self.provider),
             ignore_client_order: self.ignore_client_order,
             max_fragment_size: self.max_fragment_size,
+            max_handshake_flight_bytes: self.max_handshake_flight_bytes,
+            heartbeat_mode: self.heartbeat_mode,
             session_storage: Arc::clone(&self.session_storage),
             ticketer: Arc::clone(&self.ticketer),
+            external_psks: self.external_psks.clone(),
             cert_resolver: Arc::clone(&self.cert_resolver),
             alpn_protocols: self.alpn_protocols.clone(),
+            require_resumption_alpn_match: self.require_resumption_alpn_match,
             versions: self.versions,
             verifier: Arc::clone(&self.verifier),
             key_log: Arc::clone(&self.key_log),
@@ -332,6 +613,21 @@ impl Clone for ServerConfig {
             max_early_data_size: self.max_early_data_size,
             send_half_rtt_data: self.send_half_rtt_data,
             send_tls13_tickets: self.send_tls13_tickets,
+            send_tls13_tickets_jitter: self.send_tls13_tickets_jitter,
+            allow_tls13_compression_offers: self.allow_tls13_compression_offers,
+            on_unknown_extension: self.on_unknown_extension.clone(),
+            cookie_provider: self.cookie_provider.clone(),
+            record_padding: self.record_padding,
+            alert_observer: self.alert_observer.clone(),
+            aad_observer: self.aad_observer.clone(),
+            explicit_nonce_random: self.explicit_nonce_random,
+            alps_settings: self.alps_settings.clone(),
+            preserve_record_boundaries: self.preserve_record_boundaries,
+            require_ems: self.require_ems,
+            tls12_session_id_resumption: self.tls12_session_id_resumption,
+            on_handshake_message: self.on_handshake_message.clone(),
+            filter_cipher_suites: self.filter_cipher_suites.clone(),
+            binder_key_cache: Arc::clone(&self.binder_key_cache),
         }
     }
 }
@@ -402,8 +698,95 @@ impl ServerConfig {
             .iter()
             .any(|cs| cs.usable_for_protocol(proto))
     }
+
+    /// The cipher suites this configuration accepts, in order of preference.
+    ///
+    /// These are the suites of the configured [`CryptoProvider`] for the
+    /// enabled protocol versions.  A TLS1.2 suite is further only chosen if it
+    /// suits the type of the selected certificate's key, and QUIC connections
+    /// only use suites usable with QUIC.
+    pub fn advertised_cipher_suites(&self) -> Vec<CipherSuite> {
+        self.provider
+            .cipher_suites
+            .iter()
+            .filter(|cs| {
+                self.versions
+                    .contains(cs.version().version)
+            })
+            .map(|cs| cs.suite())
+            .collect()
+    }
+
+    /// Restricts the key exchange groups used to those in `groups`.
+    ///
+    /// Groups of the configured [`CryptoProvider`] which are not in `groups`
+    /// are no longer offered to or accepted from peers; the provider's order
+    /// of preference is kept.  A peer supporting none of the remaining groups
+    /// fails the handshake with a `handshake_failure` alert.
+    ///
+    /// Fails, leaving the configuration unchanged, if none of the provider's
+    /// groups are in `groups`.
+    pub fn restrict_kx_groups(&mut self, groups: &[NamedGroup]) -> Result<(), Error> {
+        self.provider = Arc::new(
+            self.provider
+                .restricted_to_kx_groups(groups)?,
+        );
+        Ok(())
+    }
+
+    /// Sets how many TLS1.3 tickets to send after each handshake.
+    ///
+    /// This sets [`ServerConfig::send_tls13_tickets`], limiting `n` to
+    /// [`MAX_TLS13_TICKETS`].
+    pub fn num_tickets(&mut self, n: usize) -> &mut Self {
+        self.send_tls13_tickets = Ord::min(n, MAX_TLS13_TICKETS);
+        self
+    }
+
+    /// Sets how many TLS1.3 PSK binder keys to cache.
+    ///
+    /// Checking the binder of a resumption attempt needs several key derivations
+    /// which only depend on the ticket's PSK; caching them speeds up repeated
+    /// resumptions with the same ticket.  Replaces any existing cache, which is
+    /// otherwise shared between clones of this config.  Zero disables the cache.
+    /// The default is 256.
+    pub fn binder_key_cache_size(&mut self, entries: usize) -> &mut Self {
+        self.binder_key_cache = Arc::new(BinderKeyCache::new(entries));
+        self
+    }
+
+    /// The external PSKs clients may authenticate with.
+    ///
+    /// These are never used if the client verifier mandates client
+    /// authentication, as a handshake using one skips the client's certificate.
+    pub(crate) fn usable_external_psks(&self) -> &[Arc<ExternalPsk>] {
+        match self.verifier.client_auth_mandatory() {
+            true => &[],
+            false => &self.external_psks,
+        }
+    }
+
+    /// How many tickets to send after a TLS1.3 handshake, including any jitter.
+    pub(crate) fn tls13_tickets_to_send(&self) -> Result<usize, Error> {
+        let jitter = match self.send_tls13_tickets_jitter {
+            0 => 0,
+            jitter => {
+                let jitter = Ord::min(jitter, MAX_TLS13_TICKETS);
+                rand::random_u32(self.provider.secure_random)? as usize % (jitter + 1)
+            }
+        };
+
+        Ok(Ord::min(
+            self.send_tls13_tickets
+                .saturating_add(jitter),
+            MAX_TLS13_TICKETS,
+        ))
+    }
 }
 
+/// The most TLS1.3 tickets a server sends after a handshake.
+pub const MAX_TLS13_TICKETS: usize = 32;
+
 /// Allows reading of early data in resumed TLS1.3 connections.
 ///
 /// "Early data" is also known as "0-RTT data".
@@ -450,6 +833,50 @@ impl ServerConnection {
         })
     }
 
+    /// Make a new ServerConnection carrying an opaque `connection_id`.
+    ///
+    /// The identifier is not sent to the client.  It is returned by
+    /// [`CommonState::connection_id`] and passed to the
+    /// [`ServerConfig::alert_observer`] and [`KeyLog::log_for_connection`],
+    /// so that callbacks for this connection can be told apart from those of
+    /// others sharing `config`.
+    pub fn new_with_connection_id(
+        config: Arc<ServerConfig>,
+        connection_id: Vec<u8>,
+    ) -> Result<Self, Error> {
+        let mut conn = Self::new(config)?;
+        conn.inner
+            .core
+            .common_state
+            .connection_id = Some(connection_id);
+        Ok(conn)
+    }
+
+    /// Make a new ServerConnection which refuses to negotiate any protocol
+    /// version older than `min_version`, regardless of the versions enabled
+    /// in `config`.
+    ///
+    /// This allows a single `config` to be shared between connections with
+    /// differing version requirements.  A client that does not offer a
+    /// permitted version is rejected with a `protocol_version` alert.
+    ///
+    /// Returns an error if no version enabled in `config` meets `min_version`.
+    pub fn new_with_min_version(
+        config: Arc<ServerConfig>,
+        min_version: &'static versions::SupportedProtocolVersion,
+    ) -> Result<Self, Error> {
+        let mut config = (*config).clone();
+        config.versions = config
+            .versions
+            .with_minimum(min_version.version);
+        if config.versions.is_empty() {
+            return Err(Error::General(
+                "no enabled protocol versions meet the minimum".into(),
+            ));
+        }
+        Self::new(Arc::new(config))
+    }
+
     /// Retrieves the server name, if any, used to select the certificate and
     /// private key.
     ///
@@ -524,6 +951,31 @@ impl ServerConnection {
         }
     }
 
+    /// Returns the number of bytes of early data the client may still send,
+    /// under the limit set by [`ServerConfig::max_early_data_size`].
+    ///
+    /// This is decremented as early data is received, regardless of whether it
+    /// has been read from [`ServerConnection::early_data`] yet.  A client that
+    /// sends more than this causes the connection to fail with an
+    /// `unexpected_message` alert.
+    ///
+    /// This returns `None` if early data has not been accepted for this connection.
+    pub fn early_data_left(&self) -> Option<usize> {
+        self.inner.core.data.early_data.left()
+    }
+
+    /// Returns why the client's early data was refused, if it offered some.
+    ///
+    /// This is `None` if the client did not offer early data, or if it was
+    /// accepted.  The reason is known once the client's `ClientHello` has been
+    /// processed.
+    pub fn early_data_reject_reason(&self) -> Option<EarlyDataRejectReason> {
+        self.inner
+            .core
+            .data
+            .early_data_reject_reason
+    }
+
     /// Extract secrets, so they can be used when configuring kTLS, for example.
     /// Should be used with care as it exposes secret key material.
     pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
@@ -625,6 +1077,18 @@ impl Default for Acceptor {
 }
 
 impl Acceptor {
+    /// Return an empty Acceptor carrying an opaque `connection_id`.
+    ///
+    /// The identifier is kept by the [`ServerConnection`] made from the
+    /// resulting [`Accepted`], as for [`ServerConnection::new_with_connection_id`].
+    pub fn new_with_connection_id(connection_id: Vec<u8>) -> Self {
+        let mut acceptor = Self::default();
+        if let Some(conn) = &mut acceptor.inner {
+            conn.core.common_state.connection_id = Some(connection_id);
+        }
+        acceptor
+    }
+
     /// Read TLS content from `rd`.
     ///
     /// Returns an error if this `Acceptor` has already yielded an [`Accepted`]. For more details,
@@ -695,6 +1159,9 @@ impl Accepted {
             &self.sig_schemes,
             payload.get_alpn_extension(),
             &payload.cipher_suites,
+            payload.get_namedgroups_extension(),
+            payload.get_psk(),
+            None,
         )
     }
 
@@ -708,6 +1175,17 @@ impl Accepted {
             .set_max_fragment_size(config.max_fragment_size)?;
 
         self.connection.enable_secret_extraction = config.enable_secret_extraction;
+        self.connection
+            .set_record_padding(config.record_padding, config.provider.secure_random);
+        self.connection.alert_observer = config.alert_observer.clone();
+        self.connection
+            .preserve_record_boundaries = config.preserve_record_boundaries;
+        self.connection
+            .max_handshake_flight_bytes = config.max_handshake_flight_bytes;
+        self.connection.heartbeat_mode = config.heartbeat_mode;
+        self.connection
+            .record_layer
+            .set_aad_observer(config.aad_observer.clone());
 
         let state = hs::ExpectClientHello::new(config, Vec::new());
         let mut cx = hs::ServerContext::from(&mut self.connection);
@@ -751,7 +1229,10 @@ impl State<ServerConnectionData> for Accepting {
 
 pub(super) enum EarlyDataState {
     New,
-    Accepted(ChunkVecBuffer),
+    Accepted {
+        received: ChunkVecBuffer,
+        left: usize,
+    },
     Rejected,
 }
 
@@ -767,11 +1248,21 @@ impl EarlyDataState {
     }
 
     pub(super) fn accept(&mut self, max_size: usize) {
-        *self = Self::Accepted(ChunkVecBuffer::new(Some(max_size)));
+        *self = Self::Accepted {
+            received: ChunkVecBuffer::new(Some(max_size)),
+            left: max_size,
+        };
     }
 
     fn was_accepted(&self) -> bool {
-        matches!(self, Self::Accepted(_))
+        matches!(self, Self::Accepted { .. })
+    }
+
+    fn left(&self) -> Option<usize> {
+        match self {
+            Self::Accepted { left, .. } => Some(*left),
+            _ => None,
+        }
     }
 
     pub(super) fn was_rejected(&self) -> bool {
@@ -780,7 +1271,9 @@ impl EarlyDataState {
 
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
-            Self::Accepted(ref mut received) => received.read(buf),
+            Self::Accepted {
+                ref mut received, ..
+            } => received.read(buf),
             _ => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
         }
     }
@@ -788,15 +1281,25 @@ impl EarlyDataState {
     #[cfg(read_buf)]
     fn read_buf(&mut self, cursor: core::io::BorrowedCursor<'_>) -> io::Result<()> {
         match self {
-            Self::Accepted(ref mut received) => received.read_buf(cursor),
+            Self::Accepted {
+                ref mut received, ..
+            } => received.read_buf(cursor),
             _ => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
         }
     }
 
+    /// Buffer received early data, unless it exceeds what is left of `max_early_data_size`.
+    ///
+    /// The limit covers all early data received, whether or not the application
+    /// has read it yet.
     pub(super) fn take_received_plaintext(&mut self, bytes: Payload) -> bool {
         let available = bytes.0.len();
         match self {
-            Self::Accepted(ref mut received) if received.apply_limit(available) == available => {
+            Self::Accepted {
+                ref mut received,
+                ref mut left,
+            } if available <= *left => {
+                *left -= available;
                 received.append(bytes.0);
                 true
             }
@@ -809,7 +1312,12 @@ impl Debug for EarlyDataState {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Self::New => write!(f, "EarlyDataState::New"),
-            Self::Accepted(buf) => write!(f, "EarlyDataState::Accepted({})", buf.len()),
+            Self::Accepted { received, left } => write!(
+                f,
+                "EarlyDataState::Accepted {{ received: {}, left: {} }}",
+                received.len(),
+                left
+            ),
             Self::Rejected => write!(f, "EarlyDataState::Rejected"),
         }
     }
@@ -823,6 +1331,14 @@ impl ConnectionCore<ServerConnectionData> {
         let mut common = CommonState::new(Side::Server);
         common.set_max_fragment_size(config.max_fragment_size)?;
         common.enable_secret_extraction = config.enable_secret_extraction;
+        common.set_record_padding(config.record_padding, config.provider.secure_random);
+        common.alert_observer = config.alert_observer.clone();
+        common.preserve_record_boundaries = config.preserve_record_boundaries;
+        common.max_handshake_flight_bytes = config.max_handshake_flight_bytes;
+        common.heartbeat_mode = config.heartbeat_mode;
+        common
+            .record_layer
+            .set_aad_observer(config.aad_observer.clone());
         Ok(Self::new(
             Box::new(hs::ExpectClientHello::new(config, extra_exts)),
             ServerConnectionData::default(),
@@ -843,6 +1359,38 @@ impl ConnectionCore<ServerConnectionData> {
     }
 }
 
+/// Why a server refused the early data offered by a client.
+///
+/// Returned by [`ServerConnection::early_data_reject_reason`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EarlyDataRejectReason {
+    /// Early data is not permitted by this server's configuration.
+    ///
+    /// It must be enabled with [`ServerConfig::max_early_data_size`], and is
+    /// never accepted with stateless tickets.
+    NotPermitted,
+    /// The server needed a different key share, and sent a `HelloRetryRequest`.
+    HelloRetryRequest,
+    /// None of the tickets offered were found.
+    ///
+    /// Tickets are removed from [`ServerConfig::session_storage`] when first
+    /// used, so this is how a replayed ticket is seen.  A ticket evicted from
+    /// storage, or never issued by this server, looks the same.
+    Replay,
+    /// A ticket was found, but the handshake did not resume its session, for
+    /// example because it was issued for a different server name.
+    NotResumed,
+    /// The ticket's age, as reported by the client, was not consistent with when
+    /// it was issued.
+    TicketExpired,
+    /// The protocol version, cipher suite or ALPN protocol negotiated differs
+    /// from the ticket's.
+    ParametersChanged,
+    /// Early data was refused with [`ServerConnection::reject_early_data`].
+    Policy,
+}
+
 /// State associated with a server connection.
 #[derive(Default, Debug)]
 pub struct ServerConnectionData {
@@ -850,6 +1398,7 @@ pub struct ServerConnectionData {
     pub(super) received_resumption_data: Option<Vec<u8>>,
     pub(super) resumption_data: Vec<u8>,
     pub(super) early_data: EarlyDataState,
+    pub(super) early_data_reject_reason: Option<EarlyDataRejectReason>,
 }
 
 impl ServerConnectionData {
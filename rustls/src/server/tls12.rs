@@ -1,5 +1,5 @@
 use crate::check::inappropriate_message;
-use crate::common_state::{CommonState, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, ResumptionKind, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::crypto::ActiveKeyExchange;
 use crate::enums::ProtocolVersion;
@@ -81,6 +81,11 @@ mod client_hello {
 
             if client_hello.ems_support_offered() {
                 self.using_ems = true;
+            } else if self.config.require_ems {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::HandshakeFailure,
+                    PeerIncompatible::ExtendedMasterSecretExtensionRequired,
+                ));
             }
 
             let groups_ext = client_hello
@@ -148,7 +153,10 @@ mod client_hello {
                 .or_else(|| {
                     // Perhaps resume?  If we received a ticket, the sessionid
                     // does not correspond to a real session.
-                    if client_hello.session_id.is_empty() || ticket_received {
+                    if client_hello.session_id.is_empty()
+                        || ticket_received
+                        || !self.config.tls12_session_id_resumption
+                    {
                         return None;
                     }
 
@@ -159,9 +167,14 @@ mod client_hello {
                 .and_then(|x| persist::ServerSessionValue::read_bytes(&x).ok())
                 .filter(|resumedata| {
                     hs::can_resume(self.suite.into(), &cx.data.sni, self.using_ems, resumedata)
+                        && hs::resumption_alpn_acceptable(&self.config, client_hello, resumedata)
                 });
 
             if let Some(data) = resume_data {
+                cx.common.handshake_kind = Some(HandshakeKind::Resumed(match ticket_received {
+                    true => ResumptionKind::Tls12Ticket,
+                    false => ResumptionKind::Tls12SessionId,
+                }));
                 return self.start_resumption(cx, client_hello, &client_hello.session_id, data);
             }
 
@@ -173,7 +186,9 @@ mod client_hello {
             if sigschemes.is_empty() {
                 return Err(cx.common.send_fatal_alert(
                     AlertDescription::HandshakeFailure,
-                    PeerIncompatible::NoSignatureSchemesInCommon,
+                    Error::NoSignatureSchemes {
+                        offered: sigschemes_ext,
+                    },
                 ));
             }
 
@@ -205,9 +220,13 @@ mod client_hello {
             debug_assert_eq!(ecpoint, ECPointFormat::Uncompressed);
 
             let mut ocsp_response = server_key.get_ocsp();
+            let mut sct_list = server_key.get_scts();
+
+            cx.common.handshake_kind = Some(HandshakeKind::Full);
 
             // If we're not offered a ticket or a potential session ID, allocate a session ID.
-            if !self.config.session_storage.can_cache() {
+            if !self.config.session_storage.can_cache() || !self.config.tls12_session_id_resumption
+            {
                 self.session_id = SessionId::empty();
             } else if self.session_id.is_empty() && !ticket_received {
                 self.session_id = SessionId::random(self.config.provider.secure_random)?;
@@ -221,6 +240,7 @@ mod client_hello {
                 self.suite,
                 self.using_ems,
                 &mut ocsp_response,
+                &mut sct_list,
                 client_hello,
                 None,
                 &self.randoms,
@@ -292,20 +312,23 @@ mod client_hello {
                 self.suite,
                 self.using_ems,
                 &mut None,
+                &mut None,
                 client_hello,
                 Some(&resumedata),
                 &self.randoms,
                 self.extra_exts,
             )?;
 
-            let secrets = ConnectionSecrets::new_resume(
+            let mut secrets = ConnectionSecrets::new_resume(
                 self.randoms,
                 self.suite,
                 &resumedata.master_secret.0,
             );
-            self.config.key_log.log(
+            secrets.set_explicit_nonce_random(self.config.explicit_nonce_random)?;
+            self.config.key_log.log_for_connection(
                 "CLIENT_RANDOM",
                 &secrets.randoms.client,
+                cx.common.connection_id(),
                 &secrets.master_secret,
             );
             cx.common
@@ -347,13 +370,22 @@ mod client_hello {
         suite: &'static Tls12CipherSuite,
         using_ems: bool,
         ocsp_response: &mut Option<&[u8]>,
+        sct_list: &mut Option<&[Vec<u8>]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
         randoms: &ConnectionRandoms,
         extra_exts: Vec<ServerExtension>,
     ) -> Result<bool, Error> {
         let mut ep = hs::ExtensionProcessing::new();
-        ep.process_common(config, cx, ocsp_response, hello, resumedata, extra_exts)?;
+        ep.process_common(
+            config,
+            cx,
+            ocsp_response,
+            sct_list,
+            hello,
+            resumedata,
+            extra_exts,
+        )?;
         ep.process_tls12(config, hello, using_ems);
 
         let sh = Message {
@@ -603,17 +635,24 @@ impl State<ServerConnectionData> for ExpectClientKx {
         // resulting premaster secret.
         let peer_kx_params =
             tls12::decode_ecdh_params::<ClientEcdhParams>(cx.common, &client_kx.0)?;
-        let secrets = ConnectionSecrets::from_key_exchange(
-            self.server_kx,
-            &peer_kx_params.public.0,
-            ems_seed,
-            self.randoms,
-            self.suite,
-        )?;
-
-        self.config.key_log.log(
+        let (randoms, suite) = (self.randoms, self.suite);
+        let mut secrets = cx
+            .common
+            .complete_key_exchange(self.server_kx, |kx| {
+                ConnectionSecrets::from_key_exchange(
+                    kx,
+                    &peer_kx_params.public.0,
+                    ems_seed,
+                    randoms,
+                    suite,
+                )
+            })?;
+        secrets.set_explicit_nonce_random(self.config.explicit_nonce_random)?;
+
+        self.config.key_log.log_for_connection(
             "CLIENT_RANDOM",
             &secrets.randoms.client,
+            cx.common.connection_id(),
             &secrets.master_secret,
         );
         cx.common
@@ -1,12 +1,14 @@
 use crate::check::inappropriate_handshake_message;
 use crate::check::inappropriate_message;
 use crate::common_state::Protocol;
-use crate::common_state::{CommonState, Side, State};
+use crate::common_state::{CommonState, HandshakeKind, ResumptionKind, Side, State};
 use crate::conn::ConnectionRandoms;
+use crate::crypto::hash;
 use crate::enums::ProtocolVersion;
 use crate::enums::{AlertDescription, ContentType, HandshakeType};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHash;
+use crate::key_log::ConnectionKeyLog;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
 use crate::msgs::codec::Codec;
@@ -14,6 +16,8 @@ use crate::msgs::enums::KeyUpdateRequest;
 use crate::msgs::handshake::CertificateChain;
 use crate::msgs::handshake::HandshakeMessagePayload;
 use crate::msgs::handshake::HandshakePayload;
+use crate::msgs::handshake::HasServerExtensions;
+use crate::msgs::handshake::Sct;
 use crate::msgs::handshake::{NewSessionTicketExtension, NewSessionTicketPayloadTls13};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
@@ -23,11 +27,11 @@ use crate::suites::PartiallyExtractedSecrets;
 use crate::tls13::construct_client_verify_message;
 use crate::tls13::construct_server_verify_message;
 use crate::tls13::key_schedule::{KeyScheduleTraffic, KeyScheduleTrafficWithClientFinishedPending};
-use crate::tls13::Tls13CipherSuite;
+use crate::tls13::{ExternalPsk, Tls13CipherSuite};
 use crate::verify;
 
 use super::hs::{self, HandshakeHashOrBuffer, ServerContext};
-use super::server_conn::ServerConnectionData;
+use super::server_conn::{EarlyDataRejectReason, ServerConnectionData};
 
 use alloc::borrow::ToOwned;
 use alloc::boxed::Box;
@@ -41,9 +45,10 @@ use subtle::ConstantTimeEq;
 pub(super) use client_hello::CompleteClientHelloHandling;
 
 mod client_hello {
+    use crate::crypto::hash;
     use crate::crypto::SupportedKxGroup;
     use crate::enums::SignatureScheme;
-    use crate::msgs::base::{Payload, PayloadU8};
+    use crate::msgs::base::{Payload, PayloadU16, PayloadU8};
     use crate::msgs::ccs::ChangeCipherSpecPayload;
     use crate::msgs::enums::NamedGroup;
     use crate::msgs::enums::{Compression, PSKKeyExchangeMode};
@@ -54,6 +59,7 @@ mod client_hello {
     use crate::msgs::handshake::CertificateRequestPayloadTls13;
     use crate::msgs::handshake::CertificateStatus;
     use crate::msgs::handshake::ClientHelloPayload;
+    use crate::msgs::handshake::ConvertProtocolNameList;
     use crate::msgs::handshake::HelloRetryExtension;
     use crate::msgs::handshake::HelloRetryRequest;
     use crate::msgs::handshake::KeyShareEntry;
@@ -110,6 +116,31 @@ mod client_hello {
             psk: &[u8],
             binder: &[u8],
         ) -> bool {
+            let handshake_hash = self.binder_handshake_hash(client_hello);
+
+            let real_binder = self
+                .config
+                .binder_key_cache
+                .sign_binder(suite, psk, &handshake_hash);
+
+            ConstantTimeEq::ct_eq(real_binder.as_ref(), binder).into()
+        }
+
+        fn check_external_psk_binder(
+            &self,
+            client_hello: &Message,
+            psk: &ExternalPsk,
+            binder: &[u8],
+        ) -> bool {
+            let handshake_hash = self.binder_handshake_hash(client_hello);
+
+            let real_binder = KeyScheduleEarly::new(self.suite, psk.secret())
+                .external_psk_binder_key_and_sign_verify_data(&handshake_hash);
+
+            ConstantTimeEq::ct_eq(real_binder.as_ref(), binder).into()
+        }
+
+        fn binder_handshake_hash(&self, client_hello: &Message) -> hash::Output {
             let binder_plaintext = match &client_hello.payload {
                 MessagePayload::Handshake { parsed, .. } => {
                     parsed.get_encoding_for_binder_signing()
@@ -117,15 +148,16 @@ mod client_hello {
                 _ => unreachable!(),
             };
 
-            let handshake_hash = self
-                .transcript
-                .get_hash_given(&binder_plaintext);
-
-            let key_schedule = KeyScheduleEarly::new(suite, psk);
-            let real_binder =
-                key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
+            self.transcript
+                .get_hash_given(&binder_plaintext)
+        }
 
-            ConstantTimeEq::ct_eq(real_binder.as_ref(), binder).into()
+        fn find_external_psk(&self, identity: &[u8]) -> Option<Arc<ExternalPsk>> {
+            self.config
+                .usable_external_psks()
+                .iter()
+                .find(|psk| psk.identity() == identity && psk.usable_with(self.suite))
+                .cloned()
         }
 
         fn attempt_tls13_ticket_decryption(
@@ -148,16 +180,29 @@ mod client_hello {
         pub(in crate::server) fn handle_client_hello(
             mut self,
             cx: &mut ServerContext<'_>,
-            server_key: ActiveCertifiedKey,
+            server_key: Option<ActiveCertifiedKey>,
             chm: &Message,
             client_hello: &ClientHelloPayload,
             mut sigschemes_ext: Vec<SignatureScheme>,
         ) -> hs::NextStateOrError {
             if client_hello.compression_methods.len() != 1 {
-                return Err(cx.common.send_fatal_alert(
-                    AlertDescription::IllegalParameter,
-                    PeerMisbehaved::OfferedIncorrectCompressions,
-                ));
+                if !self
+                    .config
+                    .allow_tls13_compression_offers
+                {
+                    debug!(
+                        "Rejecting ClientHello offering compression methods {:?}",
+                        client_hello.compression_methods
+                    );
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::OfferedIncorrectCompressions,
+                    ));
+                }
+                warn!(
+                    "Ignoring compression methods {:?} offered in TLS1.3 ClientHello",
+                    client_hello.compression_methods
+                );
             }
 
             let groups_ext = client_hello
@@ -187,6 +232,20 @@ mod client_hello {
                 ));
             }
 
+            // "Clients MUST NOT offer any KeyShareEntry values for groups not
+            // listed in the client's "supported_groups" extension.  Servers
+            // MAY check for violations of these rules and abort the handshake
+            // with an "illegal_parameter" alert if one is violated."
+            if shares_ext
+                .iter()
+                .any(|share| !groups_ext.contains(&share.group))
+            {
+                return Err(cx.common.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::OfferedKeyShareForUnlistedGroup,
+                ));
+            }
+
             let early_data_requested = client_hello.early_data_extension_offered();
 
             // EarlyData extension is illegal in second ClientHello
@@ -199,6 +258,19 @@ mod client_hello {
                 });
             }
 
+            if let (true, Some(cookies)) = (self.done_retry, &self.config.cookie_provider) {
+                let valid = client_hello
+                    .get_cookie_extension()
+                    .map(|cookie| cookies.verify(&cookie.0))
+                    .unwrap_or(false);
+                if !valid {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::IllegalHelloRetryCookie,
+                    ));
+                }
+            }
+
             // choose a share that we support
             let chosen_share_and_kxg = self
                 .config
@@ -228,19 +300,35 @@ mod client_hello {
                     self.transcript.add_message(chm);
 
                     if let Some(group) = retry_group_maybe {
+                        // Only one HelloRetryRequest is allowed per handshake (RFC8446
+                        // section 4.1.4), so a client that still lacks a usable share
+                        // after retrying is aborted rather than retried again.
                         if self.done_retry {
                             return Err(cx.common.send_fatal_alert(
-                                AlertDescription::IllegalParameter,
+                                AlertDescription::UnexpectedMessage,
                                 PeerMisbehaved::RefusedToFollowHelloRetryRequest,
                             ));
                         }
 
+                        let cookie = self
+                            .config
+                            .cookie_provider
+                            .as_ref()
+                            .map(|cookies| {
+                                cookies.generate(
+                                    self.transcript
+                                        .get_current_hash()
+                                        .as_ref(),
+                                )
+                            });
+
                         emit_hello_retry_request(
                             &mut self.transcript,
                             self.suite,
                             client_hello.session_id,
                             cx.common,
                             group.name(),
+                            cookie,
                         );
                         emit_fake_ccs(cx.common);
 
@@ -259,6 +347,8 @@ mod client_hello {
                         });
 
                         return if early_data_requested {
+                            cx.data.early_data_reject_reason =
+                                Some(EarlyDataRejectReason::HelloRetryRequest);
                             Ok(Box::new(ExpectAndSkipRejectedEarlyData {
                                 skip_data_left: skip_early_data,
                                 next,
@@ -277,6 +367,8 @@ mod client_hello {
 
             let mut chosen_psk_index = None;
             let mut resumedata = None;
+            let mut external_psk = None;
+            let mut psk_identity_found = false;
 
             if let Some(psk_offer) = client_hello.get_psk() {
                 if !client_hello.check_psk_ext_is_last() {
@@ -312,17 +404,34 @@ mod client_hello {
                 }
 
                 for (i, psk_id) in psk_offer.identities.iter().enumerate() {
-                    let resume = match self
-                        .attempt_tls13_ticket_decryption(&psk_id.identity.0)
-                        .map(|resumedata| {
-                            resumedata.set_freshness(psk_id.obfuscated_ticket_age, UnixTime::now())
-                        })
-                        .filter(|resumedata| {
-                            hs::can_resume(self.suite.into(), &cx.data.sni, false, resumedata)
-                        }) {
+                    if let Some(psk) = self.find_external_psk(&psk_id.identity.0) {
+                        if !self.check_external_psk_binder(chm, &psk, psk_offer.binders[i].as_ref())
+                        {
+                            return Err(cx.common.send_fatal_alert(
+                                AlertDescription::DecryptError,
+                                PeerMisbehaved::IncorrectBinder,
+                            ));
+                        }
+
+                        chosen_psk_index = Some(i);
+                        external_psk = Some(psk);
+                        psk_identity_found = true;
+                        break;
+                    }
+
+                    let resume = match self.attempt_tls13_ticket_decryption(&psk_id.identity.0) {
                         Some(resume) => resume,
                         None => continue,
                     };
+                    psk_identity_found = true;
+
+                    let resume =
+                        resume.set_freshness(psk_id.obfuscated_ticket_age, UnixTime::now());
+                    if !hs::can_resume(self.suite.into(), &cx.data.sni, false, &resume)
+                        || !hs::resumption_alpn_acceptable(&self.config, client_hello, &resume)
+                    {
+                        continue;
+                    }
 
                     if !self.check_binder(
                         self.suite,
@@ -347,8 +456,20 @@ mod client_hello {
                 self.send_tickets = 0;
                 chosen_psk_index = None;
                 resumedata = None;
+                external_psk = None;
             } else {
-                self.send_tickets = self.config.send_tls13_tickets;
+                self.send_tickets = self.config.tls13_tickets_to_send()?;
+            }
+
+            // Honour the client's ticket_request, but never send more tickets
+            // than we would have otherwise.
+            let ticket_request = client_hello.get_ticket_request_extension();
+            if let Some(request) = ticket_request {
+                let requested = match resumedata {
+                    Some(_) => request.resumption_count,
+                    None => request.new_session_count,
+                };
+                self.send_tickets = Ord::min(self.send_tickets, usize::from(requested));
             }
 
             if let Some(ref resume) = resumedata {
@@ -356,7 +477,16 @@ mod client_hello {
                 cx.common.peer_certificates = resume.client_cert_chain.clone();
             }
 
-            let full_handshake = resumedata.is_none();
+            let full_handshake = resumedata.is_none() && external_psk.is_none();
+            if full_handshake && server_key.is_none() {
+                return Err(hs::no_certificate_resolved(cx.common));
+            }
+
+            cx.common.handshake_kind = Some(match (&resumedata, &external_psk) {
+                (Some(_), _) => HandshakeKind::Resumed(ResumptionKind::Tls13Psk),
+                (None, Some(_)) => HandshakeKind::ExternalPsk,
+                (None, None) => HandshakeKind::Full,
+            });
             self.transcript.add_message(chm);
             let key_schedule = emit_server_hello(
                 &mut self.transcript,
@@ -368,43 +498,58 @@ mod client_hello {
                 chosen_psk_index,
                 resumedata
                     .as_ref()
-                    .map(|x| &x.master_secret.0[..]),
+                    .map(|x| &x.master_secret.0[..])
+                    .or_else(|| {
+                        external_psk
+                            .as_ref()
+                            .map(|psk| psk.secret())
+                    }),
                 &self.config,
             )?;
             if !self.done_retry {
                 emit_fake_ccs(cx.common);
             }
 
-            let mut ocsp_response = server_key.get_ocsp();
-            let doing_early_data = emit_encrypted_extensions(
+            let mut ocsp_response = server_key
+                .as_ref()
+                .and_then(|key| key.get_ocsp());
+            let mut sct_list = server_key
+                .as_ref()
+                .and_then(|key| key.get_scts());
+            let (doing_early_data, doing_alps) = emit_encrypted_extensions(
                 &mut self.transcript,
                 self.suite,
                 cx,
                 &mut ocsp_response,
+                &mut sct_list,
                 client_hello,
                 resumedata.as_ref(),
+                psk_identity_found,
                 self.extra_exts,
+                ticket_request.map(|_| self.send_tickets),
                 &self.config,
             )?;
 
-            let doing_client_auth = if full_handshake {
-                let client_auth =
-                    emit_certificate_req_tls13(&mut self.transcript, cx, &self.config)?;
-                emit_certificate_tls13(
-                    &mut self.transcript,
-                    cx.common,
-                    server_key.get_cert(),
-                    ocsp_response,
-                );
-                emit_certificate_verify_tls13(
-                    &mut self.transcript,
-                    cx.common,
-                    server_key.get_key(),
-                    &sigschemes_ext,
-                )?;
-                client_auth
-            } else {
-                false
+            let doing_client_auth = match (full_handshake, &server_key) {
+                (true, Some(server_key)) => {
+                    let client_auth =
+                        emit_certificate_req_tls13(&mut self.transcript, cx, &self.config)?;
+                    emit_certificate_tls13(
+                        &mut self.transcript,
+                        cx.common,
+                        server_key.get_cert(),
+                        ocsp_response,
+                        sct_list,
+                    );
+                    emit_certificate_verify_tls13(
+                        &mut self.transcript,
+                        cx.common,
+                        server_key.get_key(),
+                        &sigschemes_ext,
+                    )?;
+                    client_auth
+                }
+                _ => false,
             };
 
             // If we're not doing early data, then the next messages we receive
@@ -445,7 +590,16 @@ mod client_hello {
                 cx.common.start_outgoing_traffic();
             }
 
-            if doing_client_auth {
+            if doing_alps {
+                Ok(Box::new(ExpectClientEncryptedExtensions {
+                    config: self.config,
+                    transcript: self.transcript,
+                    suite: self.suite,
+                    key_schedule: key_schedule_traffic,
+                    send_tickets: self.send_tickets,
+                    doing_client_auth,
+                }))
+            } else if doing_client_auth {
                 Ok(Box::new(ExpectCertificate {
                     config: self.config,
                     transcript: self.transcript,
@@ -532,7 +686,7 @@ mod client_hello {
             let early_key_schedule = KeyScheduleEarly::new(suite, psk);
             early_key_schedule.client_early_traffic_secret(
                 &client_hello_hash,
-                &*config.key_log,
+                &ConnectionKeyLog::new(&*config.key_log, cx.common),
                 &randoms.client,
                 cx.common,
             );
@@ -543,12 +697,16 @@ mod client_hello {
         };
 
         // Do key exchange
-        let key_schedule = key_schedule_pre_handshake.into_handshake(kx, &share.payload.0)?;
+        let key_schedule = cx
+            .common
+            .complete_key_exchange(kx, |kx| {
+                key_schedule_pre_handshake.into_handshake(kx, &share.payload.0)
+            })?;
 
         let handshake_hash = transcript.get_current_hash();
         let key_schedule = key_schedule.derive_server_handshake_secrets(
             handshake_hash,
-            &*config.key_log,
+            &ConnectionKeyLog::new(&*config.key_log, cx.common),
             &randoms.client,
             cx.common,
         );
@@ -573,6 +731,7 @@ mod client_hello {
         session_id: SessionId,
         common: &mut CommonState,
         group: NamedGroup,
+        cookie: Option<Vec<u8>>,
     ) {
         let mut req = HelloRetryRequest {
             legacy_version: ProtocolVersion::TLSv1_2,
@@ -587,6 +746,10 @@ mod client_hello {
             .push(HelloRetryExtension::SupportedVersions(
                 ProtocolVersion::TLSv1_3,
             ));
+        if let Some(cookie) = cookie {
+            req.extensions
+                .push(HelloRetryExtension::Cookie(PayloadU16::new(cookie)));
+        }
 
         let m = Message {
             version: ProtocolVersion::TLSv1_2,
@@ -606,6 +769,7 @@ mod client_hello {
         cx: &mut ServerContext<'_>,
         client_hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
+        psk_identity_found: bool,
         suite: &'static Tls13CipherSuite,
         config: &ServerConfig,
     ) -> EarlyDataDecision {
@@ -615,18 +779,25 @@ mod client_hello {
             false => EarlyDataDecision::Disabled,
         };
 
+        /* Non-zero max_early_data_size controls whether early_data is allowed at all.
+         * We also require stateful resumption. */
+        let early_data_configured = config.max_early_data_size > 0 && !config.ticketer.enabled();
+
         let resume = match resumedata {
             Some(resume) => resume,
             None => {
                 // never any early data if not resuming.
+                if early_data_requested {
+                    cx.data.early_data_reject_reason = Some(match early_data_configured {
+                        false => EarlyDataRejectReason::NotPermitted,
+                        true if psk_identity_found => EarlyDataRejectReason::NotResumed,
+                        true => EarlyDataRejectReason::Replay,
+                    });
+                }
                 return rejected_or_disabled;
             }
         };
 
-        /* Non-zero max_early_data_size controls whether early_data is allowed at all.
-         * We also require stateful resumption. */
-        let early_data_configured = config.max_early_data_size > 0 && !config.ticketer.enabled();
-
         /* "For PSKs provisioned via NewSessionTicket, a server MUST validate
          *  that the ticket age for the selected PSK identity (computed by
          *  subtracting ticket_age_add from PskIdentity.obfuscated_ticket_age
@@ -643,15 +814,29 @@ mod client_hello {
          *  - The selected ALPN [RFC7301] protocol, if any"
          *
          * (RFC8446, 4.2.10) */
-        let early_data_possible = early_data_requested
-            && resume.is_fresh()
-            && Some(resume.version) == cx.common.negotiated_version
+        let parameters_match = Some(resume.version) == cx.common.negotiated_version
             && resume.cipher_suite == suite.common.suite
             && resume.alpn.as_ref().map(|x| &x.0) == cx.common.alpn_protocol.as_ref();
 
-        if early_data_configured && early_data_possible && !cx.data.early_data.was_rejected() {
+        let reject_reason = if !early_data_configured {
+            Some(EarlyDataRejectReason::NotPermitted)
+        } else if !resume.is_fresh() {
+            Some(EarlyDataRejectReason::TicketExpired)
+        } else if !parameters_match {
+            Some(EarlyDataRejectReason::ParametersChanged)
+        } else if cx.data.early_data.was_rejected() {
+            Some(EarlyDataRejectReason::Policy)
+        } else {
+            None
+        };
+
+        if early_data_requested && reject_reason.is_none() {
             EarlyDataDecision::Accepted
         } else {
+            if early_data_requested {
+                cx.data.early_data_reject_reason = reject_reason;
+            }
+
             if cx.common.is_quic() {
                 // Clobber value set in tls13::emit_server_hello
                 cx.common.quic.early_secret = None;
@@ -666,19 +851,64 @@ mod client_hello {
         suite: &'static Tls13CipherSuite,
         cx: &mut ServerContext<'_>,
         ocsp_response: &mut Option<&[u8]>,
+        sct_list: &mut Option<&[Vec<u8>]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
+        psk_identity_found: bool,
         extra_exts: Vec<ServerExtension>,
+        expected_tickets: Option<usize>,
         config: &ServerConfig,
-    ) -> Result<EarlyDataDecision, Error> {
+    ) -> Result<(EarlyDataDecision, bool), Error> {
         let mut ep = hs::ExtensionProcessing::new();
-        ep.process_common(config, cx, ocsp_response, hello, resumedata, extra_exts)?;
+        ep.process_common(
+            config,
+            cx,
+            ocsp_response,
+            sct_list,
+            hello,
+            resumedata,
+            extra_exts,
+        )?;
 
-        let early_data = decide_if_early_data_allowed(cx, hello, resumedata, suite, config);
+        let early_data =
+            decide_if_early_data_allowed(cx, hello, resumedata, psk_identity_found, suite, config);
         if early_data == EarlyDataDecision::Accepted {
             ep.exts.push(ServerExtension::EarlyData);
         }
 
+        // This is no more than the client's request, so fits in a u8.
+        if let Some(count) = expected_tickets {
+            ep.exts
+                .push(ServerExtension::TicketRequest(count as u8));
+        }
+
+        // ALPS: the client's settings arrive in its ClientEncryptedExtensions,
+        // which would come after any early data.  We don't support that combination.
+        let alps_settings = match (&early_data, &cx.common.alpn_protocol) {
+            (EarlyDataDecision::Accepted, _) | (_, None) => None,
+            (_, Some(agreed)) => hello
+                .get_alps_extension()
+                .filter(|offered| {
+                    offered
+                        .to_slices()
+                        .contains(&&agreed[..])
+                })
+                .and_then(|_| {
+                    config
+                        .alps_settings
+                        .iter()
+                        .find(|(protocol, _)| protocol == agreed)
+                }),
+        };
+        let doing_alps = alps_settings.is_some();
+        if let Some((_, settings)) = alps_settings {
+            debug!("Sending ALPS settings for {:?}", &cx.common.alpn_protocol);
+            ep.exts
+                .push(ServerExtension::ApplicationSettings(Payload::new(
+                    settings.clone(),
+                )));
+        }
+
         let ee = Message {
             version: ProtocolVersion::TLSv1_3,
             payload: MessagePayload::handshake(HandshakeMessagePayload {
@@ -690,7 +920,7 @@ mod client_hello {
         trace!("sending encrypted extensions {:?}", ee);
         transcript.add_message(&ee);
         cx.common.send_msg(ee, true);
-        Ok(early_data)
+        Ok((early_data, doing_alps))
     }
 
     fn emit_certificate_req_tls13(
@@ -740,6 +970,7 @@ mod client_hello {
         common: &mut CommonState,
         cert_chain: &[CertificateDer<'static>],
         ocsp_response: Option<&[u8]>,
+        sct_list: Option<&[Vec<u8>]>,
     ) {
         let mut cert_entries = vec![];
         for cert in cert_chain {
@@ -760,6 +991,17 @@ mod client_hello {
                     .exts
                     .push(CertificateExtension::CertificateStatus(cst));
             }
+
+            // Likewise SCTs.
+            if let Some(scts) = sct_list {
+                end_entity_cert
+                    .exts
+                    .push(CertificateExtension::SignedCertificateTimestamp(
+                        scts.iter()
+                            .map(|sct| Sct::from(sct.clone()))
+                            .collect(),
+                    ));
+            }
         }
 
         let cert_body = CertificatePayloadTls13::new(cert_entries);
@@ -789,7 +1031,9 @@ mod client_hello {
             .ok_or_else(|| {
                 common.send_fatal_alert(
                     AlertDescription::HandshakeFailure,
-                    PeerIncompatible::NoSignatureSchemesInCommon,
+                    Error::NoSignatureSchemes {
+                        offered: schemes.to_vec(),
+                    },
                 )
             })?;
 
@@ -840,7 +1084,7 @@ mod client_hello {
         // the Finish message is received & validated.
         key_schedule.into_traffic_with_client_finished_pending(
             hash_at_server_fin,
-            &*config.key_log,
+            &ConnectionKeyLog::new(&*config.key_log, cx.common),
             &randoms.client,
             cx.common,
         )
@@ -869,6 +1113,56 @@ impl State<ServerConnectionData> for ExpectAndSkipRejectedEarlyData {
     }
 }
 
+// --- Process client's ClientEncryptedExtensions, carrying its ALPS settings ---
+
+struct ExpectClientEncryptedExtensions {
+    config: Arc<ServerConfig>,
+    transcript: HandshakeHash,
+    suite: &'static Tls13CipherSuite,
+    key_schedule: KeyScheduleTrafficWithClientFinishedPending,
+    send_tickets: usize,
+    doing_client_auth: bool,
+}
+
+impl State<ServerConnectionData> for ExpectClientEncryptedExtensions {
+    fn handle(mut self: Box<Self>, cx: &mut ServerContext<'_>, m: Message) -> hs::NextStateOrError {
+        let exts = require_handshake_msg!(
+            m,
+            HandshakeType::ClientEncryptedExtensions,
+            HandshakePayload::ClientEncryptedExtensions
+        )?;
+
+        let settings = match exts.get_alps_settings() {
+            Some(settings) => settings.to_vec(),
+            None => {
+                return Err(cx
+                    .common
+                    .missing_extension(PeerMisbehaved::MissingApplicationSettings));
+            }
+        };
+        cx.common.peer_alps_settings = Some(settings);
+        self.transcript.add_message(&m);
+
+        if self.doing_client_auth {
+            Ok(Box::new(ExpectCertificate {
+                config: self.config,
+                transcript: self.transcript,
+                suite: self.suite,
+                key_schedule: self.key_schedule,
+                send_tickets: self.send_tickets,
+            }))
+        } else {
+            Ok(Box::new(ExpectFinished {
+                config: self.config,
+                transcript: self.transcript,
+                suite: self.suite,
+                key_schedule: self.key_schedule,
+                send_tickets: self.send_tickets,
+            }))
+        }
+    }
+}
+
 struct ExpectCertificate {
     config: Arc<ServerConfig>,
     transcript: HandshakeHash,
@@ -1189,13 +1483,16 @@ impl State<ServerConnectionData> for ExpectFinished {
         // Application data may now flow, even if we have client auth enabled.
         cx.common.start_traffic();
 
+        let handshake_hash = self.transcript.get_current_hash();
         Ok(match cx.common.is_quic() {
             true => Box::new(ExpectQuicTraffic {
                 key_schedule: key_schedule_traffic,
+                handshake_hash,
                 _fin_verified: fin,
             }),
             false => Box::new(ExpectTraffic {
                 key_schedule: key_schedule_traffic,
+                handshake_hash,
                 _fin_verified: fin,
             }),
         })
@@ -1205,6 +1502,8 @@ impl State<ServerConnectionData> for ExpectFinished {
 // --- Process traffic ---
 struct ExpectTraffic {
     key_schedule: KeyScheduleTraffic,
+    /// Transcript hash up to and including the client's Finished.
+    handshake_hash: hash::Output,
     _fin_verified: verify::FinishedMessageVerified,
 }
 
@@ -1275,10 +1574,26 @@ impl State<ServerConnectionData> for ExpectTraffic {
         self.key_schedule
             .extract_secrets(Side::Server)
     }
+
+    fn resumption_master_secret(&self) -> Option<Vec<u8>> {
+        Some(
+            self.key_schedule
+                .resumption_master_secret(&self.handshake_hash)
+                .as_ref()
+                .to_vec(),
+        )
+    }
+
+    fn send_key_update(&mut self, common: &mut CommonState) -> Result<(), Error> {
+        self.key_schedule
+            .send_key_update(common);
+        Ok(())
+    }
 }
 
 struct ExpectQuicTraffic {
     key_schedule: KeyScheduleTraffic,
+    handshake_hash: hash::Output,
     _fin_verified: verify::FinishedMessageVerified,
 }
 
@@ -1297,4 +1612,13 @@ impl State<ServerConnectionData> for ExpectQuicTraffic {
         self.key_schedule
             .export_keying_material(output, label, context)
     }
+
+    fn resumption_master_secret(&self) -> Option<Vec<u8>> {
+        Some(
+            self.key_schedule
+                .resumption_master_secret(&self.handshake_hash)
+                .as_ref()
+                .to_vec(),
+        )
+    }
 }
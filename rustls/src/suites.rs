@@ -1,6 +1,8 @@
 use crate::common_state::Protocol;
 use crate::crypto;
 use crate::crypto::cipher::{AeadKey, Iv};
+use crate::crypto::hash::HashAlgorithm;
+use crate::crypto::KeyExchangeAlgorithm;
 use crate::enums::{CipherSuite, ProtocolVersion, SignatureAlgorithm, SignatureScheme};
 #[cfg(feature = "tls12")]
 use crate::tls12::Tls12CipherSuite;
@@ -66,6 +68,56 @@ impl SupportedCipherSuite {
         self.common().suite
     }
 
+    /// Which hash function the suite uses, eg, `HashAlgorithm::SHA256`.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_provider().algorithm()
+    }
+
+    /// Which bulk encryption algorithm the suite uses.
+    ///
+    /// This is derived from the suite's identifier, and is `None` for suites
+    /// not named here (for example, ones defined by a custom provider).
+    pub fn bulk_algorithm(&self) -> Option<BulkAlgorithm> {
+        use CipherSuite::*;
+        match self.suite() {
+            TLS13_AES_128_GCM_SHA256
+            | TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+            | TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 => Some(BulkAlgorithm::Aes128Gcm),
+            TLS13_AES_256_GCM_SHA384
+            | TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+            | TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384 => Some(BulkAlgorithm::Aes256Gcm),
+            TLS13_CHACHA20_POLY1305_SHA256
+            | TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256
+            | TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256 => Some(BulkAlgorithm::Chacha20Poly1305),
+            TLS13_AES_128_CCM_SHA256 => Some(BulkAlgorithm::Aes128Ccm),
+            TLS13_AES_128_CCM_8_SHA256 => Some(BulkAlgorithm::Aes128Ccm8),
+            _ => None,
+        }
+    }
+
+    /// Whether the suite protects records using an AEAD.
+    ///
+    /// This is true of every suite rustls can use: TLS1.3 requires it, and
+    /// TLS1.2 suites are built from a [`Tls12AeadAlgorithm`].
+    ///
+    /// [`Tls12AeadAlgorithm`]: crate::crypto::cipher::Tls12AeadAlgorithm
+    pub fn is_aead(&self) -> bool {
+        true
+    }
+
+    /// How the suite exchanges keys.
+    ///
+    /// This is `None` for TLS1.3 suites, where the key exchange is negotiated
+    /// separately from the cipher suite.  All key exchanges rustls supports
+    /// are ephemeral, and therefore forward-secret.
+    pub fn key_exchange_kind(&self) -> Option<KeyExchangeAlgorithm> {
+        match self {
+            #[cfg(feature = "tls12")]
+            Self::Tls12(inner) => Some(inner.kx),
+            Self::Tls13(_) => None,
+        }
+    }
+
     /// The hash function the ciphersuite uses.
     pub(crate) fn hash_provider(&self) -> &'static dyn crypto::hash::Hash {
         self.common().hash_provider
@@ -125,6 +177,26 @@ impl SupportedCipherSuite {
     }
 }
 
+/// A bulk encryption algorithm, as used by a [`SupportedCipherSuite`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BulkAlgorithm {
+    /// AES with 128-bit keys in Galois counter mode.
+    Aes128Gcm,
+
+    /// AES with 256-bit keys in Galois counter mode.
+    Aes256Gcm,
+
+    /// ChaCha20 for confidentiality with poly1305 for authenticity.
+    Chacha20Poly1305,
+
+    /// AES with 128-bit keys in counter with CBC-MAC mode.
+    Aes128Ccm,
+
+    /// AES with 128-bit keys in counter with CBC-MAC mode, with a truncated tag.
+    Aes128Ccm8,
+}
+
 // These both O(N^2)!
 pub(crate) fn choose_ciphersuite_preferring_client(
     client_suites: &[CipherSuite],
@@ -309,4 +381,44 @@ mod tests {
             .can_resume_from(TLS13_CHACHA20_POLY1305_SHA256_INTERNAL)
             .is_none());
     }
+
+    #[test]
+    fn test_filter_by_components() {
+        let suites = crate::test_provider::DEFAULT_CIPHER_SUITES
+            .iter()
+            .filter(|scs| scs.bulk_algorithm() == Some(BulkAlgorithm::Aes256Gcm))
+            .map(|scs| scs.suite())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            suites,
+            vec![
+                CipherSuite::TLS13_AES_256_GCM_SHA384,
+                #[cfg(feature = "tls12")]
+                CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                #[cfg(feature = "tls12")]
+                CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+            ]
+        );
+
+        for scs in crate::test_provider::DEFAULT_CIPHER_SUITES {
+            assert!(scs.is_aead());
+            match scs {
+                #[cfg(feature = "tls12")]
+                SupportedCipherSuite::Tls12(_) => {
+                    assert_eq!(scs.key_exchange_kind(), Some(KeyExchangeAlgorithm::ECDHE))
+                }
+                SupportedCipherSuite::Tls13(_) => assert_eq!(scs.key_exchange_kind(), None),
+            }
+        }
+
+        assert_eq!(
+            TLS13_AES_256_GCM_SHA384.hash_algorithm(),
+            HashAlgorithm::SHA384
+        );
+        assert_eq!(
+            TLS13_CHACHA20_POLY1305_SHA256.hash_algorithm(),
+            HashAlgorithm::SHA256
+        );
+    }
 }
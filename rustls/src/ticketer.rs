@@ -9,6 +9,26 @@ use alloc::vec::Vec;
 use core::mem;
 use std::sync::{Mutex, MutexGuard};
 
+/// The AEAD algorithm a crypto provider's built-in ticketer encrypts tickets with.
+///
+/// The key is as long as the algorithm requires, and generated randomly.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TicketAead {
+    /// AES-128 in GCM mode.
+    Aes128Gcm,
+    /// AES-256 in GCM mode.
+    Aes256Gcm,
+    /// ChaCha20-Poly1305.  This is the default.
+    Chacha20Poly1305,
+}
+
+impl Default for TicketAead {
+    fn default() -> Self {
+        Self::Chacha20Poly1305
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct TicketSwitcherState {
     next: Option<Box<dyn ProducesTickets>>,
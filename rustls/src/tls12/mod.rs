@@ -3,10 +3,12 @@ use crate::conn::ConnectionRandoms;
 use crate::crypto;
 use crate::crypto::cipher::{AeadKey, MessageDecrypter, MessageEncrypter, Tls12AeadAlgorithm};
 use crate::crypto::hash;
+use crate::crypto::SecureRandom;
 use crate::enums::{AlertDescription, SignatureScheme};
 use crate::error::{Error, InvalidMessage};
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::handshake::KeyExchangeAlgorithm;
+use crate::rand;
 use crate::suites::{CipherSuiteCommon, PartiallyExtractedSecrets, SupportedCipherSuite};
 
 use alloc::boxed::Box;
@@ -89,6 +91,8 @@ pub(crate) struct ConnectionSecrets {
     pub(crate) randoms: ConnectionRandoms,
     suite: &'static Tls12CipherSuite,
     pub(crate) master_secret: [u8; 48],
+    /// Our explicit nonce starting point, if not taken from the key block.
+    explicit_nonce: Option<Vec<u8>>,
 }
 
 impl ConnectionSecrets {
@@ -103,6 +107,7 @@ impl ConnectionSecrets {
             randoms,
             suite,
             master_secret: [0u8; 48],
+            explicit_nonce: None,
         };
 
         let (label, seed) = match ems_seed {
@@ -139,12 +144,30 @@ impl ConnectionSecrets {
             randoms,
             suite,
             master_secret: [0u8; 48],
+            explicit_nonce: None,
         };
         ret.master_secret
             .copy_from_slice(master_secret);
         ret
     }
 
+    /// Take the starting point for our explicit nonces from `explicit_nonce_random`,
+    /// rather than the key block.  Does nothing if `explicit_nonce_random` is `None`.
+    pub(crate) fn set_explicit_nonce_random(
+        &mut self,
+        explicit_nonce_random: Option<&dyn SecureRandom>,
+    ) -> Result<(), Error> {
+        if let Some(secure_random) = explicit_nonce_random {
+            let len = self
+                .suite
+                .aead_alg
+                .key_block_shape()
+                .explicit_nonce_len;
+            self.explicit_nonce = Some(rand::random_vec(secure_random, len)?);
+        }
+        Ok(())
+    }
+
     /// Make a `MessageCipherPair` based on the given supported ciphersuite `self.suite`,
     /// and the session's `secrets`.
     pub(crate) fn make_cipher_pair(&self, side: Side) -> MessageCipherPair {
@@ -157,6 +180,10 @@ impl ConnectionSecrets {
         let (server_write_key, key_block) = key_block.split_at(shape.enc_key_len);
         let (client_write_iv, key_block) = key_block.split_at(shape.fixed_iv_len);
         let (server_write_iv, extra) = key_block.split_at(shape.fixed_iv_len);
+        let extra = self
+            .explicit_nonce
+            .as_deref()
+            .unwrap_or(extra);
 
         let (write_key, write_iv, read_key, read_iv) = match side {
             Side::Client => (
@@ -261,16 +288,24 @@ impl ConnectionSecrets {
         let (server_key, key_block) = key_block.split_at(shape.enc_key_len);
         let (client_iv, key_block) = key_block.split_at(shape.fixed_iv_len);
         let (server_iv, explicit_nonce) = key_block.split_at(shape.fixed_iv_len);
+        let our_explicit_nonce = self
+            .explicit_nonce
+            .as_deref()
+            .unwrap_or(explicit_nonce);
+        let (client_explicit_nonce, server_explicit_nonce) = match side {
+            Side::Client => (our_explicit_nonce, explicit_nonce),
+            Side::Server => (explicit_nonce, our_explicit_nonce),
+        };
 
         let client_secrets = self.suite.aead_alg.extract_keys(
             AeadKey::new(client_key),
             client_iv,
-            explicit_nonce,
+            client_explicit_nonce,
         )?;
         let server_secrets = self.suite.aead_alg.extract_keys(
             AeadKey::new(server_key),
             server_iv,
-            explicit_nonce,
+            server_explicit_nonce,
         )?;
 
         let (tx, rx) = match side {
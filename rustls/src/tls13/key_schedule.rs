@@ -3,12 +3,19 @@ use crate::crypto::cipher::{AeadKey, Iv, MessageDecrypter};
 use crate::crypto::tls13::{expand, Hkdf, HkdfExpander, OkmBlock, OutputLengthError};
 use crate::crypto::{hash, hmac, ActiveKeyExchange};
 use crate::error::Error;
+use crate::limited_cache;
+use crate::msgs::message::{Message, PlainMessage};
 use crate::quic;
 use crate::suites::PartiallyExtractedSecrets;
 use crate::{KeyLog, Tls13CipherSuite};
 
 use alloc::boxed::Box;
 use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use std::sync::Mutex;
+
+use zeroize::{Zeroize, Zeroizing};
 
 /// Key schedule maintenance for TLS1.3
 
@@ -16,6 +23,7 @@ use alloc::string::ToString;
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum SecretKind {
     ResumptionPskBinderKey,
+    ExternalPskBinderKey,
     ClientEarlyTrafficSecret,
     ClientHandshakeTrafficSecret,
     ServerHandshakeTrafficSecret,
@@ -31,6 +39,7 @@ impl SecretKind {
         use self::SecretKind::*;
         match self {
             ResumptionPskBinderKey => b"res binder",
+            ExternalPskBinderKey => b"ext binder",
             ClientEarlyTrafficSecret => b"c e traffic",
             ClientHandshakeTrafficSecret => b"c hs traffic",
             ServerHandshakeTrafficSecret => b"s hs traffic",
@@ -118,11 +127,139 @@ impl KeyScheduleEarly {
         &self,
         hs_hash: &hash::Output,
     ) -> hmac::Tag {
+        self.ks
+            .suite
+            .hkdf_provider
+            .hmac_sign(&self.resumption_psk_binder_finished_key(), hs_hash.as_ref())
+    }
+
+    /// Like [`Self::resumption_psk_binder_key_and_sign_verify_data`], but for
+    /// an externally-provisioned PSK.
+    pub(crate) fn external_psk_binder_key_and_sign_verify_data(
+        &self,
+        hs_hash: &hash::Output,
+    ) -> hmac::Tag {
+        let binder_key = self
+            .ks
+            .derive_for_empty_hash(SecretKind::ExternalPskBinderKey);
+        self.ks
+            .suite
+            .hkdf_provider
+            .hmac_sign(&self.ks.finished_key(&binder_key), hs_hash.as_ref())
+    }
+
+    /// The HMAC key used to sign PSK binders, which only depends on the PSK.
+    fn resumption_psk_binder_finished_key(&self) -> OkmBlock {
         let resumption_psk_binder_key = self
             .ks
             .derive_for_empty_hash(SecretKind::ResumptionPskBinderKey);
         self.ks
-            .sign_verify_data(&resumption_psk_binder_key, hs_hash)
+            .finished_key(&resumption_psk_binder_key)
+    }
+}
+
+/// A bounded cache of PSK binder keys, keyed by cipher suite and a hash of the PSK.
+///
+/// Computing a PSK binder involves an HKDF-Extract of the PSK and several
+/// HKDF-Expand-Label steps before the final HMAC, all of which depend only on
+/// the PSK.  Servers which see the same ticket many times can skip that work.
+///
+/// Both keys and values are zeroized when they are dropped, which includes
+/// eviction from the cache.
+pub(crate) struct BinderKeyCache {
+    size: usize,
+    cache: Mutex<limited_cache::LimitedCache<BinderCacheKey, Zeroizing<Vec<u8>>>>,
+}
+
+impl BinderKeyCache {
+    /// Make a new cache holding up to about `size` entries.
+    ///
+    /// A `size` of zero disables caching.
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            size,
+            cache: Mutex::new(limited_cache::LimitedCache::new(size)),
+        }
+    }
+
+    /// Compute the PSK binder for `psk` over `hs_hash`.
+    ///
+    /// This gives the same result as
+    /// [`KeyScheduleEarly::resumption_psk_binder_key_and_sign_verify_data`].
+    pub(crate) fn sign_binder(
+        &self,
+        suite: &'static Tls13CipherSuite,
+        psk: &[u8],
+        hs_hash: &hash::Output,
+    ) -> hmac::Tag {
+        if self.size == 0 {
+            return KeyScheduleEarly::new(suite, psk)
+                .resumption_psk_binder_key_and_sign_verify_data(hs_hash);
+        }
+
+        let key = BinderCacheKey {
+            suite: suite.common.suite.get_u16(),
+            psk_hash: suite
+                .common
+                .hash_provider
+                .hash(psk)
+                .as_ref()
+                .to_vec(),
+        };
+
+        let cached = self
+            .cache
+            .lock()
+            .ok()
+            .and_then(|cache| {
+                cache
+                    .get(&key)
+                    .map(|finished_key| OkmBlock::new(finished_key))
+            });
+
+        let finished_key = match cached {
+            Some(finished_key) => finished_key,
+            None => {
+                let finished_key =
+                    KeyScheduleEarly::new(suite, psk).resumption_psk_binder_finished_key();
+                if let Ok(mut cache) = self.cache.lock() {
+                    cache.insert(key, Zeroizing::new(finished_key.as_ref().to_vec()));
+                }
+                finished_key
+            }
+        };
+
+        suite
+            .hkdf_provider
+            .hmac_sign(&finished_key, hs_hash.as_ref())
+    }
+}
+
+#[derive(Clone, Hash, PartialEq, Eq)]
+struct BinderCacheKey {
+    suite: u16,
+    psk_hash: Vec<u8>,
+}
+
+impl Drop for BinderCacheKey {
+    fn drop(&mut self) {
+        self.psk_hash.zeroize();
+    }
+}
+
+impl fmt::Debug for BinderCacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BinderCacheKey")
+            .field("suite", &self.suite)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for BinderKeyCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BinderKeyCache")
+            .field("size", &self.size)
+            .finish_non_exhaustive()
     }
 }
 
@@ -468,6 +605,16 @@ impl KeyScheduleTraffic {
         self.ks.set_encrypter(&secret, common);
     }
 
+    /// Send a `KeyUpdate` under the current keys, then switch to the next ones.
+    pub(crate) fn send_key_update(&mut self, common: &mut CommonState) {
+        // A notification queued in response to the peer must go out first.
+        common.perhaps_write_key_update();
+        common.send_msg_encrypt(PlainMessage::from(Message::build_key_update_notify()));
+
+        let secret = self.next_application_traffic_secret(common.side);
+        self.ks.set_encrypter(&secret, common);
+    }
+
     pub(crate) fn update_decrypter(&mut self, common: &mut CommonState) {
         let secret = self.next_application_traffic_secret(common.side.peer());
         self.ks.set_decrypter(&secret, common);
@@ -489,13 +636,16 @@ impl KeyScheduleTraffic {
         hs_hash: &hash::Output,
         nonce: &[u8],
     ) -> OkmBlock {
-        let resumption_master_secret = self
-            .ks
-            .derive(SecretKind::ResumptionMasterSecret, hs_hash.as_ref());
+        let resumption_master_secret = self.resumption_master_secret(hs_hash);
         self.ks
             .derive_ticket_psk(&resumption_master_secret, nonce)
     }
 
+    pub(crate) fn resumption_master_secret(&self, hs_hash: &hash::Output) -> OkmBlock {
+        self.ks
+            .derive(SecretKind::ResumptionMasterSecret, hs_hash.as_ref())
+    }
+
     pub(crate) fn export_keying_material(
         &self,
         out: &mut [u8],
@@ -569,7 +719,10 @@ impl KeySchedule {
 
         common
             .record_layer
-            .set_message_encrypter(self.suite.aead_alg.encrypter(key, iv));
+            .set_message_encrypter(
+                self.suite.aead_alg.encrypter(key, iv),
+                self.suite.common.confidentiality_limit,
+            );
     }
 
     fn set_decrypter(&self, secret: &OkmBlock, common: &mut CommonState) {
@@ -676,17 +829,23 @@ impl KeySchedule {
     /// Sign the finished message consisting of `hs_hash` using the key material
     /// `base_key`.
     fn sign_verify_data(&self, base_key: &OkmBlock, hs_hash: &hash::Output) -> hmac::Tag {
-        let expander = self
-            .suite
-            .hkdf_provider
-            .expander_for_okm(base_key);
-        let hmac_key = hkdf_expand_label_block(expander.as_ref(), b"finished", &[]);
+        let hmac_key = self.finished_key(base_key);
 
         self.suite
             .hkdf_provider
             .hmac_sign(&hmac_key, hs_hash.as_ref())
     }
 
+    /// Derive the HMAC key used for finished messages and PSK binders
+    /// from `base_key`.
+    fn finished_key(&self, base_key: &OkmBlock) -> OkmBlock {
+        let expander = self
+            .suite
+            .hkdf_provider
+            .expander_for_okm(base_key);
+        hkdf_expand_label_block(expander.as_ref(), b"finished", &[])
+    }
+
     /// Derive the next application traffic secret, returning it.
     fn derive_next(&self, base_key: &OkmBlock) -> OkmBlock {
         let expander = self
@@ -832,7 +991,11 @@ where
 mod tests {
     use core::fmt::Debug;
 
-    use super::{derive_traffic_iv, derive_traffic_key, KeySchedule, SecretKind};
+    use super::{
+        derive_traffic_iv, derive_traffic_key, BinderKeyCache, KeySchedule, KeyScheduleEarly,
+        SecretKind,
+    };
+    use crate::crypto::hash;
     use crate::test_provider::ring_like::aead;
     use crate::test_provider::tls13::{
         TLS13_AES_128_GCM_SHA256_INTERNAL, TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
@@ -962,6 +1125,88 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_binder_key_cache_matches_uncached() {
+        let cache = BinderKeyCache::new(4);
+
+        for suite in [
+            TLS13_AES_128_GCM_SHA256_INTERNAL,
+            TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
+        ] {
+            for psk in [[0x11u8; 32], [0x22u8; 32]] {
+                for hs_hash in [[0xaau8; 32], [0xbbu8; 32]] {
+                    let hs_hash = hash::Output::new(&hs_hash);
+                    let expected = KeyScheduleEarly::new(suite, &psk)
+                        .resumption_psk_binder_key_and_sign_verify_data(&hs_hash);
+
+                    // first call populates the cache, second is served from it
+                    for _ in 0..2 {
+                        let actual = cache.sign_binder(suite, &psk, &hs_hash);
+                        assert_eq!(expected.as_ref(), actual.as_ref());
+                    }
+                }
+            }
+        }
+
+        // 0x22 PSK entries are cached; a different PSK must not hit them
+        let hs_hash = hash::Output::new(&[0xaa; 32]);
+        assert_ne!(
+            cache
+                .sign_binder(TLS13_AES_128_GCM_SHA256_INTERNAL, &[0x33; 32], &hs_hash)
+                .as_ref(),
+            cache
+                .sign_binder(TLS13_AES_128_GCM_SHA256_INTERNAL, &[0x22; 32], &hs_hash)
+                .as_ref(),
+        );
+    }
+
+    #[test]
+    fn test_binder_key_cache_size() {
+        let suite = TLS13_AES_128_GCM_SHA256_INTERNAL;
+        let hs_hash = hash::Output::new(&[0xaa; 32]);
+        let cached = |cache: &BinderKeyCache, psk: &[u8]| {
+            let key = super::BinderCacheKey {
+                suite: suite.common.suite.get_u16(),
+                psk_hash: suite
+                    .common
+                    .hash_provider
+                    .hash(psk)
+                    .as_ref()
+                    .to_vec(),
+            };
+            cache
+                .cache
+                .lock()
+                .unwrap()
+                .get(&key)
+                .is_some()
+        };
+
+        // entries are keyed by a hash of the PSK, never the PSK itself
+        let cache = BinderKeyCache::new(4);
+        cache.sign_binder(suite, &[0x11; 32], &hs_hash);
+        assert!(cached(&cache, &[0x11; 32]));
+
+        // old entries are evicted to make room for new ones
+        for psk in 0x20u8..0x30 {
+            cache.sign_binder(suite, &[psk; 32], &hs_hash);
+        }
+        assert!(!cached(&cache, &[0x11; 32]));
+        assert!(cached(&cache, &[0x2f; 32]));
+
+        // a zero size disables the cache, without changing the result
+        let disabled = BinderKeyCache::new(0);
+        assert_eq!(
+            disabled
+                .sign_binder(suite, &[0x11; 32], &hs_hash)
+                .as_ref(),
+            cache
+                .sign_binder(suite, &[0x11; 32], &hs_hash)
+                .as_ref(),
+        );
+        assert!(!cached(&disabled, &[0x11; 32]));
+    }
+
     fn assert_traffic_secret(
         ks: &KeySchedule,
         kind: SecretKind,
@@ -1057,4 +1302,38 @@ mod benchmarks {
             extract_traffic_secret(&ks, SecretKind::ServerApplicationTrafficSecret);
         });
     }
+
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    #[bench]
+    fn bench_binder_uncached(b: &mut test::Bencher) {
+        use super::KeyScheduleEarly;
+        use crate::crypto::hash;
+        use crate::test_provider::tls13::TLS13_AES_128_GCM_SHA256_INTERNAL;
+
+        let hs_hash = hash::Output::new(&[0u8; 32]);
+        b.iter(|| {
+            test::black_box(
+                KeyScheduleEarly::new(TLS13_AES_128_GCM_SHA256_INTERNAL, &[0u8; 32])
+                    .resumption_psk_binder_key_and_sign_verify_data(&hs_hash),
+            );
+        });
+    }
+
+    #[cfg(any(feature = "ring", feature = "aws_lc_rs"))]
+    #[bench]
+    fn bench_binder_cached(b: &mut test::Bencher) {
+        use super::BinderKeyCache;
+        use crate::crypto::hash;
+        use crate::test_provider::tls13::TLS13_AES_128_GCM_SHA256_INTERNAL;
+
+        let cache = BinderKeyCache::new(4);
+        let hs_hash = hash::Output::new(&[0u8; 32]);
+        b.iter(|| {
+            test::black_box(cache.sign_binder(
+                TLS13_AES_128_GCM_SHA256_INTERNAL,
+                &[0u8; 32],
+                &hs_hash,
+            ));
+        });
+    }
 }
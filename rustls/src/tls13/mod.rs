@@ -5,6 +5,8 @@ use crate::suites::{CipherSuiteCommon, SupportedCipherSuite};
 use alloc::vec::Vec;
 use core::fmt;
 
+use zeroize::Zeroizing;
+
 pub(crate) mod key_schedule;
 
 /// A TLS 1.3 cipher suite supported by rustls.
@@ -64,6 +66,65 @@ impl fmt::Debug for Tls13CipherSuite {
     }
 }
 
+/// An externally-provisioned TLS1.3 pre-shared key, and its identity.
+///
+/// When both peers hold the same key, the handshake is authenticated by it
+/// instead of by certificates: no Certificate or CertificateVerify messages
+/// are sent.  See [RFC8446 section 2.2] and [RFC9258].
+///
+/// Only the `psk_dhe_ke` mode is supported, so an (EC)DHE key exchange is
+/// still done and the connection has forward secrecy.
+///
+/// [RFC8446 section 2.2]: https://www.rfc-editor.org/rfc/rfc8446#section-2.2
+/// [RFC9258]: https://www.rfc-editor.org/rfc/rfc9258
+pub struct ExternalPsk {
+    identity: Vec<u8>,
+    secret: Zeroizing<Vec<u8>>,
+    hash: hash::HashAlgorithm,
+}
+
+impl ExternalPsk {
+    /// Make a new external PSK.
+    ///
+    /// `hash` is the hash function associated with the key; only cipher suites
+    /// using it can be negotiated with this key.
+    pub fn new(identity: Vec<u8>, secret: Vec<u8>, hash: hash::HashAlgorithm) -> Self {
+        Self {
+            identity,
+            secret: Zeroizing::new(secret),
+            hash,
+        }
+    }
+
+    /// The identity sent to the server to name this key.
+    pub fn identity(&self) -> &[u8] {
+        &self.identity
+    }
+
+    /// The hash function associated with this key.
+    pub fn hash(&self) -> hash::HashAlgorithm {
+        self.hash
+    }
+
+    pub(crate) fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Whether `suite` is usable with this key.
+    pub(crate) fn usable_with(&self, suite: &Tls13CipherSuite) -> bool {
+        suite.common.hash_provider.algorithm() == self.hash
+    }
+}
+
+impl fmt::Debug for ExternalPsk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalPsk")
+            .field("identity", &crate::bs_debug::BsDebug(&self.identity))
+            .field("hash", &self.hash)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Constructs the signature message specified in section 4.4.3 of RFC8446.
 pub(crate) fn construct_client_verify_message(handshake_hash: &hash::Output) -> Vec<u8> {
     construct_verify_message(handshake_hash, b"TLS 1.3, client CertificateVerify\x00")
@@ -43,6 +43,11 @@ impl ChunkVecBuffer {
             .unwrap_or_default()
     }
 
+    /// Whether nothing more can be appended under the current `limit`.
+    pub(crate) fn is_at_limit(&self) -> bool {
+        self.apply_limit(1) == 0
+    }
+
     /// How many bytes we're storing
     pub(crate) fn len(&self) -> usize {
         let mut len = 0;
@@ -83,6 +88,13 @@ impl ChunkVecBuffer {
         len
     }
 
+    /// Put `bytes` back in front of everything else stored.
+    pub(crate) fn prepend(&mut self, bytes: Vec<u8>) {
+        if !bytes.is_empty() {
+            self.chunks.push_front(bytes);
+        }
+    }
+
     /// Take one of the chunks from this object.  This
     /// function panics if the object `is_empty`.
     pub(crate) fn pop(&mut self) -> Option<Vec<u8>> {
@@ -106,6 +118,18 @@ impl ChunkVecBuffer {
         Ok(offs)
     }
 
+    /// Read data out of the first chunk of this object only, writing it
+    /// into `buf` and returning how many bytes were written there.
+    pub(crate) fn read_chunk(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.is_empty() {
+            return Ok(0);
+        }
+
+        let used = self.chunks[0].as_slice().read(buf)?;
+        self.consume(used);
+        Ok(used)
+    }
+
     #[cfg(read_buf)]
     /// Read data out of this object, writing it into `cursor`.
     pub(crate) fn read_buf(&mut self, mut cursor: core::io::BorrowedCursor<'_>) -> io::Result<()> {
@@ -119,6 +143,22 @@ impl ChunkVecBuffer {
         Ok(())
     }
 
+    #[cfg(read_buf)]
+    /// Read data out of the first chunk of this object only, writing it into `cursor`.
+    pub(crate) fn read_buf_chunk(
+        &mut self,
+        mut cursor: core::io::BorrowedCursor<'_>,
+    ) -> io::Result<()> {
+        if !self.is_empty() {
+            let chunk = self.chunks[0].as_slice();
+            let used = core::cmp::min(chunk.len(), cursor.capacity());
+            cursor.append(&chunk[..used]);
+            self.consume(used);
+        }
+
+        Ok(())
+    }
+
     fn consume(&mut self, mut used: usize) {
         while let Some(mut buf) = self.chunks.pop_front() {
             if used < buf.len() {
@@ -136,6 +136,35 @@ pub trait ServerCertVerifier: Debug + Send + Sync {
     ///
     /// This should be in priority order, with the most preferred first.
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme>;
+
+    /// Returns `true` to have the client ask the server for its
+    /// signed certificate timestamps (SCTs), and deliver them to
+    /// [`ServerCertVerifier::verify_server_scts`].  Defaults to `false`.
+    fn requests_scts(&self) -> bool {
+        false
+    }
+
+    /// Verify the signed certificate timestamps the server sent for `end_entity`.
+    ///
+    /// Each entry of `scts` is one serialized `SignedCertificateTimestamp`, as
+    /// defined by [RFC6962].  `scts` is empty if the server did not send any.
+    ///
+    /// This is only called if [`ServerCertVerifier::requests_scts`] returns `true`,
+    /// after `end_entity` has been validated by [`ServerCertVerifier::verify_server_cert`].
+    /// SCTs embedded in the certificate itself are not extracted by rustls.
+    ///
+    /// See [`crate::client::verify_scts`] for a helper that validates SCTs against
+    /// a set of known logs.
+    ///
+    /// [RFC6962]: https://datatracker.ietf.org/doc/html/rfc6962#section-3.2
+    fn verify_server_scts(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _scts: &[&[u8]],
+        _now: UnixTime,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 /// Something that can verify a client certificate chain
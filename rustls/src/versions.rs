@@ -96,4 +96,24 @@ impl EnabledVersions {
             _ => false,
         }
     }
+
+    /// Returns a copy of this set, without any versions older than `min`.
+    pub(crate) fn with_minimum(mut self, min: ProtocolVersion) -> Self {
+        #[cfg(feature = "tls12")]
+        if !ProtocolVersion::TLSv1_2.at_least(min) {
+            self.tls12 = None;
+        }
+        if !ProtocolVersion::TLSv1_3.at_least(min) {
+            self.tls13 = None;
+        }
+        self
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        #[cfg(feature = "tls12")]
+        if self.tls12.is_some() {
+            return false;
+        }
+        self.tls13.is_none()
+    }
 }
@@ -103,6 +103,14 @@ impl RootCertStore {
     }
 }
 
+/// The subject of the trust anchor certificate `der`, as a [`DistinguishedName`]
+/// comparable with those sent in `CertificateRequest`s.
+pub(crate) fn trust_anchor_subject(der: &CertificateDer<'_>) -> Result<DistinguishedName, Error> {
+    anchor_from_trusted_cert(der)
+        .map(|ta| DistinguishedName::in_sequence(ta.subject.as_ref()))
+        .map_err(pki_error)
+}
+
 impl Extend<TrustAnchor<'static>> for RootCertStore {
     fn extend<T: IntoIterator<Item = TrustAnchor<'static>>>(&mut self, iter: T) {
         self.roots.extend(iter);
@@ -1,10 +1,12 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::time::Duration;
 
 use pki_types::{CertificateDer, CertificateRevocationListDer, UnixTime};
 use webpki::{CertRevocationList, RevocationCheckDepth, UnknownStatusPolicy};
 
-use super::{pki_error, VerifierBuilderError};
+use super::{pki_error, UnknownCriticalExtensionPolicy, VerifierBuilderError, VerifierPolicy};
+use crate::crypto::hash::HashAlgorithm;
 use crate::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
 use crate::verify::{
     ClientCertVerified, ClientCertVerifier, DigitallySignedStruct, HandshakeSignatureValid,
@@ -24,6 +26,7 @@ pub struct ClientCertVerifierBuilder {
     crls: Vec<CertificateRevocationListDer<'static>>,
     revocation_check_depth: RevocationCheckDepth,
     unknown_revocation_policy: UnknownStatusPolicy,
+    policy: VerifierPolicy,
     anon_policy: AnonymousClientPolicy,
     supported_algs: WebPkiSupportedAlgorithms,
 }
@@ -40,6 +43,7 @@ impl ClientCertVerifierBuilder {
             anon_policy: AnonymousClientPolicy::Deny,
             revocation_check_depth: RevocationCheckDepth::Chain,
             unknown_revocation_policy: UnknownStatusPolicy::Deny,
+            policy: VerifierPolicy::default(),
             supported_algs,
         }
     }
@@ -132,6 +136,77 @@ impl ClientCertVerifierBuilder {
         self
     }
 
+    /// Limit the number of certificates a peer may present, including the end-entity certificate.
+    ///
+    /// Longer chains are rejected with [`CertificateError::ChainTooLong`] before path building
+    /// is attempted. The default is 10.
+    ///
+    /// [`CertificateError::ChainTooLong`]: crate::CertificateError::ChainTooLong
+    pub fn max_chain_length(mut self, max_chain_length: usize) -> Self {
+        self.policy.max_chain_length = max_chain_length;
+        self
+    }
+
+    /// Reject peer certificates carrying RSA keys smaller than `bits`.
+    ///
+    /// The end-entity and intermediate certificates presented by the peer are checked, and
+    /// violations are rejected with [`CertificateError::KeyTooSmall`]. By default any RSA key
+    /// size accepted by the signature verification algorithms is allowed.
+    ///
+    /// [`CertificateError::KeyTooSmall`]: crate::CertificateError::KeyTooSmall
+    pub fn min_rsa_bits(mut self, bits: usize) -> Self {
+        self.policy.min_rsa_bits = Some(bits);
+        self
+    }
+
+    /// Only accept signatures made using one of `hashes`.
+    ///
+    /// This applies to the issuer signatures on the certificates presented by the peer, which
+    /// are rejected with [`CertificateError::DisallowedSignatureHash`], and to handshake
+    /// signatures: schemes using other hashes are no longer offered to the peer, and are
+    /// rejected if it uses them anyway. EdDSA, and RSASSA-PSS certificate signatures, do not
+    /// identify a separate hash and are unaffected. By default all hashes are allowed.
+    ///
+    /// [`CertificateError::DisallowedSignatureHash`]: crate::CertificateError::DisallowedSignatureHash
+    pub fn allowed_hashes(mut self, hashes: impl IntoIterator<Item = HashAlgorithm>) -> Self {
+        self.policy.allowed_hashes = Some(hashes.into_iter().collect());
+        self
+    }
+
+    /// Require that the peer's certificate chains through a name-constrained CA.
+    ///
+    /// Name constraints on the trust anchor and on intermediate CAs are always enforced, as
+    /// RFC 5280 requires. With this option, a chain is also rejected with
+    /// [`CertificateError::NameConstraintsMissing`] if neither its trust anchor nor any of its
+    /// intermediates carry name constraints. This is useful for private CAs that are expected to
+    /// constrain every issuing sub-CA.
+    ///
+    /// [`CertificateError::NameConstraintsMissing`]: crate::CertificateError::NameConstraintsMissing
+    pub fn require_name_constraints(mut self) -> Self {
+        self.policy.require_name_constraints = true;
+        self
+    }
+
+    /// Tolerate `tolerance` of clock skew between us and the peer when checking
+    /// certificate validity periods.
+    ///
+    /// A certificate that is not yet valid, or has expired, is accepted if it
+    /// would be valid at a time that much later, or earlier, than the current
+    /// time.  The tolerance is applied in whole seconds.  The default is zero.
+    pub fn clock_skew_tolerance(mut self, tolerance: Duration) -> Self {
+        self.policy.clock_skew_tolerance = tolerance;
+        self
+    }
+
+    /// Choose what to do with peer certificates carrying critical extensions that
+    /// webpki does not understand.
+    ///
+    /// The default is [`UnknownCriticalExtensionPolicy::Reject`].
+    pub fn on_unknown_critical_extension(mut self, policy: UnknownCriticalExtensionPolicy) -> Self {
+        self.policy.unknown_critical_extensions = policy;
+        self
+    }
+
     /// Build a client certificate verifier. The built verifier will be used for the server to offer
     /// client certificate authentication, to control how offered client certificates are validated,
     /// and to determine what to do with anonymous clients that do not respond to the client
@@ -159,6 +234,7 @@ impl ClientCertVerifierBuilder {
             parse_crls(self.crls)?,
             self.revocation_check_depth,
             self.unknown_revocation_policy,
+            self.policy,
             self.anon_policy,
             self.supported_algs,
         )))
@@ -230,6 +306,7 @@ pub struct WebPkiClientVerifier {
     crls: Vec<CertRevocationList<'static>>,
     revocation_check_depth: RevocationCheckDepth,
     unknown_revocation_policy: UnknownStatusPolicy,
+    policy: VerifierPolicy,
     anonymous_policy: AnonymousClientPolicy,
     supported_algs: WebPkiSupportedAlgorithms,
 }
@@ -288,6 +365,7 @@ impl WebPkiClientVerifier {
     ///   when `crls` are provided.
     /// * `unknown_revocation_policy` controls how certificates with an unknown revocation status
     ///   are handled when `crls` are provided.
+    /// * `policy` holds the additional chain and signature checks to apply.
     /// * `anonymous_policy` controls whether client authentication is required, or if anonymous
     ///   clients can connect.
     /// * `supported_algs` specifies which signature verification algorithms should be used.
@@ -297,6 +375,7 @@ impl WebPkiClientVerifier {
         crls: Vec<CertRevocationList<'static>>,
         revocation_check_depth: RevocationCheckDepth,
         unknown_revocation_policy: UnknownStatusPolicy,
+        policy: VerifierPolicy,
         anonymous_policy: AnonymousClientPolicy,
         supported_algs: WebPkiSupportedAlgorithms,
     ) -> Self {
@@ -306,6 +385,7 @@ impl WebPkiClientVerifier {
             crls,
             revocation_check_depth,
             unknown_revocation_policy,
+            policy,
             anonymous_policy,
             supported_algs,
         }
@@ -334,7 +414,12 @@ impl ClientCertVerifier for WebPkiClientVerifier {
         intermediates: &[CertificateDer<'_>],
         now: UnixTime,
     ) -> Result<ClientCertVerified, Error> {
-        let cert = ParsedCertificate::try_from(end_entity)?;
+        self.policy
+            .check_chain(end_entity, intermediates)?;
+        let presented = self
+            .policy
+            .presented(end_entity, intermediates);
+        let cert = ParsedCertificate::try_from(&presented.end_entity)?;
 
         let crl_refs = self.crls.iter().collect::<Vec<_>>();
 
@@ -352,18 +437,23 @@ impl ClientCertVerifier for WebPkiClientVerifier {
             )
         };
 
-        cert.0
-            .verify_for_usage(
-                self.supported_algs.all,
-                &self.roots.roots,
-                intermediates,
-                now,
-                webpki::KeyUsage::client_auth(),
-                revocation,
-                None,
-            )
-            .map_err(pki_error)
-            .map(|_| ClientCertVerified::assertion())
+        self.policy.verify_at(now, |now| {
+            presented.with_algorithms(self.supported_algs.all, |algs| {
+                cert.0
+                    .verify_for_usage(
+                        algs,
+                        &self.roots.roots,
+                        &presented.intermediates,
+                        now,
+                        webpki::KeyUsage::client_auth(),
+                        revocation,
+                        None,
+                    )
+                    .map_err(pki_error)
+                    .and_then(|path| self.policy.check_path(&path))
+                    .map(|_| ClientCertVerified::assertion())
+            })
+        })
     }
 
     fn verify_tls12_signature(
@@ -372,7 +462,13 @@ impl ClientCertVerifier for WebPkiClientVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        verify_tls12_signature(message, cert, dss, &self.supported_algs)
+        self.policy.check_scheme(dss.scheme)?;
+        verify_tls12_signature(
+            message,
+            &self.policy.end_entity(cert),
+            dss,
+            &self.supported_algs,
+        )
     }
 
     fn verify_tls13_signature(
@@ -381,11 +477,18 @@ impl ClientCertVerifier for WebPkiClientVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        verify_tls13_signature(message, cert, dss, &self.supported_algs)
+        self.policy.check_scheme(dss.scheme)?;
+        verify_tls13_signature(
+            message,
+            &self.policy.end_entity(cert),
+            dss,
+            &self.supported_algs,
+        )
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        self.supported_algs.supported_schemes()
+        self.policy
+            .filter_schemes(self.supported_algs.supported_schemes())
     }
 }
 
@@ -0,0 +1,144 @@
+use alloc::vec::Vec;
+
+use pki_types::{CertificateDer, UnixTime};
+
+use super::verify::WebPkiSupportedAlgorithms;
+use crate::error::{CertificateError, Error};
+use crate::msgs::base::PayloadU16;
+use crate::msgs::codec::{u24, Codec, Reader};
+use crate::verify::DigitallySignedStruct;
+use crate::x509;
+
+/// A certificate transparency log, as used by [`verify_scts`].
+#[derive(Clone, Debug)]
+pub struct CtLog {
+    /// The log's identifier: the SHA-256 hash of its public key.
+    pub id: [u8; 32],
+
+    /// The log's DER-encoded `SubjectPublicKeyInfo`.
+    pub key: Vec<u8>,
+}
+
+/// Verify serialized `SignedCertificateTimestamp`s (as defined by
+/// [RFC6962](https://datatracker.ietf.org/doc/html/rfc6962#section-3.2))
+/// delivered for `end_entity` against the given set of `logs`.
+///
+/// This is intended for use from [`ServerCertVerifier::verify_server_scts`].
+/// SCTs issued by logs not in `logs` are ignored.  An SCT from a known log
+/// that is malformed, carries a bad signature, or has a timestamp after `now`
+/// causes an error.
+///
+/// Only SCTs delivered over TLS (that is, for an `x509_entry`) are supported:
+/// SCTs embedded in the certificate cover the precertificate, and cannot be
+/// checked by this function.
+///
+/// Returns the number of SCTs that were validated.  It is up to the caller
+/// to decide how many are acceptable.
+///
+/// [`ServerCertVerifier::verify_server_scts`]: crate::client::danger::ServerCertVerifier::verify_server_scts
+pub fn verify_scts(
+    end_entity: &CertificateDer<'_>,
+    scts: &[&[u8]],
+    logs: &[CtLog],
+    supported_algs: &WebPkiSupportedAlgorithms,
+    now: UnixTime,
+) -> Result<usize, Error> {
+    let mut valid = 0;
+
+    for sct in scts {
+        let sct = Sct::read(&mut Reader::init(sct))
+            .ok_or(Error::InvalidCertificate(CertificateError::InvalidSct))?;
+
+        let log = match logs
+            .iter()
+            .find(|log| log.id == sct.log_id)
+        {
+            Some(log) => log,
+            None => continue,
+        };
+
+        if sct.timestamp / 1000 > now.as_secs() {
+            return Err(CertificateError::InvalidSct.into());
+        }
+
+        let message = sct.signed_message(end_entity);
+        verify_log_signature(log, &message, &sct.signature, supported_algs)?;
+        valid += 1;
+    }
+
+    Ok(valid)
+}
+
+fn verify_log_signature(
+    log: &CtLog,
+    message: &[u8],
+    dss: &DigitallySignedStruct,
+    supported_algs: &WebPkiSupportedAlgorithms,
+) -> Result<(), Error> {
+    let (alg_id, key) = x509::spki_parts(&log.key)
+        .ok_or(Error::InvalidCertificate(CertificateError::InvalidSct))?;
+
+    let verified = supported_algs
+        .mapping
+        .iter()
+        .filter(|(scheme, _)| *scheme == dss.scheme)
+        .flat_map(|(_, algs)| algs.iter())
+        .filter(|alg| alg.public_key_alg_id().as_ref() == alg_id)
+        .any(|alg| {
+            alg.verify_signature(key, message, dss.signature())
+                .is_ok()
+        });
+
+    if !verified {
+        return Err(CertificateError::InvalidSct.into());
+    }
+
+    Ok(())
+}
+
+/// The parts of an RFC6962 `SignedCertificateTimestamp` we need.
+struct Sct {
+    log_id: [u8; 32],
+    timestamp: u64,
+    extensions: PayloadU16,
+    signature: DigitallySignedStruct,
+}
+
+impl Sct {
+    fn read(r: &mut Reader) -> Option<Self> {
+        // v1(0) is the only version defined.
+        if u8::read(r).ok()? != 0 {
+            return None;
+        }
+
+        let mut log_id = [0u8; 32];
+        log_id.copy_from_slice(r.take(32)?);
+        let timestamp = u64::read(r).ok()?;
+        let extensions = PayloadU16::read(r).ok()?;
+        let signature = DigitallySignedStruct::read(r).ok()?;
+
+        r.expect_empty("Sct").ok()?;
+
+        Some(Self {
+            log_id,
+            timestamp,
+            extensions,
+            signature,
+        })
+    }
+
+    /// The `digitally-signed` struct covered by the log's signature, for an `x509_entry`.
+    fn signed_message(&self, end_entity: &CertificateDer<'_>) -> Vec<u8> {
+        let mut message = Vec::new();
+        // sct_version: v1, signature_type: certificate_timestamp
+        0u8.encode(&mut message);
+        0u8.encode(&mut message);
+        self.timestamp.encode(&mut message);
+        // entry_type: x509_entry
+        0u16.encode(&mut message);
+        u24(end_entity.len() as u32).encode(&mut message);
+        message.extend_from_slice(end_entity);
+        self.extensions.encode(&mut message);
+        message
+    }
+}
@@ -1,21 +1,33 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt;
+use core::time::Duration;
 
-use pki_types::CertificateRevocationListDer;
+use pki_types::{
+    CertificateDer, CertificateRevocationListDer, InvalidSignature, SignatureVerificationAlgorithm,
+    UnixTime,
+};
 use std::error::Error as StdError;
 use webpki::{CertRevocationList, OwnedCertRevocationList};
 
-use crate::error::{CertRevocationListError, CertificateError, Error, OtherError};
+use crate::enums::SignatureScheme;
+use crate::error::{
+    CertRevocationListError, CertificateError, Error, OtherError, PeerIncompatible,
+};
+use crate::msgs::enums::HashAlgorithm;
+use crate::x509;
 
 mod anchors;
 mod client_verifier;
+mod ct;
 mod server_verifier;
 mod verify;
 
+pub(crate) use anchors::trust_anchor_subject;
 pub use anchors::RootCertStore;
 
 pub use client_verifier::{ClientCertVerifierBuilder, WebPkiClientVerifier};
+pub use ct::{verify_scts, CtLog};
 pub use server_verifier::{ServerCertVerifierBuilder, WebPkiServerVerifier};
 
 pub use verify::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
@@ -53,6 +65,288 @@ impl fmt::Display for VerifierBuilderError {
 
 impl StdError for VerifierBuilderError {}
 
+/// The default maximum number of certificates (including the end-entity
+/// certificate) accepted in a peer's chain.
+const DEFAULT_MAX_CHAIN_LENGTH: usize = 10;
+
+/// What the webpki verifiers do with peer certificates carrying a critical
+/// extension that webpki does not understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UnknownCriticalExtensionPolicy {
+    /// Reject the certificate with [`CertificateError::UnhandledCriticalExtension`].
+    ///
+    /// This is the default, and what RFC5280 requires.
+    Reject,
+    /// Verify the certificate as though those extensions were not critical.
+    ///
+    /// Issuer signatures are still checked over the certificates as issued.
+    Ignore,
+}
+
+/// Checks applied by the webpki verifiers in addition to path building and
+/// signature verification.
+#[derive(Debug, Clone)]
+pub(crate) struct VerifierPolicy {
+    pub(crate) max_chain_length: usize,
+    pub(crate) min_rsa_bits: Option<usize>,
+    pub(crate) allowed_hashes: Option<Vec<HashAlgorithm>>,
+    pub(crate) clock_skew_tolerance: Duration,
+    pub(crate) require_name_constraints: bool,
+    pub(crate) unknown_critical_extensions: UnknownCriticalExtensionPolicy,
+}
+
+impl VerifierPolicy {
+    /// Reject chains that violate the policy before doing any path building.
+    ///
+    /// Only the certificates the peer presented are examined: trust anchors are
+    /// the user's own choice.
+    pub(crate) fn check_chain(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+    ) -> Result<(), Error> {
+        if 1 + intermediates.len() > self.max_chain_length {
+            return Err(CertificateError::ChainTooLong.into());
+        }
+
+        for cert in core::iter::once(end_entity).chain(intermediates) {
+            if let (Some(min), Some(bits)) = (self.min_rsa_bits, x509::rsa_public_key_bits(cert)) {
+                if bits < min {
+                    return Err(CertificateError::KeyTooSmall.into());
+                }
+            }
+
+            if let Some(hash) = x509::signature_hash_algorithm(cert) {
+                if !self.hash_allowed(hash) {
+                    return Err(CertificateError::DisallowedSignatureHash.into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prepare the certificates the peer presented for webpki.
+    ///
+    /// If unknown critical extensions are ignored, certificates carrying them are
+    /// re-encoded with those extensions made non-critical; see [`PresentedChain`].
+    pub(crate) fn presented<'a>(
+        &self,
+        end_entity: &'a CertificateDer<'_>,
+        intermediates: &'a [CertificateDer<'_>],
+    ) -> PresentedChain<'a> {
+        let mut original_tbs = Vec::new();
+        let mut prepare = |cert: &'a CertificateDer<'_>| match self.clear_unknown_critical(cert) {
+            Some((new_cert, new_tbs, original)) => {
+                original_tbs.push((new_tbs, original));
+                CertificateDer::from(new_cert)
+            }
+            None => CertificateDer::from(cert.as_ref()),
+        };
+
+        let end_entity = prepare(end_entity);
+        let intermediates = intermediates
+            .iter()
+            .map(&mut prepare)
+            .collect();
+        PresentedChain {
+            end_entity,
+            intermediates,
+            original_tbs,
+        }
+    }
+
+    /// Prepare the peer's end-entity certificate for webpki, for checking
+    /// handshake signatures.
+    pub(crate) fn end_entity<'a>(&self, cert: &'a CertificateDer<'_>) -> CertificateDer<'a> {
+        match self.clear_unknown_critical(cert) {
+            Some((new_cert, _, _)) => CertificateDer::from(new_cert),
+            None => CertificateDer::from(cert.as_ref()),
+        }
+    }
+
+    fn clear_unknown_critical<'a>(
+        &self,
+        cert: &'a CertificateDer<'_>,
+    ) -> Option<(Vec<u8>, Vec<u8>, &'a [u8])> {
+        match self.unknown_critical_extensions {
+            UnknownCriticalExtensionPolicy::Reject => None,
+            UnknownCriticalExtensionPolicy::Ignore => {
+                x509::clear_unknown_critical_flags(cert, x509::WEBPKI_KNOWN_EXTENSION_OIDS)
+            }
+        }
+    }
+
+    /// Reject a verified `path` that the policy does not accept.
+    ///
+    /// Name constraints themselves are enforced by webpki during path building; this
+    /// only checks that, if required, some CA in the path actually imposes them.
+    pub(crate) fn check_path(&self, path: &webpki::VerifiedPath<'_>) -> Result<(), Error> {
+        if !self.require_name_constraints || path.anchor().name_constraints.is_some() {
+            return Ok(());
+        }
+
+        let constrained = path
+            .intermediate_certificates()
+            .any(|cert| x509::has_extension(&cert.der(), x509::NAME_CONSTRAINTS_OID));
+        if !constrained {
+            return Err(CertificateError::NameConstraintsMissing.into());
+        }
+
+        Ok(())
+    }
+
+    /// Run `verify` at `now` and, if it finds a certificate that is not yet valid
+    /// or has expired, once more at `now` moved by the clock skew tolerance.
+    pub(crate) fn verify_at<T>(
+        &self,
+        now: UnixTime,
+        verify: impl Fn(UnixTime) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let err = match verify(now) {
+            Err(err) if !self.clock_skew_tolerance.is_zero() => err,
+            result => return result,
+        };
+
+        let tolerance = self.clock_skew_tolerance.as_secs();
+        let skewed = match err {
+            Error::InvalidCertificate(CertificateError::NotValidYet) => {
+                now.as_secs().checked_add(tolerance)
+            }
+            Error::InvalidCertificate(CertificateError::Expired) => {
+                now.as_secs().checked_sub(tolerance)
+            }
+            _ => None,
+        };
+
+        match skewed {
+            Some(secs) => verify(UnixTime::since_unix_epoch(Duration::from_secs(secs))),
+            None => Err(err),
+        }
+    }
+
+    /// Reject handshake signatures made with a scheme whose hash is not allowed.
+    pub(crate) fn check_scheme(&self, scheme: SignatureScheme) -> Result<(), Error> {
+        match signature_scheme_hash(scheme) {
+            Some(hash) if !self.hash_allowed(hash) => {
+                Err(PeerIncompatible::NoSignatureSchemesInCommon.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove schemes whose hash is not allowed from `schemes`.
+    pub(crate) fn filter_schemes(&self, mut schemes: Vec<SignatureScheme>) -> Vec<SignatureScheme> {
+        schemes.retain(|scheme| self.check_scheme(*scheme).is_ok());
+        schemes
+    }
+
+    fn hash_allowed(&self, hash: HashAlgorithm) -> bool {
+        match &self.allowed_hashes {
+            Some(allowed) => allowed.contains(&hash),
+            None => true,
+        }
+    }
+}
+
+impl Default for VerifierPolicy {
+    fn default() -> Self {
+        Self {
+            max_chain_length: DEFAULT_MAX_CHAIN_LENGTH,
+            min_rsa_bits: None,
+            allowed_hashes: None,
+            clock_skew_tolerance: Duration::ZERO,
+            require_name_constraints: false,
+            unknown_critical_extensions: UnknownCriticalExtensionPolicy::Reject,
+        }
+    }
+}
+
+/// The certificates presented by a peer, as given to webpki.
+///
+/// Any certificate re-encoded to ignore unknown critical extensions no longer
+/// matches its issuer's signature, so path building must use the algorithms from
+/// [`PresentedChain::with_algorithms`], which verify such signatures over the
+/// original `tbsCertificate` instead.
+pub(crate) struct PresentedChain<'a> {
+    pub(crate) end_entity: CertificateDer<'a>,
+    pub(crate) intermediates: Vec<CertificateDer<'a>>,
+    /// Pairs of re-encoded and original `tbsCertificate`s.
+    original_tbs: Vec<(Vec<u8>, &'a [u8])>,
+}
+
+impl PresentedChain<'_> {
+    /// Call `f` with the signature verification algorithms to use for this chain.
+    pub(crate) fn with_algorithms<T>(
+        &self,
+        all: &[&'static dyn SignatureVerificationAlgorithm],
+        f: impl FnOnce(&[&dyn SignatureVerificationAlgorithm]) -> T,
+    ) -> T {
+        if self.original_tbs.is_empty() {
+            return f(all);
+        }
+
+        let restoring = all
+            .iter()
+            .map(|inner| RestoresOriginalTbs {
+                inner: *inner,
+                original_tbs: &self.original_tbs,
+            })
+            .collect::<Vec<_>>();
+        let algs = restoring
+            .iter()
+            .map(|alg| -> &dyn SignatureVerificationAlgorithm { alg })
+            .collect::<Vec<_>>();
+        f(&algs)
+    }
+}
+
+/// Verifies signatures over re-encoded `tbsCertificate`s against the originals.
+#[derive(Debug)]
+struct RestoresOriginalTbs<'a> {
+    inner: &'static dyn SignatureVerificationAlgorithm,
+    original_tbs: &'a [(Vec<u8>, &'a [u8])],
+}
+
+impl SignatureVerificationAlgorithm for RestoresOriginalTbs<'_> {
+    fn verify_signature(
+        &self,
+        public_key: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), InvalidSignature> {
+        let message = self
+            .original_tbs
+            .iter()
+            .find(|(new_tbs, _)| new_tbs == message)
+            .map(|(_, original)| *original)
+            .unwrap_or(message);
+        self.inner
+            .verify_signature(public_key, message, signature)
+    }
+
+    fn public_key_alg_id(&self) -> pki_types::AlgorithmIdentifier {
+        self.inner.public_key_alg_id()
+    }
+
+    fn signature_alg_id(&self) -> pki_types::AlgorithmIdentifier {
+        self.inner.signature_alg_id()
+    }
+}
+
+/// The hash used by `scheme`, or `None` for EdDSA which has no separate digest.
+fn signature_scheme_hash(scheme: SignatureScheme) -> Option<HashAlgorithm> {
+    use SignatureScheme::*;
+    match scheme {
+        RSA_PKCS1_SHA1 | ECDSA_SHA1_Legacy => Some(HashAlgorithm::SHA1),
+        RSA_PKCS1_SHA256 | ECDSA_NISTP256_SHA256 | RSA_PSS_SHA256 => Some(HashAlgorithm::SHA256),
+        RSA_PKCS1_SHA384 | ECDSA_NISTP384_SHA384 | RSA_PSS_SHA384 => Some(HashAlgorithm::SHA384),
+        RSA_PKCS1_SHA512 | ECDSA_NISTP521_SHA512 | RSA_PSS_SHA512 => Some(HashAlgorithm::SHA512),
+        _ => None,
+    }
+}
+
 fn pki_error(error: webpki::Error) -> Error {
     use webpki::Error::*;
     match error {
@@ -61,8 +355,10 @@ fn pki_error(error: webpki::Error) -> Error {
         CertExpired | InvalidCertValidity => CertificateError::Expired.into(),
         UnknownIssuer => CertificateError::UnknownIssuer.into(),
         CertNotValidForName => CertificateError::NotValidForName.into(),
+        NameConstraintViolation => CertificateError::NameConstraintViolation.into(),
         CertRevoked => CertificateError::Revoked.into(),
         UnknownRevocationStatus => CertificateError::UnknownRevocationStatus.into(),
+        UnsupportedCriticalExtension => CertificateError::UnhandledCriticalExtension.into(),
         IssuerNotCrlSigner => CertRevocationListError::IssuerInvalidForCrl.into(),
 
         InvalidSignatureForPublicKey
@@ -133,6 +429,12 @@ mod tests {
             Error::InvalidCertificate(CertificateError::Revoked),
         );
 
+        // Unknown critical extensions should be turned into UnhandledCriticalExtension.
+        assert_eq!(
+            pki_error(webpki::Error::UnsupportedCriticalExtension),
+            Error::InvalidCertificate(CertificateError::UnhandledCriticalExtension),
+        );
+
         // Issuer not CRL signer errors should be turned into IssuerInvalidForCrl
         assert_eq!(
             pki_error(webpki::Error::IssuerNotCrlSigner),
@@ -2,10 +2,12 @@
 use crate::log::trace;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::time::Duration;
 
 use pki_types::{CertificateDer, CertificateRevocationListDer, ServerName, UnixTime};
 use webpki::{CertRevocationList, RevocationCheckDepth, UnknownStatusPolicy};
 
+use crate::crypto::hash::HashAlgorithm;
 use crate::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
 use crate::verify::{
     DigitallySignedStruct, HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
@@ -14,7 +16,10 @@ use crate::webpki::verify::{
     verify_server_cert_signed_by_trust_anchor_impl, verify_tls12_signature, verify_tls13_signature,
     ParsedCertificate,
 };
-use crate::webpki::{parse_crls, verify_server_name, VerifierBuilderError};
+use crate::webpki::{
+    parse_crls, verify_server_name, UnknownCriticalExtensionPolicy, VerifierBuilderError,
+    VerifierPolicy,
+};
 use crate::{Error, RootCertStore, SignatureScheme};
 
 /// A builder for configuring a `webpki` server certificate verifier.
@@ -26,6 +31,7 @@ pub struct ServerCertVerifierBuilder {
     crls: Vec<CertificateRevocationListDer<'static>>,
     revocation_check_depth: RevocationCheckDepth,
     unknown_revocation_policy: UnknownStatusPolicy,
+    policy: VerifierPolicy,
     supported_algs: WebPkiSupportedAlgorithms,
 }
 
@@ -39,6 +45,7 @@ impl ServerCertVerifierBuilder {
             crls: Vec::new(),
             revocation_check_depth: RevocationCheckDepth::Chain,
             unknown_revocation_policy: UnknownStatusPolicy::Deny,
+            policy: VerifierPolicy::default(),
             supported_algs,
         }
     }
@@ -81,6 +88,77 @@ impl ServerCertVerifierBuilder {
         self
     }
 
+    /// Limit the number of certificates a peer may present, including the end-entity certificate.
+    ///
+    /// Longer chains are rejected with [`CertificateError::ChainTooLong`] before path building
+    /// is attempted. The default is 10.
+    ///
+    /// [`CertificateError::ChainTooLong`]: crate::CertificateError::ChainTooLong
+    pub fn max_chain_length(mut self, max_chain_length: usize) -> Self {
+        self.policy.max_chain_length = max_chain_length;
+        self
+    }
+
+    /// Reject peer certificates carrying RSA keys smaller than `bits`.
+    ///
+    /// The end-entity and intermediate certificates presented by the peer are checked, and
+    /// violations are rejected with [`CertificateError::KeyTooSmall`]. By default any RSA key
+    /// size accepted by the signature verification algorithms is allowed.
+    ///
+    /// [`CertificateError::KeyTooSmall`]: crate::CertificateError::KeyTooSmall
+    pub fn min_rsa_bits(mut self, bits: usize) -> Self {
+        self.policy.min_rsa_bits = Some(bits);
+        self
+    }
+
+    /// Only accept signatures made using one of `hashes`.
+    ///
+    /// This applies to the issuer signatures on the certificates presented by the peer, which
+    /// are rejected with [`CertificateError::DisallowedSignatureHash`], and to handshake
+    /// signatures: schemes using other hashes are no longer offered to the peer, and are
+    /// rejected if it uses them anyway. EdDSA, and RSASSA-PSS certificate signatures, do not
+    /// identify a separate hash and are unaffected. By default all hashes are allowed.
+    ///
+    /// [`CertificateError::DisallowedSignatureHash`]: crate::CertificateError::DisallowedSignatureHash
+    pub fn allowed_hashes(mut self, hashes: impl IntoIterator<Item = HashAlgorithm>) -> Self {
+        self.policy.allowed_hashes = Some(hashes.into_iter().collect());
+        self
+    }
+
+    /// Require that the peer's certificate chains through a name-constrained CA.
+    ///
+    /// Name constraints on the trust anchor and on intermediate CAs are always enforced, as
+    /// RFC 5280 requires. With this option, a chain is also rejected with
+    /// [`CertificateError::NameConstraintsMissing`] if neither its trust anchor nor any of its
+    /// intermediates carry name constraints. This is useful for private CAs that are expected to
+    /// constrain every issuing sub-CA.
+    ///
+    /// [`CertificateError::NameConstraintsMissing`]: crate::CertificateError::NameConstraintsMissing
+    pub fn require_name_constraints(mut self) -> Self {
+        self.policy.require_name_constraints = true;
+        self
+    }
+
+    /// Tolerate `tolerance` of clock skew between us and the peer when checking
+    /// certificate validity periods.
+    ///
+    /// A certificate that is not yet valid, or has expired, is accepted if it
+    /// would be valid at a time that much later, or earlier, than the current
+    /// time.  The tolerance is applied in whole seconds.  The default is zero.
+    pub fn clock_skew_tolerance(mut self, tolerance: Duration) -> Self {
+        self.policy.clock_skew_tolerance = tolerance;
+        self
+    }
+
+    /// Choose what to do with peer certificates carrying critical extensions that
+    /// webpki does not understand.
+    ///
+    /// The default is [`UnknownCriticalExtensionPolicy::Reject`].
+    pub fn on_unknown_critical_extension(mut self, policy: UnknownCriticalExtensionPolicy) -> Self {
+        self.policy.unknown_critical_extensions = policy;
+        self
+    }
+
     /// Build a server certificate verifier, allowing control over the root certificates to use as
     /// trust anchors, and to control how server certificate revocation checking is performed.
     ///
@@ -105,6 +183,7 @@ impl ServerCertVerifierBuilder {
             parse_crls(self.crls)?,
             self.revocation_check_depth,
             self.unknown_revocation_policy,
+            self.policy,
             self.supported_algs,
         )
         .into())
@@ -119,6 +198,7 @@ pub struct WebPkiServerVerifier {
     crls: Vec<CertRevocationList<'static>>,
     revocation_check_depth: RevocationCheckDepth,
     unknown_revocation_policy: UnknownStatusPolicy,
+    policy: VerifierPolicy,
     supported: WebPkiSupportedAlgorithms,
 }
 
@@ -164,6 +244,7 @@ impl WebPkiServerVerifier {
             Vec::default(),
             RevocationCheckDepth::Chain,
             UnknownStatusPolicy::Allow,
+            VerifierPolicy::default(),
             supported_algs,
         )
     }
@@ -177,6 +258,7 @@ impl WebPkiServerVerifier {
     ///   when `crls` are provided.
     /// * `unknown_revocation_policy` controls how certificates with an unknown revocation status
     ///   are handled when `crls` are provided.
+    /// * `policy` holds the additional chain and signature checks to apply.
     /// * `supported` is the set of supported algorithms that will be used for
     ///   certificate verification and TLS handshake signature verification.
     pub(crate) fn new(
@@ -184,6 +266,7 @@ impl WebPkiServerVerifier {
         crls: Vec<CertRevocationList<'static>>,
         revocation_check_depth: RevocationCheckDepth,
         unknown_revocation_policy: UnknownStatusPolicy,
+        policy: VerifierPolicy,
         supported: WebPkiSupportedAlgorithms,
     ) -> Self {
         Self {
@@ -191,6 +274,7 @@ impl WebPkiServerVerifier {
             crls,
             revocation_check_depth,
             unknown_revocation_policy,
+            policy,
             supported,
         }
     }
@@ -202,6 +286,11 @@ impl ServerCertVerifier for WebPkiServerVerifier {
     /// - Not Expired
     /// - Valid for DNS entry
     /// - Valid revocation status (if applicable).
+    /// - No critical extensions that `webpki` does not understand, unless configured to
+    ///   ignore them.
+    /// - No more certificates than the configured maximum chain length.
+    /// - Within the name constraints of the CAs in the chain, and chained through a
+    ///   name-constrained CA if so configured.
     ///
     /// Depending on the verifier's configuration revocation status checking may be performed for
     /// each certificate in the chain to a root CA (excluding the root itself), or only the
@@ -215,7 +304,12 @@ impl ServerCertVerifier for WebPkiServerVerifier {
         ocsp_response: &[u8],
         now: UnixTime,
     ) -> Result<ServerCertVerified, Error> {
-        let cert = ParsedCertificate::try_from(end_entity)?;
+        self.policy
+            .check_chain(end_entity, intermediates)?;
+        let presented = self
+            .policy
+            .presented(end_entity, intermediates);
+        let cert = ParsedCertificate::try_from(&presented.end_entity)?;
 
         let crl_refs = self.crls.iter().collect::<Vec<_>>();
 
@@ -237,14 +331,19 @@ impl ServerCertVerifier for WebPkiServerVerifier {
 
         // Note: we use the crate-internal `_impl` fn here in order to provide revocation
         // checking information, if applicable.
-        verify_server_cert_signed_by_trust_anchor_impl(
-            &cert,
-            &self.roots,
-            intermediates,
-            revocation,
-            now,
-            self.supported.all,
-        )?;
+        self.policy.verify_at(now, |now| {
+            presented.with_algorithms(self.supported.all, |algs| {
+                let path = verify_server_cert_signed_by_trust_anchor_impl(
+                    &cert,
+                    &self.roots,
+                    &presented.intermediates,
+                    revocation,
+                    now,
+                    algs,
+                )?;
+                self.policy.check_path(&path)
+            })
+        })?;
 
         if !ocsp_response.is_empty() {
             trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
@@ -260,7 +359,8 @@ impl ServerCertVerifier for WebPkiServerVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        verify_tls12_signature(message, cert, dss, &self.supported)
+        self.policy.check_scheme(dss.scheme)?;
+        verify_tls12_signature(message, &self.policy.end_entity(cert), dss, &self.supported)
     }
 
     fn verify_tls13_signature(
@@ -269,11 +369,13 @@ impl ServerCertVerifier for WebPkiServerVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        verify_tls13_signature(message, cert, dss, &self.supported)
+        self.policy.check_scheme(dss.scheme)?;
+        verify_tls13_signature(message, &self.policy.end_entity(cert), dss, &self.supported)
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        self.supported.supported_schemes()
+        self.policy
+            .filter_schemes(self.supported.supported_schemes())
     }
 }
 
@@ -281,10 +383,11 @@ impl ServerCertVerifier for WebPkiServerVerifier {
 mod tests {
     use std::sync::Arc;
 
-    use pki_types::{CertificateDer, CertificateRevocationListDer};
+    use pki_types::{CertificateDer, CertificateRevocationListDer, ServerName, UnixTime};
 
-    use super::{VerifierBuilderError, WebPkiServerVerifier};
-    use crate::{test_provider, RootCertStore};
+    use super::{UnknownCriticalExtensionPolicy, VerifierBuilderError, WebPkiServerVerifier};
+    use crate::verify::ServerCertVerifier;
+    use crate::{test_provider, x509, CertificateError, Error, RootCertStore};
 
     fn load_crls(crls_der: &[&[u8]]) -> Vec<CertificateRevocationListDer<'static>> {
         crls_der
@@ -409,4 +512,57 @@ mod tests {
         println!("{:?}", builder);
         builder.build().unwrap();
     }
+
+    #[test]
+    fn test_certificate_with_cleared_critical_flags_fails_signature_check() {
+        // A peer presenting a certificate as the Ignore policy would re-encode it must not
+        // be trusted: the issuer signed the original, with the extension still critical.
+        let pem = |bytes: &[u8]| {
+            rustls_pemfile::certs(&mut &bytes[..])
+                .next()
+                .unwrap()
+                .unwrap()
+        };
+        let end_entity = pem(include_bytes!(
+            "../../../test-ca/rsa/end-unknown-critical.cert"
+        ));
+        let intermediate = pem(include_bytes!("../../../test-ca/rsa/inter.cert"));
+        let (cleared, _, _) =
+            x509::clear_unknown_critical_flags(&end_entity, x509::WEBPKI_KNOWN_EXTENSION_OIDS)
+                .unwrap();
+        let server_name = ServerName::try_from("testserver.com").unwrap();
+
+        for policy in [
+            UnknownCriticalExtensionPolicy::Reject,
+            UnknownCriticalExtensionPolicy::Ignore,
+        ] {
+            let verifier = WebPkiServerVerifier::builder_with_provider(
+                load_roots(&[include_bytes!("../../../test-ca/rsa/ca.der").as_slice()]),
+                test_provider::default_provider().into(),
+            )
+            .on_unknown_critical_extension(policy)
+            .build()
+            .unwrap();
+            let verify = |end_entity: &CertificateDer<'_>| {
+                verifier
+                    .verify_server_cert(
+                        end_entity,
+                        &[intermediate.clone()],
+                        &server_name,
+                        &[],
+                        UnixTime::now(),
+                    )
+                    .map(|_| ())
+            };
+
+            assert_eq!(
+                verify(&CertificateDer::from(cleared.clone())),
+                Err(Error::InvalidCertificate(CertificateError::BadSignature))
+            );
+            assert_eq!(
+                verify(&end_entity).is_ok(),
+                policy == UnknownCriticalExtensionPolicy::Ignore
+            );
+        }
+    }
 }
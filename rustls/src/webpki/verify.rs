@@ -1,3 +1,4 @@
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::fmt;
 
@@ -6,7 +7,7 @@ use pki_types::{CertificateDer, ServerName, SignatureVerificationAlgorithm, Unix
 use super::anchors::RootCertStore;
 use super::pki_error;
 use crate::enums::SignatureScheme;
-use crate::error::{Error, PeerMisbehaved};
+use crate::error::{CertificateError, Error};
 
 use crate::verify::{DigitallySignedStruct, HandshakeSignatureValid};
 
@@ -37,18 +38,33 @@ pub fn verify_server_cert_signed_by_trust_anchor(
         now,
         supported_algs,
     )
+    .map(|_| ())
 }
 
 /// Verify that the `end_entity` has a name or alternative name matching the `server_name`
 /// note: this only verifies the name and should be used in conjuction with more verification
 /// like [verify_server_cert_signed_by_trust_anchor]
+///
+/// A mismatch is reported as [`CertificateError::NameMismatch`], carrying the
+/// DNS names the certificate is valid for.
 pub fn verify_server_name(
     cert: &ParsedCertificate,
     server_name: &ServerName<'_>,
 ) -> Result<(), Error> {
     cert.0
         .verify_is_valid_for_subject_name(server_name)
-        .map_err(pki_error)
+        .map_err(|err| match err {
+            webpki::Error::CertNotValidForName => CertificateError::NameMismatch {
+                requested: server_name.to_str().into_owned(),
+                presented: cert
+                    .0
+                    .valid_dns_names()
+                    .map(String::from)
+                    .collect(),
+            }
+            .into(),
+            err => pki_error(err),
+        })
 }
 
 /// Describes which `webpki` signature verification algorithms are supported and
@@ -98,7 +114,7 @@ impl WebPkiSupportedAlgorithms {
             .iter()
             .filter_map(|item| if item.0 == scheme { Some(item.1) } else { None })
             .next()
-            .ok_or_else(|| PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme.into())
+            .ok_or(Error::UnsupportedSignatureScheme { scheme })
     }
 }
 
@@ -151,8 +167,10 @@ pub fn verify_tls12_signature(
         }
     }
 
-    Err(pki_error(
+    Err(signature_error(
+        &cert,
         webpki::Error::UnsupportedSignatureAlgorithmForPublicKey,
+        supported_schemes,
     ))
 }
 
@@ -169,7 +187,7 @@ pub fn verify_tls13_signature(
     supported_schemes: &WebPkiSupportedAlgorithms,
 ) -> Result<HandshakeSignatureValid, Error> {
     if !dss.scheme.supported_in_tls13() {
-        return Err(PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme.into());
+        return Err(Error::UnsupportedSignatureScheme { scheme: dss.scheme });
     }
 
     let alg = supported_schemes.convert_scheme(dss.scheme)?[0];
@@ -177,10 +195,40 @@ pub fn verify_tls13_signature(
     let cert = webpki::EndEntityCert::try_from(cert).map_err(pki_error)?;
 
     cert.verify_signature(alg, msg, dss.signature())
-        .map_err(pki_error)
+        .map_err(|err| signature_error(&cert, err, supported_schemes))
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
+/// Convert an `error` from verifying a signature by `cert`'s public key.
+///
+/// webpki reports a key of the wrong type for the signature algorithm in the
+/// same way whether or not we support that type of key at all, so we ask each
+/// supported algorithm in turn whether it accepts the key to tell these apart.
+fn signature_error(
+    cert: &webpki::EndEntityCert<'_>,
+    error: webpki::Error,
+    supported_schemes: &WebPkiSupportedAlgorithms,
+) -> Error {
+    if !matches!(
+        error,
+        webpki::Error::UnsupportedSignatureAlgorithmForPublicKey
+    ) {
+        return pki_error(error);
+    }
+
+    let key_supported = supported_schemes.all.iter().any(|alg| {
+        !matches!(
+            cert.verify_signature(*alg, &[], &[]),
+            Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey)
+        )
+    });
+
+    match key_supported {
+        true => pki_error(error),
+        false => Error::UnsupportedKeyAlgorithm,
+    }
+}
+
 /// Verify that the end-entity certificate `end_entity` is a valid server cert
 /// and chains to at least one of the trust anchors in the `roots` [RootCertStore].
 ///
@@ -190,31 +238,31 @@ pub fn verify_tls13_signature(
 ///
 /// `revocation` controls how revocation checking is performed, if at all.
 ///
+/// On success, the path that was built to a trust anchor is returned.
+///
 /// This function exists to be used by [`verify_server_cert_signed_by_trust_anchor`],
 /// and differs only in providing a `Option<webpki::RevocationOptions>` argument. We
 /// can't include this argument in `verify_server_cert_signed_by_trust_anchor` because
 /// it will leak the webpki types into Rustls' public API.
-pub(crate) fn verify_server_cert_signed_by_trust_anchor_impl(
-    cert: &ParsedCertificate,
-    roots: &RootCertStore,
-    intermediates: &[CertificateDer<'_>],
+pub(crate) fn verify_server_cert_signed_by_trust_anchor_impl<'p>(
+    cert: &'p ParsedCertificate<'p>,
+    roots: &'p RootCertStore,
+    intermediates: &'p [CertificateDer<'p>],
     revocation: Option<webpki::RevocationOptions>,
     now: UnixTime,
     supported_algs: &[&dyn SignatureVerificationAlgorithm],
-) -> Result<(), Error> {
-    let result = cert.0.verify_for_usage(
-        supported_algs,
-        &roots.roots,
-        intermediates,
-        now,
-        webpki::KeyUsage::server_auth(),
-        revocation,
-        None,
-    );
-    match result {
-        Ok(_) => Ok(()),
-        Err(e) => Err(pki_error(e)),
-    }
+) -> Result<webpki::VerifiedPath<'p>, Error> {
+    cert.0
+        .verify_for_usage(
+            supported_algs,
+            &roots.roots,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+            revocation,
+            None,
+        )
+        .map_err(pki_error)
 }
 
 #[cfg(test)]
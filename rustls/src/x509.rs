@@ -2,6 +2,12 @@
 
 use alloc::vec::Vec;
 
+use crate::msgs::enums::HashAlgorithm;
+
+use core::time::Duration;
+
+use pki_types::UnixTime;
+
 pub(crate) fn asn1_wrap(tag: u8, bytes: &[u8]) -> Vec<u8> {
     let len = bytes.len();
 
@@ -38,7 +44,393 @@ pub(crate) fn wrap_in_sequence(bytes: &[u8]) -> Vec<u8> {
     asn1_wrap(DER_SEQUENCE_TAG, bytes)
 }
 
+/// Return the size in bits of the RSA public key in the DER-encoded certificate `cert`.
+///
+/// Returns `None` if the certificate does not carry an RSA key, or cannot be parsed far
+/// enough to tell.
+pub(crate) fn rsa_public_key_bits(cert: &[u8]) -> Option<usize> {
+    let (spki, _) = der_expect(tbs_fields(cert)?, DER_SEQUENCE_TAG)?;
+    let (algorithm, spki) = der_expect(spki, DER_SEQUENCE_TAG)?;
+    let (oid, _) = der_expect(algorithm, DER_OID_TAG)?;
+    if oid != RSA_ENCRYPTION_OID {
+        return None;
+    }
+
+    let (key, _) = der_expect(spki, DER_BIT_STRING_TAG)?;
+    let key = match key.split_first()? {
+        (0, key) => key,
+        _ => return None,
+    };
+    let (key, _) = der_expect(key, DER_SEQUENCE_TAG)?;
+    let (modulus, _) = der_expect(key, DER_INTEGER_TAG)?;
+
+    let leading_zero_bytes = modulus.iter().position(|&x| x != 0)?;
+    let modulus = &modulus[leading_zero_bytes..];
+    Some(modulus.len() * 8 - modulus[0].leading_zeros() as usize)
+}
+
+/// Return the public key bits from the `SubjectPublicKeyInfo` of the DER-encoded
+/// certificate `cert`.
+///
+/// Returns `None` if the certificate cannot be parsed far enough to tell.
+pub(crate) fn public_key(cert: &[u8]) -> Option<&[u8]> {
+    spki_parts(tbs_fields(cert)?).map(|(_, key)| key)
+}
+
+/// Split the DER-encoded `SubjectPublicKeyInfo` `spki` into the contents of its
+/// `AlgorithmIdentifier` and its public key bits.
+///
+/// Returns `None` if `spki` cannot be parsed, or the key is not a whole number of bytes.
+pub(crate) fn spki_parts(spki: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (spki, _) = der_expect(spki, DER_SEQUENCE_TAG)?;
+    let (algorithm, spki) = der_expect(spki, DER_SEQUENCE_TAG)?;
+    let (key, _) = der_expect(spki, DER_BIT_STRING_TAG)?;
+    match key.split_first()? {
+        (0, key) => Some((algorithm, key)),
+        _ => None,
+    }
+}
+
+/// Return the hash algorithm used by the issuer's signature over the DER-encoded
+/// certificate `cert`.
+///
+/// Returns `None` for signature algorithms without a separately-identified digest
+/// (EdDSA, and RSASSA-PSS whose digest lives in the algorithm parameters), and for
+/// certificates that cannot be parsed.
+pub(crate) fn signature_hash_algorithm(cert: &[u8]) -> Option<HashAlgorithm> {
+    let (cert, _) = der_expect(cert, DER_SEQUENCE_TAG)?;
+    let (_, _, rest) = der_read(cert)?;
+    let (algorithm, _) = der_expect(rest, DER_SEQUENCE_TAG)?;
+    let (oid, _) = der_expect(algorithm, DER_OID_TAG)?;
+
+    SIGNATURE_HASH_OIDS
+        .iter()
+        .find(|(known, _)| *known == oid)
+        .map(|(_, hash)| *hash)
+}
+
+/// Return the `notBefore` and `notAfter` times of the DER-encoded certificate `cert`.
+///
+/// Returns `None` if the certificate cannot be parsed, or a time is not in the
+/// form required by RFC5280.
+pub(crate) fn validity(cert: &[u8]) -> Option<(UnixTime, UnixTime)> {
+    let (validity, _) = der_expect(skip_tbs_fields(cert, 3)?, DER_SEQUENCE_TAG)?;
+    let (not_before, validity) = der_time(validity)?;
+    let (not_after, _) = der_time(validity)?;
+    Some((not_before, not_after))
+}
+
+/// Return whether the DER-encoded certificate `cert` carries an extension with the
+/// given `oid`, regardless of its criticality.
+///
+/// Returns `false` if the certificate cannot be parsed far enough to tell.
+pub(crate) fn has_extension(cert: &[u8], oid: &[u8]) -> bool {
+    extension_oids(cert)
+        .map(|oids| oids.iter().any(|found| *found == oid))
+        .unwrap_or(false)
+}
+
+/// Re-encode the DER-encoded certificate `cert` with the critical flag removed from
+/// each extension whose OID is not in `known`.
+///
+/// Returns the new certificate, its new `tbsCertificate` and the original
+/// `tbsCertificate`, or `None` if no extension needed changing or the certificate
+/// cannot be parsed.  The signature is left as is, so it covers only the original.
+///
+/// Everything but the removed flags and the lengths enclosing them is copied
+/// verbatim, so the two encodings differ in nothing else.  Certificates with
+/// trailing data that re-encoding would drop are not changed.
+pub(crate) fn clear_unknown_critical_flags<'a>(
+    cert: &'a [u8],
+    known: &[&[u8]],
+) -> Option<(Vec<u8>, Vec<u8>, &'a [u8])> {
+    let cert = match der_read(cert)? {
+        (DER_SEQUENCE_TAG, cert, &[]) => cert,
+        _ => return None,
+    };
+    let (mut fields, signature) = der_expect(cert, DER_SEQUENCE_TAG)?;
+    let original_tbs = &cert[..cert.len() - signature.len()];
+
+    let mut tbs = Vec::new();
+    let mut changed = false;
+    while !fields.is_empty() {
+        let (tag, contents, rest) = der_read(fields)?;
+        let field = &fields[..fields.len() - rest.len()];
+        fields = rest;
+
+        // extensions: [3] EXPLICIT
+        if tag != 0xa3 {
+            tbs.extend_from_slice(field);
+            continue;
+        }
+
+        let mut extensions = match der_read(contents)? {
+            (DER_SEQUENCE_TAG, extensions, &[]) => extensions,
+            _ => return None,
+        };
+        let mut new_extensions = Vec::new();
+        while !extensions.is_empty() {
+            let (extension, rest) = der_expect(extensions, DER_SEQUENCE_TAG)?;
+            let whole = &extensions[..extensions.len() - rest.len()];
+            extensions = rest;
+
+            let (oid, after_oid) = der_expect(extension, DER_OID_TAG)?;
+            match der_read(after_oid)? {
+                (DER_BOOLEAN_TAG, &[0xff], value) if !known.contains(&oid) => {
+                    // critical is DEFAULT FALSE, so is omitted rather than encoded as false
+                    let mut cleared = extension[..extension.len() - after_oid.len()].to_vec();
+                    cleared.extend_from_slice(value);
+                    new_extensions.extend(wrap_in_sequence(&cleared));
+                    changed = true;
+                }
+                _ => new_extensions.extend_from_slice(whole),
+            }
+        }
+        tbs.extend(asn1_wrap(tag, &wrap_in_sequence(&new_extensions)));
+    }
+
+    if !changed {
+        return None;
+    }
+
+    let tbs = wrap_in_sequence(&tbs);
+    let mut new_cert = tbs.clone();
+    new_cert.extend_from_slice(signature);
+    Some((wrap_in_sequence(&new_cert), tbs, original_tbs))
+}
+
+/// Collect the OIDs of the extensions in the DER-encoded certificate `cert`.
+fn extension_oids(cert: &[u8]) -> Option<Vec<&[u8]>> {
+    // skip subjectPublicKeyInfo too, leaving the optional unique IDs and extensions
+    let mut tbs = skip_tbs_fields(cert, 6)?;
+    let mut oids = Vec::new();
+
+    while !tbs.is_empty() {
+        let (tag, contents, rest) = der_read(tbs)?;
+        tbs = rest;
+
+        // extensions: [3] EXPLICIT
+        if tag != 0xa3 {
+            continue;
+        }
+
+        let (mut extensions, _) = der_expect(contents, DER_SEQUENCE_TAG)?;
+        while !extensions.is_empty() {
+            let (extension, rest) = der_expect(extensions, DER_SEQUENCE_TAG)?;
+            let (oid, _) = der_expect(extension, DER_OID_TAG)?;
+            oids.push(oid);
+            extensions = rest;
+        }
+    }
+
+    Some(oids)
+}
+
+/// Skip the `tbsCertificate` fields that precede `subjectPublicKeyInfo`.
+fn tbs_fields(cert: &[u8]) -> Option<&[u8]> {
+    // serialNumber, signature, issuer, validity, subject
+    skip_tbs_fields(cert, 5)
+}
+
+/// Skip the optional version and then `count` further fields of the
+/// `tbsCertificate` in `cert`.
+fn skip_tbs_fields(cert: &[u8], count: usize) -> Option<&[u8]> {
+    let (cert, _) = der_expect(cert, DER_SEQUENCE_TAG)?;
+    let (mut tbs, _) = der_expect(cert, DER_SEQUENCE_TAG)?;
+
+    // version is optional: [0] EXPLICIT
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_read(tbs)?.2;
+    }
+
+    for _ in 0..count {
+        tbs = der_read(tbs)?.2;
+    }
+
+    Some(tbs)
+}
+
+/// Read a `Time` from the front of `input`, returning it and the remaining input.
+///
+/// RFC5280 4.1.2.5 requires these be in UTC, include seconds, and end with `Z`;
+/// two-digit years from 50 onwards are in the 1900s.
+fn der_time(input: &[u8]) -> Option<(UnixTime, &[u8])> {
+    let (tag, contents, rest) = der_read(input)?;
+    let (year, contents) = match (tag, contents.len()) {
+        (DER_UTC_TIME_TAG, 13) => {
+            let yy = der_time_digits(&contents[..2])?;
+            (if yy >= 50 { 1900 + yy } else { 2000 + yy }, &contents[2..])
+        }
+        (DER_GENERALIZED_TIME_TAG, 15) => (der_time_digits(&contents[..4])?, &contents[4..]),
+        _ => return None,
+    };
+
+    if contents[10] != b'Z' {
+        return None;
+    }
+    let month = der_time_digits(&contents[0..2])?;
+    let day = der_time_digits(&contents[2..4])?;
+    let hour = der_time_digits(&contents[4..6])?;
+    let minute = der_time_digits(&contents[6..8])?;
+    let second = der_time_digits(&contents[8..10])?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    // leap seconds are permitted
+    if second > 60 || year < 1970 {
+        return None;
+    }
+
+    let seconds = days_since_epoch(year, month, day) * 86_400
+        + u64::from(hour) * 3600
+        + u64::from(minute) * 60
+        + u64::from(second);
+    Some((
+        UnixTime::since_unix_epoch(Duration::from_secs(seconds)),
+        rest,
+    ))
+}
+
+fn der_time_digits(digits: &[u8]) -> Option<u32> {
+    digits
+        .iter()
+        .try_fold(0, |acc, &d| match d {
+            b'0'..=b'9' => Some(acc * 10 + u32::from(d - b'0')),
+            _ => None,
+        })
+}
+
+/// The number of days from 1970-01-01 to the given date, which must not
+/// precede it.
+fn days_since_epoch(year: u32, month: u32, day: u32) -> u64 {
+    // Count years from March, so leap days fall at the end of each.
+    let (year, month) = match month {
+        1 | 2 => (u64::from(year) - 1, u64::from(month) + 9),
+        _ => (u64::from(year), u64::from(month) - 3),
+    };
+    let days_before_year = year * 365 + year / 4 - year / 100 + year / 400;
+    let days_before_month = (153 * month + 2) / 5;
+    // 719_468 is the number of days from 0000-03-01 to 1970-01-01.
+    days_before_year + days_before_month + u64::from(day) - 1 - 719_468
+}
+
+/// Read a DER value with the given `tag` from the front of `input`, returning its
+/// contents and the remaining input.
+fn der_expect(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    match der_read(input)? {
+        (t, contents, rest) if t == tag => Some((contents, rest)),
+        _ => None,
+    }
+}
+
+/// Read any DER value from the front of `input`, returning its tag, contents and the
+/// remaining input.
+///
+/// Lengths not in the shortest form, which DER forbids, are rejected.
+fn der_read(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&len, input) = input.split_first()?;
+
+    let (len, input) = if len <= 0x7f {
+        (len as usize, input)
+    } else {
+        let encoded_bytes = (len & 0x7f) as usize;
+        if encoded_bytes == 0 || encoded_bytes > 4 || input.len() < encoded_bytes {
+            return None;
+        }
+        let (size, input) = input.split_at(encoded_bytes);
+        let len = size
+            .iter()
+            .fold(0usize, |acc, &x| (acc << 8) | x as usize);
+        if len <= 0x7f || size[0] == 0 {
+            return None;
+        }
+        (len, input)
+    };
+
+    if input.len() < len {
+        return None;
+    }
+    let (contents, rest) = input.split_at(len);
+    Some((tag, contents, rest))
+}
+
 const DER_SEQUENCE_TAG: u8 = 0x30;
+const DER_BOOLEAN_TAG: u8 = 0x01;
+const DER_INTEGER_TAG: u8 = 0x02;
+const DER_BIT_STRING_TAG: u8 = 0x03;
+const DER_OID_TAG: u8 = 0x06;
+const DER_UTC_TIME_TAG: u8 = 0x17;
+const DER_GENERALIZED_TIME_TAG: u8 = 0x18;
+
+// 2.5.29.30
+pub(crate) const NAME_CONSTRAINTS_OID: &[u8] = &[0x55, 0x1d, 0x1e];
+
+/// The extensions webpki understands, and so accepts when critical.
+pub(crate) const WEBPKI_KNOWN_EXTENSION_OIDS: &[&[u8]] = &[
+    // id-ce-keyUsage
+    &[0x55, 0x1d, 0x0f],
+    // id-ce-subjectAltName
+    &[0x55, 0x1d, 0x11],
+    // id-ce-basicConstraints
+    &[0x55, 0x1d, 0x13],
+    NAME_CONSTRAINTS_OID,
+    // id-ce-cRLDistributionPoints
+    &[0x55, 0x1d, 0x1f],
+    // id-ce-extKeyUsage
+    &[0x55, 0x1d, 0x25],
+];
+
+// 1.2.840.113549.1.1.1
+const RSA_ENCRYPTION_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+const SIGNATURE_HASH_OIDS: &[(&[u8], HashAlgorithm)] = &[
+    // md5WithRSAEncryption
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x04],
+        HashAlgorithm::MD5,
+    ),
+    // sha1WithRSAEncryption
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05],
+        HashAlgorithm::SHA1,
+    ),
+    // sha256WithRSAEncryption
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b],
+        HashAlgorithm::SHA256,
+    ),
+    // sha384WithRSAEncryption
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c],
+        HashAlgorithm::SHA384,
+    ),
+    // sha512WithRSAEncryption
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d],
+        HashAlgorithm::SHA512,
+    ),
+    // ecdsa-with-SHA1
+    (
+        &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x01],
+        HashAlgorithm::SHA1,
+    ),
+    // ecdsa-with-SHA256
+    (
+        &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02],
+        HashAlgorithm::SHA256,
+    ),
+    // ecdsa-with-SHA384
+    (
+        &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03],
+        HashAlgorithm::SHA384,
+    ),
+    // ecdsa-with-SHA512
+    (
+        &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x04],
+        HashAlgorithm::SHA512,
+    ),
+];
 
 #[cfg(test)]
 mod tests {
@@ -107,4 +499,131 @@ mod tests {
         );
         assert_eq!(result.len(), 0x1000000 + 6);
     }
+
+    #[test]
+    fn test_rsa_public_key_bits() {
+        assert_eq!(
+            rsa_public_key_bits(include_bytes!("../../test-ca/rsa/ca.der")),
+            Some(4096)
+        );
+        assert_eq!(
+            rsa_public_key_bits(include_bytes!("../../test-ca/ecdsa/ca.der")),
+            None
+        );
+        assert_eq!(rsa_public_key_bits(&[0x30, 0x01]), None);
+    }
+
+    #[test]
+    fn test_public_key() {
+        let key = public_key(include_bytes!("../../test-ca/eddsa/ca.der")).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_eq!(public_key(&[0x30, 0x01]), None);
+    }
+
+    #[test]
+    fn test_has_extension() {
+        // id-ce-basicConstraints
+        let basic_constraints = &[0x55, 0x1d, 0x13];
+        assert!(has_extension(
+            include_bytes!("../../test-ca/rsa/ca.der"),
+            basic_constraints
+        ));
+        assert!(!has_extension(
+            include_bytes!("../../test-ca/rsa/ca.der"),
+            NAME_CONSTRAINTS_OID
+        ));
+        assert!(!has_extension(&[0x30, 0x01], basic_constraints));
+    }
+
+    #[test]
+    fn test_clear_unknown_critical_flags() {
+        let cert = include_bytes!("../../test-ca/rsa/ca.der");
+        // id-ce-basicConstraints, critical in the CA certificate
+        let basic_constraints: &[u8] = &[0x55, 0x1d, 0x13];
+        assert_eq!(
+            clear_unknown_critical_flags(cert, &[basic_constraints]),
+            None
+        );
+
+        let (new_cert, new_tbs, original_tbs) = clear_unknown_critical_flags(cert, &[]).unwrap();
+        assert_eq!(new_cert.len(), cert.len() - 3);
+        assert_eq!(new_tbs.len(), original_tbs.len() - 3);
+        assert!(has_extension(&new_cert, basic_constraints));
+        assert_eq!(validity(&new_cert), validity(cert));
+        assert_eq!(clear_unknown_critical_flags(&new_cert, &[]), None);
+        assert_eq!(clear_unknown_critical_flags(&[0x30, 0x01], &[]), None);
+    }
+
+    #[test]
+    fn test_clear_unknown_critical_flags_rejects_malformed_certs() {
+        let cert = include_bytes!("../../test-ca/rsa/ca.der");
+        // the critical flag of id-ce-basicConstraints
+        let flag = cert
+            .windows(8)
+            .position(|w| w == [0x06, 0x03, 0x55, 0x1d, 0x13, 0x01, 0x01, 0xff])
+            .unwrap()
+            + 5;
+
+        // trailing data after the certificate
+        let mut trailing = cert.to_vec();
+        trailing.push(0);
+        assert_eq!(clear_unknown_critical_flags(&trailing, &[]), None);
+
+        // extension SEQUENCE lengths that overrun, or stop short of, its contents
+        for delta in [1, -1i8] {
+            let mut bad_length = cert.to_vec();
+            bad_length[flag - 6] = bad_length[flag - 6].wrapping_add(delta as u8);
+            assert_eq!(clear_unknown_critical_flags(&bad_length, &[]), None);
+        }
+
+        // a BOOLEAN length that overruns the extension
+        let mut bad_length = cert.to_vec();
+        bad_length[flag + 1] = 0x7f;
+        assert_eq!(clear_unknown_critical_flags(&bad_length, &[]), None);
+
+        // a length in long form where the short form is required
+        assert_eq!(der_read(&[0x04, 0x81, 0x01, 0x00]), None);
+        assert_eq!(der_read(&[0x04, 0x82, 0x00, 0x80]), None);
+        let mut long = vec![0x04, 0x81, 0x80];
+        long.resize(3 + 0x80, 0);
+        assert!(der_read(&long).is_some());
+    }
+
+    #[test]
+    fn test_validity() {
+        let (not_before, not_after) = validity(include_bytes!("../../test-ca/rsa/ca.der")).unwrap();
+        assert!(not_before.as_secs() < not_after.as_secs());
+        assert_eq!(validity(&[0x30, 0x01]), None);
+    }
+
+    #[test]
+    fn test_der_time() {
+        let (time, rest) = der_time(b"\x17\x0d290414164004Zrest").unwrap();
+        assert_eq!(time.as_secs(), 1_870_879_204);
+        assert_eq!(rest, b"rest");
+
+        let (time, _) = der_time(b"\x18\x0f20500101000000Z").unwrap();
+        assert_eq!(time.as_secs(), 2_524_608_000);
+
+        // UTCTime year 50 is 1950, which precedes the epoch
+        assert_eq!(der_time(b"\x17\x0d500101000000Z"), None);
+        assert_eq!(der_time(b"\x17\x0d290414164004+"), None);
+        assert_eq!(der_time(b"\x17\x0d291314164004Z"), None);
+    }
+
+    #[test]
+    fn test_signature_hash_algorithm() {
+        assert_eq!(
+            signature_hash_algorithm(include_bytes!("../../test-ca/rsa/ca.der")),
+            Some(HashAlgorithm::SHA256)
+        );
+        assert_eq!(
+            signature_hash_algorithm(include_bytes!("../../test-ca/ecdsa/ca.der")),
+            Some(HashAlgorithm::SHA256)
+        );
+        assert_eq!(
+            signature_hash_algorithm(include_bytes!("../../test-ca/eddsa/ca.der")),
+            None
+        );
+    }
 }
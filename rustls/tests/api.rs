@@ -11,26 +11,37 @@ use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
 use pki_types::{CertificateDer, IpAddr, ServerName, UnixTime};
 use provider::cipher_suite;
 use provider::sign::RsaSigningKey;
-use rustls::client::{verify_server_cert_signed_by_trust_anchor, ResolvesClientCert, Resumption};
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::{
+    verify_server_cert_signed_by_trust_anchor, NewSessionTicketMetadata, ObservesNewTickets,
+    ResolvesClientCert, Resumption, ServerCertVerifierBuilder, TicketRequest,
+};
+use rustls::crypto::cipher::{AadDirection, ObservesAad};
+use rustls::crypto::hash::HashAlgorithm;
 use rustls::crypto::CryptoProvider;
 use rustls::internal::msgs::base::Payload;
 use rustls::internal::msgs::codec::Codec;
 use rustls::internal::msgs::enums::AlertLevel;
 use rustls::internal::msgs::handshake::{ClientExtension, HandshakePayload};
 use rustls::internal::msgs::message::{Message, MessagePayload, PlainMessage};
-use rustls::server::{ClientHello, ParsedCertificate, ResolvesServerCert};
+#[cfg(feature = "plain_messages")]
+use rustls::internal::msgs::{codec::Reader, message::OpaqueMessage};
+use rustls::internal::record_layer::advance_client_seq;
+use rustls::server::{ClientHello, EarlyDataRejectReason, ParsedCertificate, ResolvesServerCert};
 use rustls::SupportedCipherSuite;
 use rustls::{
-    sign, AlertDescription, CertificateError, ConnectionCommon, ContentType, Error, KeyLog,
-    PeerIncompatible, PeerMisbehaved, SideData,
+    sign, AlertDescription, AlpnProtocol, CertificateError, ConnectionCommon, ContentType, Error,
+    KeyLog, PeerIncompatible, PeerMisbehaved, SideData,
 };
-use rustls::{CipherSuite, ProtocolVersion, SignatureScheme};
+use rustls::{CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme};
 use rustls::{ClientConfig, ClientConnection};
 use rustls::{ConnectionTrafficSecrets, DistinguishedName};
+use rustls::{HandshakeKind, HeartbeatMode, RecordPadding, RecordVersionPolicy, ResumptionKind};
 use rustls::{ServerConfig, ServerConnection};
 use rustls::{Stream, StreamOwned};
 
@@ -103,6 +114,98 @@ fn alpn() {
     );
 }
 
+#[test]
+fn alpn_protocol_typed() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(client.alpn_protocol_typed(), None);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol_typed(), Some(AlpnProtocol::H2));
+    assert_eq!(server.alpn_protocol_typed(), Some(AlpnProtocol::H2));
+
+    assert_eq!(AlpnProtocol::from_bytes(b"http/1.1"), AlpnProtocol::Http11);
+    assert_eq!(AlpnProtocol::from_bytes(b"h3"), AlpnProtocol::H3);
+    let other = AlpnProtocol::from_bytes(b"imap");
+    assert_eq!(other, AlpnProtocol::Other(b"imap".to_vec()));
+    assert_eq!(other.as_bytes(), b"imap");
+    assert_eq!(AlpnProtocol::H2.as_bytes(), b"h2");
+}
+
+#[test]
+fn alps_settings_exchanged() {
+    for with_client_auth in [false, true] {
+        let mut server_config = match with_client_auth {
+            true => make_server_config_with_mandatory_client_auth(KeyType::Rsa),
+            false => make_server_config(KeyType::Rsa),
+        };
+        server_config.alpn_protocols = vec![b"h2".to_vec()];
+        server_config.alps_settings = vec![(b"h2".to_vec(), b"server-settings".to_vec())];
+        let mut client_config = match with_client_auth {
+            true => make_client_config_with_auth(KeyType::Rsa),
+            false => make_client_config(KeyType::Rsa),
+        };
+        client_config.alpn_protocols = vec![b"h2".to_vec()];
+        client_config.alps_settings = vec![(b"h2".to_vec(), b"client-settings".to_vec())];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            client.peer_alps_settings(b"h2"),
+            Some(&b"server-settings"[..])
+        );
+        assert_eq!(
+            server.peer_alps_settings(b"h2"),
+            Some(&b"client-settings"[..])
+        );
+        assert_eq!(client.peer_alps_settings(b"http/1.1"), None);
+
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
+    }
+}
+
+#[test]
+fn alps_settings_not_exchanged() {
+    // Server has no settings for h2
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    client_config.alps_settings = vec![(b"h2".to_vec(), b"client-settings".to_vec())];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(client.peer_alps_settings(b"h2"), None);
+    assert_eq!(server.peer_alps_settings(b"h2"), None);
+
+    // Not offered over TLS1.2
+    #[cfg(feature = "tls12")]
+    {
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.alpn_protocols = vec![b"h2".to_vec()];
+        server_config.alps_settings = vec![(b"h2".to_vec(), b"server-settings".to_vec())];
+        let mut client_config =
+            make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+        client_config.alpn_protocols = vec![b"h2".to_vec()];
+        client_config.alps_settings = vec![(b"h2".to_vec(), b"client-settings".to_vec())];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.peer_alps_settings(b"h2"), None);
+        assert_eq!(server.peer_alps_settings(b"h2"), None);
+    }
+}
+
 fn version_test(
     client_versions: &[&'static rustls::SupportedProtocolVersion],
     server_versions: &[&'static rustls::SupportedProtocolVersion],
@@ -187,6 +290,166 @@ fn versions() {
     );
 }
 
+#[cfg(feature = "tls12")]
+#[test]
+fn server_min_version_overrides_config() {
+    let server_config = Arc::new(make_server_config_with_versions(
+        KeyType::Rsa,
+        &[&rustls::version::TLS12, &rustls::version::TLS13],
+    ));
+
+    // a tls1.2-only client is still acceptable to other connections using this config
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config.clone()), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_2));
+
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server =
+        ServerConnection::new_with_min_version(server_config.clone(), &rustls::version::TLS13)
+            .unwrap();
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::Tls12NotOfferedOrEnabled
+        )))
+    );
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        client.process_new_packets().err(),
+        Some(Error::AlertReceived(AlertDescription::ProtocolVersion))
+    );
+
+    // a tls1.3-capable client is unaffected
+    let client_config = make_client_config(KeyType::Rsa);
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server =
+        ServerConnection::new_with_min_version(server_config, &rustls::version::TLS13).unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn client_min_version_overrides_config() {
+    let client_config = Arc::new(make_client_config(KeyType::Rsa));
+    let server_config = Arc::new(make_server_config_with_versions(
+        KeyType::Rsa,
+        &[&rustls::version::TLS12],
+    ));
+
+    // a tls1.2-only server is still acceptable to other connections using this config
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_2));
+
+    // the server refuses a ClientHello offering only tls1.3
+    let mut client = ClientConnection::new_with_min_version(
+        Arc::clone(&client_config),
+        server_name("localhost"),
+        &rustls::version::TLS13,
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::Tls12NotOfferedOrEnabled
+        )))
+    );
+
+    // so make the ClientHello look like a tls1.2 one, hiding supported_versions and
+    // adding a tls1.2 suite: the client refuses the server's tls1.2 reply, despite its
+    // config enabling tls1.2
+    let mut client_config = ClientConfig::clone(&client_config);
+    client_config
+        .dangerous()
+        .set_client_hello_mutator(|hello| {
+            hello
+                .extensions
+                .retain(|ext| !matches!(ext, ClientExtension::SupportedVersions(_)));
+            hello
+                .cipher_suites
+                .push(CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384);
+        });
+    let mut client = ClientConnection::new_with_min_version(
+        Arc::new(client_config),
+        server_name("localhost"),
+        &rustls::version::TLS13,
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(server_config).unwrap();
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::PeerIncompatible(
+            PeerIncompatible::ServerTlsVersionIsDisabledByOurConfig
+        )))
+    );
+    transfer(&mut client, &mut server);
+    assert_eq!(
+        server.process_new_packets().err(),
+        Some(Error::AlertReceived(AlertDescription::ProtocolVersion))
+    );
+}
+
+#[test]
+fn client_can_force_full_handshake_despite_stored_session() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(KeyType::Rsa, &[version]));
+        let server_config = Arc::new(make_server_config(KeyType::Rsa));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+
+        let mut client = ClientConnection::new_with_full_handshake(
+            Arc::clone(&client_config),
+            server_name("localhost"),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+        assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+
+        // the stored sessions were left for ordinary connections to use
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(matches!(
+            client.handshake_kind(),
+            Some(HandshakeKind::Resumed(_))
+        ));
+    }
+}
+
+#[test]
+fn min_version_must_leave_a_version_enabled() {
+    #[cfg(feature = "tls12")]
+    {
+        let client_config =
+            make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+        assert!(ClientConnection::new_with_min_version(
+            Arc::new(client_config),
+            server_name("localhost"),
+            &rustls::version::TLS13,
+        )
+        .is_err());
+    }
+
+    let server_config = make_server_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    assert!(ServerConnection::new_with_min_version(
+        Arc::new(server_config),
+        &rustls::version::TLS13
+    )
+    .is_ok());
+}
+
 fn check_read(reader: &mut dyn io::Read, bytes: &[u8]) {
     let mut buf = vec![0u8; bytes.len() + 1];
     assert_eq!(bytes.len(), reader.read(&mut buf).unwrap());
@@ -544,6 +807,49 @@ fn server_allow_any_anonymous_or_authenticated_client() {
     }
 }
 
+#[test]
+fn client_observes_optional_client_auth_request() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config_with_optional_client_auth(kt, vec![]));
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        assert!(!client.client_auth_requested());
+        do_handshake(&mut client, &mut server);
+
+        assert!(client.client_auth_requested());
+        assert_eq!(server.peer_certificates(), None);
+
+        let request = client.client_auth_request().unwrap();
+        let subjects = request
+            .root_hint_subjects()
+            .iter()
+            .map(|dn| dn.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            subjects,
+            vec![&b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponytown RSA CA"[..]]
+        );
+        assert!(request
+            .signature_schemes()
+            .contains(&SignatureScheme::RSA_PSS_SHA256));
+    }
+}
+
+#[test]
+fn client_auth_not_requested_by_default() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        do_handshake(&mut client, &mut server);
+
+        assert!(!client.client_auth_requested());
+        assert!(client.client_auth_request().is_none());
+    }
+}
+
 fn check_read_and_close(reader: &mut dyn io::Read, expect: &[u8]) {
     check_read(reader, expect);
     assert!(matches!(reader.read(&mut [0u8; 5]), Ok(0)));
@@ -805,6 +1111,7 @@ struct ServerCheckCertResolve {
     expected_sigalgs: Option<Vec<SignatureScheme>>,
     expected_alpn: Option<Vec<Vec<u8>>>,
     expected_cipher_suites: Option<Vec<CipherSuite>>,
+    expected_named_groups: Option<Vec<rustls::NamedGroup>>,
 }
 
 impl ResolvesServerCert for ServerCheckCertResolve {
@@ -855,6 +1162,17 @@ impl ResolvesServerCert for ServerCheckCertResolve {
             );
         }
 
+        if let Some(expected_named_groups) = &self.expected_named_groups {
+            let named_groups = client_hello
+                .named_groups()
+                .expect("supported_groups unexpectedly absent")
+                .collect::<Vec<_>>();
+            assert_eq!(
+                expected_named_groups, &named_groups,
+                "unexpected named groups"
+            );
+        }
+
         None
     }
 }
@@ -901,6 +1219,106 @@ fn server_cert_resolve_with_alpn() {
     }
 }
 
+#[derive(Debug)]
+struct ResolvesCertByAlpn {
+    certs: Vec<(&'static [u8], Arc<sign::CertifiedKey>)>,
+    offered: Mutex<Vec<Vec<u8>>>,
+}
+
+impl ResolvesServerCert for ResolvesCertByAlpn {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        *self.offered.lock().unwrap() = client_hello
+            .alpn()
+            .map(|protocols| protocols.map(|p| p.to_vec()).collect())
+            .unwrap_or_default();
+        let selected = client_hello.selected_alpn()?;
+        self.certs
+            .iter()
+            .find(|(protocol, _)| *protocol == selected)
+            .map(|(_, certkey)| Arc::clone(certkey))
+    }
+}
+
+#[test]
+fn server_cert_resolve_with_selected_alpn() {
+    let key_types = [KeyType::Rsa, KeyType::Ecdsa];
+    let certs = [b"h2".as_slice(), b"http/1.1".as_slice()]
+        .into_iter()
+        .zip(key_types)
+        .map(|(protocol, kt)| {
+            let key = provider::default_provider()
+                .key_provider
+                .load_private_key(kt.get_key())
+                .unwrap();
+            (
+                protocol,
+                Arc::new(sign::CertifiedKey::new(kt.get_chain(), key)),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut root_store = rustls::RootCertStore::empty();
+    for kt in key_types {
+        root_store
+            .add(kt.get_chain().pop().unwrap())
+            .unwrap();
+    }
+
+    for (client_protocols, expected) in [
+        (vec![b"h2".to_vec(), b"http/1.1".to_vec()], KeyType::Rsa),
+        (vec![b"http/1.1".to_vec()], KeyType::Ecdsa),
+    ] {
+        let mut client_config = client_config_builder()
+            .with_root_certificates(root_store.clone())
+            .with_no_client_auth();
+        client_config.alpn_protocols = client_protocols.clone();
+
+        let resolver = Arc::new(ResolvesCertByAlpn {
+            certs: certs.clone(),
+            offered: Mutex::new(Vec::new()),
+        });
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        server_config.cert_resolver = resolver.clone();
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(*resolver.offered.lock().unwrap(), client_protocols);
+        assert_eq!(
+            client.peer_certificates().unwrap()[0],
+            expected.get_chain()[0]
+        );
+        assert_eq!(server.alpn_protocol(), Some(&client_protocols[0][..]));
+    }
+}
+
+#[test]
+fn server_cert_resolve_with_named_groups() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config_with_kx_groups(
+            *kt,
+            vec![provider::kx_group::X25519, provider::kx_group::SECP256R1],
+        );
+
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+            expected_named_groups: Some(vec![
+                rustls::NamedGroup::X25519,
+                rustls::NamedGroup::secp256r1,
+            ]),
+            ..Default::default()
+        });
+
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("sni-value")).unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert!(err.is_err());
+    }
+}
+
 #[test]
 fn client_trims_terminating_dot() {
     for kt in ALL_KEY_TYPES.iter() {
@@ -1021,588 +1439,1989 @@ fn client_with_sni_disabled_does_not_send_sni() {
 }
 
 #[test]
-fn client_checks_server_certificate_with_given_name() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
+fn client_hello_record_version_is_configurable() {
+    fn first_record_version(client: &mut ClientConnection) -> [u8; 2] {
+        let mut buf = Vec::new();
+        client.write_tls(&mut buf).unwrap();
+        assert_eq!(buf[0], 0x16);
+        [buf[1], buf[2]]
+    }
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions(*kt, &[version]);
-            let mut client = ClientConnection::new(
-                Arc::new(client_config),
-                server_name("not-the-right-hostname.com"),
-            )
-            .unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
 
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::NotValidForName
-                )))
-            );
-        }
-    }
+    let client_config = make_client_config(KeyType::Rsa);
+    assert_eq!(
+        client_config.client_hello_record_version,
+        ProtocolVersion::TLSv1_0
+    );
+    let (mut client, _) = make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    assert_eq!(first_record_version(&mut client), [0x03, 0x01]);
+
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.client_hello_record_version = ProtocolVersion::TLSv1_2;
+    let client_config = Arc::new(client_config);
+    let (mut client, _) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(first_record_version(&mut client), [0x03, 0x03]);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.client_hello_record_version = ProtocolVersion::Unknown(0x0200);
+    assert!(ClientConnection::new(Arc::new(client_config), server_name("localhost")).is_err());
 }
 
+#[cfg(feature = "tls12")]
 #[test]
-fn client_checks_server_certificate_with_given_ip_address() {
-    fn check_server_name(
-        client_config: Arc<ClientConfig>,
-        server_config: Arc<ServerConfig>,
-        name: &'static str,
-    ) -> Result<(), ErrorFromPeer> {
-        let mut client = ClientConnection::new(client_config, server_name(name)).unwrap();
-        let mut server = ServerConnection::new(server_config).unwrap();
-        do_handshake_until_error(&mut client, &mut server)
+fn tls12_client_can_omit_supported_groups() {
+    fn sent_supported_groups(client_config: ClientConfig) -> bool {
+        first_client_hello(client_config)
+            .extensions
+            .iter()
+            .any(|ext| matches!(ext, ClientExtension::NamedGroups(_)))
     }
 
-    for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
+    let tls12_only = || make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    assert!(sent_supported_groups(tls12_only()));
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = Arc::new(make_client_config_with_versions(*kt, &[version]));
+    let mut client_config = tls12_only();
+    client_config.send_tls12_supported_groups = false;
+    assert!(!sent_supported_groups(client_config));
 
-            // positive ipv4 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.1"),
-                Ok(()),
-            );
+    // TLS1.3 needs the extension, so it is still sent when 1.3 is offered
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.send_tls12_supported_groups = false;
+    assert!(sent_supported_groups(client_config));
+}
 
-            // negative ipv4 case
+#[test]
+fn configs_list_advertised_cipher_suites_for_enabled_versions() {
+    let tls13_suites = vec![
+        CipherSuite::TLS13_AES_256_GCM_SHA384,
+        CipherSuite::TLS13_AES_128_GCM_SHA256,
+        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+    ];
+
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    assert_eq!(client_config.advertised_cipher_suites(), tls13_suites);
+    let server_config = make_server_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    assert_eq!(server_config.advertised_cipher_suites(), tls13_suites);
+
+    // the ClientHello offers the same suites, plus the renegotiation SCSV
+    let mut expected = tls13_suites;
+    expected.push(CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
+    assert_eq!(first_client_hello(client_config).cipher_suites, expected);
+
+    let client_config = make_client_config(KeyType::Rsa);
+    assert_eq!(
+        client_config.advertised_cipher_suites(),
+        provider::default_provider()
+            .cipher_suites
+            .iter()
+            .map(|cs| cs.suite())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn client_sends_configured_cipher_suite_order() {
+    let grease = CipherSuite::Unknown(0x3a3a);
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config
+        .set_cipher_suite_order(vec![
+            grease,
+            CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+            CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV,
+            CipherSuite::Unknown(0x1234),
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+        ])
+        .unwrap();
+    let expected = vec![
+        grease,
+        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+        CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV,
+        CipherSuite::TLS13_AES_128_GCM_SHA256,
+    ];
+    assert_eq!(client_config.advertised_cipher_suites(), expected);
+    assert_eq!(
+        first_client_hello(client_config.clone()).cipher_suites,
+        expected
+    );
+
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client
+            .negotiated_cipher_suite()
+            .map(|cs| cs.suite()),
+        Some(CipherSuite::TLS13_CHACHA20_POLY1305_SHA256)
+    );
+}
+
+#[test]
+fn cipher_suite_order_must_include_a_supported_suite() {
+    let mut client_config = make_client_config(KeyType::Rsa);
+    assert!(client_config
+        .set_cipher_suite_order(vec![
+            CipherSuite::Unknown(0x3a3a),
+            CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV,
+        ])
+        .is_err());
+    assert_eq!(
+        client_config.advertised_cipher_suites(),
+        make_client_config(KeyType::Rsa).advertised_cipher_suites()
+    );
+}
+
+#[test]
+fn cipher_suite_order_can_be_changed() {
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config
+        .set_cipher_suite_order(vec![CipherSuite::TLS13_AES_128_GCM_SHA256])
+        .unwrap();
+    client_config
+        .set_cipher_suite_order(vec![CipherSuite::TLS13_CHACHA20_POLY1305_SHA256])
+        .unwrap();
+    assert_eq!(
+        client_config.advertised_cipher_suites(),
+        vec![CipherSuite::TLS13_CHACHA20_POLY1305_SHA256]
+    );
+
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client
+            .negotiated_cipher_suite()
+            .map(|cs| cs.suite()),
+        Some(CipherSuite::TLS13_CHACHA20_POLY1305_SHA256)
+    );
+}
+
+#[test]
+fn client_records_server_hello_bytes() {
+    use rustls::internal::msgs::codec::Reader;
+    use rustls::internal::msgs::handshake::HandshakeMessagePayload;
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(client.server_hello_bytes(), None);
+        do_handshake(&mut client, &mut server);
+
+        let bytes = client.server_hello_bytes().unwrap();
+        let decoded = HandshakeMessagePayload::read(&mut Reader::init(bytes)).unwrap();
+        assert_eq!(decoded.typ, HandshakeType::ServerHello);
+        assert_eq!(decoded.get_encoding(), bytes);
+        match decoded.payload {
+            HandshakePayload::ServerHello(hello) => {
+                assert_eq!(
+                    Some(hello.cipher_suite),
+                    client
+                        .negotiated_cipher_suite()
+                        .map(|cs| cs.suite())
+                );
+            }
+            _ => panic!("expected a ServerHello"),
+        }
+    }
+}
+
+#[test]
+fn client_pads_client_hello() {
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.pad_client_hello_to = Some(512);
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+
+    // the second connection resumes, so must keep pre_shared_key last
+    for resuming in [false, true] {
+        let hello = first_client_hello(ClientConfig::clone(&client_config));
+        // including the handshake message header
+        assert!(4 + hello.get_encoding().len() >= 512);
+        assert!(hello
+            .extensions
+            .iter()
+            .any(|ext| ext.get_encoding()[..2] == [0x00, 0x15]));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            matches!(client.handshake_kind(), Some(HandshakeKind::Resumed(_))),
+            resuming
+        );
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+    }
+}
+
+#[test]
+fn client_sends_configured_psk_key_exchange_modes() {
+    use rustls::client::PSKKeyExchangeMode;
+
+    fn sent_psk_modes(client_config: ClientConfig) -> Vec<u8> {
+        first_client_hello(client_config)
+            .extensions
+            .iter()
+            .find(|ext| matches!(ext, ClientExtension::PresharedKeyModes(_)))
+            .unwrap()
+            .get_encoding()
+    }
+
+    assert_eq!(
+        sent_psk_modes(make_client_config(KeyType::Rsa)),
+        vec![0x00, 0x2d, 0x00, 0x02, 0x01, 0x01]
+    );
+
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.psk_key_exchange_modes =
+        vec![PSKKeyExchangeMode::PSK_KE, PSKKeyExchangeMode::PSK_DHE_KE];
+    assert_eq!(
+        sent_psk_modes(client_config),
+        vec![0x00, 0x2d, 0x00, 0x03, 0x02, 0x00, 0x01]
+    );
+
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.psk_key_exchange_modes = vec![PSKKeyExchangeMode::PSK_KE];
+    assert!(ClientConnection::new(Arc::new(client_config), server_name("localhost")).is_err());
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_false_start_sends_data_before_server_finished() {
+    fn server_reads_before_server_finished(enable_false_start: bool) -> bool {
+        let mut client_config =
+            make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+        client_config.enable_false_start = enable_false_start;
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        client
+            .writer()
+            .write_all(b"false start")
+            .unwrap();
+
+        // ClientHello; ServerHello..ServerHelloDone; ClientKeyExchange..Finished
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+
+        // The server's Finished has not yet reached the client.
+        assert!(client.is_handshaking());
+
+        let mut buf = [0u8; 32];
+        let read_early = match server.reader().read(&mut buf) {
+            Ok(len) => {
+                assert_eq!(&buf[..len], b"false start");
+                true
+            }
+            Err(err) => {
+                assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+                false
+            }
+        };
+
+        do_handshake(&mut client, &mut server);
+        assert!(!client.is_handshaking());
+        read_early
+    }
+
+    assert!(server_reads_before_server_finished(true));
+    assert!(!server_reads_before_server_finished(false));
+}
+
+#[cfg(feature = "tls12")]
+fn tls12_client_config_without_ems() -> ClientConfig {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config
+        .dangerous()
+        .set_client_hello_mutator(|hello| {
+            hello
+                .extensions
+                .retain(|ext| !matches!(ext, ClientExtension::ExtendedMasterSecretRequest))
+        });
+    client_config
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_required_ems_is_negotiated() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config.require_ems = true;
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.require_ems = true;
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_server_rejects_client_without_ems_when_required() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.require_ems = true;
+
+    let (mut client, mut server) =
+        make_pair_for_configs(tls12_client_config_without_ems(), server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::ExtendedMasterSecretExtensionRequired
+        )))
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_client_rejects_server_without_ems_when_required() {
+    let mut client_config = tls12_client_config_without_ems();
+    client_config.require_ems = true;
+
+    // the server only agrees to EMS if the client offers it
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::PeerIncompatible(
+            PeerIncompatible::ExtendedMasterSecretExtensionRequired
+        )))
+    );
+
+    // without the requirement, the handshake succeeds without EMS
+    let (mut client, mut server) = make_pair_for_configs(
+        tls12_client_config_without_ems(),
+        make_server_config(KeyType::Rsa),
+    );
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+}
+
+fn tls13_server_error_for_mutated_client_hello(
+    mutator: impl Fn(&mut rustls::internal::msgs::handshake::ClientHelloPayload) + Send + Sync + 'static,
+) -> Error {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config
+        .dangerous()
+        .set_client_hello_mutator(mutator);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    transfer(&mut client, &mut server);
+    let err = server
+        .process_new_packets()
+        .unwrap_err();
+
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        client.process_new_packets().err(),
+        Some(Error::AlertReceived(AlertDescription::IllegalParameter))
+    );
+    err
+}
+
+#[test]
+fn server_rejects_duplicate_key_shares() {
+    let err = tls13_server_error_for_mutated_client_hello(|hello| {
+        for ext in hello.extensions.iter_mut() {
+            if let ClientExtension::KeyShare(shares) = ext {
+                let duplicate = shares[0].clone();
+                shares.push(duplicate);
+            }
+        }
+    });
+    assert_eq!(
+        err,
+        Error::PeerMisbehaved(PeerMisbehaved::OfferedDuplicateKeyShares)
+    );
+}
+
+#[test]
+fn server_rejects_key_share_for_unlisted_group() {
+    let err = tls13_server_error_for_mutated_client_hello(|hello| {
+        for ext in hello.extensions.iter_mut() {
+            if let ClientExtension::NamedGroups(groups) = ext {
+                groups.retain(|group| *group != rustls::NamedGroup::X25519);
+            }
+        }
+    });
+    assert_eq!(
+        err,
+        Error::PeerMisbehaved(PeerMisbehaved::OfferedKeyShareForUnlistedGroup)
+    );
+}
+
+#[test]
+fn client_hello_mutator_can_remove_extensions() {
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config
+        .dangerous()
+        .set_client_hello_mutator(|hello| {
+            hello
+                .extensions
+                .retain(|ext| !matches!(ext, ClientExtension::ServerName(_)))
+        });
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+
+    let hello = first_client_hello(ClientConfig::clone(&client_config));
+    assert!(!hello.extensions.is_empty());
+    assert!(!hello
+        .extensions
+        .iter()
+        .any(|ext| matches!(ext, ClientExtension::ServerName(_))));
+    assert!(!hello
+        .get_encoding()
+        .windows(b"localhost".len())
+        .any(|w| w == b"localhost"));
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.server_name(), None);
+}
+
+#[test]
+fn client_checks_server_certificate_with_given_name() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let mut client = ClientConnection::new(
+                Arc::new(client_config),
+                server_name("not-the-right-hostname.com"),
+            )
+            .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            let err = do_handshake_until_error(&mut client, &mut server);
             assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.2"),
+                err,
                 Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::NotValidForName
+                    name_mismatch("not-the-right-hostname.com")
                 )))
             );
+        }
+    }
+}
+
+/// The error for a server certificate from `test-ca` not matching `requested`.
+fn name_mismatch(requested: &str) -> CertificateError {
+    CertificateError::NameMismatch {
+        requested: requested.to_string(),
+        presented: vec![
+            "testserver.com".to_string(),
+            "second.testserver.com".to_string(),
+            "localhost".to_string(),
+        ],
+    }
+}
+
+#[test]
+fn client_reports_name_mismatch_details() {
+    // The usual RSA test key, with a certificate only valid for right.example.com.
+    let end_entity =
+        rustls_pemfile::certs(&mut io::BufReader::new(bytes_for("rsa", "end-right.cert")))
+            .next()
+            .unwrap()
+            .unwrap();
+    let mut chain = KeyType::Rsa.get_chain();
+    chain[0] = end_entity;
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, KeyType::Rsa.get_key())
+        .unwrap();
+    let server_config = Arc::new(server_config);
+    let client_config = Arc::new(make_client_config(KeyType::Rsa));
+
+    let mut client =
+        ClientConnection::new(client_config, server_name("wrong.example.com")).unwrap();
+    let mut server = ServerConnection::new(server_config).unwrap();
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::NameMismatch {
+                requested: "wrong.example.com".to_string(),
+                presented: vec!["right.example.com".to_string()],
+            }
+        )))
+    );
+}
+
+#[test]
+fn client_checks_server_certificate_with_given_ip_address() {
+    fn check_server_name(
+        client_config: Arc<ClientConfig>,
+        server_config: Arc<ServerConfig>,
+        name: &'static str,
+    ) -> Result<(), ErrorFromPeer> {
+        let mut client = ClientConnection::new(client_config, server_name(name)).unwrap();
+        let mut server = ServerConnection::new(server_config).unwrap();
+        do_handshake_until_error(&mut client, &mut server)
+    }
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = Arc::new(make_client_config_with_versions(*kt, &[version]));
+
+            // positive ipv4 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.1"),
+                Ok(()),
+            );
+
+            // negative ipv4 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "198.51.100.2"),
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    name_mismatch("198.51.100.2")
+                )))
+            );
+
+            // positive ipv6 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::1"),
+                Ok(()),
+            );
+
+            // negative ipv6 case
+            assert_eq!(
+                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::2"),
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    name_mismatch("2001:db8::2")
+                )))
+            );
+        }
+    }
+}
+
+#[test]
+fn server_name_ip_address_requires_ip_san() {
+    use rustls::client::verify_server_name;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_cert = kt.get_chain().remove(0);
+        let server_cert = ParsedCertificate::try_from(&server_cert).unwrap();
+        // The client certificate has no iPAddress SANs.
+        let client_cert = kt.get_client_chain().remove(0);
+        let client_cert = ParsedCertificate::try_from(&client_cert).unwrap();
+
+        for ip in ["198.51.100.1", "2001:db8::1"] {
+            let name = ServerName::IpAddress(IpAddr::try_from(ip).unwrap());
+            assert_eq!(verify_server_name(&server_cert, &name), Ok(()));
+            assert!(matches!(
+                verify_server_name(&client_cert, &name),
+                Err(Error::InvalidCertificate(
+                    CertificateError::NameMismatch { .. }
+                ))
+            ));
+        }
+    }
+}
+
+#[test]
+fn client_check_server_certificate_ee_revoked() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+
+        // Setup a server verifier that will check the EE certificate's revocation status.
+        let crls = vec![kt.end_entity_crl()];
+        let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls)
+            .only_check_end_entity_revocation();
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_verifier(&[version], builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            // We expect the handshake to fail since the server's EE certificate is revoked.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+        }
+    }
+}
+
+#[test]
+fn client_check_server_certificate_ee_unknown_revocation() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+
+        // Setup a server verifier builder that will check the EE certificate's revocation status, but not
+        // allow unknown revocation status (the default). We'll provide CRLs that are not relevant
+        // to the EE cert to ensure its status is unknown.
+        let unrelated_crls = vec![kt.intermediate_crl()];
+        let forbid_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation();
+
+        // Also set up a verifier builder that will allow unknown revocation status.
+        let allow_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls)
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config =
+                make_client_config_with_verifier(&[version], forbid_unknown_verifier.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            // We expect if we use the forbid_unknown_verifier that the handshake will fail since the
+            // server's EE certificate's revocation status is unknown given the CRLs we've provided.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert!(matches!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::UnknownRevocationStatus
+                )))
+            ));
+
+            // We expect if we use the allow_unknown_verifier that the handshake will not fail.
+            let client_config =
+                make_client_config_with_verifier(&[version], allow_unknown_verifier.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok());
+        }
+    }
+}
+
+#[test]
+fn client_check_server_certificate_intermediate_revoked() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+
+        // Setup a server verifier builder that will check the full chain revocation status against a CRL
+        // that marks the intermediate certificate as revoked. We allow unknown revocation status
+        // so the EE cert's unknown status doesn't cause an error.
+        let crls = vec![kt.intermediate_crl()];
+        let full_chain_verifier_builder =
+            webpki_server_verifier_builder(get_client_root_store(*kt))
+                .with_crls(crls.clone())
+                .allow_unknown_revocation_status();
+
+        // Also set up a verifier builder that will use the same CRL, but only check the EE certificate
+        // revocation status.
+        let ee_verifier_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls.clone())
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config =
+                make_client_config_with_verifier(&[version], full_chain_verifier_builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            // We expect the handshake to fail when using the full chain verifier since the intermediate's
+            // EE certificate is revoked.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+
+            let client_config =
+                make_client_config_with_verifier(&[version], ee_verifier_builder.clone());
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            // We expect the handshake to succeed when we use the verifier that only checks the EE certificate
+            // revocation status. The revoked intermediate status should not be checked.
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok())
+        }
+    }
+}
+
+#[test]
+fn client_checks_server_certificate_chain_length() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+        let chain_length = kt.get_chain().len();
+
+        for version in rustls::ALL_VERSIONS {
+            // A chain longer than the limit is rejected.
+            let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+                .max_chain_length(chain_length - 1);
+            let client_config = make_client_config_with_verifier(&[version], builder);
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                    CertificateError::ChainTooLong
+                )))
+            );
+            transfer(&mut client, &mut server);
+            assert_eq!(
+                server.process_new_packets().err(),
+                Some(Error::AlertReceived(
+                    AlertDescription::UnsupportedCertificate
+                ))
+            );
+
+            // A chain at the limit is accepted.
+            let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+                .max_chain_length(chain_length);
+            let client_config = make_client_config_with_verifier(&[version], builder);
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            assert!(do_handshake_until_error(&mut client, &mut server).is_ok());
+        }
+    }
+}
+
+#[test]
+fn verifiers_apply_unknown_critical_extension_policy() {
+    use rustls::client::UnknownCriticalExtensionPolicy;
+
+    let end_entity = rustls_pemfile::certs(&mut io::BufReader::new(bytes_for(
+        "rsa",
+        "end-unknown-critical.cert",
+    )))
+    .next()
+    .unwrap()
+    .unwrap();
+    let chain = KeyType::Rsa.get_chain();
+    let roots = get_client_root_store(KeyType::Rsa);
+    let verify = |verifier: &dyn ServerCertVerifier, end_entity: &CertificateDer<'_>| {
+        verifier
+            .verify_server_cert(
+                end_entity,
+                &chain[1..2],
+                &server_name("testserver.com"),
+                &[],
+                UnixTime::now(),
+            )
+            .map(|_| ())
+    };
+
+    let verifier = webpki_server_verifier_builder(roots.clone())
+        .build()
+        .unwrap();
+    assert_eq!(
+        verify(verifier.as_ref(), &end_entity),
+        Err(Error::InvalidCertificate(
+            CertificateError::UnhandledCriticalExtension
+        ))
+    );
+
+    let verifier = webpki_server_verifier_builder(roots.clone())
+        .on_unknown_critical_extension(UnknownCriticalExtensionPolicy::Ignore)
+        .build()
+        .unwrap();
+    assert_eq!(verify(verifier.as_ref(), &end_entity), Ok(()));
+    assert_eq!(verify(verifier.as_ref(), &chain[0]), Ok(()));
+
+    // The issuer's signature still covers the ignored extension.
+    let mut tampered = end_entity.to_vec();
+    let at = tampered
+        .windows(7)
+        .position(|window| window == b"unknown")
+        .unwrap();
+    tampered[at] ^= 1;
+    assert_eq!(
+        verify(verifier.as_ref(), &tampered.into()),
+        Err(Error::InvalidCertificate(CertificateError::BadSignature))
+    );
+
+    // And the rest of the tbsCertificate, here the serial number.
+    let mut tampered = end_entity.to_vec();
+    let at = tampered
+        .windows(5)
+        .position(|window| window == [0xa0, 0x03, 0x02, 0x01, 0x02])
+        .unwrap();
+    // the last byte of the INTEGER following the version
+    let serial_len = tampered[at + 6] as usize;
+    tampered[at + 6 + serial_len] ^= 1;
+    assert_eq!(
+        verify(verifier.as_ref(), &tampered.into()),
+        Err(Error::InvalidCertificate(CertificateError::BadSignature))
+    );
+
+    // A malformed extension is not re-encoded into a well-formed one.
+    let mut malformed = end_entity.to_vec();
+    let at = malformed
+        .windows(7)
+        .position(|window| window == b"unknown")
+        .unwrap();
+    // the length of the OCTET STRING holding the extension's value
+    assert_eq!(malformed[at - 3], 9);
+    malformed[at - 3] = 10;
+    assert_eq!(
+        verify(verifier.as_ref(), &malformed.into()),
+        Err(Error::InvalidCertificate(CertificateError::BadEncoding))
+    );
+
+    // So does the client certificate verifier.
+    let client_verifier = |policy| {
+        webpki_client_verifier_builder(roots.clone())
+            .on_unknown_critical_extension(policy)
+            .build()
+            .unwrap()
+            .verify_client_cert(&end_entity, &chain[1..2], UnixTime::now())
+            .map(|_| ())
+    };
+    assert_eq!(
+        client_verifier(UnknownCriticalExtensionPolicy::Reject),
+        Err(Error::InvalidCertificate(
+            CertificateError::UnhandledCriticalExtension
+        ))
+    );
+    assert_eq!(
+        client_verifier(UnknownCriticalExtensionPolicy::Ignore),
+        Ok(())
+    );
+
+    // Handshake signatures are checked against the same certificate.
+    for version in rustls::ALL_VERSIONS {
+        let server_config = server_config_builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![end_entity.clone(), chain[1].clone()],
+                KeyType::Rsa.get_key(),
+            )
+            .unwrap();
+        let client_config = make_client_config_with_verifier(
+            &[version],
+            webpki_server_verifier_builder(roots.clone())
+                .on_unknown_critical_extension(UnknownCriticalExtensionPolicy::Ignore),
+        );
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn client_checks_server_rsa_key_size() {
+    let end_entity_1024 =
+        rustls_pemfile::certs(&mut io::BufReader::new(bytes_for("rsa", "end-1024.cert")))
+            .next()
+            .unwrap()
+            .unwrap();
+    let chain = KeyType::Rsa.get_chain();
+    let roots = get_client_root_store(KeyType::Rsa);
+    let verify = |verifier: &dyn ServerCertVerifier, end_entity: &CertificateDer<'_>| {
+        verifier
+            .verify_server_cert(
+                end_entity,
+                &chain[1..2],
+                &server_name("testserver.com"),
+                &[],
+                UnixTime::now(),
+            )
+            .map(|_| ())
+    };
+
+    // Without a policy, the undersized key passes path building.
+    let verifier = webpki_server_verifier_builder(roots.clone())
+        .build()
+        .unwrap();
+    assert_eq!(verify(verifier.as_ref(), &end_entity_1024), Ok(()));
+
+    let verifier = webpki_server_verifier_builder(roots.clone())
+        .min_rsa_bits(2048)
+        .build()
+        .unwrap();
+    assert_eq!(
+        verify(verifier.as_ref(), &end_entity_1024),
+        Err(Error::InvalidCertificate(CertificateError::KeyTooSmall))
+    );
+    assert_eq!(verify(verifier.as_ref(), &chain[0]), Ok(()));
+
+    // The intermediate (3072 bits) is checked too.
+    let verifier = webpki_server_verifier_builder(roots)
+        .min_rsa_bits(4096)
+        .build()
+        .unwrap();
+    assert_eq!(
+        verify(verifier.as_ref(), &chain[0]),
+        Err(Error::InvalidCertificate(CertificateError::KeyTooSmall))
+    );
+}
+
+#[test]
+fn client_tolerates_configured_clock_skew() {
+    // The `not_before` time of the test-ca RSA end-entity and intermediate certificates.
+    const NOT_BEFORE: u64 = 1_698_079_204;
+
+    let chain = KeyType::Rsa.get_chain();
+    let roots = get_client_root_store(KeyType::Rsa);
+    let verify = |tolerance: Duration, now: u64| {
+        webpki_server_verifier_builder(roots.clone())
+            .clock_skew_tolerance(tolerance)
+            .build()
+            .unwrap()
+            .verify_server_cert(
+                &chain[0],
+                &chain[1..2],
+                &server_name("testserver.com"),
+                &[],
+                UnixTime::since_unix_epoch(Duration::from_secs(now)),
+            )
+            .map(|_| ())
+    };
+
+    assert_eq!(
+        verify(Duration::ZERO, NOT_BEFORE - 30),
+        Err(Error::InvalidCertificate(CertificateError::NotValidYet))
+    );
+    assert_eq!(verify(Duration::from_secs(60), NOT_BEFORE - 30), Ok(()));
+    assert_eq!(
+        verify(Duration::from_secs(60), NOT_BEFORE - 90),
+        Err(Error::InvalidCertificate(CertificateError::NotValidYet))
+    );
+}
+
+#[test]
+fn client_enforces_name_constraints() {
+    let chain = KeyType::Rsa.get_chain();
+    let roots_constrained_to = |name_constraints: Option<&'static [u8]>| {
+        let mut roots = get_client_root_store(KeyType::Rsa)
+            .as_ref()
+            .clone();
+        roots.roots[0].name_constraints = name_constraints.map(pki_types::Der::from_slice);
+        Arc::new(roots)
+    };
+    let verify = |builder: ServerCertVerifierBuilder| {
+        builder
+            .build()
+            .unwrap()
+            .verify_server_cert(
+                &chain[0],
+                &chain[1..2],
+                &server_name("testserver.com"),
+                &[],
+                UnixTime::now(),
+            )
+            .map(|_| ())
+    };
+
+    // permittedSubtrees: dNSName corp.example.com
+    const CORP_ONLY: &[u8] = b"\xa0\x14\x30\x12\x82\x10corp.example.com";
+    assert_eq!(
+        verify(webpki_server_verifier_builder(roots_constrained_to(Some(
+            CORP_ONLY
+        )))),
+        Err(Error::InvalidCertificate(
+            CertificateError::NameConstraintViolation
+        ))
+    );
+
+    // permittedSubtrees: dNSName testserver.com, dNSName localhost
+    const TEST_NAMES: &[u8] = b"\xa0\x1f\x30\x10\x82\x0etestserver.com\x30\x0b\x82\x09localhost";
+    assert_eq!(
+        verify(
+            webpki_server_verifier_builder(roots_constrained_to(Some(TEST_NAMES)))
+                .require_name_constraints()
+        ),
+        Ok(())
+    );
+
+    // The test-ca chain carries no name constraints of its own.
+    assert_eq!(
+        verify(webpki_server_verifier_builder(roots_constrained_to(None))),
+        Ok(())
+    );
+    assert_eq!(
+        verify(
+            webpki_server_verifier_builder(roots_constrained_to(None)).require_name_constraints()
+        ),
+        Err(Error::InvalidCertificate(
+            CertificateError::NameConstraintsMissing
+        ))
+    );
+}
+
+#[test]
+fn client_rejects_small_rsa_key_with_bad_certificate() {
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+
+    for version in rustls::ALL_VERSIONS {
+        let builder =
+            webpki_server_verifier_builder(get_client_root_store(KeyType::Rsa)).min_rsa_bits(3072);
+        let client_config = make_client_config_with_verifier(&[version], builder);
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert_eq!(
+            err,
+            Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                CertificateError::KeyTooSmall
+            )))
+        );
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets().err(),
+            Some(Error::AlertReceived(AlertDescription::BadCertificate))
+        );
+    }
+}
+
+#[test]
+fn client_applies_allowed_hashes_to_server_certificates_and_signatures() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+
+        for version in rustls::ALL_VERSIONS {
+            // The test-ca chains are signed with SHA-256.  Only RSA keys can still produce a
+            // handshake signature with the remaining hashes, so the chain check is reached.
+            if matches!(kt, KeyType::Rsa) {
+                let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+                    .allowed_hashes([HashAlgorithm::SHA384, HashAlgorithm::SHA512]);
+                let client_config = make_client_config_with_verifier(&[version], builder);
+                let mut client =
+                    ClientConnection::new(Arc::new(client_config), server_name("localhost"))
+                        .unwrap();
+                let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+                let err = do_handshake_until_error(&mut client, &mut server);
+                assert_eq!(
+                    err,
+                    Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                        CertificateError::DisallowedSignatureHash
+                    )))
+                );
+            }
+
+            let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
+                .allowed_hashes([HashAlgorithm::SHA256]);
+            let verifier = builder.clone().build().unwrap();
+            assert!(verifier
+                .supported_verify_schemes()
+                .iter()
+                .all(|scheme| !matches!(
+                    scheme,
+                    SignatureScheme::RSA_PKCS1_SHA1
+                        | SignatureScheme::ECDSA_SHA1_Legacy
+                        | SignatureScheme::RSA_PKCS1_SHA384
+                        | SignatureScheme::ECDSA_NISTP384_SHA384
+                        | SignatureScheme::RSA_PSS_SHA384
+                        | SignatureScheme::RSA_PKCS1_SHA512
+                        | SignatureScheme::ECDSA_NISTP521_SHA512
+                        | SignatureScheme::RSA_PSS_SHA512
+                )));
+
+            let client_config = make_client_config_with_verifier(&[version], builder);
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            assert!(do_handshake_until_error(&mut client, &mut server).is_ok());
+        }
+    }
+}
+
+/// Simple smoke-test of the webpki verify_server_cert_signed_by_trust_anchor helper API.
+/// This public API is intended to be used by consumers implementing their own verifier and
+/// so isn't used by the other existing verifier tests.
+#[test]
+fn client_check_server_certificate_helper_api() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let chain = kt.get_chain();
+        let correct_roots = get_client_root_store(*kt);
+        let incorrect_roots = get_client_root_store(match kt {
+            KeyType::Rsa => KeyType::Ecdsa,
+            _ => KeyType::Rsa,
+        });
+        // Using the correct trust anchors, we should verify without error.
+        assert!(verify_server_cert_signed_by_trust_anchor(
+            &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
+            &correct_roots,
+            &[chain.get(1).unwrap().clone()],
+            UnixTime::now(),
+            webpki::ALL_VERIFICATION_ALGS,
+        )
+        .is_ok());
+        // Using the wrong trust anchors, we should get the expected error.
+        assert_eq!(
+            verify_server_cert_signed_by_trust_anchor(
+                &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
+                &incorrect_roots,
+                &[chain.get(1).unwrap().clone()],
+                UnixTime::now(),
+                webpki::ALL_VERIFICATION_ALGS,
+            )
+            .unwrap_err(),
+            Error::InvalidCertificate(CertificateError::UnknownIssuer)
+        );
+    }
+}
+
+#[derive(Debug)]
+struct ClientCheckCertResolve {
+    query_count: AtomicUsize,
+    expect_queries: usize,
+    expect_root_hint_subjects: Vec<Vec<u8>>,
+    expect_sigschemes: Vec<SignatureScheme>,
+}
+
+impl ClientCheckCertResolve {
+    fn new(
+        expect_queries: usize,
+        expect_root_hint_subjects: Vec<Vec<u8>>,
+        expect_sigschemes: Vec<SignatureScheme>,
+    ) -> Self {
+        Self {
+            query_count: AtomicUsize::new(0),
+            expect_queries,
+            expect_root_hint_subjects,
+            expect_sigschemes,
+        }
+    }
+}
+
+impl Drop for ClientCheckCertResolve {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let count = self.query_count.load(Ordering::SeqCst);
+            assert_eq!(count, self.expect_queries);
+        }
+    }
+}
+
+impl ResolvesClientCert for ClientCheckCertResolve {
+    fn resolve(
+        &self,
+        root_hint_subjects: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        self.query_count
+            .fetch_add(1, Ordering::SeqCst);
+
+        if sigschemes.is_empty() {
+            panic!("no signature schemes shared by server");
+        }
+
+        assert_eq!(sigschemes, self.expect_sigschemes);
+        assert_eq!(root_hint_subjects, self.expect_root_hint_subjects);
+
+        None
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+fn test_client_cert_resolve(
+    key_type: KeyType,
+    server_config: Arc<ServerConfig>,
+    expected_root_hint_subjects: Vec<Vec<u8>>,
+) {
+    for version in rustls::ALL_VERSIONS {
+        let expected_sigschemes = match version.version {
+            ProtocolVersion::TLSv1_2 => vec![
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ED25519,
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureScheme::RSA_PSS_SHA256,
+                SignatureScheme::RSA_PKCS1_SHA512,
+                SignatureScheme::RSA_PKCS1_SHA384,
+                SignatureScheme::RSA_PKCS1_SHA256,
+            ],
+            ProtocolVersion::TLSv1_3 => vec![
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ED25519,
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureScheme::RSA_PSS_SHA256,
+            ],
+            _ => unreachable!(),
+        };
+
+        println!("{:?} {:?}:", version.version, key_type);
+
+        let mut client_config = make_client_config_with_versions(key_type, &[version]);
+        client_config.client_auth_cert_resolver = Arc::new(ClientCheckCertResolve::new(
+            1,
+            expected_root_hint_subjects.clone(),
+            expected_sigschemes,
+        ));
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
+        );
+    }
+}
+
+#[test]
+fn client_cert_resolve_default() {
+    // Test that in the default configuration that a client cert resolver gets the expected
+    // CA subject hints, and supported signature algorithms.
+    for key_type in ALL_KEY_TYPES.into_iter() {
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(key_type));
+
+        // In a default configuration we expect that the verifier's trust anchors are used
+        // for the hint subjects.
+        let expected_root_hint_subjects = vec![match key_type {
+            KeyType::Rsa => &b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponytown RSA CA"[..],
+            KeyType::Ecdsa => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown ECDSA CA"[..],
+            KeyType::Ed25519 => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown EdDSA CA"[..],
+        }
+        .to_vec()];
+
+        test_client_cert_resolve(key_type, server_config, expected_root_hint_subjects);
+    }
+}
+
+#[test]
+fn client_cert_resolve_server_no_hints() {
+    // Test that a server can provide no hints and the client cert resolver gets the expected
+    // arguments.
+    for key_type in ALL_KEY_TYPES.into_iter() {
+        // Build a verifier with no hint subjects.
+        let verifier = webpki_client_verifier_builder(get_client_root_store(key_type))
+            .clear_root_hint_subjects();
+        let server_config = make_server_config_with_client_verifier(key_type, verifier);
+        let expected_root_hint_subjects = Vec::default(); // no hints expected.
+        test_client_cert_resolve(key_type, server_config.into(), expected_root_hint_subjects);
+    }
+}
+
+#[test]
+fn client_cert_resolve_server_added_hint() {
+    // Test that a server can add an extra subject above/beyond those found in its trust store
+    // and the client cert resolver gets the expected arguments.
+    let extra_name = b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponyland IDK CA".to_vec();
+    for key_type in ALL_KEY_TYPES.into_iter() {
+        let expected_hint_subjects = vec![
+            match key_type {
+                KeyType::Rsa => &b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponytown RSA CA"[..],
+                KeyType::Ecdsa => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown ECDSA CA"[..],
+                KeyType::Ed25519 => {
+                    &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown EdDSA CA"[..]
+                }
+            }
+            .to_vec(),
+            extra_name.clone(),
+        ];
+        // Create a verifier that adds the extra_name as a hint subject in addition to the ones
+        // from the root cert store.
+        let verifier = webpki_client_verifier_builder(get_client_root_store(key_type))
+            .add_root_hint_subjects([DistinguishedName::from(extra_name.clone())].into_iter());
+        let server_config = make_server_config_with_client_verifier(key_type, verifier);
+        test_client_cert_resolve(key_type, server_config.into(), expected_hint_subjects);
+    }
+}
 
-            // positive ipv6 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::1"),
-                Ok(()),
-            );
+#[test]
+fn client_auth_works() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
 
-            // negative ipv6 case
-            assert_eq!(
-                check_server_name(client_config.clone(), server_config.clone(), "2001:db8::2"),
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::NotValidForName
-                )))
-            );
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
         }
     }
 }
 
 #[test]
-fn client_check_server_certificate_ee_revoked() {
+fn client_mandatory_auth_client_revocation_works() {
     for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
+        // Create a server configuration that includes a CRL that specifies the client certificate
+        // is revoked.
+        let relevant_crls = vec![kt.client_crl()];
+        // Only check the EE certificate status. See client_mandatory_auth_intermediate_revocation_works
+        // for testing revocation status of the whole chain.
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(relevant_crls)
+            .only_check_end_entity_revocation();
+        let revoked_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_verifier_builder,
+        ));
 
-        // Setup a server verifier that will check the EE certificate's revocation status.
-        let crls = vec![kt.end_entity_crl()];
-        let builder = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls)
+        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
+        // and uses the default behaviour of treating unknown revocation status as an error.
+        let unrelated_crls = vec![kt.intermediate_crl()];
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
             .only_check_end_entity_revocation();
+        let missing_client_crl_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_verifier_builder,
+        ));
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_verifier(&[version], builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
+        // but change the builder to allow unknown revocation status.
+        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(unrelated_crls.clone())
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status();
+        let allow_missing_client_crl_server_config = Arc::new(
+            make_server_config_with_client_verifier(*kt, ee_verifier_builder),
+        );
 
-            // We expect the handshake to fail since the server's EE certificate is revoked.
+        for version in rustls::ALL_VERSIONS {
+            // Connecting to the server with a CRL that indicates the client certificate is revoked
+            // should fail with the expected error.
+            let client_config =
+                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &revoked_server_config);
             let err = do_handshake_until_error(&mut client, &mut server);
             assert_eq!(
                 err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
                     CertificateError::Revoked
                 )))
             );
+            // Connecting to the server missing CRL information for the client certificate should
+            // fail with the expected unknown revocation status error.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &missing_client_crl_server_config);
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(matches!(
+                res,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::UnknownRevocationStatus
+                )))
+            ));
+            // Connecting to the server missing CRL information for the client should not error
+            // if the server's verifier allows unknown revocation status.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &allow_missing_client_crl_server_config);
+            let res = do_handshake_until_error(&mut client, &mut server);
+            assert!(res.is_ok());
         }
     }
 }
 
 #[test]
-fn client_check_server_certificate_ee_unknown_revocation() {
+fn client_mandatory_auth_intermediate_revocation_works() {
     for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
-
-        // Setup a server verifier builder that will check the EE certificate's revocation status, but not
-        // allow unknown revocation status (the default). We'll provide CRLs that are not relevant
-        // to the EE cert to ensure its status is unknown.
-        let unrelated_crls = vec![kt.intermediate_crl()];
-        let forbid_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation();
+        // Create a server configuration that includes a CRL that specifies the intermediate certificate
+        // is revoked. We check the full chain for revocation status (default), and allow unknown
+        // revocation status so the EE's unknown revocation status isn't an error.
+        let crls = vec![kt.intermediate_crl()];
+        let full_chain_verifier_builder =
+            webpki_client_verifier_builder(get_client_root_store(*kt))
+                .with_crls(crls.clone())
+                .allow_unknown_revocation_status();
+        let full_chain_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            full_chain_verifier_builder,
+        ));
 
-        // Also set up a verifier builder that will allow unknown revocation status.
-        let allow_unknown_verifier = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls)
+        // Also create a server configuration that uses the same CRL, but that only checks the EE
+        // cert revocation status.
+        let ee_only_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
+            .with_crls(crls)
             .only_check_end_entity_revocation()
             .allow_unknown_revocation_status();
+        let ee_server_config = Arc::new(make_server_config_with_client_verifier(
+            *kt,
+            ee_only_verifier_builder,
+        ));
 
         for version in rustls::ALL_VERSIONS {
+            // When checking the full chain, we expect an error - the intermediate is revoked.
             let client_config =
-                make_client_config_with_verifier(&[version], forbid_unknown_verifier.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-
-            // We expect if we use the forbid_unknown_verifier that the handshake will fail since the
-            // server's EE certificate's revocation status is unknown given the CRLs we've provided.
+                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &full_chain_server_config);
             let err = do_handshake_until_error(&mut client, &mut server);
-            assert!(matches!(
+            assert_eq!(
                 err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::UnknownRevocationStatus
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
                 )))
-            ));
+            );
+            // However, when checking just the EE cert we expect no error - the intermediate's
+            // revocation status should not be checked.
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &ee_server_config);
+            assert!(do_handshake_until_error(&mut client, &mut server).is_ok());
+        }
+    }
+}
 
-            // We expect if we use the allow_unknown_verifier that the handshake will not fail.
-            let client_config =
-                make_client_config_with_verifier(&[version], allow_unknown_verifier.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok());
+#[test]
+fn client_optional_auth_client_revocation_works() {
+    for kt in ALL_KEY_TYPES.iter() {
+        // Create a server configuration that includes a CRL that specifies the client certificate
+        // is revoked.
+        let crls = vec![kt.client_crl()];
+        let server_config = Arc::new(make_server_config_with_optional_client_auth(*kt, crls));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            // Because the client certificate is revoked, the handshake should fail.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
         }
     }
 }
 
 #[test]
-fn client_check_server_certificate_intermediate_revoked() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config(*kt));
-
-        // Setup a server verifier builder that will check the full chain revocation status against a CRL
-        // that marks the intermediate certificate as revoked. We allow unknown revocation status
-        // so the EE cert's unknown status doesn't cause an error.
-        let crls = vec![kt.intermediate_crl()];
-        let full_chain_verifier_builder =
-            webpki_server_verifier_builder(get_client_root_store(*kt))
-                .with_crls(crls.clone())
-                .allow_unknown_revocation_status();
-
-        // Also set up a verifier builder that will use the same CRL, but only check the EE certificate
-        // revocation status.
-        let ee_verifier_builder = webpki_server_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls.clone())
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
+fn client_error_is_sticky() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    client
+        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
+        .unwrap();
+    let mut err = client.process_new_packets();
+    assert!(err.is_err());
+    err = client.process_new_packets();
+    assert!(err.is_err());
+}
+
+#[test]
+fn server_error_is_sticky() {
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    server
+        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
+        .unwrap();
+    let mut err = server.process_new_packets();
+    assert!(err.is_err());
+    err = server.process_new_packets();
+    assert!(err.is_err());
+}
+
+#[test]
+fn server_rejects_content_types_outside_allow_list() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        do_handshake(&mut client, &mut server);
+
+        server.accept_content_types(Some(vec![ContentType::Handshake]));
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::InappropriateMessage {
+                expect_types: vec![ContentType::Handshake],
+                got_type: ContentType::ApplicationData,
+            })
+        );
+        transfer(&mut server, &mut client);
+        assert_eq!(
+            client.process_new_packets(),
+            Err(Error::AlertReceived(AlertDescription::UnexpectedMessage))
+        );
+    }
+}
+
+#[test]
+fn server_accepts_content_types_in_allow_list() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+
+    server.accept_content_types(Some(vec![ContentType::ApplicationData, ContentType::Alert]));
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
+}
+
+#[test]
+fn client_can_send_arbitrary_fatal_alert() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config =
-                make_client_config_with_verifier(&[version], full_chain_verifier_builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    client
+        .dangerous_send_alert(AlertLevel::Fatal, AlertDescription::DecodeError)
+        .unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    // the alert is sent in plaintext, straight after the ClientHello
+    assert!(buf.ends_with(&[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x32]));
 
-            // We expect the handshake to fail when using the full chain verifier since the intermediate's
-            // EE certificate is revoked.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Client(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
-            );
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertSent(AlertDescription::DecodeError))
+    );
+    assert_eq!(
+        client.dangerous_send_alert(AlertLevel::Fatal, AlertDescription::DecodeError),
+        Err(Error::AlertSent(AlertDescription::DecodeError))
+    );
 
-            let client_config =
-                make_client_config_with_verifier(&[version], ee_verifier_builder.clone());
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-            // We expect the handshake to succeed when we use the verifier that only checks the EE certificate
-            // revocation status. The revoked intermediate status should not be checked.
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok())
-        }
-    }
+    server
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::DecodeError))
+    );
 }
 
-/// Simple smoke-test of the webpki verify_server_cert_signed_by_trust_anchor helper API.
-/// This public API is intended to be used by consumers implementing their own verifier and
-/// so isn't used by the other existing verifier tests.
+#[cfg(feature = "tls12")]
 #[test]
-fn client_check_server_certificate_helper_api() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let chain = kt.get_chain();
-        let correct_roots = get_client_root_store(*kt);
-        let incorrect_roots = get_client_root_store(match kt {
-            KeyType::Rsa => KeyType::Ecdsa,
-            _ => KeyType::Rsa,
-        });
-        // Using the correct trust anchors, we should verify without error.
-        assert!(verify_server_cert_signed_by_trust_anchor(
-            &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
-            &correct_roots,
-            &[chain.get(1).unwrap().clone()],
-            UnixTime::now(),
-            webpki::ALL_VERIFICATION_ALGS,
-        )
-        .is_ok());
-        // Using the wrong trust anchors, we should get the expected error.
-        assert_eq!(
-            verify_server_cert_signed_by_trust_anchor(
-                &ParsedCertificate::try_from(chain.first().unwrap()).unwrap(),
-                &incorrect_roots,
-                &[chain.get(1).unwrap().clone()],
-                UnixTime::now(),
-                webpki::ALL_VERIFICATION_ALGS,
-            )
-            .unwrap_err(),
-            Error::InvalidCertificate(CertificateError::UnknownIssuer)
-        );
-    }
+fn server_can_send_arbitrary_warning_alert_after_handshake() {
+    let client_observer = Arc::new(RecordingAlertObserver::default());
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config.alert_observer = Some(client_observer.clone());
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+
+    server
+        .dangerous_send_alert(AlertLevel::Warning, AlertDescription::UserCanceled)
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert_eq!(
+        *client_observer.alerts.lock().unwrap(),
+        vec![(None, false, AlertDescription::UserCanceled)]
+    );
+
+    // a warning alert leaves both sides usable
+    server.process_new_packets().unwrap();
+    server
+        .writer()
+        .write_all(b"still here")
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), b"still here");
 }
 
-#[derive(Debug)]
-struct ClientCheckCertResolve {
-    query_count: AtomicUsize,
-    expect_queries: usize,
-    expect_root_hint_subjects: Vec<Vec<u8>>,
-    expect_sigschemes: Vec<SignatureScheme>,
+/// Connection id, whether the alert was sent (rather than received), and the alert.
+type ObservedAlert = (Option<Vec<u8>>, bool, AlertDescription);
+
+#[derive(Debug, Default)]
+struct RecordingAlertObserver {
+    alerts: Mutex<Vec<ObservedAlert>>,
 }
 
-impl ClientCheckCertResolve {
-    fn new(
-        expect_queries: usize,
-        expect_root_hint_subjects: Vec<Vec<u8>>,
-        expect_sigschemes: Vec<SignatureScheme>,
-    ) -> Self {
-        Self {
-            query_count: AtomicUsize::new(0),
-            expect_queries,
-            expect_root_hint_subjects,
-            expect_sigschemes,
-        }
+impl rustls::ObservesAlerts for RecordingAlertObserver {
+    fn alert_sent(&self, connection_id: Option<&[u8]>, description: AlertDescription) {
+        self.alerts
+            .lock()
+            .unwrap()
+            .push((connection_id.map(|id| id.to_vec()), true, description));
     }
-}
 
-impl Drop for ClientCheckCertResolve {
-    fn drop(&mut self) {
-        if !std::thread::panicking() {
-            let count = self.query_count.load(Ordering::SeqCst);
-            assert_eq!(count, self.expect_queries);
-        }
+    fn alert_received(&self, connection_id: Option<&[u8]>, description: AlertDescription) {
+        self.alerts
+            .lock()
+            .unwrap()
+            .push((connection_id.map(|id| id.to_vec()), false, description));
     }
 }
 
-impl ResolvesClientCert for ClientCheckCertResolve {
-    fn resolve(
-        &self,
-        root_hint_subjects: &[&[u8]],
-        sigschemes: &[SignatureScheme],
-    ) -> Option<Arc<sign::CertifiedKey>> {
-        self.query_count
-            .fetch_add(1, Ordering::SeqCst);
+#[test]
+fn alert_observers_are_given_connection_id() {
+    let client_observer = Arc::new(RecordingAlertObserver::default());
+    let server_observer = Arc::new(RecordingAlertObserver::default());
 
-        if sigschemes.is_empty() {
-            panic!("no signature schemes shared by server");
-        }
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.alert_observer = Some(client_observer.clone());
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.alert_observer = Some(server_observer.clone());
 
-        assert_eq!(sigschemes, self.expect_sigschemes);
-        assert_eq!(root_hint_subjects, self.expect_root_hint_subjects);
+    let mut client = ClientConnection::new_with_connection_id(
+        Arc::new(client_config),
+        server_name("localhost"),
+        b"client-1".to_vec(),
+    )
+    .unwrap();
+    let mut server =
+        ServerConnection::new_with_connection_id(Arc::new(server_config), b"server-1".to_vec())
+            .unwrap();
+    assert_eq!(client.connection_id(), Some(&b"client-1"[..]));
+    assert_eq!(server.connection_id(), Some(&b"server-1"[..]));
 
-        None
-    }
+    do_handshake(&mut client, &mut server);
+    client.send_close_notify();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
 
-    fn has_certs(&self) -> bool {
-        true
+    assert_eq!(
+        *client_observer.alerts.lock().unwrap(),
+        vec![(
+            Some(b"client-1".to_vec()),
+            true,
+            AlertDescription::CloseNotify
+        )]
+    );
+    assert_eq!(
+        *server_observer.alerts.lock().unwrap(),
+        vec![(
+            Some(b"server-1".to_vec()),
+            false,
+            AlertDescription::CloseNotify
+        )]
+    );
+}
+
+#[derive(Debug, Default)]
+struct RecordingHandshakeObserver {
+    messages: Mutex<Vec<(HandshakeType, Vec<u8>)>>,
+}
+
+impl rustls::ObservesHandshakeMessages for RecordingHandshakeObserver {
+    fn handshake_message(&self, typ: HandshakeType, encoding: &[u8]) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push((typ, encoding.to_vec()));
     }
 }
 
-fn test_client_cert_resolve(
-    key_type: KeyType,
-    server_config: Arc<ServerConfig>,
-    expected_root_hint_subjects: Vec<Vec<u8>>,
-) {
+#[test]
+fn handshake_observers_see_transcript_in_order() {
     for version in rustls::ALL_VERSIONS {
-        let expected_sigschemes = match version.version {
+        let client_observer = Arc::new(RecordingHandshakeObserver::default());
+        let server_observer = Arc::new(RecordingHandshakeObserver::default());
+
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.on_handshake_message = Some(client_observer.clone());
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.on_handshake_message = Some(server_observer.clone());
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        let mut client_hello = Vec::new();
+        client
+            .write_tls(&mut client_hello)
+            .unwrap();
+        server
+            .read_tls(&mut &client_hello[..])
+            .unwrap();
+        server.process_new_packets().unwrap();
+        do_handshake(&mut client, &mut server);
+
+        let expected_types = match version.version {
             ProtocolVersion::TLSv1_2 => vec![
-                SignatureScheme::ECDSA_NISTP384_SHA384,
-                SignatureScheme::ECDSA_NISTP256_SHA256,
-                SignatureScheme::ED25519,
-                SignatureScheme::RSA_PSS_SHA512,
-                SignatureScheme::RSA_PSS_SHA384,
-                SignatureScheme::RSA_PSS_SHA256,
-                SignatureScheme::RSA_PKCS1_SHA512,
-                SignatureScheme::RSA_PKCS1_SHA384,
-                SignatureScheme::RSA_PKCS1_SHA256,
+                HandshakeType::ClientHello,
+                HandshakeType::ServerHello,
+                HandshakeType::Certificate,
+                HandshakeType::ServerKeyExchange,
+                HandshakeType::ServerHelloDone,
+                HandshakeType::ClientKeyExchange,
+                HandshakeType::Finished,
+                HandshakeType::Finished,
             ],
-            ProtocolVersion::TLSv1_3 => vec![
-                SignatureScheme::ECDSA_NISTP384_SHA384,
-                SignatureScheme::ECDSA_NISTP256_SHA256,
-                SignatureScheme::ED25519,
-                SignatureScheme::RSA_PSS_SHA512,
-                SignatureScheme::RSA_PSS_SHA384,
-                SignatureScheme::RSA_PSS_SHA256,
+            _ => vec![
+                HandshakeType::ClientHello,
+                HandshakeType::ServerHello,
+                HandshakeType::EncryptedExtensions,
+                HandshakeType::Certificate,
+                HandshakeType::CertificateVerify,
+                HandshakeType::Finished,
+                HandshakeType::Finished,
             ],
-            _ => unreachable!(),
         };
 
-        println!("{:?} {:?}:", version.version, key_type);
+        let client_messages = client_observer.messages.lock().unwrap();
+        let types = client_messages
+            .iter()
+            .map(|(typ, _)| *typ)
+            .collect::<Vec<_>>();
+        assert_eq!(types, expected_types);
+        // the ClientHello record has a five byte header
+        assert_eq!(client_messages[0].1, client_hello[5..]);
+        assert_eq!(*client_messages, *server_observer.messages.lock().unwrap());
+    }
+}
 
-        let mut client_config = make_client_config_with_versions(key_type, &[version]);
-        client_config.client_auth_cert_resolver = Arc::new(ClientCheckCertResolve::new(
-            1,
-            expected_root_hint_subjects.clone(),
-            expected_sigschemes,
-        ));
+#[test]
+fn connection_id_is_absent_by_default() {
+    let (client, server) = make_pair(KeyType::Rsa);
+    assert_eq!(client.connection_id(), None);
+    assert_eq!(server.connection_id(), None);
+}
 
-        let (mut client, mut server) =
-            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+#[derive(Debug, Default)]
+struct ConnectionIdKeyLog {
+    items: Mutex<Vec<(String, Option<Vec<u8>>)>>,
+}
 
-        assert_eq!(
-            do_handshake_until_error(&mut client, &mut server),
-            Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
-        );
+impl KeyLog for ConnectionIdKeyLog {
+    fn log(&self, _label: &str, _client_random: &[u8], _secret: &[u8]) {
+        unreachable!("log_for_connection is overridden");
+    }
+
+    fn log_for_connection(
+        &self,
+        label: &str,
+        _client_random: &[u8],
+        connection_id: Option<&[u8]>,
+        _secret: &[u8],
+    ) {
+        self.items
+            .lock()
+            .unwrap()
+            .push((label.into(), connection_id.map(|id| id.to_vec())));
     }
 }
 
 #[test]
-fn client_cert_resolve_default() {
-    // Test that in the default configuration that a client cert resolver gets the expected
-    // CA subject hints, and supported signature algorithms.
-    for key_type in ALL_KEY_TYPES.into_iter() {
-        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(key_type));
+fn key_logs_are_given_connection_id() {
+    for version in rustls::ALL_VERSIONS {
+        let client_key_log = Arc::new(ConnectionIdKeyLog::default());
+        let server_key_log = Arc::new(ConnectionIdKeyLog::default());
 
-        // In a default configuration we expect that the verifier's trust anchors are used
-        // for the hint subjects.
-        let expected_root_hint_subjects = vec![match key_type {
-            KeyType::Rsa => &b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponytown RSA CA"[..],
-            KeyType::Ecdsa => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown ECDSA CA"[..],
-            KeyType::Ed25519 => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown EdDSA CA"[..],
-        }
-        .to_vec()];
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.key_log = client_key_log.clone();
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.key_log = server_key_log.clone();
+
+        let mut client = ClientConnection::new_with_connection_id(
+            Arc::new(client_config),
+            server_name("localhost"),
+            b"client-1".to_vec(),
+        )
+        .unwrap();
+        let mut server =
+            ServerConnection::new_with_connection_id(Arc::new(server_config), b"server-1".to_vec())
+                .unwrap();
+        do_handshake(&mut client, &mut server);
 
-        test_client_cert_resolve(key_type, server_config, expected_root_hint_subjects);
+        let client_items = client_key_log.items.lock().unwrap();
+        let server_items = server_key_log.items.lock().unwrap();
+        assert!(!client_items.is_empty());
+        assert!(!server_items.is_empty());
+        assert!(client_items
+            .iter()
+            .all(|(_, id)| id.as_deref() == Some(&b"client-1"[..])));
+        assert!(server_items
+            .iter()
+            .all(|(_, id)| id.as_deref() == Some(&b"server-1"[..])));
     }
 }
 
 #[test]
-fn client_cert_resolve_server_no_hints() {
-    // Test that a server can provide no hints and the client cert resolver gets the expected
-    // arguments.
-    for key_type in ALL_KEY_TYPES.into_iter() {
-        // Build a verifier with no hint subjects.
-        let verifier = webpki_client_verifier_builder(get_client_root_store(key_type))
-            .clear_root_hint_subjects();
-        let server_config = make_server_config_with_client_verifier(key_type, verifier);
-        let expected_root_hint_subjects = Vec::default(); // no hints expected.
-        test_client_cert_resolve(key_type, server_config.into(), expected_root_hint_subjects);
-    }
+fn acceptor_connections_carry_connection_id() {
+    let server_key_log = Arc::new(ConnectionIdKeyLog::default());
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.key_log = server_key_log.clone();
+
+    let mut client = ClientConnection::new(
+        Arc::new(make_client_config(KeyType::Rsa)),
+        server_name("localhost"),
+    )
+    .unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = rustls::server::Acceptor::new_with_connection_id(b"server-1".to_vec());
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+    let mut server = accepted
+        .into_connection(Arc::new(server_config))
+        .unwrap();
+    assert_eq!(server.connection_id(), Some(&b"server-1"[..]));
+
+    do_handshake(&mut client, &mut server);
+    let server_items = server_key_log.items.lock().unwrap();
+    assert!(!server_items.is_empty());
+    assert!(server_items
+        .iter()
+        .all(|(_, id)| id.as_deref() == Some(&b"server-1"[..])));
 }
 
-#[test]
-fn client_cert_resolve_server_added_hint() {
-    // Test that a server can add an extra subject above/beyond those found in its trust store
-    // and the client cert resolver gets the expected arguments.
-    let extra_name = b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponyland IDK CA".to_vec();
-    for key_type in ALL_KEY_TYPES.into_iter() {
-        let expected_hint_subjects = vec![
-            match key_type {
-                KeyType::Rsa => &b"0\x1a1\x180\x16\x06\x03U\x04\x03\x0c\x0fponytown RSA CA"[..],
-                KeyType::Ecdsa => &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown ECDSA CA"[..],
-                KeyType::Ed25519 => {
-                    &b"0\x1c1\x1a0\x18\x06\x03U\x04\x03\x0c\x11ponytown EdDSA CA"[..]
-                }
-            }
-            .to_vec(),
-            extra_name.clone(),
-        ];
-        // Create a verifier that adds the extra_name as a hint subject in addition to the ones
-        // from the root cert store.
-        let verifier = webpki_client_verifier_builder(get_client_root_store(key_type))
-            .add_root_hint_subjects([DistinguishedName::from(extra_name.clone())].into_iter());
-        let server_config = make_server_config_with_client_verifier(key_type, verifier);
-        test_client_cert_resolve(key_type, server_config.into(), expected_hint_subjects);
-    }
+fn send_appdata_with_record_version(
+    client: &mut ClientConnection,
+    server: &mut ServerConnection,
+    version: [u8; 2],
+) -> Result<(), Error> {
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    buf[1..3].copy_from_slice(&version);
+    server
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    server.process_new_packets().map(|_| ())
 }
 
 #[test]
-fn client_auth_works() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
+fn server_rejects_tls13_record_with_wrong_version_when_strict() {
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
-            do_handshake(&mut client, &mut server);
-        }
-    }
+    server.set_record_version_policy(RecordVersionPolicy::Reject);
+    // still fine with the correct version
+    send_appdata_with_record_version(&mut client, &mut server, [0x03, 0x03]).unwrap();
+    check_read(&mut server.reader(), b"hello");
+
+    assert_eq!(
+        send_appdata_with_record_version(&mut client, &mut server, [0x03, 0x01]),
+        Err(Error::PeerMisbehaved(PeerMisbehaved::UnexpectedVersion))
+    );
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::IllegalParameter))
+    );
 }
 
 #[test]
-fn client_mandatory_auth_client_revocation_works() {
-    for kt in ALL_KEY_TYPES.iter() {
-        // Create a server configuration that includes a CRL that specifies the client certificate
-        // is revoked.
-        let relevant_crls = vec![kt.client_crl()];
-        // Only check the EE certificate status. See client_mandatory_auth_intermediate_revocation_works
-        // for testing revocation status of the whole chain.
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(relevant_crls)
-            .only_check_end_entity_revocation();
-        let revoked_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_verifier_builder,
-        ));
+fn server_tolerates_tls13_record_with_wrong_version_by_default() {
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
 
-        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
-        // and uses the default behaviour of treating unknown revocation status as an error.
-        let unrelated_crls = vec![kt.intermediate_crl()];
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation();
-        let missing_client_crl_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_verifier_builder,
-        ));
+    send_appdata_with_record_version(&mut client, &mut server, [0x03, 0x01]).unwrap();
+    check_read(&mut server.reader(), b"hello");
+}
 
-        // Create a server configuration that includes a CRL that doesn't cover the client certificate,
-        // but change the builder to allow unknown revocation status.
-        let ee_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(unrelated_crls.clone())
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
-        let allow_missing_client_crl_server_config = Arc::new(
-            make_server_config_with_client_verifier(*kt, ee_verifier_builder),
-        );
+fn appdata_record_len_with_padding(
+    version: &'static rustls::SupportedProtocolVersion,
+    padding: RecordPadding,
+) -> usize {
+    let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+    client_config.record_padding = padding;
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
 
-        for version in rustls::ALL_VERSIONS {
-            // Connecting to the server with a CRL that indicates the client certificate is revoked
-            // should fail with the expected error.
-            let client_config =
-                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &revoked_server_config);
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
-            );
-            // Connecting to the server missing CRL information for the client certificate should
-            // fail with the expected unknown revocation status error.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &missing_client_crl_server_config);
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(matches!(
-                res,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::UnknownRevocationStatus
-                )))
-            ));
-            // Connecting to the server missing CRL information for the client should not error
-            // if the server's verifier allows unknown revocation status.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &allow_missing_client_crl_server_config);
-            let res = do_handshake_until_error(&mut client, &mut server);
-            assert!(res.is_ok());
-        }
-    }
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let len = transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
+    len
 }
 
 #[test]
-fn client_mandatory_auth_intermediate_revocation_works() {
-    for kt in ALL_KEY_TYPES.iter() {
-        // Create a server configuration that includes a CRL that specifies the intermediate certificate
-        // is revoked. We check the full chain for revocation status (default), and allow unknown
-        // revocation status so the EE's unknown revocation status isn't an error.
-        let crls = vec![kt.intermediate_crl()];
-        let full_chain_verifier_builder =
-            webpki_client_verifier_builder(get_client_root_store(*kt))
-                .with_crls(crls.clone())
-                .allow_unknown_revocation_status();
-        let full_chain_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            full_chain_verifier_builder,
-        ));
-
-        // Also create a server configuration that uses the same CRL, but that only checks the EE
-        // cert revocation status.
-        let ee_only_verifier_builder = webpki_client_verifier_builder(get_client_root_store(*kt))
-            .with_crls(crls)
-            .only_check_end_entity_revocation()
-            .allow_unknown_revocation_status();
-        let ee_server_config = Arc::new(make_server_config_with_client_verifier(
-            *kt,
-            ee_only_verifier_builder,
-        ));
+fn fixed_record_padding_lengthens_tls13_records() {
+    let unpadded = appdata_record_len_with_padding(&rustls::version::TLS13, RecordPadding::None);
+    let padded = appdata_record_len_with_padding(&rustls::version::TLS13, RecordPadding::Fixed(16));
+    assert_eq!(padded, unpadded + 16);
 
-        for version in rustls::ALL_VERSIONS {
-            // When checking the full chain, we expect an error - the intermediate is revoked.
-            let client_config =
-                Arc::new(make_client_config_with_versions_with_auth(*kt, &[version]));
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &full_chain_server_config);
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
-            );
-            // However, when checking just the EE cert we expect no error - the intermediate's
-            // revocation status should not be checked.
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&client_config, &ee_server_config);
-            assert!(do_handshake_until_error(&mut client, &mut server).is_ok());
-        }
+    fn pad_to_64(len: usize) -> usize {
+        64 - len
     }
+    let padded = appdata_record_len_with_padding(
+        &rustls::version::TLS13,
+        RecordPadding::Callback(pad_to_64),
+    );
+    assert_eq!(padded, unpadded + 64 - 5);
+
+    let padded = appdata_record_len_with_padding(&rustls::version::TLS13, RecordPadding::UpTo(8));
+    assert!(padded >= unpadded && padded <= unpadded + 8);
 }
 
 #[test]
-fn client_optional_auth_client_revocation_works() {
-    for kt in ALL_KEY_TYPES.iter() {
-        // Create a server configuration that includes a CRL that specifies the client certificate
-        // is revoked.
-        let crls = vec![kt.client_crl()];
-        let server_config = Arc::new(make_server_config_with_optional_client_auth(*kt, crls));
+fn aligned_record_padding_rounds_up_tls13_records() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.record_padding = RecordPadding::AlignTo(256);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
 
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
-            // Because the client certificate is revoked, the handshake should fail.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
-            );
+    for len in [1, 5, 255, 256, 1000] {
+        let data = vec![0x55; len];
+        client
+            .writer()
+            .write_all(&data)
+            .unwrap();
+        let mut buf = Vec::new();
+        client.write_tls(&mut buf).unwrap();
+
+        let mut records = 0;
+        let mut rest = &buf[..];
+        while !rest.is_empty() {
+            let record_len = usize::from(u16::from_be_bytes([rest[3], rest[4]]));
+            // less the AEAD tag
+            assert_eq!((record_len - 16) % 256, 0);
+            rest = &rest[5 + record_len..];
+            records += 1;
         }
+        assert_eq!(records, 1);
+
+        server.read_tls(&mut &buf[..]).unwrap();
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), &data);
     }
 }
 
+#[cfg(feature = "tls12")]
 #[test]
-fn client_error_is_sticky() {
-    let (mut client, _) = make_pair(KeyType::Rsa);
-    client
-        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
-        .unwrap();
-    let mut err = client.process_new_packets();
-    assert!(err.is_err());
-    err = client.process_new_packets();
-    assert!(err.is_err());
+fn record_padding_does_not_apply_to_tls12() {
+    assert_eq!(
+        appdata_record_len_with_padding(&rustls::version::TLS12, RecordPadding::Fixed(16)),
+        appdata_record_len_with_padding(&rustls::version::TLS12, RecordPadding::None),
+    );
 }
 
 #[test]
-fn server_error_is_sticky() {
-    let (_, mut server) = make_pair(KeyType::Rsa);
-    server
-        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
+fn record_padding_is_limited_to_maximum_fragment_length() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.record_padding = RecordPadding::Fixed(1024);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+
+    let data = vec![0x55; 16384];
+    client
+        .writer()
+        .write_all(&data)
         .unwrap();
-    let mut err = server.process_new_packets();
-    assert!(err.is_err());
-    err = server.process_new_packets();
-    assert!(err.is_err());
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    // one record: header, full fragment, content type and tag
+    assert_eq!(buf.len(), 5 + 16384 + 1 + 16);
+
+    let mut rd = buf.as_slice();
+    while !rd.is_empty() {
+        server.read_tls(&mut rd).unwrap();
+        server.process_new_packets().unwrap();
+    }
+    check_read(&mut server.reader(), &data);
 }
 
 #[test]
@@ -1794,6 +3613,147 @@ fn client_respects_buffer_limit_post_handshake() {
     check_read(&mut server.reader(), b"01234567890123456789012345");
 }
 
+#[test]
+fn write_all_records_sends_large_buffer_in_full_records() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        do_handshake(&mut client, &mut server);
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+
+        let data = (0..1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        server.write_all_records(&data);
+
+        let mut tls = Vec::new();
+        server.write_tls(&mut tls).unwrap();
+        assert!(!server.wants_write());
+
+        let mut records = 0;
+        let mut rest = &tls[..];
+        while !rest.is_empty() {
+            assert_eq!(ContentType::from(rest[0]), ContentType::ApplicationData);
+            let len = 5 + usize::from(u16::from_be_bytes([rest[3], rest[4]]));
+            rest = &rest[len..];
+            records += 1;
+        }
+        assert_eq!(records, data.len() / 16384);
+
+        let mut received = Vec::new();
+        let mut rest = &tls[..];
+        while !rest.is_empty() {
+            client.read_tls(&mut rest).unwrap();
+            client.process_new_packets().unwrap();
+            client
+                .reader()
+                .read_to_end(&mut received)
+                .unwrap_err();
+        }
+        assert_eq!(received, data);
+    }
+}
+
+#[test]
+fn client_wants_write_after_flush_record_threshold() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    do_handshake(&mut client, &mut server);
+    assert!(!client.wants_write());
+    client.set_flush_after_records(Some(3));
+
+    for _ in 0..2 {
+        client.writer().write_all(b"a").unwrap();
+        assert!(!client.wants_write());
+    }
+    client.writer().write_all(b"a").unwrap();
+    assert!(client.wants_write());
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"aaa");
+
+    // the count starts again once everything queued has been written
+    client.writer().write_all(b"b").unwrap();
+    assert!(!client.wants_write());
+    client.writer().flush().unwrap();
+    assert!(client.wants_write());
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"b");
+}
+
+#[test]
+fn client_wants_write_after_flush_byte_threshold() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    do_handshake(&mut client, &mut server);
+    client.set_flush_after_bytes(Some(60));
+
+    // each TLS1.3 record here is 23 bytes on the wire
+    for _ in 0..2 {
+        client.writer().write_all(b"a").unwrap();
+        assert!(!client.wants_write());
+    }
+    client.writer().write_all(b"a").unwrap();
+    assert!(client.wants_write());
+
+    // alerts are never held back
+    transfer(&mut client, &mut server);
+    client.send_close_notify();
+    assert!(client.wants_write());
+
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"aaa");
+}
+
+#[test]
+fn client_wants_write_when_buffer_limit_reached_before_flush_threshold() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    do_handshake(&mut client, &mut server);
+    client.set_flush_after_records(Some(100));
+
+    // the default 64KB limit fills up long before 100 full-sized records
+    let data = vec![0x55u8; 100 * 1024];
+    let mut sent = client.writer().write(&data).unwrap();
+    assert!(sent < data.len());
+    assert_eq!(
+        client
+            .writer()
+            .write(&data[sent..])
+            .unwrap(),
+        0
+    );
+    assert!(client.wants_write());
+
+    let mut received = Vec::new();
+    while sent < data.len() || client.wants_write() {
+        while client.wants_write() {
+            let mut records = Vec::new();
+            client.write_tls(&mut records).unwrap();
+            let mut rd = &records[..];
+            while !rd.is_empty() {
+                server.read_tls(&mut rd).unwrap();
+                server.process_new_packets().unwrap();
+                server
+                    .reader()
+                    .read_to_end(&mut received)
+                    .unwrap_err();
+            }
+        }
+        sent += client
+            .writer()
+            .write(&data[sent..])
+            .unwrap();
+        client.writer().flush().unwrap();
+    }
+    assert_eq!(received, data);
+}
+
 struct OtherSession<'a, C, S>
 where
     C: DerefMut + Deref<Target = ConnectionCommon<S>>,
@@ -1827,108 +3787,469 @@ where
         }
     }
 
-    fn new_buffered(sess: &'a mut C) -> OtherSession<'a, C, S> {
-        let mut os = OtherSession::new(sess);
-        os.buffered = true;
-        os
-    }
+    fn new_buffered(sess: &'a mut C) -> OtherSession<'a, C, S> {
+        let mut os = OtherSession::new(sess);
+        os.buffered = true;
+        os
+    }
+
+    fn new_fails(sess: &'a mut C) -> OtherSession<'a, C, S> {
+        let mut os = OtherSession::new(sess);
+        os.fail_ok = true;
+        os
+    }
+
+    fn flush_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let mut total = 0;
+        let mut lengths = vec![];
+        for bytes in b {
+            let write_len = if self.short_writes {
+                if bytes.len() > 5 {
+                    bytes.len() / 2
+                } else {
+                    bytes.len()
+                }
+            } else {
+                bytes.len()
+            };
+
+            let l = self
+                .sess
+                .read_tls(&mut io::Cursor::new(&bytes[..write_len]))?;
+            lengths.push(l);
+            total += l;
+            if bytes.len() != l {
+                break;
+            }
+        }
+
+        let rc = self.sess.process_new_packets();
+        if !self.fail_ok {
+            rc.unwrap();
+        } else if rc.is_err() {
+            self.last_error = rc.err();
+        }
+
+        self.writevs.push(lengths);
+        Ok(total)
+    }
+}
+
+impl<'a, C, S> io::Read for OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn read(&mut self, mut b: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        self.sess.write_tls(b.by_ref())
+    }
+}
+
+impl<'a, C, S> io::Write for OtherSession<'a, C, S>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+    S: SideData,
+{
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        unreachable!()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let buffer = mem::take(&mut self.buffer);
+            let slices = buffer
+                .iter()
+                .map(|b| io::IoSlice::new(b))
+                .collect::<Vec<_>>();
+            self.flush_vectored(&slices)?;
+        }
+        Ok(())
+    }
+
+    fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        if self.buffered {
+            self.buffer
+                .extend(b.iter().map(|s| s.to_vec()));
+            return Ok(b.iter().map(|s| s.len()).sum());
+        }
+        self.flush_vectored(b)
+    }
+}
+
+#[test]
+fn server_read_returns_wouldblock_when_no_data() {
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    assert!(matches!(server.reader().read(&mut [0u8; 1]),
+                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+}
+
+#[test]
+fn client_read_returns_wouldblock_when_no_data() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    assert!(matches!(client.reader().read(&mut [0u8; 1]),
+                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+}
+
+#[test]
+fn server_read_can_preserve_record_boundaries() {
+    for preserve in [false, true] {
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.preserve_record_boundaries = preserve;
+
+        let (mut client, mut server) =
+            make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+        do_handshake(&mut client, &mut server);
+
+        // two writes, so two records
+        client
+            .writer()
+            .write_all(b"abc")
+            .unwrap();
+        client
+            .writer()
+            .write_all(b"def")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+
+        let mut buf = [0u8; 16];
+        if preserve {
+            assert_eq!(server.reader().read(&mut buf).unwrap(), 3);
+            assert_eq!(&buf[..3], b"abc");
+            assert_eq!(server.reader().read(&mut buf).unwrap(), 3);
+            assert_eq!(&buf[..3], b"def");
+        } else {
+            assert_eq!(server.reader().read(&mut buf).unwrap(), 6);
+            assert_eq!(&buf[..6], b"abcdef");
+        }
+    }
+}
+
+#[test]
+fn write_buffer_len_counts_unflushed_records() {
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.write_buffer_len(), 0);
+
+    client
+        .writer()
+        .write_all(&[0u8; 100])
+        .unwrap();
+
+    // header, payload, inner content type, AEAD tag
+    let record_len = 5 + 100 + 1 + 16;
+    assert_eq!(client.write_buffer_len(), record_len);
+    assert_eq!(
+        client
+            .process_new_packets()
+            .unwrap()
+            .tls_bytes_to_write(),
+        record_len
+    );
+
+    let mut buf = Vec::new();
+    assert_eq!(client.write_tls(&mut buf).unwrap(), record_len);
+    assert_eq!(client.write_buffer_len(), 0);
+}
+
+#[derive(Debug, Default)]
+struct RecordingWatermarkObserver {
+    crossings: Mutex<Vec<(&'static str, usize)>>,
+}
+
+impl rustls::ObservesReadWatermarks for RecordingWatermarkObserver {
+    fn high_watermark_reached(&self, buffered: usize) {
+        self.crossings
+            .lock()
+            .unwrap()
+            .push(("high", buffered));
+    }
+
+    fn low_watermark_reached(&self, buffered: usize) {
+        self.crossings
+            .lock()
+            .unwrap()
+            .push(("low", buffered));
+    }
+}
+
+#[test]
+fn read_watermarks_report_crossings() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+
+    let observer = Arc::new(RecordingWatermarkObserver::default());
+    assert!(server
+        .set_read_watermarks(200, 100)
+        .is_err());
+    server
+        .set_read_watermarks(100, 200)
+        .unwrap();
+    server.set_read_watermark_observer(Some(observer.clone()));
+
+    client
+        .writer()
+        .write_all(&[0u8; 150])
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert!(observer
+        .crossings
+        .lock()
+        .unwrap()
+        .is_empty());
+
+    client
+        .writer()
+        .write_all(&[0u8; 150])
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert_eq!(*observer.crossings.lock().unwrap(), vec![("high", 300)]);
+
+    let mut buf = [0u8; 150];
+    server
+        .reader()
+        .read_exact(&mut buf[..100])
+        .unwrap();
+    assert_eq!(observer.crossings.lock().unwrap().len(), 1);
+
+    server
+        .reader()
+        .read_exact(&mut buf[..100])
+        .unwrap();
+    assert_eq!(
+        *observer.crossings.lock().unwrap(),
+        vec![("high", 300), ("low", 100)]
+    );
+}
+
+#[test]
+fn heartbeat_round_trip() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.heartbeat_mode = Some(HeartbeatMode::PeerAllowedToSend);
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.heartbeat_mode = Some(HeartbeatMode::PeerNotAllowedToSend);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
 
-    fn new_fails(sess: &'a mut C) -> OtherSession<'a, C, S> {
-        let mut os = OtherSession::new(sess);
-        os.fail_ok = true;
-        os
+        // The server refused to receive heartbeats, but may send them.
+        assert!(client.send_heartbeat(b"ping").is_err());
+        server.send_heartbeat(b"ping").unwrap();
+        assert!(server.heartbeat_pending());
+        assert!(server.send_heartbeat(b"ping").is_err());
+
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        assert!(!server.heartbeat_pending());
+        server.send_heartbeat(b"pong").unwrap();
     }
+}
 
-    fn flush_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        let mut total = 0;
-        let mut lengths = vec![];
-        for bytes in b {
-            let write_len = if self.short_writes {
-                if bytes.len() > 5 {
-                    bytes.len() / 2
-                } else {
-                    bytes.len()
-                }
-            } else {
-                bytes.len()
-            };
+#[test]
+fn heartbeat_response_must_match_request() {
+    let key_log = Arc::new(KeyLogToVec::new("client"));
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.heartbeat_mode = Some(HeartbeatMode::PeerAllowedToSend);
+    client_config.key_log = key_log.clone();
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.heartbeat_mode = Some(HeartbeatMode::PeerAllowedToSend);
+    // so the server's first application traffic record is ours
+    server_config.send_tls13_tickets = 0;
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
 
-            let l = self
-                .sess
-                .read_tls(&mut io::Cursor::new(&bytes[..write_len]))?;
-            lengths.push(l);
-            total += l;
-            if bytes.len() != l {
-                break;
-            }
-        }
+    client.send_heartbeat(b"hello").unwrap();
+    assert!(client.heartbeat_pending());
 
-        let rc = self.sess.process_new_packets();
-        if !self.fail_ok {
-            rc.unwrap();
-        } else if rc.is_err() {
-            self.last_error = rc.err();
-        }
+    // Answer in place of the server, with a shorter payload than was requested.
+    let suite = match client.negotiated_cipher_suite() {
+        Some(SupportedCipherSuite::Tls13(suite)) => suite,
+        _ => unreachable!(),
+    };
+    let secret = key_log
+        .take()
+        .into_iter()
+        .find(|item| item.label == "SERVER_TRAFFIC_SECRET_0")
+        .unwrap()
+        .secret;
+    let (key, iv) = traffic_key_and_iv(suite, &secret);
+    let mut encrypter = suite.aead_alg.encrypter(key, iv);
+
+    let mut response = vec![0x02, 0x00, 0x04];
+    response.extend_from_slice(b"hell");
+    response.extend_from_slice(&[0u8; 16]);
+    let record = encrypter
+        .encrypt(
+            PlainMessage {
+                typ: ContentType::Heartbeat,
+                version: ProtocolVersion::TLSv1_2,
+                payload: Payload::new(response),
+            }
+            .borrow(),
+            0,
+        )
+        .unwrap();
+    client
+        .read_tls(&mut &record.encode()[..])
+        .unwrap();
+    assert_eq!(
+        client.process_new_packets().err(),
+        Some(Error::PeerMisbehaved(
+            PeerMisbehaved::HeartbeatResponseMismatch
+        ))
+    );
+}
 
-        self.writevs.push(lengths);
-        Ok(total)
+/// The number of records the negotiated suite may protect under one key.
+fn key_usage_limit(conn: &ClientConnection) -> u64 {
+    // The default AES-GCM suites' limits are far below the record layer's own
+    // limit just short of sequence number rollover.
+    match conn.negotiated_cipher_suite().unwrap() {
+        SupportedCipherSuite::Tls13(suite) => suite.common.confidentiality_limit,
+        #[cfg(feature = "tls12")]
+        SupportedCipherSuite::Tls12(suite) => suite.common.confidentiality_limit,
     }
 }
 
-impl<'a, C, S> io::Read for OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    fn read(&mut self, mut b: &mut [u8]) -> io::Result<usize> {
-        self.reads += 1;
-        self.sess.write_tls(b.by_ref())
-    }
+fn read_all(
+    conn: &mut (impl DerefMut + Deref<Target = ConnectionCommon<impl SideData>>),
+) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let _ = conn.reader().read_to_end(&mut buf);
+    buf
 }
 
-impl<'a, C, S> io::Write for OtherSession<'a, C, S>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<S>>,
-    S: SideData,
-{
-    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
-        unreachable!()
-    }
+#[test]
+fn tls13_updates_keys_at_confidentiality_limit() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.enable_secret_extraction = true;
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.enable_secret_extraction = true;
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
 
-    fn flush(&mut self) -> io::Result<()> {
-        if !self.buffer.is_empty() {
-            let buffer = mem::take(&mut self.buffer);
-            let slices = buffer
-                .iter()
-                .map(|b| io::IoSlice::new(b))
-                .collect::<Vec<_>>();
-            self.flush_vectored(&slices)?;
-        }
-        Ok(())
-    }
+    let limit = key_usage_limit(&client);
+    advance_client_seq(&mut client, &mut server, limit - 2);
 
-    fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        if self.buffered {
-            self.buffer
-                .extend(b.iter().map(|s| s.to_vec()));
-            return Ok(b.iter().map(|s| s.len()).sum());
-        }
-        self.flush_vectored(b)
+    // This record leaves just the last sequence number allowed for the key,
+    // which carries a KeyUpdate before the write returns.
+    client
+        .writer()
+        .write_all(b"hello ")
+        .unwrap();
+    client
+        .writer()
+        .write_all(b"world")
+        .unwrap();
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert_eq!(read_all(&mut server), b"hello world");
+
+    // "world" was the first record under the new key.
+    let client_tx = client
+        .dangerous_extract_secrets()
+        .unwrap()
+        .tx;
+    let server_rx = server
+        .dangerous_extract_secrets()
+        .unwrap()
+        .rx;
+    assert_eq!((client_tx.0, server_rx.0), (1, 1));
+}
+
+#[test]
+fn tls13_updates_keys_within_a_write() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.enable_secret_extraction = true;
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.enable_secret_extraction = true;
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    let limit = key_usage_limit(&client);
+    advance_client_seq(&mut client, &mut server, limit - 3);
+
+    // Three records' worth: two fit under the old key, then the KeyUpdate
+    // goes out before the third is encrypted under the new key.
+    let data = vec![0x55u8; 3 * 16384];
+    client
+        .writer()
+        .write_all(&data)
+        .unwrap();
+
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let mut rd = buf.as_slice();
+    let mut received = Vec::new();
+    while !rd.is_empty() {
+        server.read_tls(&mut rd).unwrap();
+        server.process_new_packets().unwrap();
+        received.extend(read_all(&mut server));
     }
+    assert_eq!(received, data);
+
+    let client_tx = client
+        .dangerous_extract_secrets()
+        .unwrap()
+        .tx;
+    let server_rx = server
+        .dangerous_extract_secrets()
+        .unwrap()
+        .rx;
+    assert_eq!((client_tx.0, server_rx.0), (1, 1));
 }
 
+#[cfg(feature = "tls12")]
 #[test]
-fn server_read_returns_wouldblock_when_no_data() {
-    let (_, mut server) = make_pair(KeyType::Rsa);
-    assert!(matches!(server.reader().read(&mut [0u8; 1]),
-                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+fn tls12_closes_at_confidentiality_limit() {
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+
+    let limit = key_usage_limit(&client);
+    advance_client_seq(&mut client, &mut server, limit - 2);
+
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+
+    // TLS1.2 cannot update its keys, so the last record allowed for the key
+    // closes the connection instead, and nothing further is encrypted.
+    client
+        .writer()
+        .write_all(b"world")
+        .unwrap();
+    client.writer().write_all(b"!").unwrap();
+
+    transfer(&mut client, &mut server);
+    let io_state = server.process_new_packets().unwrap();
+    assert!(io_state.peer_has_closed());
+    assert_eq!(read_all(&mut server), b"hello");
 }
 
 #[test]
-fn client_read_returns_wouldblock_when_no_data() {
-    let (mut client, _) = make_pair(KeyType::Rsa);
-    assert!(matches!(client.reader().read(&mut [0u8; 1]),
-                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+fn heartbeats_are_not_negotiated_by_default() {
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.heartbeat_mode = Some(HeartbeatMode::PeerAllowedToSend);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+
+    assert!(client.send_heartbeat(b"ping").is_err());
+    assert!(server.send_heartbeat(b"ping").is_err());
 }
 
 #[test]
@@ -2616,7 +4937,7 @@ fn sni_resolver_rejects_wrong_names() {
         )
     );
     assert_eq!(
-        Err(Error::InvalidCertificate(CertificateError::NotValidForName)),
+        Err(Error::InvalidCertificate(name_mismatch("not-localhost"))),
         resolver.add(
             "not-localhost",
             sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
@@ -2631,6 +4952,202 @@ fn sni_resolver_rejects_wrong_names() {
     );
 }
 
+#[test]
+fn mismatched_key_and_certificate_are_rejected_at_build_time() {
+    // The RSA server certificate with the RSA client key.
+    let chain = KeyType::Rsa.get_chain();
+    let key = || KeyType::Rsa.get_client_key();
+
+    let err = server_config_builder()
+        .with_no_client_auth()
+        .with_single_cert(chain.clone(), key())
+        .unwrap_err();
+    assert_eq!(err, Error::InconsistentKeys);
+
+    let err = client_config_builder()
+        .with_root_certificates(get_client_root_store(KeyType::Rsa))
+        .with_client_auth_cert(chain.clone(), key())
+        .unwrap_err();
+    assert_eq!(err, Error::InconsistentKeys);
+
+    let signing_key = provider::default_provider()
+        .key_provider
+        .load_private_key(key())
+        .unwrap();
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    assert_eq!(
+        resolver.add("localhost", sign::CertifiedKey::new(chain, signing_key)),
+        Err(Error::InconsistentKeys)
+    );
+}
+
+/// A signing key which claims its signatures use `scheme`, whatever the peer offered.
+#[derive(Debug)]
+struct MisreportedSchemeKey {
+    inner: Arc<dyn sign::SigningKey>,
+    scheme: SignatureScheme,
+}
+
+impl sign::SigningKey for MisreportedSchemeKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        let inner = self.inner.choose_scheme(offered)?;
+        Some(Box::new(MisreportedSchemeSigner {
+            inner,
+            scheme: self.scheme,
+        }))
+    }
+
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        self.inner.algorithm()
+    }
+}
+
+#[derive(Debug)]
+struct MisreportedSchemeSigner {
+    inner: Box<dyn sign::Signer>,
+    scheme: SignatureScheme,
+}
+
+impl sign::Signer for MisreportedSchemeSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        self.inner.sign(message)
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+#[test]
+fn client_rejects_certificate_verify_with_unoffered_scheme() {
+    let kt = KeyType::Rsa;
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(MisreportedSchemeKey {
+        inner: Arc::new(RsaSigningKey::new(&kt.get_key()).unwrap()),
+        scheme: SignatureScheme::ED448,
+    });
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    resolver
+        .add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key),
+        )
+        .unwrap();
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+
+    let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Client(Error::UnsupportedSignatureScheme {
+            scheme: SignatureScheme::ED448
+        }))
+    );
+    transfer(&mut client, &mut server);
+    assert_eq!(
+        server.process_new_packets().err(),
+        Some(Error::AlertReceived(AlertDescription::IllegalParameter))
+    );
+}
+
+/// A server certificate verifier which checks handshake signatures, but trusts any certificate.
+#[derive(Debug)]
+struct ChecksSignaturesOnly(rustls::crypto::WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for ChecksSignaturesOnly {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+#[test]
+fn client_rejects_certificate_with_unknown_key_algorithm() {
+    // Swap the end-entity certificate's id-ecPublicKey OID for an unallocated one.
+    const EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+    let kt = KeyType::Ecdsa;
+    let mut chain = kt.get_chain();
+    let mut end_entity = chain[0].as_ref().to_vec();
+    let offset = end_entity
+        .windows(EC_PUBLIC_KEY.len())
+        .position(|w| w == EC_PUBLIC_KEY)
+        .unwrap();
+    end_entity[offset + EC_PUBLIC_KEY.len() - 1] = 0x7f;
+    chain[0] = CertificateDer::from(end_entity);
+
+    let signing_key = provider::default_provider()
+        .key_provider
+        .load_private_key(kt.get_key())
+        .unwrap();
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(FixedServerCert(Arc::new(sign::CertifiedKey::new(
+        chain,
+        signing_key,
+    ))));
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(ChecksSignaturesOnly(
+                provider::default_provider().signature_verification_algorithms,
+            )));
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert_eq!(
+            err,
+            Err(ErrorFromPeer::Client(Error::UnsupportedKeyAlgorithm))
+        );
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets().err(),
+            Some(Error::AlertReceived(
+                AlertDescription::UnsupportedCertificate
+            ))
+        );
+    }
+}
+
+#[derive(Debug)]
+struct FixedServerCert(Arc<sign::CertifiedKey>);
+
+impl ResolvesServerCert for FixedServerCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        Some(Arc::clone(&self.0))
+    }
+}
+
 #[test]
 fn sni_resolver_lower_cases_configured_names() {
     let kt = KeyType::Rsa;
@@ -2709,6 +5226,108 @@ fn sni_resolver_rejects_bad_certs() {
     );
 }
 
+#[cfg(feature = "tls12")]
+#[test]
+fn sig_schemes_resolver_chooses_compatible_chain() {
+    let mut resolver = rustls::server::ResolvesServerCertUsingSigSchemes::new();
+    for kt in [KeyType::Rsa, KeyType::Ecdsa] {
+        let signing_key = provider::default_provider()
+            .key_provider
+            .load_private_key(kt.get_key())
+            .unwrap();
+        resolver
+            .add(sign::CertifiedKey::new(kt.get_chain(), signing_key))
+            .unwrap();
+    }
+
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    // the client's offered signature schemes prefer ECDSA
+    let client_config = Arc::new(make_client_config(KeyType::Ecdsa));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client.peer_certificates().unwrap()[0],
+        KeyType::Ecdsa.get_chain()[0]
+    );
+
+    // this client can only use RSA
+    let client_config = Arc::new(finish_client_config(
+        KeyType::Rsa,
+        ClientConfig::builder_with_provider(
+            CryptoProvider {
+                cipher_suites: vec![cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256],
+                ..provider::default_provider()
+            }
+            .into(),
+        )
+        .with_protocol_versions(&[&rustls::version::TLS12])
+        .unwrap(),
+    ));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client.peer_certificates().unwrap()[0],
+        KeyType::Rsa.get_chain()[0]
+    );
+}
+
+#[test]
+fn issuers_resolver_chooses_client_chain_under_accepted_ca() {
+    for accepted in [KeyType::Rsa, KeyType::Ecdsa] {
+        let mut resolver = rustls::client::ResolvesClientCertUsingIssuers::new();
+        for kt in [KeyType::Rsa, KeyType::Ecdsa] {
+            let signing_key = provider::default_provider()
+                .key_provider
+                .load_private_key(kt.get_client_key())
+                .unwrap();
+            resolver
+                .add(
+                    kt.get_chain().last().unwrap(),
+                    sign::CertifiedKey::new(kt.get_client_chain(), signing_key),
+                )
+                .unwrap();
+        }
+        let resolver = Arc::new(resolver);
+
+        let server_config = Arc::new(make_server_config_with_client_verifier(
+            KeyType::Rsa,
+            webpki_client_verifier_builder(get_client_root_store(accepted)),
+        ));
+
+        for version in rustls::ALL_VERSIONS {
+            let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+            client_config.client_auth_cert_resolver = resolver.clone();
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
+
+            assert_eq!(
+                server.peer_certificates().unwrap()[0],
+                accepted.get_client_chain()[0]
+            );
+        }
+    }
+}
+
+#[test]
+fn issuers_resolver_rejects_mismatched_key() {
+    let mut resolver = rustls::client::ResolvesClientCertUsingIssuers::new();
+    let signing_key = provider::default_provider()
+        .key_provider
+        .load_private_key(KeyType::Ecdsa.get_client_key())
+        .unwrap();
+    assert_eq!(
+        resolver.add(
+            KeyType::Rsa.get_chain().last().unwrap(),
+            sign::CertifiedKey::new(KeyType::Rsa.get_client_chain(), signing_key),
+        ),
+        Err(Error::InconsistentKeys)
+    );
+}
+
 fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
     let mut client_secret = [0u8; 64];
     let mut server_secret = [0u8; 64];
@@ -2782,6 +5401,97 @@ fn test_tls13_exporter() {
     }
 }
 
+#[test]
+fn peers_agree_on_finished_verify_data() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(client.finished_verify_data(), None);
+        assert_eq!(server.finished_verify_data(), None);
+        do_handshake(&mut client, &mut server);
+
+        let (client_local, client_peer) = client.finished_verify_data().unwrap();
+        let (server_local, server_peer) = server.finished_verify_data().unwrap();
+        assert_eq!(client_local, server_peer);
+        assert_eq!(client_peer, server_local);
+        assert_ne!(client_local, client_peer);
+
+        let expected_len = match version.version {
+            ProtocolVersion::TLSv1_2 => 12,
+            // The output length of SHA-384, for the default TLS13_AES_256_GCM_SHA384 suite.
+            _ => 48,
+        };
+        assert_eq!(client_local.len(), expected_len);
+        assert_eq!(client_peer.len(), expected_len);
+    }
+}
+
+#[test]
+fn peers_agree_on_resumption_master_secret() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.enable_secret_extraction = true;
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.enable_secret_extraction = true;
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(client.resumption_master_secret(), None);
+        assert_eq!(server.resumption_master_secret(), None);
+        do_handshake(&mut client, &mut server);
+
+        let client_secret = client.resumption_master_secret();
+        match version.version {
+            ProtocolVersion::TLSv1_2 => assert_eq!(client_secret, None),
+            _ => {
+                // The output length of SHA-384, for the default TLS13_AES_256_GCM_SHA384 suite.
+                assert_eq!(client_secret.as_ref().map(Vec::len), Some(48));
+            }
+        }
+        assert_eq!(client_secret, server.resumption_master_secret());
+
+        // receiving tickets does not change it
+        server.send_close_notify();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        assert_eq!(client.resumption_master_secret(), client_secret);
+    }
+}
+
+#[test]
+fn resumption_master_secret_requires_secret_extraction() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(client.resumption_master_secret(), None);
+    assert_eq!(server.resumption_master_secret(), None);
+}
+
+#[test]
+fn negotiated_parameters_report_shared_secret_len() {
+    for version in rustls::ALL_VERSIONS {
+        // X25519 is the most preferred group by default
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(
+            client
+                .negotiated_parameters()
+                .shared_secret_len,
+            None
+        );
+        do_handshake(&mut client, &mut server);
+
+        for params in [
+            client.negotiated_parameters(),
+            server.negotiated_parameters(),
+        ] {
+            assert_eq!(params.protocol_version, Some(version.version));
+            assert_eq!(params.key_exchange_group, Some(rustls::NamedGroup::X25519));
+            assert_eq!(params.shared_secret_len, Some(32));
+        }
+    }
+}
+
 #[test]
 fn test_tls13_exporter_maximum_output_length() {
     let client_config =
@@ -3007,6 +5717,71 @@ fn negotiated_ciphersuite_server() {
     }
 }
 
+#[derive(Debug)]
+struct WithdrawSuite {
+    withdrawn: CipherSuite,
+    seen: Mutex<Vec<CipherSuite>>,
+}
+
+impl rustls::server::FiltersCipherSuites for WithdrawSuite {
+    fn filter(&self, acceptable: &[CipherSuite]) -> Vec<CipherSuite> {
+        *self.seen.lock().unwrap() = acceptable.to_vec();
+        acceptable
+            .iter()
+            .copied()
+            .filter(|suite| *suite != self.withdrawn)
+            .collect()
+    }
+}
+
+#[test]
+fn server_filter_withdraws_clients_preferred_suite() {
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    let client_prefs = client_config.advertised_cipher_suites();
+
+    let filter = Arc::new(WithdrawSuite {
+        withdrawn: client_prefs[0],
+        seen: Mutex::new(Vec::new()),
+    });
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.filter_cipher_suites = Some(filter.clone());
+
+    do_suite_test(
+        client_config,
+        server_config,
+        find_suite(client_prefs[1]),
+        ProtocolVersion::TLSv1_3,
+    );
+    assert!(filter
+        .seen
+        .lock()
+        .unwrap()
+        .contains(&client_prefs[0]));
+}
+
+#[test]
+fn server_filter_removing_all_suites_fails_handshake() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.filter_cipher_suites = Some(Arc::new(WithdrawAll));
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::NoCipherSuitesInCommon
+        )))
+    );
+}
+
+#[derive(Debug)]
+struct WithdrawAll;
+
+impl rustls::server::FiltersCipherSuites for WithdrawAll {
+    fn filter(&self, _acceptable: &[CipherSuite]) -> Vec<CipherSuite> {
+        Vec::new()
+    }
+}
+
 #[derive(Debug, PartialEq)]
 struct KeyLogItem {
     label: String,
@@ -3047,6 +5822,207 @@ impl KeyLog for KeyLogToVec {
     }
 }
 
+/// Read every record `conn` wants to send.
+#[cfg(feature = "plain_messages")]
+fn take_records(
+    conn: &mut (impl DerefMut + Deref<Target = ConnectionCommon<impl SideData>>),
+) -> Vec<OpaqueMessage> {
+    let mut bytes = Vec::new();
+    while conn.wants_write() {
+        conn.write_tls(&mut bytes).unwrap();
+    }
+
+    let mut rd = Reader::init(&bytes);
+    let mut records = Vec::new();
+    while rd.any_left() {
+        records.push(OpaqueMessage::read(&mut rd).unwrap());
+    }
+    records
+}
+
+/// Build a decrypter for a TLS1.3 traffic secret from the key log, as a
+/// kernel TLS offload would.
+#[cfg(feature = "plain_messages")]
+fn decrypter_for_secret(
+    suite: &rustls::Tls13CipherSuite,
+    secret: &[u8],
+) -> Box<dyn rustls::crypto::cipher::MessageDecrypter> {
+    let (key, iv) = traffic_key_and_iv(suite, secret);
+    suite.aead_alg.decrypter(key, iv)
+}
+
+/// Derive the record protection key and IV for a TLS1.3 traffic secret.
+fn traffic_key_and_iv(
+    suite: &rustls::Tls13CipherSuite,
+    secret: &[u8],
+) -> (rustls::crypto::cipher::AeadKey, rustls::crypto::cipher::Iv) {
+    fn expand_label<const N: usize>(
+        expander: &dyn rustls::crypto::tls13::HkdfExpander,
+        label: &[u8],
+    ) -> [u8; N] {
+        let mut out = [0u8; N];
+        expander
+            .expand_slice(
+                &[
+                    &(N as u16).to_be_bytes(),
+                    &[(b"tls13 ".len() + label.len()) as u8],
+                    b"tls13 ",
+                    label,
+                    &[0],
+                ],
+                &mut out,
+            )
+            .unwrap();
+        out
+    }
+
+    let expander = suite
+        .hkdf_provider
+        .expander_for_okm(&rustls::crypto::tls13::OkmBlock::new(secret));
+    let key: [u8; 32] = expand_label(&*expander, b"key");
+    let iv: [u8; 12] = expand_label(&*expander, b"iv");
+    (key.into(), iv.into())
+}
+
+#[cfg(feature = "plain_messages")]
+#[test]
+fn process_message_drives_handshake_with_external_decryption() {
+    use rustls::CoreEffect;
+
+    let key_log = Arc::new(KeyLogToVec::new("server"));
+    let client_config = finish_client_config(
+        KeyType::Rsa,
+        ClientConfig::builder_with_provider(
+            CryptoProvider {
+                cipher_suites: vec![cipher_suite::TLS13_AES_256_GCM_SHA384],
+                ..provider::default_provider()
+            }
+            .into(),
+        )
+        .with_safe_default_protocol_versions()
+        .unwrap(),
+    );
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.key_log = key_log.clone();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    let client_secret = |label: &str| {
+        let suite = cipher_suite::TLS13_AES_256_GCM_SHA384
+            .tls13()
+            .unwrap();
+        let items = key_log.items.lock().unwrap();
+        let item = items
+            .iter()
+            .find(|item| item.label == label)
+            .unwrap();
+        decrypter_for_secret(suite, &item.secret)
+    };
+
+    // The ClientHello is unencrypted.
+    let mut effects = Vec::new();
+    for record in take_records(&mut client) {
+        effects.extend(server.process_message(record.into_plain_message()));
+    }
+    let flight = match &effects[..] {
+        [CoreEffect::EmitRecords(flight)] => flight,
+        _ => panic!("unexpected effects {:?}", effects),
+    };
+    assert_eq!(flight[0].typ, ContentType::Handshake);
+    assert!(!server.wants_write());
+
+    let mut bytes = Vec::new();
+    for record in flight {
+        bytes.extend(record.clone().encode());
+    }
+    let mut rd = &bytes[..];
+    while !rd.is_empty() {
+        client.read_tls(&mut rd).unwrap();
+        client.process_new_packets().unwrap();
+    }
+
+    // The client's Finished is encrypted under its handshake traffic secret.
+    let mut decrypter = client_secret("CLIENT_HANDSHAKE_TRAFFIC_SECRET");
+    let mut seq = 0;
+    let mut effects = Vec::new();
+    for record in take_records(&mut client) {
+        let msg = match record.typ {
+            ContentType::ChangeCipherSpec => record.into_plain_message(),
+            _ => {
+                seq += 1;
+                decrypter
+                    .decrypt(record, seq - 1)
+                    .unwrap()
+            }
+        };
+        effects.extend(server.process_message(msg));
+    }
+    match &effects[..] {
+        [CoreEffect::EmitRecords(tickets), CoreEffect::HandshakeComplete] => {
+            assert_eq!(tickets.len(), 4);
+        }
+        _ => panic!("unexpected effects {:?}", effects),
+    }
+    assert!(!server.is_handshaking());
+
+    client
+        .writer()
+        .write_all(b"hello world")
+        .unwrap();
+    let mut decrypter = client_secret("CLIENT_TRAFFIC_SECRET_0");
+    let mut records = take_records(&mut client);
+    assert_eq!(records.len(), 1);
+    let msg = decrypter
+        .decrypt(records.remove(0), 0)
+        .unwrap();
+    match &server.process_message(msg)[..] {
+        [CoreEffect::DeliverAppData(data)] => assert_eq!(data, b"hello world"),
+        effects => panic!("unexpected effects {:?}", effects),
+    }
+    assert_eq!(
+        server
+            .reader()
+            .read(&mut [0u8; 1])
+            .err()
+            .map(|e| e.kind()),
+        Some(io::ErrorKind::WouldBlock)
+    );
+}
+
+#[cfg(feature = "plain_messages")]
+#[test]
+fn process_message_reports_errors() {
+    use rustls::CoreEffect;
+
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    let client_hello = take_records(&mut client).remove(0);
+    server.process_message(
+        client_hello
+            .clone()
+            .into_plain_message(),
+    );
+
+    // A second ClientHello is fatal, and the server's alert is reported
+    // alongside the error.
+    let effects = server.process_message(
+        client_hello
+            .clone()
+            .into_plain_message(),
+    );
+    match &effects[..] {
+        [CoreEffect::EmitRecords(alert), CoreEffect::Error(err)] => {
+            assert_eq!(alert.len(), 1);
+            assert!(matches!(err, Error::InappropriateHandshakeMessage { .. }));
+        }
+        _ => panic!("unexpected effects {:?}", effects),
+    }
+
+    // Further messages do no work and report the same error.
+    match &server.process_message(client_hello.into_plain_message())[..] {
+        [CoreEffect::Error(Error::InappropriateHandshakeMessage { .. })] => {}
+        effects => panic!("unexpected effects {:?}", effects),
+    }
+}
+
 #[cfg(feature = "tls12")]
 #[test]
 fn key_log_for_tls12() {
@@ -3252,6 +6228,33 @@ fn vectored_write_for_server_handshake_with_half_rtt_data() {
     check_read(&mut client.reader(), b"012345678901234567890123456789");
 }
 
+#[test]
+fn server_half_rtt_data_arrives_before_client_finished() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.send_half_rtt_data = true;
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert!(server.is_handshaking());
+
+    // the server has sent its Finished, so writes are encrypted and sent right away
+    server
+        .writer()
+        .write_all(b"half-rtt")
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), b"half-rtt");
+
+    // the client's Finished has not yet been processed
+    assert!(server.is_handshaking());
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert!(!server.is_handshaking());
+}
+
 fn check_half_rtt_does_not_work(server_config: ServerConfig) {
     let (mut client, mut server) =
         make_pair_for_configs(make_client_config_with_auth(KeyType::Rsa), server_config);
@@ -3292,22 +6295,73 @@ fn check_half_rtt_does_not_work(server_config: ServerConfig) {
     }
 
     assert!(!server.is_handshaking());
-    assert!(!client.is_handshaking());
-    check_read(&mut client.reader(), b"012345678901234567890123456789");
-}
+    assert!(!client.is_handshaking());
+    check_read(&mut client.reader(), b"012345678901234567890123456789");
+}
+
+#[test]
+fn vectored_write_for_server_handshake_no_half_rtt_with_client_auth() {
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa);
+    server_config.send_half_rtt_data = true; // ask even though it will be ignored
+    check_half_rtt_does_not_work(server_config);
+}
+
+#[test]
+fn vectored_write_for_server_handshake_no_half_rtt_by_default() {
+    let server_config = make_server_config(KeyType::Rsa);
+    assert!(!server_config.send_half_rtt_data);
+    check_half_rtt_does_not_work(server_config);
+}
+
+#[test]
+fn new_session_ticket_interleaved_with_application_data() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.send_half_rtt_data = true;
+    server_config.send_tls13_tickets = 1;
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    let mut server_flight = Vec::new();
+    server
+        .write_tls(&mut server_flight)
+        .unwrap();
+
+    // half-rtt data is encrypted before the client's finished is received,
+    // so precedes the ticket
+    server
+        .writer()
+        .write_all(b"first")
+        .unwrap();
+    let mut records = Vec::new();
+    server.write_tls(&mut records).unwrap();
+
+    let mut rd = &server_flight[..];
+    while !rd.is_empty() {
+        client.read_tls(&mut rd).unwrap();
+        client.process_new_packets().unwrap();
+    }
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert!(!server.is_handshaking());
+    assert_eq!(server.post_handshake_messages_processed(), 0);
 
-#[test]
-fn vectored_write_for_server_handshake_no_half_rtt_with_client_auth() {
-    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa);
-    server_config.send_half_rtt_data = true; // ask even though it will be ignored
-    check_half_rtt_does_not_work(server_config);
-}
+    // ticket
+    server.write_tls(&mut records).unwrap();
+    server
+        .writer()
+        .write_all(b"second")
+        .unwrap();
+    server.write_tls(&mut records).unwrap();
 
-#[test]
-fn vectored_write_for_server_handshake_no_half_rtt_by_default() {
-    let server_config = make_server_config(KeyType::Rsa);
-    assert!(!server_config.send_half_rtt_data);
-    check_half_rtt_does_not_work(server_config);
+    assert_eq!(client.post_handshake_messages_processed(), 0);
+    client
+        .read_tls(&mut &records[..])
+        .unwrap();
+    client.process_new_packets().unwrap();
+    assert_eq!(client.post_handshake_messages_processed(), 1);
+    check_read(&mut client.reader(), b"firstsecond");
 }
 
 #[test]
@@ -3457,18 +6511,22 @@ struct ClientStorage {
 
 impl ClientStorage {
     fn new() -> Self {
+        Self::with_storage(Arc::new(rustls::client::ClientSessionMemoryCache::new(
+            1024,
+        )))
+    }
+
+    fn with_storage(storage: Arc<dyn rustls::client::ClientSessionStore>) -> Self {
         Self {
-            storage: Arc::new(rustls::client::ClientSessionMemoryCache::new(1024)),
+            storage,
             ops: Mutex::new(Vec::new()),
         }
     }
 
-    #[cfg(feature = "tls12")]
     fn ops(&self) -> Vec<ClientStorageOp> {
         self.ops.lock().unwrap().clone()
     }
 
-    #[cfg(feature = "tls12")]
     fn ops_and_reset(&self) -> Vec<ClientStorageOp> {
         std::mem::take(&mut self.ops.lock().unwrap())
     }
@@ -3567,115 +6625,465 @@ impl rustls::client::ClientSessionStore for ClientStorage {
     }
 }
 
-#[test]
-fn tls13_stateful_resumption() {
-    let kt = KeyType::Rsa;
-    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+#[test]
+fn tls13_stateful_resumption() {
+    let kt = KeyType::Rsa;
+    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    let storage = Arc::new(ServerStorage::new());
+    server_config.session_storage = storage.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(storage.puts(), 4);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_c2s > full_c2s);
+    assert!(resume_s2c < full_s2c);
+    assert_eq!(storage.puts(), 8);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 1);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+
+    // resumed again
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(resume_s2c, resume2_s2c);
+    assert_eq!(resume_c2s, resume2_c2s);
+    assert_eq!(storage.puts(), 12);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 2);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+}
+
+#[test]
+fn stateless_resumption_with_non_default_ticket_aead() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(KeyType::Rsa, &[version]));
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.ticketer =
+            provider::Ticketer::new_with_aead(rustls::ticketer::TicketAead::Aes256Gcm).unwrap();
+        let server_config = Arc::new(server_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        let expected = match version.version {
+            ProtocolVersion::TLSv1_2 => ResumptionKind::Tls12Ticket,
+            _ => ResumptionKind::Tls13Psk,
+        };
+        assert_eq!(
+            server.handshake_kind(),
+            Some(HandshakeKind::Resumed(expected))
+        );
+    }
+}
+
+#[test]
+fn tls13_stateless_resumption() {
+    let kt = KeyType::Rsa;
+    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.ticketer = provider::Ticketer::new().unwrap();
+    let storage = Arc::new(ServerStorage::new());
+    server_config.session_storage = storage.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_c2s > full_c2s);
+    assert!(resume_s2c < full_s2c);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+
+    // resumed again
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(resume_s2c, resume2_s2c);
+    assert_eq!(resume_c2s, resume2_c2s);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+}
+
+#[derive(Debug, PartialEq)]
+struct OfferedPsks {
+    identities: Vec<Vec<u8>>,
+    ages: Vec<u32>,
+}
+
+#[derive(Debug)]
+struct RecordPskIdentities {
+    inner: Arc<dyn ResolvesServerCert>,
+    seen: Mutex<Vec<Option<OfferedPsks>>>,
+}
+
+impl ResolvesServerCert for RecordPskIdentities {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        let offered = client_hello
+            .psk_identities()
+            .zip(client_hello.psk_obfuscated_ticket_ages())
+            .map(|(identities, ages)| OfferedPsks {
+                identities: identities
+                    .map(|id| id.to_vec())
+                    .collect(),
+                ages: ages.collect(),
+            });
+        self.seen.lock().unwrap().push(offered);
+        self.inner.resolve(client_hello)
+    }
+}
+
+#[test]
+fn server_observes_offered_psk_identities() {
+    let kt = KeyType::Rsa;
+    let client_config = Arc::new(make_client_config_with_versions(
+        kt,
+        &[&rustls::version::TLS13],
+    ));
+
+    let mut server_config = make_server_config(kt);
+    server_config.ticketer = provider::Ticketer::new().unwrap();
+    let recorder = Arc::new(RecordPskIdentities {
+        inner: Arc::clone(&server_config.cert_resolver),
+        seen: Mutex::new(Vec::new()),
+    });
+    server_config.cert_resolver = recorder.clone();
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(matches!(
+        client.handshake_kind(),
+        Some(HandshakeKind::Resumed(_))
+    ));
+
+    let seen = recorder.seen.lock().unwrap();
+    assert_eq!(seen.len(), 2);
+    assert_eq!(seen[0], None);
+    let offered = seen[1].as_ref().unwrap();
+    assert_eq!(offered.identities.len(), 1);
+    assert!(!offered.identities[0].is_empty());
+    assert_eq!(offered.ages.len(), 1);
+}
+
+#[derive(Debug, Default)]
+struct RecordNewTickets(Mutex<Vec<(String, NewSessionTicketMetadata)>>);
+
+impl ObservesNewTickets for RecordNewTickets {
+    fn new_ticket(&self, server_name: &ServerName<'_>, ticket: &NewSessionTicketMetadata) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((server_name.to_str().into_owned(), *ticket));
+    }
+}
+
+#[test]
+fn client_observes_new_tls13_tickets() {
+    let kt = KeyType::Rsa;
+    let observer = Arc::new(RecordNewTickets::default());
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.on_new_ticket = Some(observer.clone());
+    let mut server_config = make_server_config(kt);
+    server_config.send_tls13_tickets = 2;
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    let seen = observer.0.lock().unwrap();
+    assert_eq!(seen.len(), 2);
+    for (name, ticket) in seen.iter() {
+        assert_eq!(name, "localhost");
+        assert_eq!(ticket.version, ProtocolVersion::TLSv1_3);
+        assert_eq!(
+            Some(ticket.cipher_suite),
+            client
+                .negotiated_cipher_suite()
+                .map(|suite| suite.suite())
+        );
+        assert!(ticket.lifetime > Duration::ZERO);
+        assert!(ticket.ticket_len > 0);
+    }
+}
+
+#[test]
+fn tls13_client_reports_resumed_ticket_age() {
+    use rustls::client::ClientSessionStore;
+
+    let kt = KeyType::Rsa;
+    let storage = Arc::new(rustls::client::ClientSessionMemoryCache::new(32));
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.resumption = Resumption::store(storage.clone());
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.send_tls13_tickets = 1;
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.resumed_ticket(), None);
+
+    for rewind in [100, 24 * 60 * 60 - 100] {
+        // pretend the ticket was received `rewind` seconds ago
+        let mut ticket = storage
+            .take_tls13_ticket(&server_name("localhost"))
+            .unwrap();
+        ticket.rewind_epoch(rewind);
+        storage.insert_tls13_ticket(server_name("localhost"), ticket);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        let resumed = client.resumed_ticket().unwrap();
+        let age = resumed.age().as_secs();
+        assert!((u64::from(rewind)..u64::from(rewind) + 5).contains(&age));
+        assert_eq!(resumed.lifetime(), Some(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(resumed.is_near_expiry(), rewind > 100);
+    }
+}
+
+#[test]
+fn session_state_export_and_import() {
+    let kt = KeyType::Rsa;
+    for version in rustls::ALL_VERSIONS {
+        let server_config = Arc::new(make_server_config(kt));
+
+        // complete a full handshake, and export the session it yielded
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        assert_eq!(client.export_session_state(), None);
+        do_handshake(&mut client, &mut server);
+        let state = rustls::Connection::from(client)
+            .export_session_state()
+            .unwrap();
+        assert_eq!(
+            rustls::Connection::from(server).export_session_state(),
+            None
+        );
+
+        // a fresh client config with this state resumes
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        ClientConnection::import_session_state(&client_config, server_name("localhost"), &state)
+            .unwrap();
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(matches!(
+            client.handshake_kind(),
+            Some(HandshakeKind::Resumed(_))
+        ));
+
+        // without it, it does not
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+
+        // truncated state is rejected
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        assert!(ClientConnection::import_session_state(
+            &client_config,
+            server_name("localhost"),
+            &state[..state.len() - 1]
+        )
+        .is_err());
+    }
+}
+
+fn check_handshake_kinds(
+    client_config: ClientConfig,
+    server_config: ServerConfig,
+    resumed: ResumptionKind,
+) {
     let client_config = Arc::new(client_config);
-
-    let mut server_config = make_server_config(kt);
-    let storage = Arc::new(ServerStorage::new());
-    server_config.session_storage = storage.clone();
     let server_config = Arc::new(server_config);
 
-    // full handshake
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(storage.puts(), 4);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    assert_eq!(client.handshake_kind(), None);
+    assert_eq!(server.handshake_kind(), None);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::Full));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
 
-    // resumed
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
-    assert!(resume_c2s > full_c2s);
-    assert!(resume_s2c < full_s2c);
-    assert_eq!(storage.puts(), 8);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 1);
+    do_handshake(&mut client, &mut server);
     assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
+        client.handshake_kind(),
+        Some(HandshakeKind::Resumed(resumed))
     );
-
-    // resumed again
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(resume_s2c, resume2_s2c);
-    assert_eq!(resume_c2s, resume2_c2s);
-    assert_eq!(storage.puts(), 12);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 2);
     assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
+        server.handshake_kind(),
+        Some(HandshakeKind::Resumed(resumed))
     );
 }
 
 #[test]
-fn tls13_stateless_resumption() {
+fn handshake_kind_reports_resumption_mechanism() {
     let kt = KeyType::Rsa;
-    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
-    let client_config = Arc::new(client_config);
+    check_handshake_kinds(
+        make_client_config_with_versions(kt, &[&rustls::version::TLS13]),
+        make_server_config(kt),
+        ResumptionKind::Tls13Psk,
+    );
+
+    #[cfg(feature = "tls12")]
+    {
+        check_handshake_kinds(
+            make_client_config_with_versions(kt, &[&rustls::version::TLS12]),
+            make_server_config(kt),
+            ResumptionKind::Tls12SessionId,
+        );
+
+        let mut server_config = make_server_config(kt);
+        server_config.ticketer = provider::Ticketer::new().unwrap();
+        check_handshake_kinds(
+            make_client_config_with_versions(kt, &[&rustls::version::TLS12]),
+            server_config,
+            ResumptionKind::Tls12Ticket,
+        );
+    }
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_session_id_resumption_can_be_disabled() {
+    let kt = KeyType::Rsa;
+    for enabled in [true, false] {
+        let client_config = Arc::new(make_client_config_with_versions(
+            kt,
+            &[&rustls::version::TLS12],
+        ));
+        let mut server_config = make_server_config(kt);
+        server_config.tls12_session_id_resumption = enabled;
+        let server_config = Arc::new(server_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+        let expected = match enabled {
+            true => HandshakeKind::Resumed(ResumptionKind::Tls12SessionId),
+            false => HandshakeKind::Full,
+        };
+        assert_eq!(client.handshake_kind(), Some(expected));
+        assert_eq!(server.handshake_kind(), Some(expected));
+    }
 
+    // tickets are unaffected
     let mut server_config = make_server_config(kt);
+    server_config.tls12_session_id_resumption = false;
     server_config.ticketer = provider::Ticketer::new().unwrap();
-    let storage = Arc::new(ServerStorage::new());
-    server_config.session_storage = storage.clone();
+    check_handshake_kinds(
+        make_client_config_with_versions(kt, &[&rustls::version::TLS12]),
+        server_config,
+        ResumptionKind::Tls12Ticket,
+    );
+}
+
+fn resume_with_different_alpn(
+    version: &'static rustls::SupportedProtocolVersion,
+    strict: bool,
+) -> HandshakeKind {
+    let kt = KeyType::Rsa;
+    let mut server_config = make_server_config(kt);
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    server_config.require_resumption_alpn_match = strict;
     let server_config = Arc::new(server_config);
 
-    // full handshake
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    let mut client_config = make_client_config_with_versions(kt, &[version]);
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config.clone()), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
 
-    // resumed
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
-    assert!(resume_c2s > full_c2s);
-    assert!(resume_s2c < full_s2c);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    // shares the session store with the first config
+    client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"http/1.1"[..]));
+    assert_eq!(client.handshake_kind(), server.handshake_kind());
+    server.handshake_kind().unwrap()
+}
 
-    // resumed again
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(resume_s2c, resume2_s2c);
-    assert_eq!(resume_c2s, resume2_c2s);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+#[test]
+fn strict_resumption_alpn_falls_back_to_full_handshake() {
+    for version in rustls::ALL_VERSIONS {
+        assert!(matches!(
+            resume_with_different_alpn(version, false),
+            HandshakeKind::Resumed(_)
+        ));
+        assert_eq!(
+            resume_with_different_alpn(version, true),
+            HandshakeKind::Full
+        );
+    }
 }
 
 #[test]
@@ -3769,14 +7177,203 @@ fn early_data_can_be_rejected_by_server() {
         client
             .early_data()
             .unwrap()
-            .write(b"hello")
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+    server.reject_early_data();
+    do_handshake(&mut client, &mut server);
+
+    assert!(!client.is_early_data_accepted());
+    assert_eq!(
+        server.early_data_reject_reason(),
+        Some(EarlyDataRejectReason::Policy)
+    );
+}
+
+#[test]
+fn replayed_early_data_is_rejected_with_reason() {
+    let (client_config, server_config) = early_data_configs();
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    let mut first_flight = Vec::new();
+    client
+        .write_tls(&mut first_flight)
+        .unwrap();
+
+    server
+        .read_tls(&mut &first_flight[..])
+        .unwrap();
+    server.process_new_packets().unwrap();
+    assert!(server.early_data().is_some());
+    assert_eq!(server.early_data_reject_reason(), None);
+
+    // the ticket was used up by the first server, so the replay is refused
+    let mut replay_server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    replay_server
+        .read_tls(&mut &first_flight[..])
+        .unwrap();
+    replay_server
+        .process_new_packets()
+        .unwrap();
+    assert!(replay_server.early_data().is_none());
+    assert_eq!(
+        replay_server.early_data_reject_reason(),
+        Some(EarlyDataRejectReason::Replay)
+    );
+}
+
+#[test]
+fn early_data_reject_reason_reports_disabled_server() {
+    let (client_config, server_config) = early_data_configs();
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let mut server_config = (*server_config).clone();
+    server_config.max_early_data_size = 0;
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config));
+    client
+        .early_data()
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+    do_handshake(&mut client, &mut server);
+
+    assert!(!client.is_early_data_accepted());
+    assert_eq!(
+        server.early_data_reject_reason(),
+        Some(EarlyDataRejectReason::NotPermitted)
+    );
+}
+
+#[test]
+fn early_data_left_is_decremented_and_enforced_by_server() {
+    let (client_config, server_config) = early_data_configs();
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.early_data_left(), None);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert_eq!(server.early_data_left(), Some(1234 - 5));
+
+    // Reading early data does not restore the budget.
+    let mut received_early_data = [0u8; 5];
+    server
+        .early_data()
+        .unwrap()
+        .read_exact(&mut received_early_data)
+        .unwrap();
+    assert_eq!(&received_early_data[..], b"hello");
+    assert_eq!(server.early_data_left(), Some(1234 - 5));
+    do_handshake(&mut client, &mut server);
+
+    // The client's ticket allows it to send more than this server accepts.
+    let mut server_config = (*server_config).clone();
+    server_config.max_early_data_size = 5;
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello world")
+            .unwrap(),
+        11
+    );
+    let errs = do_handshake_until_both_error(&mut client, &mut server);
+    assert_eq!(
+        errs,
+        Err(vec![
+            ErrorFromPeer::Server(Error::PeerMisbehaved(
+                PeerMisbehaved::TooMuchEarlyDataReceived
+            )),
+            ErrorFromPeer::Client(Error::AlertReceived(AlertDescription::UnexpectedMessage)),
+        ])
+    );
+}
+
+#[test]
+fn early_data_size_is_carried_in_tickets_and_bounds_client() {
+    use rustls::client::ClientSessionStore;
+
+    let kt = KeyType::Rsa;
+    let storage = Arc::new(rustls::client::ClientSessionMemoryCache::new(16));
+    let mut client_config = make_client_config(kt);
+    client_config.enable_early_data = true;
+    client_config.resumption = Resumption::store(storage.clone());
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.max_early_data_size = 5;
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let name = server_name("localhost");
+    let ticket = storage
+        .take_tls13_ticket(&name)
+        .unwrap();
+    assert_eq!(ticket.max_early_data_size(), 5);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .bytes_left(),
+        5
+    );
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello world")
             .unwrap(),
         5
     );
-    server.reject_early_data();
     do_handshake(&mut client, &mut server);
+    assert!(client.is_early_data_accepted());
 
-    assert!(!client.is_early_data_accepted());
+    // Tickets issued without the early_data extension do not allow 0-RTT.
+    let storage = Arc::new(rustls::client::ClientSessionMemoryCache::new(16));
+    let mut client_config = make_client_config(kt);
+    client_config.enable_early_data = true;
+    client_config.resumption = Resumption::store(storage.clone());
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(kt));
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let ticket = storage
+        .take_tls13_ticket(&name)
+        .unwrap();
+    assert_eq!(ticket.max_early_data_size(), 0);
+
+    let (mut client, _) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client.early_data().is_none());
 }
 
 mod test_quic {
@@ -4518,6 +8115,57 @@ fn test_client_config_keyshare_mismatch() {
     assert!(do_handshake_until_error(&mut client, &mut server).is_err());
 }
 
+#[test]
+fn restricted_kx_groups_reject_peer_without_common_group() {
+    use rustls::NamedGroup;
+
+    for version in rustls::ALL_VERSIONS {
+        let mut server_config = make_server_config_with_versions(KeyType::Rsa, &[version]);
+        server_config
+            .restrict_kx_groups(&[NamedGroup::X25519])
+            .unwrap();
+        let client_config =
+            make_client_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::SECP256R1]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+                PeerIncompatible::NoKxGroupsInCommon
+            )))
+        );
+        transfer(&mut server, &mut client);
+        assert_eq!(
+            client.process_new_packets().err(),
+            Some(Error::AlertReceived(AlertDescription::HandshakeFailure))
+        );
+
+        // a client restricted to X25519 no longer offers secp256r1
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config
+            .restrict_kx_groups(&[NamedGroup::X25519])
+            .unwrap();
+        let server_config =
+            make_server_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::SECP256R1]);
+        let (mut client, mut server) = make_pair_for_configs(client_config.clone(), server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+                PeerIncompatible::NoKxGroupsInCommon
+            )))
+        );
+
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        do_handshake(&mut client, &mut server);
+    }
+
+    let mut client_config = make_client_config(KeyType::Rsa);
+    assert_eq!(
+        client_config.restrict_kx_groups(&[NamedGroup::FFDHE2048]),
+        Err(Error::General("no kx groups configured".into()))
+    );
+}
+
 #[cfg(feature = "tls12")]
 #[test]
 fn test_client_sends_helloretryrequest() {
@@ -4527,93 +8175,462 @@ fn test_client_sends_helloretryrequest() {
         vec![provider::kx_group::SECP384R1, provider::kx_group::X25519],
     );
 
-    let storage = Arc::new(ClientStorage::new());
-    client_config.resumption = Resumption::store(storage.clone());
+    let storage = Arc::new(ClientStorage::new());
+    client_config.resumption = Resumption::store(storage.clone());
+
+    // but server only accepts x25519, so a HRR is required
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::X25519]);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    // client sends hello
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert!(wrlen > 200);
+        assert_eq!(pipe.writevs.len(), 1);
+        assert!(pipe.writevs[0].len() == 1);
+    }
+
+    // server sends HRR
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert!(wrlen < 100); // just the hello retry request
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() == 2); // hello retry request and CCS
+    }
+
+    // client sends fixed hello
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert!(wrlen > 200); // just the client hello retry
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() == 2); // only a CCS & client hello retry
+    }
+
+    // server completes handshake
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert!(wrlen > 200);
+        assert_eq!(pipe.writevs.len(), 1);
+        assert!(pipe.writevs[0].len() == 5); // server hello / encrypted exts / cert / cert-verify / finished
+    }
+
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+
+    // client only did following storage queries:
+    println!("storage {:#?}", storage.ops());
+    assert_eq!(storage.ops().len(), 9);
+    assert!(matches!(
+        storage.ops()[0],
+        ClientStorageOp::TakeTls13Ticket(_, false)
+    ));
+    assert!(matches!(
+        storage.ops()[1],
+        ClientStorageOp::GetTls12Session(_, false)
+    ));
+    assert!(matches!(
+        storage.ops()[2],
+        ClientStorageOp::GetKxHint(_, None)
+    ));
+    assert!(matches!(
+        storage.ops()[3],
+        ClientStorageOp::SetKxHint(_, rustls::NamedGroup::X25519)
+    ));
+    assert!(matches!(
+        storage.ops()[4],
+        ClientStorageOp::RemoveTls12Session(_)
+    ));
+    // server sends 4 tickets by default
+    assert!(matches!(
+        storage.ops()[5],
+        ClientStorageOp::InsertTls13Ticket(_)
+    ));
+    assert!(matches!(
+        storage.ops()[6],
+        ClientStorageOp::InsertTls13Ticket(_)
+    ));
+    assert!(matches!(
+        storage.ops()[7],
+        ClientStorageOp::InsertTls13Ticket(_)
+    ));
+    assert!(matches!(
+        storage.ops()[8],
+        ClientStorageOp::InsertTls13Ticket(_)
+    ));
+}
+
+fn offer_compression_methods(
+    methods: Vec<rustls::internal::msgs::enums::Compression>,
+) -> impl Fn(&mut Message) -> Altered {
+    move |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                ch.compression_methods = methods.clone();
+                *encoded = Payload::new(parsed.get_encoding());
+            }
+        }
+        Altered::InPlace
+    }
+}
+
+fn tls13_server_handles_compression_offer(
+    allow_offers: bool,
+    methods: Vec<rustls::internal::msgs::enums::Compression>,
+) -> (Result<(), Error>, Result<(), Error>) {
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.allow_tls13_compression_offers = allow_offers;
+
+    let (client, server) = make_pair_for_configs(client_config, server_config);
+    let (mut client, mut server) = (client.into(), server.into());
+    transfer_altered(&mut client, offer_compression_methods(methods), &mut server);
+    let server_result = server.process_new_packets().map(|_| ());
+    transfer_altered(&mut server, unaltered, &mut client);
+    let client_result = client.process_new_packets().map(|_| ());
+    (server_result, client_result)
+}
+
+#[test]
+fn tls13_server_rejects_non_null_compression_offer_by_default() {
+    use rustls::internal::msgs::enums::Compression;
+
+    assert_eq!(
+        tls13_server_handles_compression_offer(
+            false,
+            vec![Compression::Null, Compression::Deflate]
+        ),
+        (
+            Err(Error::PeerMisbehaved(
+                PeerMisbehaved::OfferedIncorrectCompressions
+            )),
+            Err(Error::AlertReceived(AlertDescription::IllegalParameter)),
+        )
+    );
+}
+
+#[test]
+fn tls13_server_can_ignore_non_null_compression_offer() {
+    use rustls::internal::msgs::enums::Compression;
+
+    // the server proceeds with its flight.  (The handshake cannot complete,
+    // because the client's transcript does not include the alteration.)
+    let (server_result, _) =
+        tls13_server_handles_compression_offer(true, vec![Compression::Null, Compression::Deflate]);
+    assert_eq!(server_result, Ok(()));
+
+    // null compression is still required
+    assert_eq!(
+        tls13_server_handles_compression_offer(true, vec![Compression::Deflate]),
+        (
+            Err(Error::PeerIncompatible(
+                PeerIncompatible::NullCompressionRequired
+            )),
+            Err(Error::AlertReceived(AlertDescription::IllegalParameter)),
+        )
+    );
+}
+
+#[test]
+fn server_rejects_duplicate_client_hello_extensions() {
+    fn duplicate_supported_versions(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                let versions = ch
+                    .extensions
+                    .iter()
+                    .find(|ext| matches!(ext, ClientExtension::SupportedVersions(_)))
+                    .cloned()
+                    .unwrap();
+                ch.extensions.insert(0, versions);
+                *encoded = Payload::new(parsed.get_encoding());
+            }
+        }
+        Altered::InPlace
+    }
+
+    let (client, server) = make_pair(KeyType::Rsa);
+    let (mut client, mut server) = (client.into(), server.into());
+    transfer_altered(&mut client, duplicate_supported_versions, &mut server);
+    assert_eq!(
+        server.process_new_packets().err(),
+        Some(Error::PeerMisbehaved(
+            PeerMisbehaved::DuplicateClientHelloExtensions
+        ))
+    );
+    transfer_altered(&mut server, unaltered, &mut client);
+    assert_eq!(
+        client.process_new_packets().err(),
+        Some(Error::AlertReceived(AlertDescription::DecodeError))
+    );
+}
+
+#[test]
+fn client_rejects_duplicate_server_hello_extensions() {
+    use rustls::internal::msgs::handshake::ServerExtension;
+
+    fn duplicate_supported_versions(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ServerHello(sh) = &mut parsed.payload {
+                let versions = sh
+                    .extensions
+                    .iter()
+                    .find(|ext| matches!(ext, ServerExtension::SupportedVersions(_)))
+                    .cloned()
+                    .unwrap();
+                sh.extensions.insert(0, versions);
+                *encoded = Payload::new(parsed.get_encoding());
+            }
+        }
+        Altered::InPlace
+    }
+
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    let (client, server) = make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    let (mut client, mut server) = (client.into(), server.into());
+    transfer_altered(&mut client, unaltered, &mut server);
+    server.process_new_packets().unwrap();
+    transfer_altered(&mut server, duplicate_supported_versions, &mut client);
+    assert_eq!(
+        client.process_new_packets().err(),
+        Some(Error::PeerMisbehaved(
+            PeerMisbehaved::DuplicateServerHelloExtensions
+        ))
+    );
+    transfer_altered(&mut client, unaltered, &mut server);
+    assert_eq!(
+        server.process_new_packets().err(),
+        Some(Error::AlertReceived(AlertDescription::DecodeError))
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn server_declines_static_rsa_key_exchange() {
+    // rustls does not implement static RSA key exchange, so a client offering
+    // only such suites has nothing in common with the server.
+    fn offer_static_rsa_only(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                ch.cipher_suites = vec![CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256];
+                *encoded = Payload::new(parsed.get_encoding());
+            }
+        }
+        Altered::InPlace
+    }
+
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    let server_config = make_server_config(KeyType::Rsa);
+
+    let (client, server) = make_pair_for_configs(client_config, server_config);
+    let (mut client, mut server) = (client.into(), server.into());
+    transfer_altered(&mut client, offer_static_rsa_only, &mut server);
+    assert_eq!(
+        server.process_new_packets().err(),
+        Some(Error::PeerIncompatible(
+            PeerIncompatible::NoCipherSuitesInCommon
+        ))
+    );
+    transfer_altered(&mut server, unaltered, &mut client);
+    assert_eq!(
+        client.process_new_packets().err(),
+        Some(Error::AlertReceived(AlertDescription::HandshakeFailure))
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn client_limits_total_size_of_handshake_flight() {
+    fn server_flight(client_config: ClientConfig) -> (Vec<usize>, Result<rustls::IoState, Error>) {
+        let (client, server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        let (mut client, mut server) = (client.into(), server.into());
+        transfer_altered(&mut client, unaltered, &mut server);
+        server.process_new_packets().unwrap();
+
+        let sizes = Mutex::new(Vec::new());
+        transfer_altered(
+            &mut server,
+            |msg: &mut Message| {
+                if let MessagePayload::Handshake { encoded, .. } = &msg.payload {
+                    sizes
+                        .lock()
+                        .unwrap()
+                        .push(encoded.0.len());
+                }
+                Altered::InPlace
+            },
+            &mut client,
+        );
+        (sizes.into_inner().unwrap(), client.process_new_packets())
+    }
+
+    // the TLS1.2 server flight is in plaintext, so we can see its messages
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    let (sizes, result) = server_flight(client_config.clone());
+    assert!(sizes.len() > 1);
+    assert!(result.is_ok());
+
+    // every message fits within the limit, but the flight as a whole does not
+    let mut client_config = client_config;
+    client_config.max_handshake_flight_bytes = Some(*sizes.iter().max().unwrap());
+    let (_, result) = server_flight(client_config.clone());
+    assert_eq!(
+        result.err(),
+        Some(Error::InvalidMessage(
+            rustls::InvalidMessage::HandshakeFlightTooLarge
+        ))
+    );
+
+    client_config.max_handshake_flight_bytes = Some(sizes.iter().sum());
+    let (_, result) = server_flight(client_config);
+    assert!(result.is_ok());
+}
+
+#[derive(Debug, Default)]
+struct TestCookies {
+    reject: bool,
+    verified: Mutex<Vec<Vec<u8>>>,
+}
+
+impl rustls::server::CookieProvider for TestCookies {
+    fn generate(&self, client_hello_hash: &[u8]) -> Vec<u8> {
+        let mut cookie = b"cookie:".to_vec();
+        cookie.extend_from_slice(client_hello_hash);
+        cookie
+    }
+
+    fn verify(&self, cookie: &[u8]) -> bool {
+        self.verified
+            .lock()
+            .unwrap()
+            .push(cookie.to_vec());
+        !self.reject && cookie.starts_with(b"cookie:")
+    }
+}
+
+fn hrr_pair_with_cookies(cookies: Arc<TestCookies>) -> (rustls::Connection, rustls::Connection) {
+    // client prefers a secp384r1 key share, server only accepts x25519
+    let client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        vec![provider::kx_group::SECP384R1, provider::kx_group::X25519],
+    );
+    let mut server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::X25519]);
+    server_config.cookie_provider = Some(cookies);
+
+    let (client, server) = make_pair_for_configs(client_config, server_config);
+    let (mut client, mut server) = (client.into(), server.into());
+    transfer_altered(&mut client, unaltered, &mut server);
+    server.process_new_packets().unwrap();
+    transfer_altered(&mut server, unaltered, &mut client);
+    client.process_new_packets().unwrap();
+    (client, server)
+}
 
-    // but server only accepts x25519, so a HRR is required
-    let server_config =
-        make_server_config_with_kx_groups(KeyType::Rsa, vec![provider::kx_group::X25519]);
+fn unaltered(_: &mut Message) -> Altered {
+    Altered::InPlace
+}
 
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+#[test]
+fn test_server_hrr_cookie_is_echoed_and_accepted() {
+    let cookies = Arc::new(TestCookies::default());
+    let (mut client, mut server) = hrr_pair_with_cookies(cookies.clone());
 
-    // client sends hello
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert!(wrlen > 200);
-        assert_eq!(pipe.writevs.len(), 1);
-        assert!(pipe.writevs[0].len() == 1);
+    let sent_cookie = Mutex::new(None);
+    let check_retried_hello = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, .. } = &msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &parsed.payload {
+                for ext in &ch.extensions {
+                    if let ClientExtension::Cookie(cookie) = ext {
+                        *sent_cookie.lock().unwrap() = Some(cookie.0.clone());
+                    }
+                }
+            }
+        }
+        Altered::InPlace
+    };
+    transfer_altered(&mut client, check_retried_hello, &mut server);
+    server.process_new_packets().unwrap();
+    while server.is_handshaking() || client.is_handshaking() {
+        transfer_altered(&mut server, unaltered, &mut client);
+        client.process_new_packets().unwrap();
+        transfer_altered(&mut client, unaltered, &mut server);
+        server.process_new_packets().unwrap();
     }
 
-    // server sends HRR
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert!(wrlen < 100); // just the hello retry request
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() == 2); // hello retry request and CCS
-    }
+    let sent_cookie = sent_cookie
+        .into_inner()
+        .unwrap()
+        .expect("retried ClientHello lacks cookie");
+    assert!(sent_cookie.starts_with(b"cookie:"));
+    assert_eq!(*cookies.verified.lock().unwrap(), vec![sent_cookie]);
+}
 
-    // client sends fixed hello
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert!(wrlen > 200); // just the client hello retry
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() == 2); // only a CCS & client hello retry
-    }
+#[test]
+fn test_server_rejects_hrr_cookie_that_fails_verification() {
+    let cookies = Arc::new(TestCookies {
+        reject: true,
+        ..Default::default()
+    });
+    let (mut client, mut server) = hrr_pair_with_cookies(cookies.clone());
 
-    // server completes handshake
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert!(wrlen > 200);
-        assert_eq!(pipe.writevs.len(), 1);
-        assert!(pipe.writevs[0].len() == 5); // server hello / encrypted exts / cert / cert-verify / finished
-    }
+    transfer_altered(&mut client, unaltered, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::IllegalHelloRetryCookie
+        ))
+    );
+    transfer_altered(&mut server, unaltered, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::IllegalParameter))
+    );
+    assert_eq!(cookies.verified.lock().unwrap().len(), 1);
+}
 
-    do_handshake_until_error(&mut client, &mut server).unwrap();
+#[test]
+fn test_server_rejects_second_hello_retry() {
+    use rustls::internal::msgs::handshake::KeyShareEntry;
 
-    // client only did following storage queries:
-    println!("storage {:#?}", storage.ops());
-    assert_eq!(storage.ops().len(), 9);
-    assert!(matches!(
-        storage.ops()[0],
-        ClientStorageOp::TakeTls13Ticket(_, false)
-    ));
-    assert!(matches!(
-        storage.ops()[1],
-        ClientStorageOp::GetTls12Session(_, false)
-    ));
-    assert!(matches!(
-        storage.ops()[2],
-        ClientStorageOp::GetKxHint(_, None)
-    ));
-    assert!(matches!(
-        storage.ops()[3],
-        ClientStorageOp::SetKxHint(_, rustls::NamedGroup::X25519)
-    ));
-    assert!(matches!(
-        storage.ops()[4],
-        ClientStorageOp::RemoveTls12Session(_)
-    ));
-    // server sends 4 tickets by default
-    assert!(matches!(
-        storage.ops()[5],
-        ClientStorageOp::InsertTls13Ticket(_)
-    ));
-    assert!(matches!(
-        storage.ops()[6],
-        ClientStorageOp::InsertTls13Ticket(_)
-    ));
-    assert!(matches!(
-        storage.ops()[7],
-        ClientStorageOp::InsertTls13Ticket(_)
-    ));
-    assert!(matches!(
-        storage.ops()[8],
-        ClientStorageOp::InsertTls13Ticket(_)
-    ));
+    let (mut client, mut server) = hrr_pair_with_cookies(Arc::new(TestCookies::default()));
+
+    // the retried hello ignores the requested x25519 group
+    let ignore_requested_group = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                for ext in ch.extensions.iter_mut() {
+                    if let ClientExtension::KeyShare(shares) = ext {
+                        *shares = vec![KeyShareEntry::new(
+                            rustls::NamedGroup::secp384r1,
+                            &[0x42; 97],
+                        )];
+                    }
+                }
+                *encoded = Payload::new(parsed.get_encoding());
+            }
+        }
+        Altered::InPlace
+    };
+    transfer_altered(&mut client, ignore_requested_group, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::RefusedToFollowHelloRetryRequest
+        ))
+    );
+    assert!(server.is_handshaking());
+
+    transfer_altered(&mut server, unaltered, &mut client);
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::UnexpectedMessage))
+    );
 }
 
 #[test]
@@ -4730,6 +8747,101 @@ fn test_client_attempts_to_use_unsupported_kx_group() {
     ));
 }
 
+#[test]
+fn server_sends_configured_number_of_tickets() {
+    fn tickets_received(server_config: ServerConfig) -> usize {
+        let storage = Arc::new(ClientStorage::new());
+        let mut client_config = make_client_config(KeyType::Rsa);
+        client_config.resumption = Resumption::store(storage.clone());
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        storage
+            .ops()
+            .iter()
+            .filter(|op| matches!(op, ClientStorageOp::InsertTls13Ticket(_)))
+            .count()
+    }
+
+    for n in [0, 1, 7] {
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.num_tickets(n);
+        assert_eq!(tickets_received(server_config), n);
+    }
+
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.num_tickets(1000);
+    assert_eq!(
+        server_config.send_tls13_tickets,
+        rustls::server::MAX_TLS13_TICKETS
+    );
+    assert_eq!(
+        tickets_received(server_config),
+        rustls::server::MAX_TLS13_TICKETS
+    );
+
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.num_tickets(2);
+    server_config.send_tls13_tickets_jitter = 3;
+    let server_config = Arc::new(server_config);
+    for _ in 0..8 {
+        let sent = tickets_received((*server_config).clone());
+        assert!((2..=5).contains(&sent), "sent {sent} tickets");
+    }
+}
+
+#[test]
+fn server_honours_client_ticket_request() {
+    fn ticket_requesting_config(storage: &Arc<ClientStorage>) -> Arc<ClientConfig> {
+        let mut client_config = make_client_config(KeyType::Rsa);
+        client_config.resumption = Resumption::store(storage.clone());
+        client_config.ticket_request = Some(TicketRequest {
+            new_session_count: 2,
+            resumption_count: 1,
+        });
+        Arc::new(client_config)
+    }
+
+    fn tickets_received(
+        storage: &ClientStorage,
+        client_config: &Arc<ClientConfig>,
+        server_config: &Arc<ServerConfig>,
+    ) -> usize {
+        let before = storage.ops().len();
+        let (mut client, mut server) = make_pair_for_arc_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        storage.ops()[before..]
+            .iter()
+            .filter(|op| matches!(op, ClientStorageOp::InsertTls13Ticket(_)))
+            .count()
+    }
+
+    // The server would send 4 tickets by default.
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+    let storage = Arc::new(ClientStorage::new());
+    let client_config = ticket_requesting_config(&storage);
+    assert_eq!(
+        tickets_received(&storage, &client_config, &server_config),
+        2
+    );
+    // A resumed handshake gets the resumption count.
+    assert_eq!(
+        tickets_received(&storage, &client_config, &server_config),
+        1
+    );
+
+    // The request does not raise the server's own limit.
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.num_tickets(1);
+    let server_config = Arc::new(server_config);
+    let storage = Arc::new(ClientStorage::new());
+    let client_config = ticket_requesting_config(&storage);
+    assert_eq!(
+        tickets_received(&storage, &client_config, &server_config),
+        1
+    );
+}
+
 #[cfg(feature = "tls12")]
 #[test]
 fn test_tls13_client_resumption_does_not_reuse_tickets() {
@@ -4783,6 +8895,43 @@ fn test_tls13_client_resumption_does_not_reuse_tickets() {
     assert!(matches!(ops[0], ClientStorageOp::TakeTls13Ticket(_, false)));
 }
 
+#[test]
+fn client_limits_stored_tls13_tickets_per_server() {
+    let shared_storage = Arc::new(ClientStorage::with_storage(Arc::new(
+        rustls::client::ClientSessionMemoryCache::new_with_tls13_tickets_per_server(1024, 3),
+    )));
+
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.resumption = Resumption::store(shared_storage.clone());
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.num_tickets(10);
+    let server_config = Arc::new(server_config);
+
+    // first handshake: server sends 10 tickets
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let inserted = shared_storage
+        .ops_and_reset()
+        .iter()
+        .filter(|op| matches!(op, ClientStorageOp::InsertTls13Ticket(_)))
+        .count();
+    assert_eq!(inserted, 10);
+
+    // only 3 of them were kept
+    for expect_ticket in [true, true, true, false] {
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+
+        let ops = shared_storage.ops_and_reset();
+        assert!(ops.iter().any(
+            |op| matches!(op, ClientStorageOp::TakeTls13Ticket(_, taken) if *taken == expect_ticket)
+        ));
+    }
+}
+
 #[test]
 fn test_client_mtu_reduction() {
     struct CollectWrites {
@@ -4933,17 +9082,142 @@ fn handshakes_complete_and_data_flows_with_gratuitious_max_fragment_sizes() {
     }
 }
 
+/// Like `transfer`, but returns the size of the largest TLS record sent.
+fn transfer_measuring_records(
+    left: &mut (impl DerefMut + Deref<Target = ConnectionCommon<impl SideData>>),
+    right: &mut (impl DerefMut + Deref<Target = ConnectionCommon<impl SideData>>),
+) -> usize {
+    let mut largest = 0;
+
+    while left.wants_write() {
+        let mut buf = Vec::new();
+        left.write_tls(&mut buf).unwrap();
+
+        let mut records = &buf[..];
+        while !records.is_empty() {
+            let len = 5 + usize::from(u16::from_be_bytes([records[3], records[4]]));
+            largest = largest.max(len);
+            records = &records[len..];
+        }
+
+        let mut reader = &buf[..];
+        while !reader.is_empty() {
+            right.read_tls(&mut reader).unwrap();
+        }
+    }
+
+    largest
+}
+
+#[test]
+fn max_record_size_bounds_every_record_sent() {
+    const MTU: usize = 1200;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        for version in rustls::ALL_VERSIONS {
+            println!("test kt={kt:?} version={version:?}");
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let server_config = make_server_config(*kt);
+            let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+            client
+                .set_max_record_size(Some(MTU))
+                .unwrap();
+            server
+                .set_max_record_size(Some(MTU))
+                .unwrap();
+
+            let mut largest = 0;
+            while client.is_handshaking() || server.is_handshaking() {
+                largest = largest.max(transfer_measuring_records(&mut client, &mut server));
+                server.process_new_packets().unwrap();
+                largest = largest.max(transfer_measuring_records(&mut server, &mut client));
+                client.process_new_packets().unwrap();
+            }
+
+            let data = [0x5a; 5000];
+            server
+                .writer()
+                .write_all(&data)
+                .unwrap();
+            largest = largest.max(transfer_measuring_records(&mut server, &mut client));
+            client.process_new_packets().unwrap();
+            check_read(&mut client.reader(), &data);
+
+            client
+                .writer()
+                .write_all(&data)
+                .unwrap();
+            largest = largest.max(transfer_measuring_records(&mut client, &mut server));
+            server.process_new_packets().unwrap();
+            check_read(&mut server.reader(), &data);
+
+            assert!(largest <= MTU, "sent a {largest} byte record");
+            // the certificate flight alone needs the full MTU
+            assert!(largest > MTU - 100);
+        }
+    }
+}
+
+#[test]
+fn bad_max_record_sizes() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    assert_eq!(
+        client.set_max_record_size(Some(63)),
+        Err(Error::BadMaxFragmentSize)
+    );
+    assert_eq!(client.set_max_record_size(Some(64)), Ok(()));
+    assert_eq!(client.set_max_record_size(None), Ok(()));
+}
+
 fn assert_lt(left: usize, right: usize) {
     if left >= right {
         panic!("expected {} < {}", left, right);
     }
-}
+}
+
+#[test]
+fn connection_types_are_not_huge() {
+    // Arbitrary sizes
+    assert_lt(mem::size_of::<ServerConnection>(), 1600);
+    assert_lt(mem::size_of::<ClientConnection>(), 1600);
+}
+
+#[derive(Debug, Default)]
+struct CollectUnknownExtensions(Mutex<Vec<(u16, Vec<u8>)>>);
+
+impl rustls::server::ObservesUnknownExtensions for CollectUnknownExtensions {
+    fn observe(&self, typ: u16, payload: &[u8]) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((typ, payload.to_vec()));
+    }
+}
+
+#[test]
+fn test_server_ignores_grease_extension() {
+    fn insert_grease_extension(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                let grease = ClientExtension::read_bytes(&[0x0a, 0x0a, 0x00, 0x01, 0x00]).unwrap();
+                ch.extensions.insert(0, grease);
+            }
+
+            *encoded = Payload::new(parsed.get_encoding());
+        }
+        Altered::InPlace
+    }
 
-#[test]
-fn connection_types_are_not_huge() {
-    // Arbitrary sizes
-    assert_lt(mem::size_of::<ServerConnection>(), 1600);
-    assert_lt(mem::size_of::<ClientConnection>(), 1600);
+    let observer = Arc::new(CollectUnknownExtensions::default());
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.on_unknown_extension = Some(observer.clone());
+
+    let (client, server) = make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    let (mut client, mut server) = (client.into(), server.into());
+    transfer_altered(&mut client, insert_grease_extension, &mut server);
+    assert!(server.process_new_packets().is_ok());
+    assert!(server.wants_write());
+    assert_eq!(*observer.0.lock().unwrap(), vec![(0x0a0a, vec![0x00])]);
 }
 
 #[test]
@@ -5059,6 +9333,28 @@ fn test_client_rejects_illegal_tls13_ccs() {
     );
 }
 
+#[test]
+fn test_server_rejects_tls13_ccs_with_wrong_payload() {
+    for (payload, expected) in [
+        (0x01, Ok(())),
+        (
+            0x00,
+            Err(Error::PeerMisbehaved(
+                PeerMisbehaved::IllegalMiddleboxChangeCipherSpec,
+            )),
+        ),
+    ] {
+        let (mut client, mut server) = make_pair(KeyType::Rsa);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        assert_eq!(server.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+
+        let ccs = [0x14, 0x03, 0x03, 0x00, 0x01, payload];
+        server.read_tls(&mut &ccs[..]).unwrap();
+        assert_eq!(server.process_new_packets().map(|_| ()), expected);
+    }
+}
+
 /// https://github.com/rustls/rustls/issues/797
 #[cfg(feature = "tls12")]
 #[test]
@@ -5649,3 +9945,508 @@ fn test_client_construction_requires_64_bytes_of_random_material() {
     ClientConnection::new(Arc::new(client_config), server_name("localhost"))
         .expect("check how much random material ClientConnection::new consumes");
 }
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_explicit_nonce_random_controls_tls12_gcm_nonces() {
+    static NONCE_RANDOM: FaultyRandom = FaultyRandom {
+        rand_queue: Mutex::new(b"\x10\x20\x30\x40\x50\x60\x70\x80"),
+    };
+    static SERVER_NONCE_RANDOM: FaultyRandom = FaultyRandom {
+        rand_queue: Mutex::new(b"\x01\x02\x03\x04\x05\x06\x07\x08"),
+    };
+
+    let mut client_config = finish_client_config(
+        KeyType::Rsa,
+        rustls::ClientConfig::builder_with_provider(
+            CryptoProvider {
+                cipher_suites: vec![cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256],
+                ..provider::default_provider()
+            }
+            .into(),
+        )
+        .with_protocol_versions(&[&rustls::version::TLS12])
+        .unwrap(),
+    );
+    client_config.explicit_nonce_random = Some(&NONCE_RANDOM);
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.explicit_nonce_random = Some(&SERVER_NONCE_RANDOM);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    // The explicit nonce follows the record header, and is the starting point
+    // xor the sequence number.  The client's Finished was record 0.
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    assert_eq!(&buf[5..13], b"\x10\x20\x30\x40\x50\x60\x70\x81");
+
+    server.read_tls(&mut &buf[..]).unwrap();
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
+
+    server
+        .writer()
+        .write_all(b"world")
+        .unwrap();
+    let mut buf = Vec::new();
+    server.write_tls(&mut buf).unwrap();
+    assert_eq!(&buf[5..13], b"\x01\x02\x03\x04\x05\x06\x07\x09");
+
+    client.read_tls(&mut &buf[..]).unwrap();
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), b"world");
+}
+
+#[test]
+fn test_server_hello_can_be_built_and_encoded() {
+    use rustls::internal::msgs::codec::Reader;
+    use rustls::internal::msgs::enums::{Compression, NamedGroup};
+    use rustls::internal::msgs::handshake::{
+        HandshakeMessagePayload, KeyShareEntry, Random, ServerExtension, ServerHelloPayload,
+        SessionId,
+    };
+    use rustls::HandshakeType;
+
+    // Deliberately odd choices: an unknown version, and a compression method
+    // that no client should accept.
+    let server_hello = HandshakeMessagePayload {
+        typ: HandshakeType::ServerHello,
+        payload: HandshakePayload::ServerHello(ServerHelloPayload {
+            legacy_version: ProtocolVersion::Unknown(0x0305),
+            random: Random::from([0x5a; 32]),
+            session_id: SessionId::read_bytes(&[4, 1, 2, 3, 4]).unwrap(),
+            cipher_suite: CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+            compression_method: Compression::Deflate,
+            extensions: vec![
+                ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_3),
+                ServerExtension::KeyShare(KeyShareEntry::new(NamedGroup::X25519, &[0x42; 32])),
+            ],
+        }),
+    };
+    let encoded = server_hello.get_encoding();
+
+    let decoded = HandshakeMessagePayload::read(&mut Reader::init(&encoded)).unwrap();
+    assert_eq!(decoded.typ, HandshakeType::ServerHello);
+    let (expected, actual) = match (&server_hello.payload, &decoded.payload) {
+        (HandshakePayload::ServerHello(expected), HandshakePayload::ServerHello(actual)) => {
+            (expected, actual)
+        }
+        _ => panic!("unexpected payload {:?}", decoded.payload),
+    };
+    assert_eq!(actual.legacy_version, expected.legacy_version);
+    assert_eq!(actual.random, expected.random);
+    assert_eq!(actual.session_id, expected.session_id);
+    assert_eq!(actual.cipher_suite, expected.cipher_suite);
+    assert_eq!(actual.compression_method, expected.compression_method);
+    assert_eq!(
+        actual
+            .extensions
+            .iter()
+            .map(Codec::get_encoding)
+            .collect::<Vec<_>>(),
+        expected
+            .extensions
+            .iter()
+            .map(Codec::get_encoding)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(decoded.get_encoding(), encoded);
+}
+
+#[test]
+fn tls13_certificate_message_round_trips() {
+    use rustls::internal::msgs::codec::Reader;
+    use rustls::internal::msgs::handshake::{CertificateExtension, HandshakeMessagePayload};
+
+    fn push_u24(bytes: &mut Vec<u8>, len: usize) {
+        bytes.extend_from_slice(&(len as u32).to_be_bytes()[1..]);
+    }
+
+    // status_request, carrying an OCSP response, then an unknown extension
+    let exts = [
+        &[
+            0x00, 0x05, 0x00, 0x07, 0x01, 0x00, 0x00, 0x03, 0x01, 0x02, 0x03,
+        ][..],
+        &[0xff, 0x00, 0x00, 0x02, 0xab, 0xcd][..],
+    ]
+    .concat();
+
+    let mut entries = Vec::new();
+    for (i, cert) in KeyType::Rsa
+        .get_chain()
+        .iter()
+        .enumerate()
+    {
+        push_u24(&mut entries, cert.as_ref().len());
+        entries.extend_from_slice(cert.as_ref());
+        match i {
+            0 => {
+                entries.extend_from_slice(&(exts.len() as u16).to_be_bytes());
+                entries.extend_from_slice(&exts);
+            }
+            _ => entries.extend_from_slice(&[0, 0]),
+        }
+    }
+
+    let mut body = vec![0x00];
+    push_u24(&mut body, entries.len());
+    body.extend_from_slice(&entries);
+
+    let mut message = vec![0x0b];
+    push_u24(&mut message, body.len());
+    message.extend_from_slice(&body);
+
+    let parsed = HandshakeMessagePayload::read_version(
+        &mut Reader::init(&message),
+        ProtocolVersion::TLSv1_3,
+    )
+    .unwrap();
+    match &parsed.payload {
+        HandshakePayload::CertificateTls13(payload) => {
+            assert!(payload.context.0.is_empty());
+            assert_eq!(payload.entries.len(), KeyType::Rsa.get_chain().len());
+            assert_eq!(payload.entries[0].cert, KeyType::Rsa.get_chain()[0]);
+            assert!(matches!(
+                payload.entries[0].exts[..],
+                [
+                    CertificateExtension::CertificateStatus(_),
+                    CertificateExtension::Unknown(_)
+                ]
+            ));
+            assert!(payload.entries[1].exts.is_empty());
+        }
+        other => panic!("unexpected payload {:?}", other),
+    }
+
+    assert_eq!(parsed.get_encoding(), message);
+}
+
+#[derive(Debug)]
+struct NoServerCert;
+
+impl ResolvesServerCert for NoServerCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        None
+    }
+}
+
+fn external_psk_configs(
+    client_secret: &[u8],
+    server_secret: &[u8],
+) -> (ClientConfig, ServerConfig) {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.external_psk = Some(Arc::new(rustls::ExternalPsk::new(
+        b"client-identity".to_vec(),
+        client_secret.to_vec(),
+        HashAlgorithm::SHA256,
+    )));
+
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cert_resolver = Arc::new(NoServerCert);
+    server_config.external_psks = vec![Arc::new(rustls::ExternalPsk::new(
+        b"client-identity".to_vec(),
+        server_secret.to_vec(),
+        HashAlgorithm::SHA256,
+    ))];
+
+    (client_config, server_config)
+}
+
+#[test]
+fn external_psk_handshake_without_certificates() {
+    let (client_config, server_config) = external_psk_configs(&[0x42; 32], &[0x42; 32]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(client.handshake_kind(), Some(HandshakeKind::ExternalPsk));
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::ExternalPsk));
+    assert!(client.peer_certificates().is_none());
+    assert!(server.peer_certificates().is_none());
+    assert_eq!(
+        client
+            .negotiated_cipher_suite()
+            .map(|suite| suite.suite()),
+        Some(CipherSuite::TLS13_AES_128_GCM_SHA256)
+    );
+
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
+}
+
+#[test]
+fn external_psk_handshake_fails_with_mismatched_secret() {
+    let (client_config, server_config) = external_psk_configs(&[0x42; 32], &[0x24; 32]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerMisbehaved(
+            PeerMisbehaved::IncorrectBinder
+        )))
+    );
+}
+
+#[test]
+fn external_psk_not_used_when_client_auth_is_mandatory() {
+    let psk = || {
+        Arc::new(rustls::ExternalPsk::new(
+            b"client-identity".to_vec(),
+            vec![0x42; 32],
+            HashAlgorithm::SHA384,
+        ))
+    };
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa);
+    server_config.external_psks = vec![psk()];
+    let server_config = Arc::new(server_config);
+
+    // without a client certificate, the handshake must fail
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.external_psk = Some(psk());
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server = ServerConnection::new(server_config.clone()).unwrap();
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
+    );
+
+    // with one, the handshake authenticates it as usual
+    let mut client_config =
+        make_client_config_with_versions_with_auth(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.external_psk = Some(psk());
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server = ServerConnection::new(server_config).unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::Full));
+    assert!(server.peer_certificates().is_some());
+
+    // optional client auth is skipped by external PSK handshakes
+    let mut server_config = make_server_config_with_optional_client_auth(KeyType::Rsa, vec![]);
+    server_config.external_psks = vec![psk()];
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.external_psk = Some(psk());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.handshake_kind(), Some(HandshakeKind::ExternalPsk));
+    assert!(server.peer_certificates().is_none());
+}
+
+#[derive(Debug)]
+struct AadRecorder(Mutex<Vec<(u64, Vec<u8>, AadDirection)>>);
+
+impl ObservesAad for AadRecorder {
+    fn on_aad(&self, seq: u64, aad: &[u8], direction: AadDirection) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((seq, aad.to_vec(), direction));
+    }
+}
+
+#[test]
+fn aad_observer_sees_tls13_record_aad() {
+    let client_aad = Arc::new(AadRecorder(Mutex::new(Vec::new())));
+    let server_aad = Arc::new(AadRecorder(Mutex::new(Vec::new())));
+
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.aad_observer = Some(client_aad.clone());
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.aad_observer = Some(server_aad.clone());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(!client_aad.0.lock().unwrap().is_empty());
+    client_aad.0.lock().unwrap().clear();
+    server_aad.0.lock().unwrap().clear();
+
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    // application_data, legacy_record_version, and the length of the
+    // payload, its content type, and the tag.
+    let expected_aad = vec![0x17, 0x03, 0x03, 0x00, 5 + 1 + 16];
+    assert_eq!(
+        *client_aad.0.lock().unwrap(),
+        vec![(0, expected_aad.clone(), AadDirection::Encrypt)]
+    );
+    assert_eq!(
+        *server_aad.0.lock().unwrap(),
+        vec![(0, expected_aad, AadDirection::Decrypt)]
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn aad_observer_sees_tls12_record_aad() {
+    let client_aad = Arc::new(AadRecorder(Mutex::new(Vec::new())));
+    let server_aad = Arc::new(AadRecorder(Mutex::new(Vec::new())));
+
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config.aad_observer = Some(client_aad.clone());
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.aad_observer = Some(server_aad.clone());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    client_aad.0.lock().unwrap().clear();
+    server_aad.0.lock().unwrap().clear();
+
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    // the sequence number (after Finished), application_data, the record
+    // version, and the length of the plaintext.
+    let expected_aad = vec![0, 0, 0, 0, 0, 0, 0, 1, 0x17, 0x03, 0x03, 0x00, 5];
+    assert_eq!(
+        *client_aad.0.lock().unwrap(),
+        vec![(1, expected_aad.clone(), AadDirection::Encrypt)]
+    );
+    assert_eq!(
+        *server_aad.0.lock().unwrap(),
+        vec![(1, expected_aad, AadDirection::Decrypt)]
+    );
+}
+
+fn client_config_sending_fallback_scsv(
+    versions: &[&'static rustls::SupportedProtocolVersion],
+) -> ClientConfig {
+    let mut client_config = make_client_config_with_versions(KeyType::Rsa, versions);
+    client_config
+        .dangerous()
+        .set_client_hello_mutator(|hello| {
+            hello
+                .cipher_suites
+                .push(CipherSuite::TLS_FALLBACK_SCSV)
+        });
+    client_config
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn server_rejects_inappropriate_fallback() {
+    let client_config = client_config_sending_fallback_scsv(&[&rustls::version::TLS12]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    assert_eq!(
+        do_handshake_until_both_error(&mut client, &mut server),
+        Err(vec![
+            ErrorFromPeer::Server(Error::PeerMisbehaved(PeerMisbehaved::InappropriateFallback)),
+            ErrorFromPeer::Client(Error::AlertReceived(
+                AlertDescription::InappropriateFallback
+            )),
+        ])
+    );
+
+    // a server that only supports TLS1.2 was not fallen back from
+    let client_config = client_config_sending_fallback_scsv(&[&rustls::version::TLS12]);
+    let server_config = make_server_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+}
+
+#[test]
+fn server_accepts_fallback_scsv_with_highest_version() {
+    let client_config = client_config_sending_fallback_scsv(&[&rustls::version::TLS13]);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+}
+
+#[test]
+fn peer_certificate_validity_reports_end_entity_times() {
+    // `openssl x509 -in test-ca/rsa/end.cert -noout -dates`
+    let not_before = UnixTime::since_unix_epoch(Duration::from_secs(1_698_079_204));
+    let not_after = UnixTime::since_unix_epoch(Duration::from_secs(1_870_879_204));
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(client.peer_certificate_validity(), None);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(
+            client.peer_certificate_validity(),
+            Some((not_before, not_after))
+        );
+        assert_eq!(server.peer_certificate_validity(), None);
+    }
+}
+
+fn inject_application_data(conn: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>) {
+    let record = [0x17, 0x03, 0x03, 0x00, 0x05, b'h', b'e', b'l', b'l', b'o'];
+    conn.read_tls(&mut &record[..]).unwrap();
+    let err = conn.process_new_packets().unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::InappropriateMessage {
+                got_type: ContentType::ApplicationData,
+                ..
+            }
+        ),
+        "unexpected error {:?}",
+        err
+    );
+}
+
+#[test]
+fn application_data_before_handshake_completes_is_rejected() {
+    // before the ClientHello
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    inject_application_data(&mut server);
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        client.process_new_packets().err(),
+        Some(Error::AlertReceived(AlertDescription::UnexpectedMessage))
+    );
+
+    #[cfg(feature = "tls12")]
+    {
+        // TLS1.2 server, before the client's key exchange
+        let client_config =
+            make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        inject_application_data(&mut server);
+
+        // TLS1.2 client, before the server's Finished
+        let client_config =
+            make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        inject_application_data(&mut client);
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets().err(),
+            Some(Error::AlertReceived(AlertDescription::UnexpectedMessage))
+        );
+    }
+}
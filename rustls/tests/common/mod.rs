@@ -10,7 +10,8 @@ use webpki::anchor_from_trusted_cert;
 
 use rustls::client::{ServerCertVerifierBuilder, WebPkiServerVerifier};
 use rustls::internal::msgs::codec::Reader;
-use rustls::internal::msgs::message::{Message, OpaqueMessage, PlainMessage};
+use rustls::internal::msgs::handshake::{ClientHelloPayload, HandshakePayload};
+use rustls::internal::msgs::message::{Message, MessagePayload, OpaqueMessage, PlainMessage};
 use rustls::server::{ClientCertVerifierBuilder, WebPkiClientVerifier};
 use rustls::Connection;
 use rustls::Error;
@@ -63,6 +64,8 @@ embed_files! {
     (ECDSA_END_FULLCHAIN, "ecdsa", "end.fullchain");
     (ECDSA_END_KEY, "ecdsa", "end.key");
     (ECDSA_END_REQ, "ecdsa", "end.req");
+    (ECDSA_CT_LOG_KEY, "ecdsa", "ct-log.key");
+    (ECDSA_CT_LOG_SPKI, "ecdsa", "ct-log.spki.der");
     (ECDSA_INTER_CERT, "ecdsa", "inter.cert");
     (ECDSA_INTER_KEY, "ecdsa", "inter.key");
     (ECDSA_INTER_REQ, "ecdsa", "inter.req");
@@ -102,6 +105,9 @@ embed_files! {
     (RSA_CLIENT_CRL_PEM, "rsa", "client.revoked.crl.pem");
     (RSA_INTERMEDIATE_CRL_PEM, "rsa", "inter.revoked.crl.pem");
     (RSA_END_CERT, "rsa", "end.cert");
+    (RSA_END_1024_CERT, "rsa", "end-1024.cert");
+    (RSA_END_RIGHT_CERT, "rsa", "end-right.cert");
+    (RSA_END_UNKNOWN_CRITICAL_CERT, "rsa", "end-unknown-critical.cert");
     (RSA_END_CHAIN, "rsa", "end.chain");
     (RSA_END_FULLCHAIN, "rsa", "end.fullchain");
     (RSA_END_KEY, "rsa", "end.key");
@@ -246,7 +252,7 @@ impl KeyType {
         self.get_crl("inter")
     }
 
-    fn get_client_key(&self) -> PrivateKeyDer<'static> {
+    pub fn get_client_key(&self) -> PrivateKeyDer<'static> {
         PrivateKeyDer::Pkcs8(
             rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(
                 self.bytes_for("client.key"),
@@ -544,6 +550,25 @@ pub fn make_pair_for_arc_configs(
     )
 }
 
+/// Decode the first ClientHello sent by a client using `config`.
+pub fn first_client_hello(config: ClientConfig) -> ClientHelloPayload {
+    let mut client = ClientConnection::new(Arc::new(config), server_name("localhost")).unwrap();
+    let mut bytes = Vec::new();
+    client.write_tls(&mut bytes).unwrap();
+
+    let msg = OpaqueMessage::read(&mut Reader::init(&bytes)).unwrap();
+    match Message::try_from(msg.into_plain_message())
+        .unwrap()
+        .payload
+    {
+        MessagePayload::Handshake { parsed, .. } => match parsed.payload {
+            HandshakePayload::ClientHello(hello) => hello,
+            _ => panic!("expected a ClientHello"),
+        },
+        _ => panic!("expected a handshake message"),
+    }
+}
+
 pub fn do_handshake(
     client: &mut (impl DerefMut + Deref<Target = ConnectionCommon<impl SideData>>),
     server: &mut (impl DerefMut + Deref<Target = ConnectionCommon<impl SideData>>),
@@ -4,15 +4,21 @@
 
 mod common;
 use crate::common::{
-    do_handshake, do_handshake_until_both_error, make_client_config_with_versions,
-    make_pair_for_arc_configs, make_server_config, ErrorFromPeer, ALL_KEY_TYPES,
+    bytes_for, do_handshake, do_handshake_until_both_error, make_client_config_with_versions,
+    make_pair_for_arc_configs, make_server_config, provider, server_config_builder, ErrorFromPeer,
+    KeyType, ALL_KEY_TYPES,
 };
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{verify_scts, CtLog};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
 use rustls::DigitallySignedStruct;
-use rustls::{AlertDescription, Error, InvalidMessage, SignatureScheme};
+use rustls::{AlertDescription, CertificateError, Error, InvalidMessage, SignatureScheme};
+use rustls::{ClientConnection, ServerConnection};
 
-use pki_types::{CertificateDer, ServerName, UnixTime};
+use pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
 
+use std::io;
 use std::sync::Arc;
 
 #[test]
@@ -144,9 +150,7 @@ fn client_can_override_certificate_verification_and_offer_no_signature_schemes()
             assert_eq!(
                 errs,
                 Err(vec![
-                    ErrorFromPeer::Server(Error::PeerIncompatible(
-                        rustls::PeerIncompatible::NoSignatureSchemesInCommon
-                    )),
+                    ErrorFromPeer::Server(Error::NoSignatureSchemes { offered: vec![] }),
                     ErrorFromPeer::Client(Error::AlertReceived(AlertDescription::HandshakeFailure)),
                 ])
             );
@@ -154,12 +158,244 @@ fn client_can_override_certificate_verification_and_offer_no_signature_schemes()
     }
 }
 
+#[test]
+fn client_offering_only_ed25519_to_rsa_server_fails_with_offered_schemes() {
+    let verifier = Arc::new(MockServerVerifier::offers_only(vec![
+        SignatureScheme::ED25519,
+    ]));
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(verifier.clone());
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        let errs = do_handshake_until_both_error(&mut client, &mut server);
+        assert_eq!(
+            errs,
+            Err(vec![
+                ErrorFromPeer::Server(Error::NoSignatureSchemes {
+                    offered: vec![SignatureScheme::ED25519],
+                }),
+                ErrorFromPeer::Client(Error::AlertReceived(AlertDescription::HandshakeFailure)),
+            ])
+        );
+    }
+}
+
+#[test]
+fn client_can_require_rfc1123_server_names() {
+    let verifier = Arc::new(MockServerVerifier::accepts_anything());
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+    let name = ServerName::try_from("internal_service.example").unwrap();
+    // long labels are refused before rustls sees the name, so cannot be relaxed
+    assert!(ServerName::try_from(format!("{}.example", "a".repeat(64)).as_str()).is_err());
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = |require_rfc1123_server_name| {
+            let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+            client_config
+                .dangerous()
+                .set_certificate_verifier(verifier.clone());
+            client_config.require_rfc1123_server_name = require_rfc1123_server_name;
+            Arc::new(client_config)
+        };
+
+        // underscores are accepted by default
+        let mut client = ClientConnection::new(client_config(false), name.clone()).unwrap();
+        let mut server = ServerConnection::new(server_config.clone()).unwrap();
+        do_handshake(&mut client, &mut server);
+
+        let mut client = ClientConnection::new(client_config(true), name.clone()).unwrap();
+        let mut server = ServerConnection::new(server_config.clone()).unwrap();
+        let errs = do_handshake_until_both_error(&mut client, &mut server);
+        assert_eq!(
+            errs,
+            Err(vec![
+                ErrorFromPeer::Client(Error::InvalidCertificate(CertificateError::NotValidForName)),
+                ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::BadCertificate)),
+            ])
+        );
+    }
+}
+
+#[test]
+fn client_rfc1123_server_names_use_real_verifier() {
+    let server_config = Arc::new(make_server_config(KeyType::Rsa));
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.require_rfc1123_server_name = true;
+        let client_config = Arc::new(client_config);
+
+        for name in ["testserver.com", "second.testserver.com"] {
+            let name = ServerName::try_from(name).unwrap();
+            let mut client = ClientConnection::new(client_config.clone(), name).unwrap();
+            let mut server = ServerConnection::new(server_config.clone()).unwrap();
+            do_handshake(&mut client, &mut server);
+        }
+
+        let name = ServerName::try_from("second_.testserver.com").unwrap();
+        let mut client = ClientConnection::new(client_config.clone(), name).unwrap();
+        let mut server = ServerConnection::new(server_config.clone()).unwrap();
+        let errs = do_handshake_until_both_error(&mut client, &mut server);
+        assert_eq!(
+            errs,
+            Err(vec![
+                ErrorFromPeer::Client(Error::InvalidCertificate(CertificateError::NotValidForName)),
+                ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::BadCertificate)),
+            ])
+        );
+    }
+}
+
+#[test]
+fn client_can_request_and_verify_scts() {
+    let kt = KeyType::Rsa;
+    let log = CtLog {
+        id: ct_log_id(),
+        key: bytes_for("ecdsa", "ct-log.spki.der").to_vec(),
+    };
+    let end_entity = kt.get_chain().remove(0);
+    let timestamp = (UnixTime::now().as_secs() - 60) * 1000;
+    let good_sct = sign_sct(&end_entity, timestamp);
+    // Changing the timestamp invalidates the log's signature.
+    let mut bad_sct = good_sct.clone();
+    bad_sct[1 + 32 + 7] ^= 1;
+    // SCTs from logs we don't know are ignored.
+    let mut unknown_log_sct = good_sct.clone();
+    unknown_log_sct[1] ^= 1;
+
+    let server_config_with_scts = |scts: Vec<Vec<u8>>| {
+        let key = provider::default_provider()
+            .key_provider
+            .load_private_key(kt.get_key())
+            .unwrap();
+        let mut certified_key = CertifiedKey::new(kt.get_chain(), key);
+        certified_key.scts = scts;
+        Arc::new(
+            server_config_builder()
+                .with_no_client_auth()
+                .with_cert_resolver(Arc::new(AlwaysResolves(Arc::new(certified_key)))),
+        )
+    };
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = |verifier: MockServerVerifier| {
+            let mut client_config = make_client_config_with_versions(kt, &[version]);
+            client_config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(verifier));
+            Arc::new(client_config)
+        };
+
+        // SCTs are delivered to, and validated by, a verifier that asks for them.
+        let (mut client, mut server) = make_pair_for_arc_configs(
+            &client_config(MockServerVerifier::requires_scts(vec![log.clone()])),
+            &server_config_with_scts(vec![unknown_log_sct.clone(), good_sct.clone()]),
+        );
+        do_handshake(&mut client, &mut server);
+
+        // Clients that don't ask aren't sent any.
+        let (mut client, mut server) = make_pair_for_arc_configs(
+            &client_config(MockServerVerifier::accepts_anything()),
+            &server_config_with_scts(vec![good_sct.clone()]),
+        );
+        do_handshake(&mut client, &mut server);
+
+        for (scts, expected) in [
+            (vec![bad_sct.clone()], CertificateError::InvalidSct),
+            (
+                vec![unknown_log_sct.clone()],
+                CertificateError::ApplicationVerificationFailure,
+            ),
+            (vec![], CertificateError::ApplicationVerificationFailure),
+        ] {
+            let (mut client, mut server) = make_pair_for_arc_configs(
+                &client_config(MockServerVerifier::requires_scts(vec![log.clone()])),
+                &server_config_with_scts(scts),
+            );
+            let errs = do_handshake_until_both_error(&mut client, &mut server);
+            let alert = AlertDescription::from(expected.clone());
+            assert_eq!(
+                errs,
+                Err(vec![
+                    ErrorFromPeer::Client(Error::InvalidCertificate(expected)),
+                    ErrorFromPeer::Server(Error::AlertReceived(alert)),
+                ])
+            );
+        }
+    }
+}
+
+/// The ID of the mock CT log in `test-ca`: the SHA-256 hash of its public key.
+fn ct_log_id() -> [u8; 32] {
+    let hash = provider::cipher_suite::TLS13_AES_128_GCM_SHA256
+        .tls13()
+        .unwrap()
+        .common
+        .hash_provider
+        .hash(bytes_for("ecdsa", "ct-log.spki.der"));
+    hash.as_ref().try_into().unwrap()
+}
+
+/// Have the mock CT log in `test-ca` issue an SCT for `end_entity`.
+fn sign_sct(end_entity: &CertificateDer<'_>, timestamp: u64) -> Vec<u8> {
+    let key = rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(bytes_for(
+        "ecdsa",
+        "ct-log.key",
+    )))
+    .next()
+    .unwrap()
+    .unwrap();
+    let signer = provider::default_provider()
+        .key_provider
+        .load_private_key(PrivateKeyDer::Pkcs8(key))
+        .unwrap()
+        .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+        .unwrap();
+
+    let mut signed = vec![0, 0];
+    signed.extend_from_slice(&timestamp.to_be_bytes());
+    signed.extend_from_slice(&[0, 0]);
+    signed.extend_from_slice(&(end_entity.len() as u32).to_be_bytes()[1..]);
+    signed.extend_from_slice(end_entity);
+    signed.extend_from_slice(&[0, 0]);
+    let signature = signer.sign(&signed).unwrap();
+
+    let mut sct = vec![0];
+    sct.extend_from_slice(&ct_log_id());
+    sct.extend_from_slice(&timestamp.to_be_bytes());
+    sct.extend_from_slice(&[0, 0]);
+    sct.extend_from_slice(
+        &SignatureScheme::ECDSA_NISTP256_SHA256
+            .get_u16()
+            .to_be_bytes(),
+    );
+    sct.extend_from_slice(&(signature.len() as u16).to_be_bytes());
+    sct.extend_from_slice(&signature);
+    sct
+}
+
+#[derive(Debug)]
+struct AlwaysResolves(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for AlwaysResolves {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
 #[derive(Debug)]
 pub struct MockServerVerifier {
     cert_rejection_error: Option<Error>,
     tls12_signature_error: Option<Error>,
     tls13_signature_error: Option<Error>,
     signature_schemes: Vec<SignatureScheme>,
+    ct_logs: Option<Vec<CtLog>>,
 }
 
 impl ServerCertVerifier for MockServerVerifier {
@@ -219,6 +455,31 @@ impl ServerCertVerifier for MockServerVerifier {
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
         self.signature_schemes.clone()
     }
+
+    fn requests_scts(&self) -> bool {
+        self.ct_logs.is_some()
+    }
+
+    fn verify_server_scts(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        scts: &[&[u8]],
+        now: UnixTime,
+    ) -> Result<(), Error> {
+        println!(
+            "verify_server_scts({:?}, {:?}, {:?})",
+            end_entity, scts, now
+        );
+        let logs = self
+            .ct_logs
+            .as_deref()
+            .unwrap_or_default();
+        let supported_algs = provider::default_provider().signature_verification_algorithms;
+        match verify_scts(end_entity, scts, logs, &supported_algs, now)? {
+            0 => Err(CertificateError::ApplicationVerificationFailure.into()),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl MockServerVerifier {
@@ -250,9 +511,20 @@ impl MockServerVerifier {
         }
     }
 
+    pub fn requires_scts(ct_logs: Vec<CtLog>) -> Self {
+        MockServerVerifier {
+            ct_logs: Some(ct_logs),
+            ..Default::default()
+        }
+    }
+
     pub fn offers_no_signature_schemes() -> Self {
+        Self::offers_only(vec![])
+    }
+
+    pub fn offers_only(signature_schemes: Vec<SignatureScheme>) -> Self {
         MockServerVerifier {
-            signature_schemes: vec![],
+            signature_schemes,
             ..Default::default()
         }
     }
@@ -264,6 +536,7 @@ impl Default for MockServerVerifier {
             cert_rejection_error: None,
             tls12_signature_error: None,
             tls13_signature_error: None,
+            ct_logs: None,
             signature_schemes: vec![
                 SignatureScheme::RSA_PSS_SHA256,
                 SignatureScheme::RSA_PKCS1_SHA256,
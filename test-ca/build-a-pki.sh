@@ -47,6 +47,15 @@ openssl rsa \
           -in rsa/client.key \
           -out rsa/client.rsa
 
+# An undersized end-entity key, for exercising minimum key size policies.
+openssl req -nodes \
+          -newkey rsa:1024 \
+          -keyout rsa/end-1024.key \
+          -out rsa/end-1024.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=testserver.com"
+
 # ecdsa
 openssl ecparam -name prime256v1 -out ecdsa/nistp256.pem
 openssl ecparam -name secp384r1 -out ecdsa/nistp384.pem
@@ -88,6 +97,16 @@ openssl req -nodes \
           -days 2000 \
           -subj "/CN=ponytown client"
 
+# A mock certificate transparency log key, for signing test SCTs.
+openssl genpkey -algorithm EC \
+          -pkeyopt ec_paramgen_curve:P-256 \
+          -out ecdsa/ct-log.key
+openssl pkey \
+          -in ecdsa/ct-log.key \
+          -pubout \
+          -outform DER \
+          -out ecdsa/ct-log.spki.der
+
 # eddsa
 
 # TODO: add support for Ed448
@@ -220,6 +239,38 @@ for kt in rsa ecdsa eddsa ; do
   openssl asn1parse -in $kt/ca.cert -out $kt/ca.der > /dev/null
 done
 
+openssl x509 -req \
+          -in rsa/end-1024.req \
+          -out rsa/end-1024.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 457 \
+          -extensions v3_end -extfile openssl.cnf
+
+# The usual end-entity key, but only valid for right.example.com.
+openssl x509 -req \
+          -in rsa/end.req \
+          -out rsa/end-right.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 458 \
+          -extensions v3_end_right -extfile openssl.cnf
+
+# The usual end-entity key, with a critical extension webpki does not know.
+openssl x509 -req \
+          -in rsa/end.req \
+          -out rsa/end-unknown-critical.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 459 \
+          -extensions v3_end_unknown_critical -extfile openssl.cnf
+
 # Tidy up openssl CA state.
 rm index.txt* || true
 rm crlnumber* || true